@@ -30,13 +30,15 @@ use fedimint_mint::MintGen;
 use mint_client::api::{
     FederationApiExt, GlobalFederationApi, IFederationApi, WsFederationApi, WsFederationConnect,
 };
+use mint_client::ln::history::LightningPayment;
+use mint_client::ln::offer::StaticPaymentCode;
 use mint_client::mint::SpendableNote;
 use mint_client::query::EventuallyConsistent;
 use mint_client::utils::{
     from_hex, parse_bitcoin_amount, parse_ecash, parse_fedimint_amount, parse_node_pub_key,
     serialize_ecash,
 };
-use mint_client::{module_decode_stubs, Client, UserClientConfig};
+use mint_client::{module_decode_stubs, Client, ClientError, UserClientConfig};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tracing_subscriber::EnvFilter;
@@ -82,6 +84,10 @@ enum CliOutput {
         contract_id: ContractId,
     },
 
+    LnPayParts {
+        contract_ids: Vec<ContractId>,
+    },
+
     Fetch {
         issuance: Vec<OutPoint>,
     },
@@ -103,6 +109,26 @@ enum CliOutput {
         paid_in_tx: OutPoint,
     },
 
+    AcceptPayment {
+        accepted_in_tx: TransactionId,
+    },
+
+    LnOffer {
+        payment_code: StaticPaymentCode,
+    },
+
+    ClaimOfferPayments {
+        claimed_in_tx: Option<OutPoint>,
+    },
+
+    LnAddress {
+        address: String,
+    },
+
+    LnHistory {
+        payments: Vec<LightningPayment>,
+    },
+
     WaitBlockHeight {
         reached: u64,
     },
@@ -217,6 +243,10 @@ enum Command {
     /// Generate a new peg-in address, funds sent to it can later be claimed
     PegInAddress,
 
+    /// Generate a new peg-in address and register it with the federation, funds sent to it can
+    /// later be claimed by outpoint using `peg-in-outpoint`
+    PegInRegister,
+
     /// Send direct method call to the API, waiting for all peers to agree on a response
     Api {
         method: String,
@@ -233,6 +263,9 @@ enum Command {
         transaction: Transaction,
     },
 
+    /// Issue notes for a deposit to a registered peg-in address, identified by `txid:vout`
+    PegInOutpoint { outpoint: bitcoin::OutPoint },
+
     /// Reissue notes received from a third party to avoid double spends
     Reissue {
         #[clap(value_parser = parse_ecash)]
@@ -259,7 +292,12 @@ enum Command {
     },
 
     /// Pay a lightning invoice via a gateway
-    LnPay { bolt11: lightning_invoice::Invoice },
+    LnPay {
+        bolt11: lightning_invoice::Invoice,
+        /// Split the payment across up to this many gateways
+        #[clap(long)]
+        parts: Option<usize>,
+    },
 
     /// Fetch (re-)issued notes and finalize issuance process
     Fetch,
@@ -273,11 +311,32 @@ enum Command {
         amount: Amount,
         description: String,
         expiry_time: Option<u64>,
+        /// Hold the payment until it's accepted with `accept-payment`
+        #[clap(long)]
+        hold: bool,
     },
 
     /// Wait for incoming invoice to be paid
     WaitInvoice { invoice: lightning_invoice::Invoice },
 
+    /// Accept the held payment of a hold invoice
+    AcceptPayment { invoice: lightning_invoice::Invoice },
+
+    /// Create a reusable offer answered by the gateway to receive any number of payments
+    LnOffer { description: String },
+
+    /// Claim all payments received via reusable offers
+    ClaimOfferPayments,
+
+    /// Register a Lightning address with the gateway that receives via a new reusable offer
+    LnAddress {
+        username: String,
+        description: String,
+    },
+
+    /// List all Lightning payments made and received with their receipts
+    LnHistory,
+
     /// Wait for the fed to reach a consensus block height
     WaitBlockHeight { height: u64 },
 
@@ -470,6 +529,22 @@ async fn handle_command(
                 address: (peg_in_address),
             })
         }
+        Command::PegInRegister => {
+            let registration = match client.register_pegin_address(&mut rng).await {
+                Ok((address, out_point)) => client
+                    .wallet_client()
+                    .await_peg_in_registration(out_point)
+                    .await
+                    .map(|_| address)
+                    .map_err(ClientError::from),
+                Err(e) => Err(e),
+            };
+            registration.transform(
+                |v| CliOutput::PegInAddress { address: (v) },
+                CliErrorKind::GeneralFederationError,
+                "peg-in registration failed (no further information)",
+            )
+        }
         Command::PegIn {
             txout_proof,
             transaction,
@@ -481,6 +556,14 @@ async fn handle_command(
                 CliErrorKind::GeneralFederationError,
                 "peg-in failed (no further information)",
             ),
+        Command::PegInOutpoint { outpoint } => client
+            .peg_in_registered(outpoint, &mut rng)
+            .await
+            .transform(
+                |v| CliOutput::PegIn { id: (v) },
+                CliErrorKind::GeneralFederationError,
+                "peg-in failed (no further information)",
+            ),
 
         Command::Reissue { notes } => {
             let id = client.reissue(notes, &mut rng).await;
@@ -576,48 +659,117 @@ async fn handle_command(
                 )),
             }
         }
-        Command::LnPay { bolt11 } => {
-            match client.fund_outgoing_ln_contract(bolt11, &mut rng).await {
-                Ok((contract_id, outpoint)) => {
+        Command::LnPay {
+            bolt11,
+            parts: Some(max_parts),
+        } => {
+            match client
+                .fund_outgoing_ln_contract_parts(bolt11, max_parts, &mut rng)
+                .await
+            {
+                Ok((parts, txid)) => {
+                    let outpoint = OutPoint { txid, out_idx: 0 };
                     match client.await_outgoing_contract_acceptance(outpoint).await {
                         Ok(_) => client
-                            .await_outgoing_contract_execution(contract_id, &mut rng)
+                            .await_outgoing_contract_parts_execution(&parts, &mut rng)
                             .await
                             .transform(
-                                |_| CliOutput::LnPay {
-                                    contract_id: (contract_id),
+                                |_| CliOutput::LnPayParts {
+                                    contract_ids: parts
+                                        .iter()
+                                        .map(|part| part.contract_id)
+                                        .collect(),
                                 },
                                 CliErrorKind::GeneralFederationError,
-                                "gateway failed to execute contract",
+                                "gateways failed to execute contracts",
                             ),
                         Err(e) => Err(CliError::from(
                             CliErrorKind::Timeout,
-                            "contract wasn't accepted in time",
+                            "contracts weren't accepted in time",
                             Some(Box::new(e)),
                         )),
                     }
                 }
                 Err(e) => Err(CliError::from(
                     CliErrorKind::GeneralFederationError,
-                    "Failure creating outgoing LN contract",
+                    "Failure creating outgoing LN contracts",
                     Some(Box::new(e)),
                 )),
             }
         }
+        Command::LnPay {
+            bolt11,
+            parts: None,
+        } => {
+            // Invoices of other members of our federation are paid without a Lightning hop
+            if client.is_internal_invoice(&bolt11).await.unwrap_or(false) {
+                match client.pay_internal_invoice(bolt11, &mut rng).await {
+                    Ok((contract_id, outpoint)) => client
+                        .await_internal_payment(contract_id, outpoint, &mut rng)
+                        .await
+                        .transform(
+                            |_| CliOutput::LnPay { contract_id },
+                            CliErrorKind::GeneralFederationError,
+                            "recipient failed to claim internal payment",
+                        ),
+                    Err(e) => Err(CliError::from(
+                        CliErrorKind::GeneralFederationError,
+                        "Failure funding internal payment",
+                        Some(Box::new(e)),
+                    )),
+                }
+            } else {
+                match client.fund_outgoing_ln_contract(bolt11, &mut rng).await {
+                    Ok((contract_id, outpoint)) => {
+                        match client.await_outgoing_contract_acceptance(outpoint).await {
+                            Ok(_) => client
+                                .await_outgoing_contract_execution(contract_id, &mut rng)
+                                .await
+                                .transform(
+                                    |_| CliOutput::LnPay {
+                                        contract_id: (contract_id),
+                                    },
+                                    CliErrorKind::GeneralFederationError,
+                                    "gateway failed to execute contract",
+                                ),
+                            Err(e) => Err(CliError::from(
+                                CliErrorKind::Timeout,
+                                "contract wasn't accepted in time",
+                                Some(Box::new(e)),
+                            )),
+                        }
+                    }
+                    Err(e) => Err(CliError::from(
+                        CliErrorKind::GeneralFederationError,
+                        "Failure creating outgoing LN contract",
+                        Some(Box::new(e)),
+                    )),
+                }
+            }
+        }
         Command::LnInvoice {
             amount,
             description,
             expiry_time,
-        } => client
-            .generate_confirmed_invoice(amount, description, &mut rng, expiry_time)
-            .await
-            .transform(
+            hold,
+        } => {
+            let confirmed_invoice = if hold {
+                client
+                    .generate_confirmed_hold_invoice(amount, description, &mut rng, expiry_time)
+                    .await
+            } else {
+                client
+                    .generate_confirmed_invoice(amount, description, &mut rng, expiry_time)
+                    .await
+            };
+            confirmed_invoice.transform(
                 |confirmed_invoice| CliOutput::LnInvoice {
                     invoice: (confirmed_invoice.invoice),
                 },
                 CliErrorKind::GeneralFederationError,
                 "couldn't create invoice",
-            ),
+            )
+        }
         Command::WaitInvoice { invoice } => {
             let contract_id = (*invoice.payment_hash()).into();
             client
@@ -631,6 +783,44 @@ async fn handle_command(
                     "invoice did not get paid in time",
                 )
         }
+        Command::AcceptPayment { invoice } => {
+            let contract_id = (*invoice.payment_hash()).into();
+            client
+                .accept_incoming_payment(contract_id, &mut rng)
+                .await
+                .transform(
+                    |accepted_in_tx| CliOutput::AcceptPayment { accepted_in_tx },
+                    CliErrorKind::GeneralFederationError,
+                    "couldn't accept payment",
+                )
+        }
+        Command::LnOffer { description } => client
+            .create_reusable_offer(description, &mut rng)
+            .await
+            .transform(
+                |payment_code| CliOutput::LnOffer { payment_code },
+                CliErrorKind::GeneralFederationError,
+                "couldn't create offer",
+            ),
+        Command::ClaimOfferPayments => client.claim_offer_payments(&mut rng).await.transform(
+            |claimed_in_tx| CliOutput::ClaimOfferPayments { claimed_in_tx },
+            CliErrorKind::GeneralFederationError,
+            "couldn't claim offer payments",
+        ),
+        Command::LnAddress {
+            username,
+            description,
+        } => client
+            .create_lightning_address(username, description, &mut rng)
+            .await
+            .transform(
+                |address| CliOutput::LnAddress { address },
+                CliErrorKind::GeneralFederationError,
+                "couldn't register lightning address",
+            ),
+        Command::LnHistory => Ok(CliOutput::LnHistory {
+            payments: client.ln_payment_history().await,
+        }),
         Command::WaitBlockHeight { height } => {
             client.await_consensus_block_height(height).await.transform(
                 |_| CliOutput::WaitBlockHeight { reached: (height) },
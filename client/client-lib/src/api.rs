@@ -21,7 +21,8 @@ use fedimint_api::{dyn_newtype_define, NumPeers, OutPoint, PeerId, TransactionId
 use fedimint_core::epoch::{SerdeEpochHistory, SignedEpochOutcome};
 use fedimint_core::modules::ln::contracts::incoming::IncomingContractOffer;
 use fedimint_core::modules::ln::contracts::ContractId;
-use fedimint_core::modules::ln::{ContractAccount, LightningGateway};
+use fedimint_core::modules::ln::{ContractAccount, LightningGateway, SignedLightningGateway};
+use fedimint_core::modules::wallet::txoproof::PegInProof;
 use fedimint_core::modules::wallet::PegOutFees;
 use fedimint_core::outcome::legacy::TryIntoOutcome;
 use fedimint_core::outcome::{self, TransactionStatus};
@@ -72,6 +73,14 @@ pub enum MemberError {
 }
 
 impl MemberError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            MemberError::Rpc(JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)))
+                if e.code() == 404
+        )
+    }
+
     pub fn is_retryable(&self) -> bool {
         match self {
             MemberError::ResponseDeserialization(_) => false,
@@ -108,6 +117,11 @@ impl FederationError {
     pub fn is_retryable(&self) -> bool {
         self.0.iter().any(|(_, e)| e.is_retryable())
     }
+
+    /// Whether all members answered that the requested item doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        !self.0.is_empty() && self.0.values().all(MemberError::is_not_found)
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
@@ -532,12 +546,20 @@ where
 #[cfg_attr(not(target_family = "wasm"), async_trait)]
 pub trait LnFederationApi {
     async fn fetch_contract(&self, contract: ContractId) -> FederationResult<ContractAccount>;
+    /// Like [`LnFederationApi::fetch_contract`], but returns `None` right away if the contract
+    /// doesn't exist instead of waiting for it
+    async fn fetch_contract_if_exists(
+        &self,
+        contract: ContractId,
+    ) -> FederationResult<Option<ContractAccount>>;
     async fn fetch_offer(
         &self,
         payment_hash: Sha256Hash,
     ) -> FederationResult<IncomingContractOffer>;
+    /// Proof of work difficulty the next offer has to meet given the offers of the current epoch
+    async fn fetch_offer_difficulty(&self) -> FederationResult<u8>;
     async fn fetch_gateways(&self) -> FederationResult<Vec<LightningGateway>>;
-    async fn register_gateway(&self, gateway: &LightningGateway) -> FederationResult<()>;
+    async fn register_gateway(&self, gateway: &SignedLightningGateway) -> FederationResult<()>;
     async fn offer_exists(&self, payment_hash: Sha256Hash) -> FederationResult<bool>;
 }
 
@@ -555,6 +577,23 @@ where
         )
         .await
     }
+    async fn fetch_contract_if_exists(
+        &self,
+        contract: ContractId,
+    ) -> FederationResult<Option<ContractAccount>> {
+        let result = self
+            .request_with_strategy(
+                CurrentConsensus::new(self.all_members().one_honest()),
+                format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/account"),
+                erased_single_param(&contract),
+            )
+            .await;
+        match result {
+            Ok(account) => Ok(Some(account)),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
     async fn fetch_offer(
         &self,
         payment_hash: Sha256Hash,
@@ -567,6 +606,14 @@ where
         .await
     }
 
+    async fn fetch_offer_difficulty(&self) -> FederationResult<u8> {
+        self.request_current_consensus(
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/offer_difficulty"),
+            erased_no_param(),
+        )
+        .await
+    }
+
     async fn fetch_gateways(&self) -> FederationResult<Vec<LightningGateway>> {
         self.request_union(
             format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/list_gateways"),
@@ -575,7 +622,7 @@ where
         .await
     }
 
-    async fn register_gateway(&self, gateway: &LightningGateway) -> FederationResult<()> {
+    async fn register_gateway(&self, gateway: &SignedLightningGateway) -> FederationResult<()> {
         self.request_current_consensus(
             format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/register_gateway"),
             erased_single_param(gateway),
@@ -649,6 +696,10 @@ pub trait WalletFederationApi {
         address: &Address,
         amount: bitcoin::Amount,
     ) -> FederationResult<Option<PegOutFees>>;
+    async fn fetch_registered_peg_in(
+        &self,
+        outpoint: bitcoin::OutPoint,
+    ) -> FederationResult<Option<PegInProof>>;
 }
 
 #[cfg_attr(target_family = "wasm", async_trait(? Send))]
@@ -677,6 +728,17 @@ where
         )
         .await
     }
+
+    async fn fetch_registered_peg_in(
+        &self,
+        outpoint: bitcoin::OutPoint,
+    ) -> FederationResult<Option<PegInProof>> {
+        self.request_current_consensus(
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_WALLET}/registered_peg_in"),
+            erased_single_param(&outpoint),
+        )
+        .await
+    }
 }
 
 /// Mint API client that will try to run queries against all `members` expecting equal
@@ -47,11 +47,16 @@ use fedimint_core::{
     modules::{
         ln::{
             contracts::{
-                incoming::{IncomingContract, IncomingContractOffer, OfferId},
+                incoming::{
+                    IncomingContract, IncomingContractHold, IncomingContractOffer,
+                    IncomingContractVersion, OfferId,
+                },
+                outgoing::PrunedInvoice,
                 Contract, ContractId, DecryptedPreimage, IdentifyableContract,
                 OutgoingContractOutcome, Preimage,
             },
-            ContractOutput, LightningGateway, LightningOutput,
+            ContractOutput, GatewayFees, GatewayLiquidity, LightningGateway, LightningOutput,
+            SignedLightningGateway,
         },
         mint::BlindNonce,
         wallet::txoproof::TxOutProof,
@@ -60,12 +65,19 @@ use fedimint_core::{
 };
 use fedimint_derive_secret::{ChildId, DerivableSecret};
 use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use lightning::ln::PaymentSecret;
 use lightning::routing::gossip::RoutingFees;
 use lightning::routing::router::{RouteHint, RouteHintHop};
 use lightning_invoice::{CreationError, Invoice, InvoiceBuilder, DEFAULT_EXPIRY_TIME};
-use ln::{db::LightningGatewayKey, PayInvoicePayload};
+use ln::gateway::{
+    rank_gateways, switch_gateway_for_payment, FederationProbe, GatewayCandidate, GatewayProbe,
+    ProbePayload,
+};
+use ln::{
+    db::LightningGatewayKey, CreateInvoicePayload, CreateInvoiceResponse, PayInvoicePayload,
+    PayInvoiceResponse,
+};
 use mint::NoteIssuanceRequests;
 use rand::distributions::Standard;
 use rand::prelude::*;
@@ -74,15 +86,25 @@ use secp256k1_zkp::{All, Secp256k1};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use threshold_crypto::PublicKey;
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
 use url::Url;
 
 use crate::db::ClientSecretKey;
 use crate::ln::db::{
-    OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey,
-    OutgoingPaymentClaimKeyPrefix, OutgoingPaymentKey,
+    GatewayOfferKey, GatewayPaymentKey, GatewayPaymentKeyPrefix, InternalPaymentKey,
+    LightningAddressKey, OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix,
+    OutgoingPaymentClaimKey, OutgoingPaymentClaimKeyPrefix, OutgoingPaymentKey,
+    OutgoingPaymentKeyPrefix, OutgoingRefundKey, ReusableOfferKey, ReusableOfferKeyPrefix,
+};
+use crate::ln::history::{receiving_gateway, LightningPayment, PaymentDirection, PaymentStatus};
+use crate::ln::offer::{
+    self, GatewayOffer, OfferPayment, RegisterLightningAddressPayload, RegisterOfferPayload,
+    RegisterOfferResponse, ReusableOffer, ReusableOfferId, StaticPaymentCode, OFFER_PAYMENT_BATCH,
+};
+use crate::ln::outgoing::{
+    split_payment_by_liquidity, GatewayPayment, GatewayPaymentState, OutgoingContractAccount,
+    OutgoingPaymentPart, OutgoingRefundEvent,
 };
-use crate::ln::outgoing::OutgoingContractAccount;
 use crate::ln::LnClientError;
 use crate::mint::db::{NoteKey, PendingNotesKeyPrefix};
 use crate::mint::MintClientError;
@@ -91,13 +113,34 @@ use crate::utils::{network_to_currency, ClientContext};
 use crate::wallet::WalletClientError;
 use crate::{
     api::MemberError,
-    ln::{incoming::ConfirmedInvoice, LnClient},
+    ln::{
+        incoming::{hold_accept_keypair, ConfirmedInvoice},
+        LnClient,
+    },
     mint::{MintClient, SpendableNote},
     wallet::WalletClient,
 };
 
 /// Number of blocks until outgoing lightning contracts times out and user client can get refund
 const OUTGOING_LN_CONTRACT_TIMELOCK: u64 = 500;
+
+/// Minimum CLTV delta of the last hop of HTLCs paying our invoices
+const MIN_FINAL_CLTV_EXPIRY: u64 = 18;
+
+/// Minimum CLTV delta of the last hop of HTLCs paying hold invoices, so the gateway can hold them
+/// until [`HOLD_INCOMING_CONTRACT_TIMELOCK`] expired
+const HOLD_INVOICE_MIN_FINAL_CLTV_EXPIRY: u64 = 144;
+
+/// Number of blocks the recipient of a hold invoice has to accept the payment before the gateway
+/// can claim back the funds
+const HOLD_INCOMING_CONTRACT_TIMELOCK: u64 = 36;
+
+/// How long we wait for a gateway to answer a liveness probe
+const GATEWAY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the refund watcher checks our pending outgoing contracts
+const REFUND_WATCHER_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Mint module's secret key derivation child id
 pub const MINT_SECRET_CHILD_ID: ChildId = ChildId(0);
 
@@ -109,6 +152,8 @@ pub type UserClient = Client<UserClientConfig>;
 pub struct PaymentParameters {
     pub max_delay: u64,
     pub invoice_amount: Amount,
+    /// Amount we have to pay, less than `invoice_amount` if we only pay a part of the invoice
+    pub payment_amount: Amount,
     pub max_send_amount: Amount,
     pub payment_hash: sha256::Hash,
     pub maybe_internal: bool,
@@ -129,12 +174,18 @@ pub struct GatewayClientConfig {
     /// All clients in this federation should use this value as `short_channel_id`
     /// when creating invoices to be settled by this gateway.
     pub mint_channel_id: u64,
+    /// Fees the gateway advertises and enforces for routing payments
+    #[serde(default)]
+    pub fees: GatewayFees,
 }
 
 impl GatewayClientConfig {
     pub fn to_gateway_registration_info(
         &self,
         route_hints: Vec<fedimint_core::modules::ln::route_hints::RouteHint>,
+        liquidity: Option<GatewayLiquidity>,
+        issues_invoices: bool,
+        supports_mpp: bool,
         time_to_live: Duration,
     ) -> LightningGateway {
         LightningGateway {
@@ -143,7 +194,11 @@ impl GatewayClientConfig {
             node_pub_key: self.node_pub_key,
             api: self.api.clone(),
             route_hints,
+            fees: self.fees,
             valid_until: SystemTime::now() + time_to_live,
+            liquidity,
+            issues_invoices,
+            supports_mpp,
         }
     }
 }
@@ -153,6 +208,9 @@ pub struct Client<C> {
     context: Arc<ClientContext>,
     #[allow(unused)]
     root_secret: DerivableSecret,
+    /// Held while refunding an outgoing contract, so the refund watcher doesn't race refunds we
+    /// claim in the foreground
+    outgoing_refund_lock: futures::lock::Mutex<()>,
 }
 
 impl<C> Client<C> {
@@ -192,8 +250,8 @@ impl AsRef<ClientConfig> for UserClientConfig {
 impl PaymentParameters {
     // FIXME: change to absolute fee to avoid rounding errors
     pub fn max_fee_percent(&self) -> f64 {
-        let max_absolute_fee = self.max_send_amount - self.invoice_amount;
-        (max_absolute_fee.msats as f64) / (self.invoice_amount.msats as f64)
+        let max_absolute_fee = self.max_send_amount - self.payment_amount;
+        (max_absolute_fee.msats as f64) / (self.payment_amount.msats as f64)
     }
 }
 
@@ -315,6 +373,7 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
                 secp,
             }),
             root_secret,
+            outgoing_refund_lock: Default::default(),
         }
     }
 
@@ -362,6 +421,34 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
         self.submit_tx_with_change(tx, &mut rng).await
     }
 
+    /// Claims a peg-in to an address registered via [`Self::register_pegin_address`] using only
+    /// the outpoint of the deposit, the federation supplies the proof
+    pub async fn peg_in_registered<R: RngCore + CryptoRng>(
+        &self,
+        outpoint: bitcoin::OutPoint,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let mut tx = TransactionBuilder::default();
+
+        let peg_in_proof = self
+            .context
+            .api
+            .fetch_registered_peg_in(outpoint)
+            .await?
+            .ok_or(WalletClientError::RegisteredPegInNotFound)?;
+        let (peg_in_key, peg_in_proof) = self
+            .wallet_client()
+            .create_registered_pegin_input(peg_in_proof)
+            .await?;
+
+        tx.input(
+            &mut vec![peg_in_key],
+            Input::Wallet(WalletInput(Box::new(peg_in_proof))),
+        );
+
+        self.submit_tx_with_change(tx, &mut rng).await
+    }
+
     /// Submits a transaction to the fed, making change using our change module
     ///
     /// TODO: For safety, if the submission fails, the DB write still occurs.  We should instead ensure the state of the client and consensus are always the same.
@@ -378,6 +465,107 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
         Ok(result)
     }
 
+    /// Waits until the federation decided on a submitted transaction, returns an error if it was
+    /// rejected
+    pub async fn await_tx_accepted(&self, txid: TransactionId) -> Result<()> {
+        loop {
+            match self.context.api.fetch_tx_outcome(&txid).await {
+                Ok(TransactionStatus::Accepted { .. }) => return Ok(()),
+                Ok(TransactionStatus::Rejected(reason)) => {
+                    return Err(ClientError::TransactionRejected(reason))
+                }
+                Err(_) => sleep(Duration::from_secs(1)).await,
+            }
+        }
+    }
+
+    /// Builds an invoice that makes the payer route through `gateway`, which funds an incoming
+    /// contract for `payment_hash` in exchange for the HTLC
+    #[allow(clippy::too_many_arguments)]
+    fn build_gateway_invoice<R: RngCore + CryptoRng>(
+        &self,
+        gateway: &LightningGateway,
+        invoice_amount: Amount,
+        description: InvoiceDescription,
+        payment_hash: sha256::Hash,
+        payment_secret: PaymentSecret,
+        expiry_time: Option<u64>,
+        min_final_cltv_expiry: u64,
+        mut rng: R,
+    ) -> Result<Invoice> {
+        // Temporary lightning node pubkey
+        let (node_secret_key, node_public_key) = self.context.secp.generate_keypair(&mut rng);
+
+        // Route hint instructing payer how to route to gateway
+        let route_hint_last_hop = RouteHintHop {
+            src_node_id: gateway.node_pub_key,
+            short_channel_id: gateway.mint_channel_id,
+            fees: RoutingFees {
+                base_msat: 0,
+                proportional_millionths: 0,
+            },
+            cltv_expiry_delta: 30,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        };
+        let route_hints = if gateway.route_hints.is_empty() {
+            vec![RouteHint(vec![route_hint_last_hop])]
+        } else {
+            gateway
+                .route_hints
+                .iter()
+                .map(|rh| {
+                    RouteHint(
+                        rh.to_ldk_route_hint()
+                            .0
+                            .iter()
+                            .cloned()
+                            .chain(once(route_hint_last_hop.clone()))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+
+        let duration_since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let invoice_builder = InvoiceBuilder::new(network_to_currency(
+            self.config
+                .as_ref()
+                .get_first_module_by_kind::<WalletClientConfig>("wallet")
+                .expect("must have wallet config available")
+                .1
+                .network,
+        ))
+        .amount_milli_satoshis(invoice_amount.msats);
+        let mut invoice_builder = match description {
+            InvoiceDescription::Direct(description) => invoice_builder.description(description),
+            InvoiceDescription::Hash(hash) => invoice_builder.description_hash(hash),
+        }
+        .payment_hash(payment_hash)
+        .payment_secret(payment_secret)
+        .duration_since_epoch(duration_since_epoch)
+        .min_final_cltv_expiry(min_final_cltv_expiry)
+        .payee_pub_key(node_public_key)
+        .expiry_time(Duration::from_secs(
+            expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
+        ));
+
+        for rh in route_hints {
+            invoice_builder = invoice_builder.private_route(rh);
+        }
+
+        invoice_builder
+            .build_signed(|hash| {
+                self.context
+                    .secp
+                    .sign_ecdsa_recoverable(hash, &node_secret_key)
+            })
+            .map_err(ClientError::InvoiceError)
+    }
+
     /// Spent some [`SpendableNote`]s to receive a freshly minted ones
     ///
     /// This is useful in scenarios where certain notes were handed over
@@ -507,7 +695,7 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
             + (peg_out.amount + peg_out.fees.amount()).into();
         let (mut keys, input) = self.mint_client().select_input(funding_amount).await?;
         tx.input(&mut keys, input);
-        let peg_out_idx = tx.output(Output::Wallet(WalletOutput(peg_out)));
+        let peg_out_idx = tx.output(Output::Wallet(WalletOutput::PegOut(peg_out)));
 
         let fedimint_tx_id = self.submit_tx_with_change(tx, &mut rng).await?;
 
@@ -535,6 +723,50 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
         address
     }
 
+    /// Returns a new peg-in address and submits a transaction registering it with the federation
+    ///
+    /// After the registration output was accepted guardians scan confirmed blocks for deposits to
+    /// the address, so the peg-in can be claimed with [`Self::peg_in_registered`] without access
+    /// to a bitcoin node. Registrations cost a fee and expire
+    /// [`PEG_IN_REGISTRATION_EXPIRY`](fedimint_core::modules::wallet::PEG_IN_REGISTRATION_EXPIRY)
+    /// blocks after they were accepted, deposits confirmed later have to be claimed with a proof. Funds should only be sent once
+    /// [`await_peg_in_registration`](crate::wallet::WalletClient::await_peg_in_registration)
+    /// succeeded for the returned [`OutPoint`].
+    pub async fn register_pegin_address<R: RngCore + CryptoRng>(
+        &self,
+        mut rng: R,
+    ) -> Result<(Address, OutPoint)> {
+        let fee = self
+            .wallet_client()
+            .config
+            .fee_consensus
+            .peg_in_registration_abs;
+        if fee == Amount::ZERO {
+            return Err(WalletClientError::PegInRegistrationDisabled.into());
+        }
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let (address, registration) = self
+            .wallet_client()
+            .create_pegin_registration_output(&mut dbtx, &mut rng)
+            .await;
+        dbtx.commit_tx().await.expect("DB Error");
+
+        let mut tx = TransactionBuilder::default();
+        let (mut keys, input) = self.mint_client().select_input(fee).await?;
+        tx.input(&mut keys, input);
+        let registration_idx = tx.output(Output::Wallet(registration));
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+
+        Ok((
+            address,
+            OutPoint {
+                txid,
+                out_idx: registration_idx,
+            },
+        ))
+    }
+
     /// Issues a spendable amount of ecash
     ///
     /// **WARNING** the ecash will be deleted from the database, the returned ecash must be
@@ -709,6 +941,122 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
             .fetch_epoch_history(epoch, epoch_pk, &self.context.decoders)
             .await?)
     }
+
+    /// Hold terms for the incoming contract funding `offer`, leaving the recipient of a hold
+    /// invoice some time to accept the payment
+    async fn incoming_contract_hold(
+        &self,
+        offer: &IncomingContractOffer,
+    ) -> Result<Option<IncomingContractHold>> {
+        let Some(accept_key) = offer.hold_key else {
+            return Ok(None);
+        };
+
+        let consensus_height = self.context.api.fetch_consensus_block_height().await?;
+        Ok(Some(IncomingContractHold {
+            accept_key,
+            timelock: (consensus_height + HOLD_INCOMING_CONTRACT_TIMELOCK) as u32,
+            accepted: false,
+        }))
+    }
+
+    /// Funds an incoming contract from our notes, returns the out point of the contract
+    async fn fund_incoming_contract(
+        &self,
+        incoming_output: LightningOutput,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<OutPoint> {
+        let amount = match &incoming_output {
+            LightningOutput::Contract(output) => output.amount,
+            _ => unreachable!("We only fund incoming contracts"),
+        };
+
+        let mut builder = TransactionBuilder::default();
+        let (mut keys, input) = self.mint_client().select_input(amount).await?;
+        builder.input(&mut keys, input);
+        builder.output(Output::LN(incoming_output));
+        let txid = self.submit_tx_with_change(builder, rng).await?;
+        Ok(OutPoint { txid, out_idx: 0 })
+    }
+
+    /// Claims back the funds of an incoming contract we funded with `funder_key`, possible if its
+    /// preimage turned out to be invalid or a held contract wasn't accepted in time
+    async fn refund_funded_incoming_contract(
+        &self,
+        contract_id: ContractId,
+        funder_key: bitcoin::KeyPair,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<TransactionId> {
+        let contract_account = self.ln_client().get_incoming_contract(contract_id).await?;
+
+        let mut builder = TransactionBuilder::default();
+        builder.input(&mut vec![funder_key], Input::LN(contract_account.claim()));
+        self.submit_tx_with_change(builder, rng).await
+    }
+
+    /// Wait for the preimage of an incoming contract we funded to be decrypted by the federation
+    pub async fn await_preimage_decryption(&self, outpoint: OutPoint) -> Result<Preimage> {
+        Ok(self
+            .context
+            .api
+            .await_output_outcome::<Preimage>(
+                outpoint,
+                Duration::from_secs(10),
+                &self.context.decoders,
+            )
+            .await?)
+    }
+
+    /// Waits for the preimage of an incoming contract we funded with `funder_key`, claiming back
+    /// held contracts that weren't accepted by their timelock
+    async fn await_funded_incoming_contract_preimage(
+        &self,
+        outpoint: OutPoint,
+        contract_id: ContractId,
+        funder_key: bitcoin::KeyPair,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<Preimage> {
+        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+        let Some(hold) = contract.contract.hold else {
+            return self.await_preimage_decryption(outpoint).await;
+        };
+
+        loop {
+            match self.await_preimage_decryption(outpoint).await {
+                Err(ClientError::OutputOutcome(OutputOutcomeError::Timeout(_))) => {}
+                result => return result,
+            }
+
+            let consensus_height = self.context.api.fetch_consensus_block_height().await?;
+            if consensus_height < hold.timelock as u64 {
+                continue;
+            }
+
+            // The recipient may still accept the payment until our refund went through
+            let txid = match self
+                .refund_funded_incoming_contract(contract_id, funder_key, &mut rng)
+                .await
+            {
+                Ok(txid) => txid,
+                Err(error) => {
+                    debug!(%error, "Could not refund held incoming contract");
+                    continue;
+                }
+            };
+            loop {
+                match self.context.api.fetch_tx_outcome(&txid).await {
+                    Ok(TransactionStatus::Accepted { .. }) => {
+                        return Err(ClientError::HoldInvoiceNotAccepted)
+                    }
+                    Ok(TransactionStatus::Rejected(reason)) => {
+                        debug!(%reason, "Refund of held incoming contract was rejected");
+                        break;
+                    }
+                    Err(_) => sleep(Duration::from_secs(1)).await,
+                }
+            }
+        }
+    }
 }
 
 impl Client<UserClientConfig> {
@@ -716,26 +1064,77 @@ impl Client<UserClientConfig> {
         Ok(self.context.api.fetch_gateways().await?)
     }
 
+    /// Returns the active gateway, switching to the best registered one if it isn't set or its
+    /// registration expired
+    ///
+    /// An expired active gateway is kept if it renewed its registration in the meantime.
     pub async fn fetch_active_gateway(&self) -> Result<LightningGateway> {
-        // FIXME: forgetting about old gws might not always be ideal. We assume that the gateway stays the same except for route hints for now.
-        if let Some(gateway) = self
+        let active_gateway = self
             .context
             .db
             .begin_transaction()
             .await
             .get_value(&LightningGatewayKey)
             .await
-            .expect("DB error")
-            .filter(|gw| gw.valid_until > SystemTime::now())
-        {
-            return Ok(gateway);
+            .expect("DB error");
+
+        let now = SystemTime::now();
+        match active_gateway {
+            Some(gateway) if gateway.valid_until > now => Ok(gateway),
+            Some(expired) => {
+                let renewed = self
+                    .fetch_registered_gateways()
+                    .await?
+                    .into_iter()
+                    .find(|gateway| {
+                        gateway.node_pub_key == expired.node_pub_key && gateway.valid_until > now
+                    });
+                match renewed {
+                    Some(gateway) => {
+                        self.save_active_gateway(&gateway).await;
+                        Ok(gateway)
+                    }
+                    None => {
+                        debug!(gateway = %expired.api, "Active gateway expired, switching");
+                        self.switch_active_gateway(None).await
+                    }
+                }
+            }
+            None => self.switch_active_gateway(None).await,
+        }
+    }
+
+    /// Returns the active gateway if it can probably route our payment, otherwise switches to the
+    /// best registered gateway that can, see [`switch_gateway_for_payment`]
+    async fn fetch_gateway_for_payment(
+        &self,
+        can_route: impl Fn(&LightningGateway) -> bool,
+    ) -> Result<LightningGateway> {
+        let active_gateway = self.fetch_active_gateway().await?;
+        if can_route(&active_gateway) {
+            return Ok(active_gateway);
         }
 
-        self.switch_active_gateway(None).await
+        let ranked = self
+            .probe_and_rank_gateways(self.fetch_registered_gateways().await?, None)
+            .await;
+        match switch_gateway_for_payment(&active_gateway, ranked, can_route) {
+            Some(gateway) => {
+                info!(gateway = %gateway.api, "Switching to gateway with enough liquidity");
+                self.save_active_gateway(&gateway).await;
+                Ok(gateway)
+            }
+            None => {
+                debug!("No gateway reports enough liquidity, keeping the active one");
+                Ok(active_gateway)
+            }
+        }
     }
+
     /// Switches the clients active gateway to a registered gateway with the given node pubkey.
-    /// If no pubkey is given (node_pub_key == None) the first available registered gateway is activated.
-    /// This behavior is useful for scenarios where we don't know any registered gateways in advance.
+    /// If no pubkey is given (node_pub_key == None) the best registered gateway according to
+    /// [`rank_gateways`] is activated. This behavior is useful for scenarios where we don't know
+    /// any registered gateways in advance.
     pub async fn switch_active_gateway(
         &self,
         node_pub_key: Option<secp256k1::PublicKey>,
@@ -749,23 +1148,124 @@ impl Client<UserClientConfig> {
             // If a pubkey was provided, try to select and activate a gateway with that pubkey.
             Some(pub_key) => gateways
                 .into_iter()
-                .find(|g| g.node_pub_key == pub_key)
+                .find(|g| g.node_pub_key == pub_key && g.valid_until > SystemTime::now())
                 .ok_or_else(|| {
                     debug!("Could not find gateway with public key {:?}", pub_key);
                     ClientError::GatewayNotFound
                 })?,
-            // Otherwise (no pubkey provided), select and activate the first registered gateway.
+            // Otherwise (no pubkey provided), select and activate the best registered gateway.
             None => {
-                debug!("No public key for gateway supplied, using first registered one");
-                gateways[0].clone()
+                debug!("No public key for gateway supplied, selecting best registered one");
+                self.select_gateway(gateways, None).await?
             }
         };
+        self.save_active_gateway(&gateway).await;
+        Ok(gateway)
+    }
+
+    /// Switches to the best gateway other than `failed` for future payments
+    async fn fail_over_gateway(&self, failed: secp256k1::PublicKey) {
+        let new_gateway = match self.fetch_registered_gateways().await {
+            Ok(gateways) => self.select_gateway(gateways, Some(failed)).await,
+            Err(error) => Err(error),
+        };
+
+        match new_gateway {
+            Ok(gateway) => {
+                info!(gateway = %gateway.api, "Failing over to new gateway");
+                self.save_active_gateway(&gateway).await;
+            }
+            Err(error) => {
+                warn!(%error, "No other gateway to fail over to");
+            }
+        }
+    }
+
+    async fn save_active_gateway(&self, gateway: &LightningGateway) {
         let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.insert_entry(&LightningGatewayKey, &gateway)
+        dbtx.insert_entry(&LightningGatewayKey, gateway)
             .await
             .expect("DB error");
         dbtx.commit_tx().await.expect("DB Error");
-        Ok(gateway)
+    }
+
+    /// Probes all `gateways` that haven't expired and returns the best one, skipping `failed`
+    async fn select_gateway(
+        &self,
+        gateways: Vec<LightningGateway>,
+        failed: Option<secp256k1::PublicKey>,
+    ) -> Result<LightningGateway> {
+        self.probe_and_rank_gateways(gateways, failed)
+            .await
+            .into_iter()
+            .next()
+            .ok_or(ClientError::NoGateways)
+    }
+
+    /// Probes all `gateways` that haven't expired and orders them from best to worst, skipping
+    /// `failed`
+    async fn probe_and_rank_gateways(
+        &self,
+        gateways: Vec<LightningGateway>,
+        failed: Option<secp256k1::PublicKey>,
+    ) -> Vec<LightningGateway> {
+        let candidates = futures::future::join_all(
+            gateways
+                .into_iter()
+                .filter(|gateway| Some(gateway.node_pub_key) != failed)
+                .map(|gateway| async move {
+                    let info = self.probe_gateway(&gateway).await;
+                    GatewayCandidate { gateway, info }
+                }),
+        )
+        .await;
+
+        if !candidates.is_empty() && candidates.iter().all(|candidate| candidate.info.is_none()) {
+            warn!("No gateway answered our probe, falling back to unprobed gateways");
+        }
+
+        rank_gateways(candidates, SystemTime::now())
+    }
+
+    /// Queries the public `/probe` endpoint of a gateway, returning its info about our federation
+    /// if it is reachable and serves us with the key and fees it registered
+    async fn probe_gateway(&self, gateway: &LightningGateway) -> Option<FederationProbe> {
+        let request = reqwest::Client::new()
+            .post(
+                gateway
+                    .api
+                    .join("probe")
+                    .expect("'probe' contains no invalid characters for a URL")
+                    .as_str(),
+            )
+            .json(&ProbePayload)
+            .send();
+
+        let probe: GatewayProbe = match fedimint_api::task::timeout(GATEWAY_PROBE_TIMEOUT, async {
+            request
+                .await?
+                .error_for_status()?
+                .json::<GatewayProbe>()
+                .await
+        })
+        .await
+        {
+            Ok(Ok(probe)) => probe,
+            Ok(Err(error)) => {
+                debug!(gateway = %gateway.api, %error, "Gateway probe failed");
+                return None;
+            }
+            Err(_) => {
+                debug!(gateway = %gateway.api, "Gateway probe timed out");
+                return None;
+            }
+        };
+
+        probe.federations.into_iter().find(|federation| {
+            federation.federation_id == self.config.0.federation_id
+                && federation.mint_pubkey == gateway.mint_pub_key
+                && federation.fees == gateway.fees
+        })
     }
 
     pub async fn fund_outgoing_ln_contract<R: RngCore + CryptoRng>(
@@ -773,7 +1273,10 @@ impl Client<UserClientConfig> {
         invoice: Invoice,
         mut rng: R,
     ) -> Result<(ContractId, OutPoint)> {
-        let gateway = self.fetch_active_gateway().await?;
+        let invoice_amount = Amount::from_msats(invoice.amount_milli_satoshis().unwrap_or(0));
+        let gateway = self
+            .fetch_gateway_for_payment(|gateway| gateway.can_send(invoice_amount))
+            .await?;
         let mut dbtx = self.context.db.begin_transaction().await;
         let mut tx = TransactionBuilder::default();
 
@@ -784,7 +1287,7 @@ impl Client<UserClientConfig> {
             .ln_client()
             .create_outgoing_output(
                 &mut dbtx,
-                invoice,
+                invoice.clone(),
                 &gateway,
                 absolute_timelock as u32,
                 &mut rng,
@@ -799,7 +1302,9 @@ impl Client<UserClientConfig> {
                 let amount = c.amount;
                 (contract_id, amount)
             }
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
+            LightningOutput::Offer(_)
+            | LightningOutput::CancelOutgoing { .. }
+            | LightningOutput::AcceptIncoming { .. } => {
                 panic!()
             } // FIXME: impl TryFrom
         };
@@ -810,15 +1315,288 @@ impl Client<UserClientConfig> {
         let txid = self.submit_tx_with_change(tx, &mut rng).await?;
         let outpoint = OutPoint { txid, out_idx: 0 };
 
+        self.ln_client()
+            .save_payment(
+                contract_id,
+                &LightningPayment::new(
+                    PaymentDirection::Outgoing,
+                    invoice,
+                    invoice_amount,
+                    amount.saturating_sub(invoice_amount),
+                    vec![gateway.node_pub_key],
+                ),
+            )
+            .await;
+
         debug!("Funded outgoing contract {} in {}", contract_id, outpoint);
         Ok((contract_id, outpoint))
     }
 
-    /// Claims a refund for an expired or cancelled outgoing contract
+    /// Funds a multi-part payment of `invoice` across up to `max_parts` of the best registered
+    /// gateways that support paying parts of invoices with one outgoing contract each, split in
+    /// proportion to their reported outbound liquidity
     ///
-    /// This can be necessary when the Lightning gateway cannot route the payment, is malicious or
-    /// offline. The function returns the out point of the e-cash output generated as change.
-    pub async fn try_refund_outgoing_contract(
+    /// Large payments often fail because a single gateway lacks the outbound liquidity to route
+    /// them. Since the recipient only settles a payment once all of its parts arrived, no gateway
+    /// can claim its contract unless the whole invoice got paid.
+    pub async fn fund_outgoing_ln_contract_parts<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        max_parts: usize,
+        mut rng: R,
+    ) -> Result<(Vec<OutgoingPaymentPart>, TransactionId)> {
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ClientError::InvoiceMissingAmount)?,
+        );
+        if !invoice
+            .features()
+            .map_or(false, |features| features.supports_basic_mpp())
+        {
+            return Err(ClientError::MultiPartNotSupported);
+        }
+
+        // Prefer gateways that report enough liquidity for an even share of the payment
+        let share = Amount::from_msats(invoice_amount.msats / max_parts.max(1) as u64);
+        let mpp_gateways = self
+            .fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .filter(|gateway| gateway.supports_mpp)
+            .collect();
+        let mut gateways = self.probe_and_rank_gateways(mpp_gateways, None).await;
+        gateways.sort_by_key(|gateway| !gateway.can_send(share));
+        let gateways = gateways.into_iter().take(max_parts).collect::<Vec<_>>();
+        if gateways.is_empty() {
+            return Err(ClientError::NoGateways);
+        }
+
+        let consensus_height = self.context.api.fetch_consensus_block_height().await?;
+        let absolute_timelock = consensus_height + OUTGOING_LN_CONTRACT_TIMELOCK;
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut tx = TransactionBuilder::default();
+        let mut parts = vec![];
+        let mut total_amount = Amount::ZERO;
+        let liquidity = gateways
+            .iter()
+            .map(|gateway| gateway.liquidity.map(|liquidity| liquidity.outbound))
+            .collect::<Vec<_>>();
+        let part_amounts = split_payment_by_liquidity(invoice_amount, &liquidity);
+        for (gateway, amount) in gateways
+            .into_iter()
+            .zip(part_amounts)
+            .filter(|(_, amount)| *amount != Amount::ZERO)
+        {
+            let output = self
+                .ln_client()
+                .create_outgoing_part_output(
+                    &mut dbtx,
+                    invoice.clone(),
+                    amount,
+                    &gateway,
+                    absolute_timelock as u32,
+                    &mut rng,
+                )
+                .await?;
+
+            let contract_id = match &output {
+                LightningOutput::Contract(c) => {
+                    total_amount += c.amount;
+                    c.contract.contract_id()
+                }
+                _ => unreachable!("We only create outgoing contracts"),
+            };
+
+            tx.output(Output::LN(output));
+            parts.push(OutgoingPaymentPart {
+                gateway,
+                contract_id,
+                amount,
+            });
+        }
+
+        dbtx.commit_tx().await.expect("DB Error");
+
+        let (mut keys, input) = self.mint_client().select_input(total_amount).await?;
+        tx.input(&mut keys, input);
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+
+        if let Some(first_part) = parts.first() {
+            self.ln_client()
+                .save_payment(
+                    first_part.contract_id,
+                    &LightningPayment::new(
+                        PaymentDirection::Outgoing,
+                        invoice,
+                        invoice_amount,
+                        total_amount.saturating_sub(invoice_amount),
+                        parts.iter().map(|part| part.gateway.node_pub_key).collect(),
+                    ),
+                )
+                .await;
+        }
+
+        debug!("Funded {} outgoing contracts in {}", parts.len(), txid);
+        Ok((parts, txid))
+    }
+
+    /// Returns true if the invoice was issued through a gateway of our federation for an offer
+    /// that exists, so we can pay it by funding its incoming contract directly
+    pub async fn is_internal_invoice(&self, invoice: &Invoice) -> Result<bool> {
+        let last_hops = invoice
+            .route_hints()
+            .into_iter()
+            .filter_map(|rh| rh.0.last().cloned())
+            .collect::<Vec<_>>();
+        if last_hops.is_empty() {
+            return Ok(false);
+        }
+
+        let gateways = self.fetch_registered_gateways().await?;
+        let routes_through_our_gateway = last_hops.iter().any(|hop| {
+            gateways.iter().any(|gateway| {
+                hop.src_node_id == gateway.node_pub_key
+                    && hop.short_channel_id == gateway.mint_channel_id
+            })
+        });
+        if !routes_through_our_gateway {
+            return Ok(false);
+        }
+
+        Ok(self
+            .ln_client()
+            .offer_exists(*invoice.payment_hash())
+            .await?)
+    }
+
+    /// Pays an invoice of a member of our federation by funding the incoming contract of its
+    /// offer from our notes, saving the gateway fees and the Lightning round trip
+    ///
+    /// Returns the contract id and the out point to await the preimage with
+    /// [`Self::await_internal_payment`].
+    pub async fn pay_internal_invoice<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        mut rng: R,
+    ) -> Result<(ContractId, OutPoint)> {
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ClientError::InvoiceMissingAmount)?,
+        );
+        let offer = self.ln_client().get_offer(*invoice.payment_hash()).await?;
+        if &offer.hash != invoice.payment_hash() || offer.amount > invoice_amount {
+            return Err(ClientError::InvalidOffer);
+        }
+
+        let hold = self.incoming_contract_hold(&offer).await?;
+        let contract_id = ContractId::from(offer.hash);
+        let payment = LightningPayment::new(
+            PaymentDirection::Outgoing,
+            invoice.clone(),
+            offer.amount,
+            Amount::ZERO,
+            vec![],
+        );
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let incoming_output = self
+            .ln_client()
+            .create_internal_payment_output(&mut dbtx, invoice, offer, hold, &mut rng)
+            .await?;
+        dbtx.commit_tx().await.expect("DB Error");
+
+        let outpoint = self
+            .fund_incoming_contract(incoming_output, &mut rng)
+            .await?;
+
+        self.ln_client().save_payment(contract_id, &payment).await;
+
+        debug!("Funded internal payment {} in {}", contract_id, outpoint);
+        Ok((contract_id, outpoint))
+    }
+
+    /// Waits for the payee's preimage of an internal payment, claiming back our funds if it
+    /// turned out invalid or a held payment wasn't accepted in time
+    pub async fn await_internal_payment(
+        &self,
+        contract_id: ContractId,
+        outpoint: OutPoint,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<Preimage> {
+        let payment = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&InternalPaymentKey(contract_id))
+            .await
+            .expect("DB error")
+            .ok_or(ClientError::UnknownInternalPayment)?;
+
+        let payment_hash = *payment.invoice.payment_hash();
+        let result = match self
+            .await_funded_incoming_contract_preimage(
+                outpoint,
+                contract_id,
+                payment.funder_key,
+                &mut rng,
+            )
+            .await
+        {
+            result @ (Ok(_) | Err(ClientError::HoldInvoiceNotAccepted)) => result,
+            // Keep the payment around so we can keep waiting for the federation later
+            Err(ClientError::OutputOutcome(OutputOutcomeError::Timeout(_))) => {
+                return Err(ClientError::Timeout)
+            }
+            Err(error) => {
+                debug!(%error, "Internal payment failed, claiming back our funds");
+                self.refund_funded_incoming_contract(contract_id, payment.funder_key, &mut rng)
+                    .await?;
+                Err(ClientError::RefundedFailedPayment)
+            }
+        };
+
+        let (status, preimage) = match &result {
+            Ok(preimage) => (PaymentStatus::Succeeded, Some(preimage.clone())),
+            Err(_) => (PaymentStatus::Refunded, None),
+        };
+        self.ln_client()
+            .update_payment(
+                payment_hash,
+                PaymentDirection::Outgoing,
+                contract_id,
+                status,
+                preimage,
+            )
+            .await;
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&InternalPaymentKey(contract_id))
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+
+        result
+    }
+
+    /// Claims a refund for an expired or cancelled outgoing contract
+    ///
+    /// This can be necessary when the Lightning gateway cannot route the payment, is malicious or
+    /// offline. The function waits for the federation to accept the refund transaction and returns
+    /// the out point of the e-cash output generated as change.
+    pub async fn try_refund_outgoing_contract(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<OutPoint> {
+        let _refund_guard = self.outgoing_refund_lock.lock().await;
+        self.refund_outgoing_contract(contract_id, rng).await
+    }
+
+    /// Refunds the outgoing contract, the caller has to hold `outgoing_refund_lock`
+    async fn refund_outgoing_contract(
         &self,
         contract_id: ContractId,
         rng: impl RngCore + CryptoRng,
@@ -838,16 +1616,203 @@ impl Client<UserClientConfig> {
             .ln_client()
             .create_refund_outgoing_contract_input(&contract_data);
         tx.input(&mut vec![*refund_key], Input::LN(refund_input));
-        let txid = self.submit_tx_with_change(tx, rng).await?;
 
+        // The refund is recorded before it is submitted, so we don't mistake it for a claim by
+        // the gateway once the contract is empty
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let final_tx = tx.build(self, &mut dbtx, rng).await;
+        let outpoint = OutPoint {
+            txid: final_tx.tx_hash(),
+            out_idx: 0,
+        };
+        dbtx.insert_entry(&OutgoingRefundKey(contract_id), &outpoint)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+
+        // We keep tracking the contract until the refund went through, so it can be retried if
+        // it's rejected
+        let result = match self.context.api.submit_transaction(final_tx).await {
+            Ok(txid) => self.await_tx_accepted(txid).await,
+            Err(error) => Err(error.into()),
+        };
+        if let Err(error) = result {
+            let mut dbtx = self.context.db.begin_transaction().await;
+            dbtx.remove_entry(&OutgoingRefundKey(contract_id))
+                .await
+                .expect("DB error");
+            dbtx.commit_tx().await.expect("DB Error");
+            return Err(error);
+        }
+
+        self.forget_refunded_outgoing_contract(contract_id).await?;
+        Ok(outpoint)
+    }
+
+    /// Marks the payment of an outgoing contract we got refunded as such and stops tracking it
+    async fn forget_refunded_outgoing_contract(&self, contract_id: ContractId) -> Result<()> {
         let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.remove_entry(&OutgoingPaymentKey(contract_id))
+        let contract_data = dbtx
+            .remove_entry(&OutgoingPaymentKey(contract_id))
             .await
             .expect("DB error")
             .ok_or(ClientError::DeleteUnknownOutgoingContract)?;
+        dbtx.remove_entry(&OutgoingRefundKey(contract_id))
+            .await
+            .expect("DB error");
         dbtx.commit_tx().await.expect("DB Error");
 
-        Ok(OutPoint { txid, out_idx: 0 })
+        self.ln_client()
+            .update_payment(
+                contract_data.contract_account.contract.hash,
+                PaymentDirection::Outgoing,
+                contract_id,
+                PaymentStatus::Refunded,
+                None,
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Spawns a task that watches all our pending outgoing contracts, claiming refunds as soon as
+    /// the gateway cancels them or their timelock passes
+    ///
+    /// Every contract the watcher settles is reported on the returned stream. Contracts the
+    /// gateway claimed are forgotten, so the watcher doesn't have to check them again.
+    pub async fn spawn_refund_watcher(
+        self: Arc<Self>,
+        task_group: &mut task::TaskGroup,
+    ) -> impl Stream<Item = OutgoingRefundEvent> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        task_group
+            .spawn("outgoing refund watcher", move |handle| async move {
+                while !handle.is_shutting_down() {
+                    for event in self.refund_outgoing_contracts(rand::rngs::OsRng).await {
+                        // Nobody may be listening, we keep refunding anyway
+                        let _ = sender.unbounded_send(event);
+                    }
+                    sleep(REFUND_WATCHER_INTERVAL).await;
+                }
+            })
+            .await;
+        receiver
+    }
+
+    /// Claims refunds for all our outgoing contracts that were cancelled by their gateway or timed
+    /// out and forgets the ones the gateway claimed
+    ///
+    /// Contracts are settled one at a time while holding `outgoing_refund_lock`, so a refund
+    /// claimed in the foreground meanwhile is neither repeated nor reported as claimed.
+    pub async fn refund_outgoing_contracts(
+        &self,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Vec<OutgoingRefundEvent> {
+        let consensus_height = match self.context.api.fetch_consensus_block_height().await {
+            Ok(height) => height,
+            Err(error) => {
+                debug!(%error, "Could not fetch consensus block height");
+                return vec![];
+            }
+        };
+        let pending = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&OutgoingPaymentKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").0 .0)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut events = vec![];
+        for contract_id in pending {
+            let _refund_guard = self.outgoing_refund_lock.lock().await;
+            let (exists, our_refund) = {
+                let mut dbtx = self.context.db.begin_transaction().await;
+                let exists = dbtx
+                    .get_value(&OutgoingPaymentKey(contract_id))
+                    .await
+                    .expect("DB error")
+                    .is_some();
+                let our_refund = dbtx
+                    .get_value(&OutgoingRefundKey(contract_id))
+                    .await
+                    .expect("DB error");
+                (exists, our_refund)
+            };
+            if !exists {
+                continue;
+            }
+
+            let account = match self.ln_client().get_outgoing_contract(contract_id).await {
+                Ok(account) => account,
+                Err(error) => {
+                    debug!(%contract_id, %error, "Could not fetch outgoing contract");
+                    continue;
+                }
+            };
+
+            if account.amount == Amount::ZERO {
+                // Either our refund we didn't get to forget about or the gateway's claim emptied it
+                if let Some(outpoint) = our_refund {
+                    match self.context.api.fetch_tx_outcome(&outpoint.txid).await {
+                        Ok(TransactionStatus::Accepted { .. }) => {
+                            events.push(
+                                match self.forget_refunded_outgoing_contract(contract_id).await {
+                                    Ok(()) => OutgoingRefundEvent::Refunded {
+                                        contract_id,
+                                        outpoint,
+                                    },
+                                    Err(error) => OutgoingRefundEvent::RefundFailed {
+                                        contract_id,
+                                        error: error.to_string(),
+                                    },
+                                },
+                            );
+                            continue;
+                        }
+                        Ok(TransactionStatus::Rejected(_)) => {}
+                        Err(error) => {
+                            debug!(%contract_id, %error, "Could not fetch our refund transaction");
+                            continue;
+                        }
+                    }
+                }
+
+                self.update_outgoing_payment(contract_id, PaymentStatus::Succeeded, None)
+                    .await;
+                let mut dbtx = self.context.db.begin_transaction().await;
+                dbtx.remove_entry(&OutgoingPaymentKey(contract_id))
+                    .await
+                    .expect("DB error");
+                dbtx.remove_entry(&OutgoingRefundKey(contract_id))
+                    .await
+                    .expect("DB error");
+                dbtx.commit_tx().await.expect("DB Error");
+                events.push(OutgoingRefundEvent::Claimed { contract_id });
+                continue;
+            }
+
+            if !account.contract.cancelled && (account.contract.timelock as u64) > consensus_height
+            {
+                continue;
+            }
+
+            events.push(
+                match self.refund_outgoing_contract(contract_id, &mut rng).await {
+                    Ok(outpoint) => OutgoingRefundEvent::Refunded {
+                        contract_id,
+                        outpoint,
+                    },
+                    Err(error) => OutgoingRefundEvent::RefundFailed {
+                        contract_id,
+                        error: error.to_string(),
+                    },
+                },
+            );
+        }
+        events
     }
 
     pub async fn await_outgoing_contract_acceptance(&self, outpoint: OutPoint) -> Result<()> {
@@ -906,16 +1871,55 @@ impl Client<UserClientConfig> {
         self.await_invoice_confirmation(txid, invoice, payment_keypair)
             .await
     }
+
+    /// Like [`Self::generate_confirmed_invoice`], but the federation holds the payment until we
+    /// accept it with [`Self::accept_incoming_payment`]. If we don't do so within
+    /// [`HOLD_INCOMING_CONTRACT_TIMELOCK`] blocks the gateway claims it back and the payer gets
+    /// refunded.
+    pub async fn generate_confirmed_hold_invoice<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        description: String,
+        mut rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<ConfirmedInvoice> {
+        let (txid, invoice, payment_keypair) = self
+            .submit_unsigned_invoice(amount, description, true, &mut rng, expiry_time)
+            .await?;
+
+        self.await_invoice_confirmation(txid, invoice, payment_keypair)
+            .await
+    }
+
     pub async fn generate_unsigned_invoice_and_submit<R: RngCore + CryptoRng>(
         &self,
         amount: Amount,
         description: String,
+        rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<(TransactionId, Invoice, KeyPair)> {
+        self.submit_unsigned_invoice(amount, description, false, rng, expiry_time)
+            .await
+    }
+
+    async fn submit_unsigned_invoice<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        description: String,
+        hold: bool,
         mut rng: R,
         expiry_time: Option<u64>,
     ) -> Result<(TransactionId, Invoice, KeyPair)> {
         let payment_keypair = KeyPair::new(&self.context.secp, &mut rng);
         let (invoice, ln_output) = self
-            .generate_unsigned_invoice(amount, description, payment_keypair, &mut rng, expiry_time)
+            .generate_unsigned_invoice(
+                amount,
+                description,
+                payment_keypair,
+                hold,
+                &mut rng,
+                expiry_time,
+            )
             .await?;
 
         // There is no input here because this is just an announcement
@@ -931,87 +1935,63 @@ impl Client<UserClientConfig> {
         amount: Amount,
         description: String,
         payment_keypair: KeyPair,
+        hold: bool,
         mut rng: R,
         expiry_time: Option<u64>,
     ) -> Result<(Invoice, Output)> {
-        let gateway = self.fetch_active_gateway().await?;
-        let raw_payment_secret: [u8; 32] = payment_keypair.x_only_public_key().0.serialize();
-        let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&raw_payment_secret);
-        let payment_secret = PaymentSecret(raw_payment_secret);
-
-        // Temporary lightning node pubkey
-        let (node_secret_key, node_public_key) = self.context.secp.generate_keypair(&mut rng);
-
-        // Route hint instructing payer how to route to gateway
-        let route_hint_last_hop = RouteHintHop {
-            src_node_id: gateway.node_pub_key,
-            short_channel_id: gateway.mint_channel_id,
-            fees: RoutingFees {
-                base_msat: 0,
-                proportional_millionths: 0,
-            },
-            cltv_expiry_delta: 30,
-            htlc_minimum_msat: None,
-            htlc_maximum_msat: None,
-        };
-        let route_hints = if gateway.route_hints.is_empty() {
-            vec![RouteHint(vec![route_hint_last_hop])]
+        let gateway = self
+            .fetch_gateway_for_payment(|gateway| gateway.can_receive(amount))
+            .await?;
+        let preimage = Preimage::from_claim_key(&payment_keypair.x_only_public_key().0);
+        let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+        let payment_secret = PaymentSecret(preimage.0);
+        // The payer covers the gateway's fee so we receive the full amount
+        let invoice_amount = amount + gateway.fees.incoming.amount(amount);
+
+        let (min_final_cltv_expiry, hold_key) = if hold {
+            let accept_keypair = hold_accept_keypair(&self.context.secp, &payment_keypair);
+            (
+                HOLD_INVOICE_MIN_FINAL_CLTV_EXPIRY,
+                Some(accept_keypair.x_only_public_key().0),
+            )
         } else {
-            gateway
-                .route_hints
-                .iter()
-                .map(|rh| {
-                    RouteHint(
-                        rh.to_ldk_route_hint()
-                            .0
-                            .iter()
-                            .cloned()
-                            .chain(once(route_hint_last_hop.clone()))
-                            .collect(),
-                    )
-                })
-                .collect()
+            (MIN_FINAL_CLTV_EXPIRY, None)
         };
 
-        let duration_since_epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-
-        let mut invoice_builder = InvoiceBuilder::new(network_to_currency(
-            self.config
-                .0
-                .get_first_module_by_kind::<WalletClientConfig>("wallet")
-                .expect("must have wallet config available")
-                .1
-                .network,
-        ))
-        .amount_milli_satoshis(amount.msats)
-        .description(description)
-        .payment_hash(payment_hash)
-        .payment_secret(payment_secret)
-        .duration_since_epoch(duration_since_epoch)
-        .min_final_cltv_expiry(18)
-        .payee_pub_key(node_public_key)
-        .expiry_time(Duration::from_secs(
-            expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
-        ));
-
-        for rh in route_hints {
-            invoice_builder = invoice_builder.private_route(rh);
-        }
-
-        let invoice = invoice_builder.build_signed(|hash| {
-            self.context
-                .secp
-                .sign_ecdsa_recoverable(hash, &node_secret_key)
-        })?;
+        let invoice = if gateway.issues_invoices {
+            let payload = CreateInvoicePayload {
+                federation_id: self.config.0.federation_id.clone(),
+                payment_hash,
+                amount: invoice_amount,
+                description,
+                expiry_time,
+                min_final_cltv_expiry,
+            };
+            self.request_gateway_invoice(&gateway, &payload).await?
+        } else {
+            self.build_gateway_invoice(
+                &gateway,
+                invoice_amount,
+                InvoiceDescription::Direct(description),
+                payment_hash,
+                payment_secret,
+                expiry_time,
+                min_final_cltv_expiry,
+                &mut rng,
+            )?
+        };
 
-        let offer_output = self.ln_client().create_offer_output(
-            amount,
-            payment_hash,
-            Preimage(raw_payment_secret),
-            expiry_time,
-        );
+        let offer_output = self
+            .ln_client()
+            .create_offer_output(
+                amount,
+                payment_hash,
+                preimage,
+                Some(invoice_expires_at(&invoice)),
+                hold_key,
+                IncomingContractVersion::V1,
+            )
+            .await?;
         let ln_output = Output::LN(offer_output);
 
         Ok((invoice, ln_output))
@@ -1030,10 +2010,24 @@ impl Client<UserClientConfig> {
             .api
             .await_output_outcome::<OfferId>(outpoint, timeout, &self.context.decoders)
             .await?;
+        let offer = self.ln_client().get_offer(*invoice.payment_hash()).await?;
+        let invoice_amount = Amount::from_msats(invoice.amount_milli_satoshis().unwrap_or(0));
         let confirmed = ConfirmedInvoice {
             invoice,
             keypair: payment_keypair,
         };
+        self.ln_client()
+            .save_payment(
+                confirmed.contract_id(),
+                &LightningPayment::new(
+                    PaymentDirection::Incoming,
+                    confirmed.invoice.clone(),
+                    offer.amount,
+                    invoice_amount.saturating_sub(offer.amount),
+                    vec![receiving_gateway(&confirmed.invoice)],
+                ),
+            )
+            .await;
         self.ln_client().save_confirmed_invoice(&confirmed).await;
         Ok(confirmed)
     }
@@ -1049,14 +2043,57 @@ impl Client<UserClientConfig> {
 
         // Input claims this contract
         let mut tx = TransactionBuilder::default();
-        tx.input(&mut vec![ci.keypair], Input::LN(contract.claim()));
+        tx.input(
+            &mut vec![ci.keypair],
+            Input::LN(contract.claim_with_key(&ci.keypair)),
+        );
         let txid = self.submit_tx_with_change(tx, &mut rng).await?;
 
-        // TODO: Update database if invoice is paid or expired
+        let preimage = match contract.contract.decrypted_preimage {
+            DecryptedPreimage::Some(preimage) => Some(preimage),
+            DecryptedPreimage::Pending | DecryptedPreimage::Invalid => None,
+        };
+        self.ln_client()
+            .update_payment(
+                contract.contract.hash,
+                PaymentDirection::Incoming,
+                contract_id,
+                PaymentStatus::Succeeded,
+                preimage,
+            )
+            .await;
 
         Ok(OutPoint { txid, out_idx: 0 })
     }
 
+    /// Accepts a payment to one of our hold invoices held by the federation. Once the preimage
+    /// was decrypted the gateway settles the payment and we can claim the contract.
+    pub async fn accept_incoming_payment(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<TransactionId> {
+        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+        let ci = self.ln_client().get_confirmed_invoice(contract_id).await?;
+
+        let accept_keypair = hold_accept_keypair(&self.context.secp, &ci.keypair);
+        match &contract.contract.hold {
+            Some(hold) if hold.accept_key == accept_keypair.x_only_public_key().0 => {}
+            _ => return Err(ClientError::NotHoldInvoice),
+        }
+
+        let signature = self.context.secp.sign_schnorr(
+            &contract.contract.acceptance_message().into(),
+            &accept_keypair,
+        );
+        let mut tx = TransactionBuilder::default();
+        tx.output(Output::LN(
+            self.ln_client()
+                .create_accept_incoming_output(contract_id, signature),
+        ));
+        self.submit_tx_with_change(tx, rng).await
+    }
+
     /// Notify gateway that we've escrowed notes they can claim by routing our payment and wait
     /// for them to do so
     pub async fn await_outgoing_contract_execution(
@@ -1066,41 +2103,437 @@ impl Client<UserClientConfig> {
     ) -> Result<()> {
         let gateway = self.fetch_active_gateway().await?;
 
-        let payload = PayInvoicePayload::new(self.config.0.federation_id.clone(), contract_id);
+        let payload = PayInvoicePayload::new(self.config.0.federation_id.clone(), contract_id);
+        if let Some(preimage) = self.request_outgoing_payment(&gateway, &payload).await? {
+            self.update_outgoing_payment(contract_id, PaymentStatus::Succeeded, Some(preimage))
+                .await;
+            return Ok(());
+        }
+
+        if let Err(error) = self.refund_failed_outgoing_payment(contract_id, rng).await {
+            self.update_outgoing_payment(contract_id, PaymentStatus::Failed, None)
+                .await;
+            return Err(error);
+        }
+
+        self.fail_over_gateway(gateway.node_pub_key).await;
+        Err(ClientError::RefundedFailedPayment)
+    }
+
+    /// Notifies the gateways of all parts of a multi-part payment and waits for them to pay
+    ///
+    /// If any part fails the others can't succeed either, since the recipient doesn't settle
+    /// incomplete payments. Once all gateways gave up we refund every part that failed.
+    pub async fn await_outgoing_contract_parts_execution(
+        &self,
+        parts: &[OutgoingPaymentPart],
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<()> {
+        let results = futures::future::join_all(parts.iter().map(|part| async move {
+            let payload = PayInvoicePayload::new_part(
+                self.config.0.federation_id.clone(),
+                part.contract_id,
+                part.amount,
+            );
+            self.request_outgoing_payment(&part.gateway, &payload).await
+        }))
+        .await;
+
+        let preimage = results
+            .iter()
+            .find_map(|result| result.as_ref().ok()?.clone());
+        let failed_parts = parts
+            .iter()
+            .zip(results)
+            .filter(|(part, result)| match result {
+                Ok(Some(_)) => false,
+                Ok(None) => {
+                    warn!(gateway = %part.gateway.api, "Gateway failed to pay its part");
+                    true
+                }
+                Err(error) => {
+                    warn!(gateway = %part.gateway.api, %error, "Gateway failed to pay its part");
+                    true
+                }
+            })
+            .map(|(part, _)| part)
+            .collect::<Vec<_>>();
+
+        let Some(first_part) = parts.first() else {
+            return Ok(());
+        };
+        if failed_parts.is_empty() {
+            self.update_outgoing_payment(
+                first_part.contract_id,
+                PaymentStatus::Succeeded,
+                preimage,
+            )
+            .await;
+            return Ok(());
+        }
+
+        let mut refunded_all = true;
+        for part in failed_parts {
+            if let Err(error) = self
+                .refund_failed_outgoing_payment(part.contract_id, &mut rng)
+                .await
+            {
+                warn!(contract_id = %part.contract_id, %error, "Could not refund failed part");
+                refunded_all = false;
+            }
+        }
+
+        if refunded_all {
+            Err(ClientError::RefundedFailedPayment)
+        } else {
+            self.update_outgoing_payment(first_part.contract_id, PaymentStatus::Failed, None)
+                .await;
+            Err(ClientError::FailedPaymentNoRefund)
+        }
+    }
+
+    /// Updates the receipt of the payment the outgoing contract `contract_id` belongs to, keeping
+    /// `preimage` as proof of payment if it matches the invoice
+    async fn update_outgoing_payment(
+        &self,
+        contract_id: ContractId,
+        status: PaymentStatus,
+        preimage: Option<Preimage>,
+    ) {
+        let Some(contract_data) = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&OutgoingPaymentKey(contract_id))
+            .await
+            .expect("DB error")
+        else {
+            return;
+        };
+
+        let payment_hash = contract_data.contract_account.contract.hash;
+        let preimage = preimage.filter(|preimage| sha256::Hash::hash(&preimage.0) == payment_hash);
+        self.ln_client()
+            .update_payment(
+                payment_hash,
+                PaymentDirection::Outgoing,
+                contract_id,
+                status,
+                preimage,
+            )
+            .await;
+    }
+
+    /// Returns the receipts of all Lightning payments we made and received, oldest first
+    pub async fn ln_payment_history(&self) -> Vec<LightningPayment> {
+        self.ln_client().list_payments().await
+    }
+
+    /// Asks `gateway` to pay for an outgoing contract, returning the preimage if it succeeded
+    async fn request_outgoing_payment(
+        &self,
+        gateway: &LightningGateway,
+        payload: &PayInvoicePayload,
+    ) -> Result<Option<Preimage>> {
+        let future = reqwest::Client::new()
+            .post(
+                gateway
+                    .api
+                    .join("pay_invoice")
+                    .expect("'pay_invoice' contains no invalid characters for a URL")
+                    .as_str(),
+            )
+            .json(payload)
+            .send();
+        let response = fedimint_api::task::timeout(Duration::from_secs(120), future)
+            .await
+            .map_err(|_| ClientError::OutgoingPaymentTimeout)?
+            .map_err(ClientError::HttpError)?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+
+        Ok(Some(response.json::<PayInvoiceResponse>().await?.preimage))
+    }
+
+    /// Asks `gateway` to issue an invoice paying to its own Lightning node, checking that it
+    /// matches what we requested
+    async fn request_gateway_invoice(
+        &self,
+        gateway: &LightningGateway,
+        payload: &CreateInvoicePayload,
+    ) -> Result<Invoice> {
+        let invoice = reqwest::Client::new()
+            .post(
+                gateway
+                    .api
+                    .join("create_invoice")
+                    .expect("'create_invoice' contains no invalid characters for a URL")
+                    .as_str(),
+            )
+            .json(payload)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<CreateInvoiceResponse>()
+            .await?
+            .invoice;
+
+        if *invoice.payment_hash() != payload.payment_hash
+            || invoice.amount_milli_satoshis() != Some(payload.amount.msats)
+            || invoice.recover_payee_pub_key() != gateway.node_pub_key
+        {
+            return Err(ClientError::InvalidGatewayInvoice);
+        }
+
+        Ok(invoice)
+    }
+
+    /// Waits for the gateway to cancel the contract of a payment it failed to route and refunds it
+    async fn refund_failed_outgoing_payment(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<OutPoint> {
+        fedimint_api::task::timeout(
+            Duration::from_secs(10),
+            self.ln_client().await_outgoing_refundable(contract_id),
+        )
+        .await
+        .map_err(|_| ClientError::FailedPaymentNoRefund)??;
+
+        self.try_refund_outgoing_contract(contract_id, rng).await
+    }
+
+    /// Registers a new reusable offer with our active gateway, returning the static payment code
+    /// payers can request invoices for it with
+    pub async fn create_reusable_offer<R: RngCore + CryptoRng>(
+        &self,
+        description: String,
+        rng: R,
+    ) -> Result<StaticPaymentCode> {
+        let gateway = self.fetch_active_gateway().await?;
+        let payload = self
+            .new_reusable_offer(description, gateway.clone(), rng)
+            .await;
+        let offer_id = ReusableOfferId::from_offer_key(&payload.offer_key);
+        register_offer_with_gateway(&gateway, &payload).await?;
+
+        Ok(StaticPaymentCode {
+            gateway_api: gateway.api,
+            federation_id: self.config.0.federation_id.clone(),
+            offer_id,
+        })
+    }
+
+    /// Saves a new reusable offer answered by `gateway` and returns the payload registering it
+    /// with its first [`OFFER_PAYMENT_BATCH`] payments
+    pub async fn new_reusable_offer<R: RngCore + CryptoRng>(
+        &self,
+        description: String,
+        gateway: LightningGateway,
+        mut rng: R,
+    ) -> RegisterOfferPayload {
+        let keypair = KeyPair::new(&self.context.secp, &mut rng);
+        let offer_id =
+            ReusableOfferId::from_offer_key(&secp256k1_zkp::PublicKey::from_keypair(&keypair));
+        let reusable_offer = ReusableOffer {
+            keypair,
+            description,
+            gateway,
+            next_claim_index: 0,
+            next_payment_index: OFFER_PAYMENT_BATCH,
+        };
+        let payload = self.register_offer_payload(&reusable_offer, 0..OFFER_PAYMENT_BATCH);
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_new_entry(&ReusableOfferKey(offer_id), &reusable_offer)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+
+        payload
+    }
+
+    /// Signed payload handing the payments with the given `indices` to the offer's gateway
+    fn register_offer_payload(
+        &self,
+        reusable_offer: &ReusableOffer,
+        indices: std::ops::Range<u64>,
+    ) -> RegisterOfferPayload {
+        let threshold_pub_key = self.ln_client().config.threshold_pub_key;
+        let payments = indices
+            .map(|index| {
+                offer::offer_payment(
+                    &self.context.secp,
+                    &reusable_offer.keypair,
+                    &threshold_pub_key,
+                    index,
+                )
+            })
+            .collect();
+        RegisterOfferPayload::new(
+            &self.context.secp,
+            self.config.0.federation_id.clone(),
+            &reusable_offer.keypair,
+            reusable_offer.description.clone(),
+            payments,
+        )
+    }
+
+    /// Hands new payments to the gateway of a reusable offer once it runs low on unused ones
+    async fn top_up_reusable_offer(&self, reusable_offer: &mut ReusableOffer) -> Result<()> {
+        let next_payment_index = reusable_offer.next_payment_index;
+        let status = register_offer_with_gateway(
+            &reusable_offer.gateway,
+            &self.register_offer_payload(reusable_offer, next_payment_index..next_payment_index),
+        )
+        .await?;
+        if status.unused_payments >= OFFER_PAYMENT_BATCH / 2 {
+            return Ok(());
+        }
+
+        let top_up_end = next_payment_index + (OFFER_PAYMENT_BATCH - status.unused_payments);
+        register_offer_with_gateway(
+            &reusable_offer.gateway,
+            &self.register_offer_payload(reusable_offer, next_payment_index..top_up_end),
+        )
+        .await?;
+        reusable_offer.next_payment_index = top_up_end;
+        Ok(())
+    }
 
-        let future = reqwest::Client::new()
+    /// Creates a reusable offer and registers `username` for it with our active gateway
+    ///
+    /// Returns the resulting Lightning address, which is only resolvable if the gateway's API is
+    /// served at the root of its domain.
+    pub async fn create_lightning_address<R: RngCore + CryptoRng>(
+        &self,
+        username: String,
+        description: String,
+        rng: R,
+    ) -> Result<String> {
+        let payment_code = self.create_reusable_offer(description, rng).await?;
+        let reusable_offer = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&ReusableOfferKey(payment_code.offer_id))
+            .await
+            .expect("DB error")
+            .expect("We just created the offer");
+
+        let payload = RegisterLightningAddressPayload::new(
+            &self.context.secp,
+            payment_code.federation_id,
+            username.clone(),
+            &reusable_offer.keypair,
+        );
+        reqwest::Client::new()
             .post(
-                gateway
-                    .api
-                    .join("pay_invoice")
-                    .expect("'pay_invoice' contains no invalid characters for a URL")
+                payment_code
+                    .gateway_api
+                    .join("register_lightning_address")
+                    .expect("'register_lightning_address' contains no invalid characters for a URL")
                     .as_str(),
             )
             .json(&payload)
-            .send();
-        let result = fedimint_api::task::timeout(Duration::from_secs(120), future)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let domain = payment_code
+            .gateway_api
+            .host_str()
+            .expect("gateway API URLs always have a host");
+        Ok(format!("{username}@{domain}"))
+    }
+
+    /// Claims all payments to our reusable offers whose preimage the federation has decrypted and
+    /// tops up the payments their gateways can issue invoices for
+    ///
+    /// Returns the out point of the e-cash output generated as change or `None` if there was
+    /// nothing to claim.
+    pub async fn claim_offer_payments<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+    ) -> Result<Option<OutPoint>> {
+        let offers: Vec<(ReusableOfferKey, ReusableOffer)> = self
+            .context
+            .db
+            .begin_transaction()
             .await
-            .map_err(|_| ClientError::OutgoingPaymentTimeout)?
-            .map_err(ClientError::HttpError);
+            .find_by_prefix(&ReusableOfferKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .collect()
+            .await;
 
-        match result {
-            Ok(response) => {
-                if response.status().is_success() {
-                    return Ok(());
+        let mut tx = TransactionBuilder::default();
+        let mut has_claims = false;
+        let mut updated_offers = vec![];
+        for (key, mut reusable_offer) in offers {
+            // Payments are settled in any order and the gateway may not have handed out all
+            // payments yet, so we scan all payments handed to it and only skip over a contiguous
+            // range of settled ones on the next scan
+            let mut all_settled = true;
+            for index in reusable_offer.next_claim_index..reusable_offer.next_payment_index {
+                let payment_keypair =
+                    offer::payment_keypair(&self.context.secp, &reusable_offer.keypair, index);
+                let preimage = offer::payment_preimage(&secp256k1_zkp::PublicKey::from_keypair(
+                    &payment_keypair,
+                ));
+                let contract_id = ContractId::from_hash(sha256::Hash::hash(&preimage.0));
+
+                // Not funded yet, or not even handed out by the gateway
+                let settled = match self.ln_client().find_incoming_contract(contract_id).await? {
+                    None => false,
+                    Some(account) => match account.contract.decrypted_preimage {
+                        DecryptedPreimage::Pending => false,
+                        DecryptedPreimage::Some(_) if account.amount != Amount::ZERO => {
+                            tx.input(
+                                &mut vec![payment_keypair],
+                                Input::LN(account.claim_with_key(&payment_keypair)),
+                            );
+                            has_claims = true;
+                            true
+                        }
+                        // Either already claimed or refunded to the gateway
+                        DecryptedPreimage::Some(_) | DecryptedPreimage::Invalid => true,
+                    },
+                };
+
+                all_settled &= settled;
+                if all_settled {
+                    reusable_offer.next_claim_index = index + 1;
                 }
+            }
 
-                fedimint_api::task::timeout(
-                    Duration::from_secs(10),
-                    self.ln_client().await_outgoing_refundable(contract_id),
-                )
-                .await
-                .map_err(|_| ClientError::FailedPaymentNoRefund)??;
-
-                self.try_refund_outgoing_contract(contract_id, rng).await?;
-                Err(ClientError::RefundedFailedPayment)
+            // Failing to reach the gateway mustn't keep us from claiming our payments
+            if let Err(error) = self.top_up_reusable_offer(&mut reusable_offer).await {
+                warn!(%error, gateway = %reusable_offer.gateway.api, "Failed to top up reusable offer");
             }
-            Err(e) => Err(e),
+            updated_offers.push((key, reusable_offer));
+        }
+
+        let outpoint = if has_claims {
+            let txid = self.submit_tx_with_change(tx, rng).await?;
+            Some(OutPoint { txid, out_idx: 0 })
+        } else {
+            None
+        };
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        for (key, reusable_offer) in updated_offers {
+            dbtx.insert_entry(&key, &reusable_offer)
+                .await
+                .expect("DB error");
         }
+        dbtx.commit_tx().await.expect("DB Error");
+
+        Ok(outpoint)
     }
 }
 
@@ -1116,11 +2549,12 @@ impl Client<GatewayClientConfig> {
             .map_err(ClientError::LnClientError)
     }
 
-    /// Check if we can claim the contract account and returns the max delay in blocks for how long
-    /// other nodes on the route are allowed to delay the payment.
+    /// Checks that we can pay for the contract and returns the parameters to pay it with, if
+    /// `part_amount` is given we only pay that part of a multi-part payment
     pub async fn validate_outgoing_account(
         &self,
         account: &OutgoingContractAccount,
+        part_amount: Option<Amount>,
     ) -> Result<PaymentParameters> {
         let our_pub_key = secp256k1_zkp::XOnlyPublicKey::from_keypair(&self.config.redeem_key).0;
 
@@ -1132,15 +2566,22 @@ impl Client<GatewayClientConfig> {
             return Err(ClientError::NotOurKey);
         }
 
-        let invoice: Invoice = account.contract.invoice.clone();
-        let invoice_amount = Amount::from_msats(
-            invoice
-                .amount_milli_satoshis()
-                .ok_or(ClientError::InvoiceMissingAmount)?,
-        );
+        let pruned_invoice = &account.contract.invoice;
+        let invoice_amount = pruned_invoice
+            .amount
+            .ok_or(ClientError::InvoiceMissingAmount)?;
+
+        let payment_amount = part_amount.unwrap_or(invoice_amount);
+        if payment_amount == Amount::ZERO || payment_amount > invoice_amount {
+            return Err(ClientError::InvalidPartAmount(payment_amount));
+        }
 
-        if account.amount < invoice_amount {
-            return Err(ClientError::Underfunded(invoice_amount, account.amount));
+        let gateway_fee = self.config.fees.outgoing.amount(payment_amount);
+        if account.amount < payment_amount + gateway_fee {
+            return Err(ClientError::Underfunded(
+                payment_amount + gateway_fee,
+                account.amount,
+            ));
         }
 
         let consensus_block_height = self.context.api.fetch_consensus_block_height().await?;
@@ -1154,16 +2595,28 @@ impl Client<GatewayClientConfig> {
         Ok(PaymentParameters {
             max_delay,
             invoice_amount,
-            max_send_amount: account.amount,
-            payment_hash: *invoice.payment_hash(),
-            maybe_internal: self.is_maybe_internal_payment(&invoice),
+            payment_amount,
+            max_send_amount: account.amount - gateway_fee,
+            payment_hash: pruned_invoice.payment_hash,
+            // Our offers can only be funded with the whole invoice amount at once
+            maybe_internal: payment_amount == invoice_amount
+                && self.is_maybe_internal_payment(pruned_invoice),
         })
     }
 
+    /// Checks whether the timelock of the contract passed, so we can't claim it anymore
+    pub async fn is_outgoing_contract_timed_out(
+        &self,
+        account: &OutgoingContractAccount,
+    ) -> Result<bool> {
+        let consensus_block_height = self.context.api.fetch_consensus_block_height().await?;
+        Ok(consensus_block_height >= account.contract.timelock as u64)
+    }
+
     /// Returns true if the invoice contains us as a routing hint
-    fn is_maybe_internal_payment(&self, invoice: &Invoice) -> bool {
+    fn is_maybe_internal_payment(&self, invoice: &PrunedInvoice) -> bool {
         let maybe_route_hint_first_id = invoice
-            .route_hints()
+            .route_hints
             .first()
             .and_then(|rh| rh.0.last())
             .map(|hop| hop.src_node_id);
@@ -1177,24 +2630,124 @@ impl Client<GatewayClientConfig> {
     ///
     /// Note though that extended periods of staying offline will result in loss of funds anyway if
     /// the client can not claim the respective contract in time.
-    pub async fn save_outgoing_payment(&self, contract: OutgoingContractAccount) {
+    pub async fn save_outgoing_payment(
+        &self,
+        contract_account: OutgoingContractAccount,
+        part_amount: Option<Amount>,
+        state: GatewayPaymentState,
+    ) {
         let mut dbtx = self.context.db.begin_transaction().await;
         dbtx.insert_entry(
-            &OutgoingContractAccountKey(contract.contract.contract_id()),
-            &contract,
+            &GatewayPaymentKey(contract_account.contract.contract_id()),
+            &GatewayPayment {
+                contract_account,
+                part_amount,
+                state,
+            },
         )
         .await
         .expect("DB error");
         dbtx.commit_tx().await.expect("DB Error");
     }
 
-    /// Lists all previously saved transactions that have not been driven to completion so far
-    pub async fn list_pending_outgoing(&self) -> Vec<OutgoingContractAccount> {
+    /// Records the progress of a saved outgoing payment, so it is resumed from there after a crash
+    pub async fn update_outgoing_payment(
+        &self,
+        contract_id: ContractId,
+        state: GatewayPaymentState,
+    ) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut payment = dbtx
+            .get_value(&GatewayPaymentKey(contract_id))
+            .await
+            .expect("DB error")
+            .ok_or(ClientError::UnknownOutgoingPayment)?;
+        payment.state = state;
+        dbtx.insert_entry(&GatewayPaymentKey(contract_id), &payment)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+        Ok(())
+    }
+
+    /// Moves the outgoing payments saved before we tracked their progress to [`GatewayPaymentKey`]
+    ///
+    /// Payments whose claim was submitted are dropped unless the contract still holds funds, in
+    /// which case the claim got lost and the preimage is obtained again.
+    pub async fn migrate_legacy_outgoing_payments(&self) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut accounts = dbtx
+            .find_by_prefix(&OutgoingContractAccountKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").1)
+            .collect::<Vec<_>>()
+            .await;
+        let claims = dbtx
+            .find_by_prefix(&OutgoingPaymentClaimKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").0 .0)
+            .collect::<Vec<_>>()
+            .await;
+        dbtx.commit_tx().await.expect("DB Error");
+        if accounts.is_empty() && claims.is_empty() {
+            return Ok(());
+        }
+
+        for contract_id in &claims {
+            let account = self.fetch_outgoing_contract(*contract_id).await?;
+            if account.amount != Amount::ZERO {
+                accounts.push(account);
+            }
+        }
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        for contract_id in claims {
+            dbtx.remove_entry(&OutgoingPaymentClaimKey(contract_id))
+                .await
+                .expect("DB error");
+        }
+        for contract_account in accounts {
+            let contract_id = contract_account.contract.contract_id();
+            dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
+                .await
+                .expect("DB error");
+            if dbtx
+                .get_value(&GatewayPaymentKey(contract_id))
+                .await
+                .expect("DB error")
+                .is_some()
+            {
+                continue;
+            }
+
+            let state = if self.is_maybe_internal_payment(&contract_account.contract.invoice) {
+                GatewayPaymentState::BuyingOffer
+            } else {
+                GatewayPaymentState::PayingInvoice
+            };
+            info!(%contract_id, ?state, "Migrating legacy outgoing payment");
+            dbtx.insert_new_entry(
+                &GatewayPaymentKey(contract_id),
+                &GatewayPayment {
+                    contract_account,
+                    part_amount: None,
+                    state,
+                },
+            )
+            .await
+            .expect("DB error");
+        }
+        dbtx.commit_tx().await.expect("DB Error");
+        Ok(())
+    }
+
+    /// Lists all previously saved payments that have not been driven to completion so far
+    pub async fn list_pending_outgoing(&self) -> Vec<GatewayPayment> {
         self.context
             .db
             .begin_transaction()
             .await
-            .find_by_prefix(&OutgoingContractAccountKeyPrefix)
+            .find_by_prefix(&GatewayPaymentKeyPrefix)
             .await
             .map(|res| res.expect("DB error").1)
             .collect()
@@ -1205,14 +2758,15 @@ impl Client<GatewayClientConfig> {
     pub async fn abort_outgoing_payment(&self, contract_id: ContractId) -> Result<()> {
         // FIXME: needs outbox pattern
         let mut dbtx = self.context.db.begin_transaction().await;
-        let contract_account = dbtx
-            .remove_entry(&OutgoingContractAccountKey(contract_id))
+        let payment = dbtx
+            .remove_entry(&GatewayPaymentKey(contract_id))
             .await
             .expect("DB error")
             .ok_or(ClientError::CancelUnknownOutgoingContract)?;
         dbtx.commit_tx().await.expect("DB Error");
 
-        self.cancel_outgoing_contract(contract_account).await
+        self.cancel_outgoing_contract(payment.contract_account)
+            .await
     }
 
     /// Cancel an outgoing contract we haven't accepted yet, possibly because it was underfunded
@@ -1252,24 +2806,37 @@ impl Client<GatewayClientConfig> {
         preimage: Preimage,
         rng: impl RngCore + CryptoRng,
     ) -> Result<OutPoint> {
-        let mut dbtx = self.context.db.begin_transaction().await;
         let mut tx = TransactionBuilder::default();
 
         let contract = self.ln_client().get_outgoing_contract(contract_id).await?;
-        let input = Input::LN(contract.claim(preimage));
+        let input = Input::LN(contract.claim(preimage.clone()));
+        tx.input(&mut vec![self.config.redeem_key], input);
 
-        dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
-            .await
-            .expect("DB Error");
-        dbtx.insert_entry(&OutgoingPaymentClaimKey(contract_id), &())
+        // The claim is recorded before it is submitted, so we await its outcome after a crash
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let final_tx = tx.build(self, &mut dbtx, rng).await;
+        let out_point = OutPoint {
+            txid: final_tx.tx_hash(),
+            out_idx: 0,
+        };
+        let payment = dbtx
+            .get_value(&GatewayPaymentKey(contract_id))
             .await
-            .expect("DB Error");
+            .expect("DB error");
+        if let Some(mut payment) = payment {
+            payment.state = GatewayPaymentState::Claimed {
+                preimage,
+                out_point,
+            };
+            dbtx.insert_entry(&GatewayPaymentKey(contract_id), &payment)
+                .await
+                .expect("DB error");
+        }
         dbtx.commit_tx().await.expect("DB Error");
 
-        tx.input(&mut vec![self.config.redeem_key], input);
-        let txid = self.submit_tx_with_change(tx, rng).await?;
+        self.context.api.submit_transaction(final_tx).await?;
 
-        Ok(OutPoint { txid, out_idx: 0 })
+        Ok(out_point)
     }
 
     /// Buy a lightning preimage listed for sale inside the federation
@@ -1292,87 +2859,245 @@ impl Client<GatewayClientConfig> {
         // Fetch offer for this payment hash
         let offer: IncomingContractOffer = self.ln_client().get_offer(*payment_hash).await?;
 
-        if &offer.amount > htlc_amount {
+        if offer.amount + self.config.fees.incoming.amount(offer.amount) > *htlc_amount {
             return Err(ClientError::ViolatedFeePolicy);
         }
         if &offer.hash != payment_hash {
             return Err(ClientError::InvalidOffer);
         }
 
-        // Inputs
-        let mut builder = TransactionBuilder::default();
-        let (mut keys, input) = self.mint_client().select_input(offer.amount).await?;
-        builder.input(&mut keys, input);
-
-        // Outputs
         let our_pub_key = secp256k1_zkp::XOnlyPublicKey::from_keypair(&self.config.redeem_key).0;
+        let hold = self.incoming_contract_hold(&offer).await?;
         let contract = Contract::Incoming(IncomingContract {
             hash: offer.hash,
             encrypted_preimage: offer.encrypted_preimage.clone(),
             decrypted_preimage: DecryptedPreimage::Pending,
             gateway_key: our_pub_key,
+            hold,
+            version: offer.version,
+        });
+        let incoming_output = LightningOutput::Contract(ContractOutput {
+            amount: offer.amount,
+            contract: contract.clone(),
         });
-        let incoming_output = fedimint_core::transaction::legacy::Output::LN(
-            LightningOutput::Contract(ContractOutput {
-                amount: offer.amount,
-                contract: contract.clone(),
-            }),
-        );
 
-        // Submit transaction
-        builder.output(incoming_output);
-        let txid = self.submit_tx_with_change(builder, rng).await?;
-        let outpoint = OutPoint { txid, out_idx: 0 };
+        let outpoint = self.fund_incoming_contract(incoming_output, rng).await?;
 
         // FIXME: Save this contract in DB
         Ok((outpoint, contract.contract_id()))
     }
 
-    /// Claw back funds after incoming contract that had invalid preimage
-    #[instrument(name = "Client::refund_incoming_contract", skip(self, rng))]
-    pub async fn refund_incoming_contract(
+    /// Starts answering invoice requests for the reusable offer of a user or tops up the payments
+    /// of an offer we already answer requests for
+    ///
+    /// Payments we already queued or handed out are ignored, so no payment is ever handed out
+    /// twice.
+    pub async fn register_reusable_offer(
         &self,
-        contract_id: ContractId,
-        rng: impl RngCore + CryptoRng,
-    ) -> Result<TransactionId> {
-        let contract_account = self.ln_client().get_incoming_contract(contract_id).await?;
+        payload: RegisterOfferPayload,
+    ) -> Result<RegisterOfferResponse> {
+        if !payload.verify(&self.context.secp) {
+            return Err(ClientError::InvalidSignature);
+        }
+        let offer_id = ReusableOfferId::from_offer_key(&payload.offer_key);
 
-        let mut builder = TransactionBuilder::default();
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut gateway_offer = dbtx
+            .get_value(&GatewayOfferKey(offer_id))
+            .await
+            .expect("DB error")
+            .unwrap_or(GatewayOffer {
+                offer_key: payload.offer_key,
+                description: payload.description,
+                payments: vec![],
+                next_index: 0,
+            });
+        gateway_offer.top_up(payload.payments);
+        dbtx.insert_entry(&GatewayOfferKey(offer_id), &gateway_offer)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
 
-        // Input claims this contract
-        builder.input(
-            &mut vec![self.config.redeem_key],
-            Input::LN(contract_account.claim()),
-        );
-        let mint_tx_id = self.submit_tx_with_change(builder, rng).await?;
-        Ok(mint_tx_id)
+        Ok(RegisterOfferResponse {
+            offer_id,
+            unused_payments: gateway_offer.payments.len() as u64,
+        })
     }
 
-    /// Lists all claim transactions for outgoing contracts that we have submitted but were not part
-    /// of the consensus yet.
-    pub async fn list_pending_claimed_outgoing(&self) -> Vec<ContractId> {
-        self.context
-            .db
-            .begin_transaction()
-            .await
-            .find_by_prefix(&OutgoingPaymentClaimKeyPrefix)
+    /// Answers an invoice request for a reusable offer
+    ///
+    /// Registers an incoming contract offer selling the encrypted preimage of the offer's next
+    /// payment with the federation and returns an invoice for it that routes through us. The payer pays
+    /// exactly `invoice_amount`, our incoming fee is deducted from what the offer's owner
+    /// receives. If a `description_hash` is given the invoice commits to it instead of the offer's
+    /// description.
+    #[instrument(name = "Client::create_offer_invoice", skip(self, route_hints, rng))]
+    pub async fn create_offer_invoice<R: RngCore + CryptoRng>(
+        &self,
+        offer_id: ReusableOfferId,
+        invoice_amount: Amount,
+        description_hash: Option<sha256::Hash>,
+        route_hints: Vec<fedimint_core::modules::ln::route_hints::RouteHint>,
+        mut rng: R,
+    ) -> Result<Invoice> {
+        let fee = self.config.fees.incoming.amount(invoice_amount);
+        if invoice_amount <= fee {
+            return Err(ClientError::ViolatedFeePolicy);
+        }
+        // Charging the fee on the whole invoice amount keeps us on the safe side of the check in
+        // `buy_preimage_offer`, which charges it on the offer amount only
+        let amount = invoice_amount - fee;
+
+        // Take the payment before using it so concurrent requests never share one
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut gateway_offer = dbtx
+            .get_value(&GatewayOfferKey(offer_id))
             .await
-            .map(|res| res.expect("DB error").0 .0)
-            .collect()
+            .expect("DB error")
+            .ok_or(ClientError::UnknownReusableOffer)?;
+        if gateway_offer.payments.is_empty() {
+            return Err(ClientError::NoOfferPaymentsLeft);
+        }
+        let OfferPayment {
+            hash: payment_hash,
+            encrypted_preimage,
+            ..
+        } = gateway_offer.payments.remove(0);
+        dbtx.insert_entry(&GatewayOfferKey(offer_id), &gateway_offer)
             .await
-    }
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
 
-    /// Wait for a lightning preimage gateway has purchased to be decrypted by the federation
-    pub async fn await_preimage_decryption(&self, outpoint: OutPoint) -> Result<Preimage> {
-        Ok(self
-            .context
+        let gateway = self.config.to_gateway_registration_info(
+            route_hints,
+            None,
+            false,
+            false,
+            Duration::ZERO,
+        );
+        let description = match description_hash {
+            Some(hash) => InvoiceDescription::Hash(hash),
+            None => InvoiceDescription::Direct(gateway_offer.description),
+        };
+        let invoice = self.build_gateway_invoice(
+            &gateway,
+            invoice_amount,
+            description,
+            payment_hash,
+            PaymentSecret(rng.gen()),
+            None,
+            MIN_FINAL_CLTV_EXPIRY,
+            &mut rng,
+        )?;
+
+        let mut tx = TransactionBuilder::default();
+        let offer_output = self
+            .ln_client()
+            .create_encrypted_offer_output(
+                amount,
+                payment_hash,
+                encrypted_preimage,
+                Some(invoice_expires_at(&invoice)),
+                None,
+                IncomingContractVersion::V1,
+            )
+            .await?;
+        tx.output(Output::LN(offer_output));
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        self.context
             .api
-            .await_output_outcome::<Preimage>(
-                outpoint,
-                Duration::from_secs(10),
+            .await_output_outcome::<OfferId>(
+                OutPoint { txid, out_idx: 0 },
+                Duration::from_secs(15),
                 &self.context.decoders,
             )
-            .await?)
+            .await?;
+
+        Ok(invoice)
+    }
+
+    /// Maps a username to a reusable offer we answer invoice requests for, so payments to the
+    /// Lightning address of that username are received via the offer
+    ///
+    /// Only the owner of the offer may register usernames for it.
+    pub async fn register_lightning_address(
+        &self,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let gateway_offer = dbtx
+            .get_value(&GatewayOfferKey(payload.offer_id))
+            .await
+            .expect("DB error")
+            .ok_or(ClientError::UnknownReusableOffer)?;
+        if !payload.verify(&self.context.secp, &gateway_offer.offer_key) {
+            return Err(ClientError::InvalidSignature);
+        }
+
+        match dbtx
+            .get_value(&LightningAddressKey(payload.username.clone()))
+            .await
+            .expect("DB error")
+        {
+            Some(registered) if registered == payload.offer_id => return Ok(()),
+            Some(_) => return Err(ClientError::LightningAddressTaken),
+            None => {}
+        }
+
+        dbtx.insert_new_entry(&LightningAddressKey(payload.username), &payload.offer_id)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+        Ok(())
+    }
+
+    /// Looks up the reusable offer the Lightning address of `username` pays to
+    pub async fn lookup_lightning_address(
+        &self,
+        username: &str,
+    ) -> Option<(ReusableOfferId, GatewayOffer)> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let offer_id = dbtx
+            .get_value(&LightningAddressKey(username.to_owned()))
+            .await
+            .expect("DB error")?;
+        let offer = dbtx
+            .get_value(&GatewayOfferKey(offer_id))
+            .await
+            .expect("DB error")?;
+        Some((offer_id, offer))
+    }
+
+    /// Claw back funds after incoming contract that had invalid preimage
+    #[instrument(name = "Client::refund_incoming_contract", skip(self, rng))]
+    pub async fn refund_incoming_contract(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<TransactionId> {
+        self.refund_funded_incoming_contract(contract_id, self.config.redeem_key, rng)
+            .await
+    }
+
+    /// Wait for the preimage of an incoming contract we funded to be decrypted
+    ///
+    /// Held contracts are only decrypted once their recipient accepted the payment. If they didn't
+    /// by the contract's timelock we claim back our funds and fail with
+    /// [`ClientError::HoldInvoiceNotAccepted`].
+    #[instrument(name = "Client::await_incoming_contract_preimage", skip(self, rng))]
+    pub async fn await_incoming_contract_preimage(
+        &self,
+        outpoint: OutPoint,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<Preimage> {
+        self.await_funded_incoming_contract_preimage(
+            outpoint,
+            contract_id,
+            self.config.redeem_key,
+            rng,
+        )
+        .await
     }
 
     // TODO: improve error propagation on tx transmission
@@ -1391,13 +3116,13 @@ impl Client<GatewayClientConfig> {
                 &self.context.decoders,
             )
             .await?;
-        // We remove the entry that indicates we are still waiting for transaction
+        // We remove the payment that indicates we are still waiting for transaction
         // confirmation. This does not mean we are finished yet. As a last step we need
         // to fetch the blind signatures for the newly issued notes, but as long as the
         // federation is honest as a whole they will produce the signatures, so we don't
         // have to worry
         let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.remove_entry(&OutgoingPaymentClaimKey(contract_id))
+        dbtx.remove_entry(&GatewayPaymentKey(contract_id))
             .await
             .expect("DB error");
         dbtx.commit_tx().await.expect("DB Error");
@@ -1413,11 +3138,18 @@ impl Client<GatewayClientConfig> {
             .collect()
     }
 
-    /// Register this gateway with the federation
+    /// Register this gateway with the federation, signing the registration with our redeem key
     pub async fn register_with_federation(&self, config: LightningGateway) -> Result<()> {
+        let signature = self.context.secp.sign_schnorr(
+            &config.registration_message().into(),
+            &self.config.redeem_key,
+        );
         self.context
             .api
-            .register_gateway(&config)
+            .register_gateway(&SignedLightningGateway {
+                gateway: config,
+                signature,
+            })
             .await
             .map_err(ClientError::MintApiError)
     }
@@ -1444,6 +3176,32 @@ impl Debug for ClientSecret {
     }
 }
 
+/// Registers a reusable offer with `gateway` or tops up its payments
+async fn register_offer_with_gateway(
+    gateway: &LightningGateway,
+    payload: &RegisterOfferPayload,
+) -> Result<RegisterOfferResponse> {
+    Ok(reqwest::Client::new()
+        .post(
+            gateway
+                .api
+                .join("register_offer")
+                .expect("'register_offer' contains no invalid characters for a URL")
+                .as_str(),
+        )
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Unix time in seconds at which the invoice expires, the federation drops our offer from then on
+fn invoice_expires_at(invoice: &Invoice) -> u64 {
+    (invoice.duration_since_epoch() + invoice.expiry_time()).as_secs()
+}
+
 /// Builds a fake module registry which is only usable for decoding messages since the client isn't
 /// modularized yet but we need the decoding functionality.
 pub fn module_decode_stubs() -> ModuleDecoderRegistry {
@@ -1491,6 +3249,12 @@ pub mod serde_keypair {
     }
 }
 
+/// What an invoice built by [`Client::build_gateway_invoice`] commits to as its description
+enum InvoiceDescription {
+    Direct(String),
+    Hash(sha256::Hash),
+}
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Error querying federation: {0}")]
@@ -1521,6 +3285,10 @@ pub enum ClientError {
     InvoiceMissingAmount,
     #[error("Outgoing contract is underfunded, wants us to pay {0}, but only contains {1}")]
     Underfunded(Amount, Amount),
+    #[error("Invalid amount {0} to pay for a part of the invoice")]
+    InvalidPartAmount(Amount),
+    #[error("The invoice doesn't support being paid in several parts")]
+    MultiPartNotSupported,
     #[error("The contract's timeout is in the past or does not allow for a safety margin")]
     TimeoutTooClose,
     #[error("No offer")]
@@ -1541,6 +3309,10 @@ pub enum ClientError {
     GatewayNotFound,
     #[error("HTTP Error {0}")]
     HttpError(#[from] reqwest::Error),
+    #[error("The invoice issued by the gateway doesn't match our request")]
+    InvalidGatewayInvoice,
+    #[error("No outgoing payment is being processed for this contract")]
+    UnknownOutgoingPayment,
     #[error("Outgoing payment timeout")]
     OutgoingPaymentTimeout,
     #[error("Invalid amount tier {0:?}")]
@@ -1561,12 +3333,26 @@ pub enum ClientError {
     DeleteUnknownOutgoingContract,
     #[error("Timeout")]
     Timeout,
+    #[error("Transaction was rejected: {0}")]
+    TransactionRejected(String),
     #[error("Failed to spend ecash, all spend attempts re-used an ecash note")]
     SpendReusedNote,
     #[error("The contract is already cancelled and can't be processed by the gateway")]
     CancelledContract,
     #[error("The client config cannot be verified because {0:?}")]
     ConfigVerify(ConfigVerifyError),
+    #[error("Unknown reusable offer")]
+    UnknownReusableOffer,
+    #[error("The reusable offer has no payments left, its owner has to top it up")]
+    NoOfferPaymentsLeft,
+    #[error("Lightning address is already taken by another offer")]
+    LightningAddressTaken,
+    #[error("The incoming contract doesn't belong to one of our hold invoices")]
+    NotHoldInvoice,
+    #[error("The recipient didn't accept the held payment in time, we got a refund")]
+    HoldInvoiceNotAccepted,
+    #[error("No internal payment is being processed for this contract")]
+    UnknownInternalPayment,
 }
 
 #[derive(Debug, Error)]
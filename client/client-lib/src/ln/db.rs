@@ -1,12 +1,16 @@
+use bitcoin_hashes::sha256::Hash as Sha256Hash;
 use fedimint_api::db::DatabaseKeyPrefixConst;
 use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::OutPoint;
 use fedimint_core::modules::ln::contracts::ContractId;
 use fedimint_core::modules::ln::LightningGateway;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use super::history::{LightningPayment, PaymentDirection};
 use super::incoming::ConfirmedInvoice;
-use super::outgoing::OutgoingContractAccount;
+use super::offer::{GatewayOffer, ReusableOffer, ReusableOfferId};
+use super::outgoing::{GatewayPayment, InternalPaymentData, OutgoingContractAccount};
 use crate::ln::outgoing::OutgoingContractData;
 
 #[repr(u8)]
@@ -17,6 +21,13 @@ pub enum DbKeyPrefix {
     OutgoingContractAccount = 0x25,
     ConfirmedInvoice = 0x26,
     LightningGateway = 0x28,
+    ReusableOffer = 0x2c,
+    GatewayOffer = 0x2d,
+    LightningAddress = 0x2e,
+    GatewayPayment = 0x2f,
+    InternalPayment = 0x30,
+    LightningPayment = 0x31,
+    OutgoingRefund = 0x32,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -43,6 +54,8 @@ impl DatabaseKeyPrefixConst for OutgoingPaymentKeyPrefix {
     type Value = OutgoingContractData;
 }
 
+/// Outgoing contract a gateway submitted the claim of, only kept to migrate it to
+/// [`GatewayPaymentKey`]
 #[derive(Debug, Encodable, Decodable, Serialize)]
 pub struct OutgoingPaymentClaimKey(pub ContractId);
 
@@ -61,6 +74,8 @@ impl DatabaseKeyPrefixConst for OutgoingPaymentClaimKeyPrefix {
     type Value = ();
 }
 
+/// Outgoing contract a gateway was paying the invoice of, only kept to migrate it to
+/// [`GatewayPaymentKey`]
 #[derive(Debug, Encodable, Decodable, Serialize)]
 pub struct OutgoingContractAccountKey(pub ContractId);
 
@@ -114,3 +129,143 @@ impl DatabaseKeyPrefixConst for LightningGatewayKeyPrefix {
     type Key = LightningGatewayKey;
     type Value = LightningGateway;
 }
+
+/// Reusable offer created by us that we receive payments to
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ReusableOfferKey(pub ReusableOfferId);
+
+impl DatabaseKeyPrefixConst for ReusableOfferKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::ReusableOffer as u8;
+    type Key = Self;
+    type Value = ReusableOffer;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ReusableOfferKeyPrefix;
+
+impl DatabaseKeyPrefixConst for ReusableOfferKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::ReusableOffer as u8;
+    type Key = ReusableOfferKey;
+    type Value = ReusableOffer;
+}
+
+/// Reusable offer of a user that we as a gateway answer invoice requests for
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct GatewayOfferKey(pub ReusableOfferId);
+
+impl DatabaseKeyPrefixConst for GatewayOfferKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::GatewayOffer as u8;
+    type Key = Self;
+    type Value = GatewayOffer;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayOfferKeyPrefix;
+
+impl DatabaseKeyPrefixConst for GatewayOfferKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::GatewayOffer as u8;
+    type Key = GatewayOfferKey;
+    type Value = GatewayOffer;
+}
+
+/// Username of a Lightning address we as a gateway serve, mapped to the offer it pays to
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LightningAddressKey(pub String);
+
+impl DatabaseKeyPrefixConst for LightningAddressKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::LightningAddress as u8;
+    type Key = Self;
+    type Value = ReusableOfferId;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LightningAddressKeyPrefix;
+
+impl DatabaseKeyPrefixConst for LightningAddressKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::LightningAddress as u8;
+    type Key = LightningAddressKey;
+    type Value = ReusableOfferId;
+}
+
+/// Outgoing payment we as a gateway are processing, removed once it completed or was aborted
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct GatewayPaymentKey(pub ContractId);
+
+impl DatabaseKeyPrefixConst for GatewayPaymentKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::GatewayPayment as u8;
+    type Key = Self;
+    type Value = GatewayPayment;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayPaymentKeyPrefix;
+
+impl DatabaseKeyPrefixConst for GatewayPaymentKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::GatewayPayment as u8;
+    type Key = GatewayPaymentKey;
+    type Value = GatewayPayment;
+}
+
+/// Payment to a member of our federation we made without a gateway, removed once it completed
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct InternalPaymentKey(pub ContractId);
+
+impl DatabaseKeyPrefixConst for InternalPaymentKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::InternalPayment as u8;
+    type Key = Self;
+    type Value = InternalPaymentData;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InternalPaymentKeyPrefix;
+
+impl DatabaseKeyPrefixConst for InternalPaymentKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::InternalPayment as u8;
+    type Key = InternalPaymentKey;
+    type Value = InternalPaymentData;
+}
+
+/// Receipt of a payment in our payment history, which is never pruned
+///
+/// Receipts are kept per contract, so paying an invoice again after a refund adds a new one.
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LightningPaymentKey {
+    pub payment_hash: Sha256Hash,
+    pub direction: PaymentDirection,
+    /// Contract funding the payment, the first part's for multi-part payments
+    pub contract_id: ContractId,
+}
+
+impl DatabaseKeyPrefixConst for LightningPaymentKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::LightningPayment as u8;
+    type Key = Self;
+    type Value = LightningPayment;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LightningPaymentKeyPrefix;
+
+impl DatabaseKeyPrefixConst for LightningPaymentKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::LightningPayment as u8;
+    type Key = LightningPaymentKey;
+    type Value = LightningPayment;
+}
+
+/// Refund of an outgoing contract we submitted, to tell it apart from a claim by the gateway
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OutgoingRefundKey(pub ContractId);
+
+impl DatabaseKeyPrefixConst for OutgoingRefundKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::OutgoingRefund as u8;
+    type Key = Self;
+    type Value = OutPoint;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OutgoingRefundKeyPrefix;
+
+impl DatabaseKeyPrefixConst for OutgoingRefundKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::OutgoingRefund as u8;
+    type Key = OutgoingRefundKey;
+    type Value = OutPoint;
+}
@@ -0,0 +1,238 @@
+use std::cmp::Reverse;
+
+use fedimint_api::config::FederationId;
+use fedimint_api::time::SystemTime;
+use fedimint_api::Amount;
+use fedimint_core::modules::ln::{GatewayFees, LightningGateway};
+use serde::{Deserialize, Serialize};
+
+/// Payment amount used to compare the fees of different gateways
+const FEE_REFERENCE_AMOUNT: Amount = Amount::from_sats(100_000);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InfoPayload;
+
+/// Information a gateway reports to its operator about one of its federations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationInfo {
+    pub federation_id: FederationId,
+    pub mint_pubkey: secp256k1_zkp::XOnlyPublicKey,
+    /// E-cash held by the gateway in this federation, limiting the incoming payments it can fund
+    #[serde(default)]
+    pub balance: Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GatewayInfo {
+    pub version_hash: String,
+    pub federations: Vec<FederationInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProbePayload;
+
+/// Publicly available information a gateway reports about one of its federations, only meant to
+/// show clients that it is alive and which fees it currently charges
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationProbe {
+    pub federation_id: FederationId,
+    pub mint_pubkey: secp256k1_zkp::XOnlyPublicKey,
+    pub fees: GatewayFees,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GatewayProbe {
+    pub federations: Vec<FederationProbe>,
+}
+
+/// A registered gateway together with the result of probing its `/probe` endpoint
+#[derive(Debug, Clone)]
+pub struct GatewayCandidate {
+    pub gateway: LightningGateway,
+    /// `None` if the gateway could not be reached or doesn't serve our federation with the key and
+    /// fees it registered
+    pub info: Option<FederationProbe>,
+}
+
+/// Orders the still valid gateways from best to worst
+///
+/// Gateways that answered the probe are always preferred over unreachable ones. Among those the
+/// cheapest one for a [`FEE_REFERENCE_AMOUNT`] payment in both directions wins, ties are broken by
+/// the channel liquidity the gateways registered.
+pub fn rank_gateways(candidates: Vec<GatewayCandidate>, now: SystemTime) -> Vec<LightningGateway> {
+    let mut candidates = candidates
+        .into_iter()
+        .filter(|candidate| candidate.gateway.valid_until > now)
+        .collect::<Vec<_>>();
+
+    candidates.sort_by_key(|candidate| {
+        let fees = candidate.gateway.fees;
+        let total_fee =
+            fees.incoming.amount(FEE_REFERENCE_AMOUNT) + fees.outgoing.amount(FEE_REFERENCE_AMOUNT);
+        (
+            candidate.info.is_none(),
+            total_fee,
+            Reverse(
+                candidate
+                    .gateway
+                    .liquidity
+                    .map(|liquidity| liquidity.outbound + liquidity.inbound),
+            ),
+        )
+    });
+
+    candidates
+        .into_iter()
+        .map(|candidate| candidate.gateway)
+        .collect()
+}
+
+/// Returns the gateway to switch to for a payment `can_route` checks the liquidity for, or `None`
+/// to keep the `active` one
+///
+/// We only switch if the active gateway can't route the payment, to the best `ranked` gateway that
+/// can. The liquidity gateways report is only a coarse estimate, so we keep the active gateway if
+/// none of them seems to be able to route the payment.
+pub fn switch_gateway_for_payment(
+    active: &LightningGateway,
+    ranked: Vec<LightningGateway>,
+    can_route: impl Fn(&LightningGateway) -> bool,
+) -> Option<LightningGateway> {
+    if can_route(active) {
+        return None;
+    }
+    ranked.into_iter().find(|gateway| can_route(gateway))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::modules::ln::{GatewayLiquidity, RoutingFee};
+    use url::Url;
+
+    use super::*;
+
+    fn gateway(
+        index: u8,
+        proportional_millionths: u32,
+        liquidity: Amount,
+        valid_until: SystemTime,
+    ) -> LightningGateway {
+        LightningGateway {
+            mint_channel_id: 0,
+            mint_pub_key: secp256k1_zkp::XOnlyPublicKey::from_slice(&[42; 32][..]).unwrap(),
+            node_pub_key: secp256k1_zkp::PublicKey::from_slice(&[2; 33][..]).unwrap(),
+            api: Url::parse(&format!("http://gateway{index}.example.com")).unwrap(),
+            route_hints: vec![],
+            fees: GatewayFees {
+                incoming: Default::default(),
+                outgoing: RoutingFee {
+                    base_msat: 0,
+                    proportional_millionths,
+                },
+            },
+            valid_until,
+            liquidity: Some(GatewayLiquidity {
+                outbound: liquidity,
+                inbound: Amount::ZERO,
+            }),
+            issues_invoices: false,
+            supports_mpp: false,
+        }
+    }
+
+    fn probe(gateway: &LightningGateway) -> Option<FederationProbe> {
+        Some(FederationProbe {
+            federation_id: FederationId::dummy(),
+            mint_pubkey: gateway.mint_pub_key,
+            fees: gateway.fees,
+        })
+    }
+
+    fn candidate(gateway: LightningGateway, reachable: bool) -> GatewayCandidate {
+        GatewayCandidate {
+            info: reachable.then(|| probe(&gateway)).flatten(),
+            gateway,
+        }
+    }
+
+    #[test]
+    fn ranks_by_liveness_fees_and_capacity() {
+        let now = SystemTime::now();
+        let valid = now + Duration::from_secs(600);
+        let expired = now - Duration::from_secs(1);
+
+        let ranked = rank_gateways(
+            vec![
+                candidate(gateway(0, 0, Amount::from_sats(1_000_000), valid), false),
+                candidate(gateway(1, 0, Amount::from_sats(1_000_000), expired), true),
+                candidate(gateway(2, 1000, Amount::from_sats(1_000), valid), true),
+                candidate(gateway(3, 100, Amount::from_sats(1_000), valid), true),
+                candidate(gateway(4, 100, Amount::from_sats(2_000), valid), true),
+            ],
+            now,
+        );
+
+        let ranked_hosts = ranked
+            .iter()
+            .map(|gateway| gateway.api.host_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranked_hosts,
+            vec![
+                "gateway4.example.com",
+                "gateway3.example.com",
+                "gateway2.example.com",
+                "gateway0.example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn switches_gateway_only_if_active_one_lacks_liquidity() {
+        let valid = SystemTime::now() + Duration::from_secs(600);
+        let active = gateway(0, 0, Amount::from_sats(1_000), valid);
+        let ranked = vec![
+            gateway(1, 0, Amount::from_sats(10_000), valid),
+            gateway(2, 100, Amount::from_sats(100_000), valid),
+        ];
+        let host = |gateway: Option<LightningGateway>| {
+            gateway.map(|gateway| gateway.api.host_str().unwrap().to_owned())
+        };
+
+        fn can_send(amount: Amount) -> impl Fn(&LightningGateway) -> bool {
+            move |gateway| gateway.can_send(amount)
+        }
+
+        // The active gateway is kept even if better ones are registered
+        assert_eq!(
+            switch_gateway_for_payment(&active, ranked.clone(), can_send(Amount::from_sats(1_000))),
+            None
+        );
+
+        // Otherwise we take the best ranked gateway with enough liquidity
+        assert_eq!(
+            host(switch_gateway_for_payment(
+                &active,
+                ranked.clone(),
+                can_send(Amount::from_sats(5_000))
+            )),
+            Some("gateway1.example.com".to_owned())
+        );
+        assert_eq!(
+            host(switch_gateway_for_payment(
+                &active,
+                ranked.clone(),
+                can_send(Amount::from_sats(50_000))
+            )),
+            Some("gateway2.example.com".to_owned())
+        );
+
+        // No gateway seems to be able to route the payment, so we keep trying the active one
+        assert_eq!(
+            switch_gateway_for_payment(&active, ranked, can_send(Amount::from_sats(500_000))),
+            None
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use bitcoin::secp256k1;
+use bitcoin_hashes::sha256::Hash as Sha256Hash;
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::time::SystemTime;
+use fedimint_api::Amount;
+use fedimint_core::modules::ln::contracts::Preimage;
+use lightning_invoice::Invoice;
+use serde::Serialize;
+
+/// Whether we paid or received a Lightning payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    /// The payment is still in flight or, for incoming payments, wasn't claimed yet
+    Pending,
+    /// The invoice got paid, incoming payments were claimed
+    Succeeded,
+    /// The payment failed and our funds were returned to us
+    Refunded,
+    /// The payment failed and we couldn't get our funds back yet
+    Failed,
+}
+
+/// Receipt of a Lightning payment that is kept in our payment history after it completed
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct LightningPayment {
+    pub direction: PaymentDirection,
+    pub invoice: Invoice,
+    /// Amount the recipient receives, excluding fees
+    pub amount: Amount,
+    /// Fees paid to gateways on top of `amount`, for incoming payments they were paid by the payer
+    pub fee: Amount,
+    /// Node public keys of the gateways routing the payment, empty if it never left the federation
+    pub gateways: Vec<secp256k1::PublicKey>,
+    /// Only known for payments we received or paid to members of our own federation
+    pub preimage: Option<Preimage>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+    pub status: PaymentStatus,
+}
+
+impl LightningPayment {
+    /// Receipt for a payment that was just initiated
+    pub fn new(
+        direction: PaymentDirection,
+        invoice: Invoice,
+        amount: Amount,
+        fee: Amount,
+        gateways: Vec<secp256k1::PublicKey>,
+    ) -> Self {
+        let now = SystemTime::now();
+        LightningPayment {
+            direction,
+            invoice,
+            amount,
+            fee,
+            gateways,
+            preimage: None,
+            created_at: now,
+            updated_at: now,
+            status: PaymentStatus::Pending,
+        }
+    }
+
+    pub fn payment_hash(&self) -> Sha256Hash {
+        *self.invoice.payment_hash()
+    }
+}
+
+/// Node public key of the gateway that receives payments to an invoice issued for one of our
+/// offers, which is either the last hop of its route hints or the payee itself
+pub fn receiving_gateway(invoice: &Invoice) -> secp256k1::PublicKey {
+    invoice
+        .route_hints()
+        .first()
+        .and_then(|rh| rh.0.last())
+        .map(|hop| hop.src_node_id)
+        .unwrap_or_else(|| invoice.recover_payee_pub_key())
+}
@@ -1,12 +1,16 @@
-use bitcoin::secp256k1::KeyPair;
+use bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey, Signing};
+use bitcoin_hashes::sha256::Hash as Sha256;
+use bitcoin_hashes::{Hash as BitcoinHash, HashEngine};
 use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::Amount;
-use fedimint_core::modules::ln::contracts::incoming::IncomingContract;
-use fedimint_core::modules::ln::contracts::{ContractId, IdentifyableContract};
+use fedimint_core::modules::ln::contracts::incoming::{IncomingContract, IncomingContractVersion};
+use fedimint_core::modules::ln::contracts::{ContractId, IdentifyableContract, Preimage};
 use fedimint_core::modules::ln::LightningInput;
 use lightning_invoice::Invoice;
 use serde::Serialize;
 
+const HOLD_ACCEPT_KEY_TAG: &[u8] = b"hold invoice accept key";
+
 #[derive(Debug, Clone, Encodable, Decodable)]
 pub struct IncomingContractAccount {
     pub amount: Amount,
@@ -21,6 +25,21 @@ impl IncomingContractAccount {
             witness: None,
         }
     }
+
+    /// Claims the decrypted contract, revealing the claim key if the contract version requires it
+    pub fn claim_with_key(&self, claim_key: &KeyPair) -> LightningInput {
+        let witness = match self.contract.version {
+            IncomingContractVersion::V0 => None,
+            IncomingContractVersion::V1 => {
+                Some(Preimage(claim_key.x_only_public_key().0.serialize()))
+            }
+        };
+
+        LightningInput {
+            witness,
+            ..self.claim()
+        }
+    }
 }
 
 // TODO: should this have some kind of "state" enum - e.g. pending, paid, expired
@@ -48,3 +67,15 @@ impl ConfirmedInvoice {
         (*self.invoice.payment_hash()).into()
     }
 }
+
+/// Keypair accepting the payment of a hold invoice, derived from its payment keypair
+///
+/// The offer publishes the accept key, so it must not be the payment key that is the preimage.
+pub fn hold_accept_keypair<C: Signing>(secp: &Secp256k1<C>, payment_keypair: &KeyPair) -> KeyPair {
+    let mut engine = Sha256::engine();
+    engine.input(HOLD_ACCEPT_KEY_TAG);
+    engine.input(&SecretKey::from_keypair(payment_keypair).secret_bytes());
+    let secret_key = SecretKey::from_slice(&Sha256::from_engine(engine).into_inner())
+        .expect("hash is a valid secret key with overwhelming probability");
+    KeyPair::from_secret_key(secp, &secret_key)
+}
@@ -1,6 +1,9 @@
 // TODO: once user and mint client are merged, make this private again
 pub mod db;
+pub mod gateway;
+pub mod history;
 pub mod incoming;
+pub mod offer;
 pub mod outgoing;
 
 use std::sync::Arc;
@@ -11,12 +14,18 @@ use fedimint_api::config::FederationId;
 use fedimint_api::core::client::ClientModule;
 use fedimint_api::db::DatabaseTransaction;
 use fedimint_api::module::TransactionItemAmount;
-use fedimint_api::task::timeout;
+use fedimint_api::task::{spawn_blocking, timeout};
+use fedimint_api::time::SystemTime;
 use fedimint_api::Amount;
 use fedimint_core::modules::ln::common::LightningDecoder;
-use fedimint_core::modules::ln::config::LightningClientConfig;
-use fedimint_core::modules::ln::contracts::incoming::IncomingContractOffer;
-use fedimint_core::modules::ln::contracts::outgoing::OutgoingContract;
+use fedimint_core::modules::ln::config::{
+    LightningClientConfig, MAX_OFFER_PROOF_OF_WORK_DIFFICULTY,
+};
+use fedimint_core::modules::ln::contracts::incoming::{
+    DecryptedPreimage, IncomingContract, IncomingContractHold, IncomingContractOffer,
+    IncomingContractVersion,
+};
+use fedimint_core::modules::ln::contracts::outgoing::{OutgoingContract, PrunedInvoice};
 use fedimint_core::modules::ln::contracts::{
     Contract, ContractId, EncryptedPreimage, FundedContract, IdentifyableContract, Preimage,
 };
@@ -32,9 +41,13 @@ use thiserror::Error;
 use self::db::ConfirmedInvoiceKey;
 use self::incoming::ConfirmedInvoice;
 use crate::api::{FederationError, LnFederationApi, WalletFederationApi};
-use crate::ln::db::{OutgoingPaymentKey, OutgoingPaymentKeyPrefix};
+use crate::ln::db::{
+    InternalPaymentKey, LightningPaymentKey, LightningPaymentKeyPrefix, OutgoingPaymentKey,
+    OutgoingPaymentKeyPrefix,
+};
+use crate::ln::history::{LightningPayment, PaymentDirection, PaymentStatus};
 use crate::ln::incoming::IncomingContractAccount;
-use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
+use crate::ln::outgoing::{InternalPaymentData, OutgoingContractAccount, OutgoingContractData};
 use crate::utils::ClientContext;
 
 #[derive(Debug)]
@@ -65,12 +78,12 @@ impl ClientModule for LnClient {
                 amount: account_output.amount,
                 fee: self.config.fee_consensus.contract_output,
             },
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
-                TransactionItemAmount {
-                    amount: Amount::ZERO,
-                    fee: Amount::ZERO,
-                }
-            }
+            LightningOutput::Offer(_)
+            | LightningOutput::CancelOutgoing { .. }
+            | LightningOutput::AcceptIncoming { .. } => TransactionItemAmount {
+                amount: Amount::ZERO,
+                fee: Amount::ZERO,
+            },
         }
     }
 }
@@ -85,17 +98,32 @@ impl LnClient {
         invoice: Invoice,
         gateway: &LightningGateway,
         timelock: u32,
-        mut rng: impl RngCore + CryptoRng + 'a,
+        rng: impl RngCore + CryptoRng + 'a,
     ) -> Result<LightningOutput> {
-        let contract_amount = {
-            let invoice_amount_msat = invoice
+        let invoice_amount = Amount::from_msats(
+            invoice
                 .amount_milli_satoshis()
-                .ok_or(LnClientError::MissingInvoiceAmount)?;
-            // TODO: better define fee handling
-            // Add 1% fee margin
-            let contract_amount_msat = invoice_amount_msat + (invoice_amount_msat / 100);
-            Amount::from_msats(contract_amount_msat)
-        };
+                .ok_or(LnClientError::MissingInvoiceAmount)?,
+        );
+        self.create_outgoing_part_output(dbtx, invoice, invoice_amount, gateway, timelock, rng)
+            .await
+    }
+
+    /// Creates an outgoing contract for `gateway` to pay `amount` of the invoice, which may be
+    /// less than its total if the payment is split into several parts
+    pub async fn create_outgoing_part_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransaction<'b>,
+        invoice: Invoice,
+        amount: Amount,
+        gateway: &LightningGateway,
+        timelock: u32,
+        mut rng: impl RngCore + CryptoRng + 'a,
+    ) -> Result<LightningOutput> {
+        // TODO: better define fee handling
+        // Add 1% fee margin for routing fees on the Lightning network
+        let contract_amount =
+            amount + Amount::from_msats(amount.msats / 100) + gateway.fees.outgoing.amount(amount);
 
         let user_sk = bitcoin::KeyPair::new(&self.context.secp, &mut rng);
 
@@ -104,7 +132,7 @@ impl LnClient {
             gateway_key: gateway.mint_pub_key,
             timelock,
             user_key: user_sk.x_only_public_key().0,
-            invoice,
+            invoice: PrunedInvoice::from(&invoice),
             cancelled: false,
         };
 
@@ -129,6 +157,44 @@ impl LnClient {
         }))
     }
 
+    /// Create an output that funds the incoming contract of `offer` directly, paying an invoice
+    /// issued by a member of our own federation without routing it through a gateway. The
+    /// contract can be claimed back with the key we store if the preimage turns out invalid.
+    pub async fn create_internal_payment_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut DatabaseTransaction<'b>,
+        invoice: Invoice,
+        offer: IncomingContractOffer,
+        hold: Option<IncomingContractHold>,
+        mut rng: impl RngCore + CryptoRng + 'a,
+    ) -> Result<LightningOutput> {
+        let funder_key = bitcoin::KeyPair::new(&self.context.secp, &mut rng);
+
+        let contract = IncomingContract {
+            hash: offer.hash,
+            encrypted_preimage: offer.encrypted_preimage,
+            decrypted_preimage: DecryptedPreimage::Pending,
+            gateway_key: funder_key.x_only_public_key().0,
+            hold,
+            version: offer.version,
+        };
+
+        dbtx.insert_entry(
+            &InternalPaymentKey(contract.contract_id()),
+            &InternalPaymentData {
+                funder_key,
+                invoice,
+            },
+        )
+        .await
+        .expect("DB Error");
+
+        Ok(LightningOutput::Contract(ContractOutput {
+            amount: offer.amount,
+            contract: Contract::Incoming(contract),
+        }))
+    }
+
     pub async fn get_contract_account(&self, id: ContractId) -> Result<ContractAccount> {
         timeout(Duration::from_secs(30), self.context.api.fetch_contract(id))
             .await
@@ -188,6 +254,33 @@ impl LnClient {
             _ => Err(LnClientError::WrongAccountType),
         }
     }
+
+    /// Fetches an incoming contract that may not have been funded yet, returning `None` instead of
+    /// waiting for it if the federation doesn't know it
+    pub async fn find_incoming_contract(
+        &self,
+        id: ContractId,
+    ) -> Result<Option<IncomingContractAccount>> {
+        let account = timeout(
+            Duration::from_secs(30),
+            self.context.api.fetch_contract_if_exists(id),
+        )
+        .await
+        .map_err(|_e| LnClientError::Timeout)?
+        .map_err(LnClientError::ApiError)?;
+
+        match account {
+            None => Ok(None),
+            Some(ContractAccount {
+                amount,
+                contract: FundedContract::Incoming(c),
+            }) => Ok(Some(IncomingContractAccount {
+                amount,
+                contract: c.contract,
+            })),
+            Some(_) => Err(LnClientError::WrongAccountType),
+        }
+    }
     pub async fn refundable_outgoing_contracts(
         &self,
         block_height: u64,
@@ -224,22 +317,67 @@ impl LnClient {
         )
     }
 
-    pub fn create_offer_output(
+    pub async fn create_offer_output(
         &self,
         amount: Amount,
         payment_hash: Sha256Hash,
         payment_secret: Preimage,
         expiry_time: Option<u64>,
-    ) -> LightningOutput {
-        LightningOutput::Offer(IncomingContractOffer {
+        hold_key: Option<secp256k1_zkp::XOnlyPublicKey>,
+        version: IncomingContractVersion,
+    ) -> Result<LightningOutput> {
+        self.create_encrypted_offer_output(
+            amount,
+            payment_hash,
+            EncryptedPreimage::new(payment_secret, &self.config.threshold_pub_key),
+            expiry_time,
+            hold_key,
+            version,
+        )
+        .await
+    }
+
+    /// Like [`LnClient::create_offer_output`] for a preimage someone else encrypted, e.g. the
+    /// recipient of a payment to a reusable offer
+    pub async fn create_encrypted_offer_output(
+        &self,
+        amount: Amount,
+        payment_hash: Sha256Hash,
+        encrypted_preimage: EncryptedPreimage,
+        expiry_time: Option<u64>,
+        hold_key: Option<secp256k1_zkp::XOnlyPublicKey>,
+        version: IncomingContractVersion,
+    ) -> Result<LightningOutput> {
+        let mut offer = IncomingContractOffer {
             amount,
             hash: payment_hash,
-            encrypted_preimage: EncryptedPreimage::new(
-                payment_secret,
-                &self.config.threshold_pub_key,
-            ),
+            encrypted_preimage,
             expiry_time,
+            hold_key,
+            version,
+            nonce: 0,
+        };
+        // Offers don't require any inputs, so the federation may demand some work for them instead.
+        // If the difficulty rises with the offers of the current epoch we have to ask for it.
+        let rate_limit = &self.config.offer_rate_limit;
+        let difficulty = if rate_limit.offers_per_difficulty_bit.is_some() {
+            self.context
+                .api
+                .fetch_offer_difficulty()
+                .await
+                .map_err(LnClientError::ApiError)?
+        } else {
+            rate_limit.proof_of_work_difficulty
+        };
+        if difficulty > MAX_OFFER_PROOF_OF_WORK_DIFFICULTY {
+            return Err(LnClientError::ProofOfWorkTooDifficult(difficulty));
+        }
+        let offer = spawn_blocking(move || {
+            offer.solve_proof_of_work(difficulty);
+            offer
         })
+        .await;
+        Ok(LightningOutput::Offer(offer))
     }
 
     pub async fn get_offer(&self, payment_hash: Sha256Hash) -> Result<IncomingContractOffer> {
@@ -281,6 +419,62 @@ impl LnClient {
         Ok(confirmed_invoice)
     }
 
+    /// Adds the receipt of a payment we just initiated with the contract `contract_id` to our
+    /// payment history
+    pub async fn save_payment(&self, contract_id: ContractId, payment: &LightningPayment) {
+        let key = LightningPaymentKey {
+            payment_hash: payment.payment_hash(),
+            direction: payment.direction,
+            contract_id,
+        };
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&key, payment).await.expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+    }
+
+    /// Updates the status of a payment in our payment history, payments we have no receipt for
+    /// are ignored
+    pub async fn update_payment(
+        &self,
+        payment_hash: Sha256Hash,
+        direction: PaymentDirection,
+        contract_id: ContractId,
+        status: PaymentStatus,
+        preimage: Option<Preimage>,
+    ) {
+        let key = LightningPaymentKey {
+            payment_hash,
+            direction,
+            contract_id,
+        };
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let Some(mut payment) = dbtx.get_value(&key).await.expect("DB error") else {
+            return;
+        };
+
+        payment.status = status;
+        payment.preimage = preimage.or(payment.preimage);
+        payment.updated_at = SystemTime::now();
+        dbtx.insert_entry(&key, &payment).await.expect("DB error");
+        dbtx.commit_tx().await.expect("DB Error");
+    }
+
+    /// Returns our payment history, oldest payments first
+    pub async fn list_payments(&self) -> Vec<LightningPayment> {
+        let mut payments = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&LightningPaymentKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").1)
+            .collect::<Vec<_>>()
+            .await;
+        payments.sort_by_key(|payment| payment.created_at);
+        payments
+    }
+
     /// Used by gateway to prematurely return funds to the user if the payment failed
     pub fn create_cancel_outgoing_output(
         &self,
@@ -292,12 +486,28 @@ impl LnClient {
             gateway_signature: signature,
         }
     }
+
+    /// Used by the recipient of a hold invoice to accept the payment held by the federation
+    pub fn create_accept_incoming_output(
+        &self,
+        contract_id: ContractId,
+        signature: secp256k1_zkp::schnorr::Signature,
+    ) -> LightningOutput {
+        LightningOutput::AcceptIncoming {
+            contract: contract_id,
+            signature,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PayInvoicePayload {
     pub federation_id: FederationId,
     pub contract_id: ContractId,
+    /// Amount the gateway should pay if the invoice is paid in several parts by different
+    /// gateways, `None` to pay the whole invoice
+    #[serde(default)]
+    pub part_amount: Option<Amount>,
 }
 
 impl PayInvoicePayload {
@@ -305,10 +515,50 @@ impl PayInvoicePayload {
         Self {
             contract_id,
             federation_id,
+            part_amount: None,
+        }
+    }
+
+    /// Requests to pay only `part_amount` of the contract's invoice
+    pub fn new_part(
+        federation_id: FederationId,
+        contract_id: ContractId,
+        part_amount: Amount,
+    ) -> Self {
+        Self {
+            contract_id,
+            federation_id,
+            part_amount: Some(part_amount),
         }
     }
 }
 
+/// Response of a gateway that paid an invoice on our behalf
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PayInvoiceResponse {
+    /// Proof that the invoice was paid
+    pub preimage: Preimage,
+}
+
+/// Requests an invoice from a gateway whose Lightning node has to be the payee of the payments it
+/// receives, see [`LightningGateway::issues_invoices`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateInvoicePayload {
+    pub federation_id: FederationId,
+    /// Hash of the preimage that gets decrypted by the federation once the invoice is paid
+    pub payment_hash: Sha256Hash,
+    /// Amount the payer pays, including the gateway's incoming fee
+    pub amount: Amount,
+    pub description: String,
+    pub expiry_time: Option<u64>,
+    pub min_final_cltv_expiry: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateInvoiceResponse {
+    pub invoice: Invoice,
+}
+
 pub type Result<T> = std::result::Result<T, LnClientError>;
 
 #[derive(Debug, Error)]
@@ -323,6 +573,8 @@ pub enum LnClientError {
     WrongAccountType,
     #[error("No ConfirmedOffer found for contract ID {0}")]
     NoConfirmedInvoice(ContractId),
+    #[error("The federation demands an infeasible proof of work of {0} bits for offers")]
+    ProofOfWorkTooDifficult(u8),
 }
 
 #[cfg(test)]
@@ -339,8 +591,11 @@ mod tests {
     use fedimint_api::{Amount, OutPoint, TransactionId};
     use fedimint_core::modules::ln::common::LightningDecoder;
     use fedimint_core::modules::ln::config::LightningClientConfig;
+    use fedimint_core::modules::ln::contracts::outgoing::PrunedInvoice;
     use fedimint_core::modules::ln::contracts::{ContractId, IdentifyableContract};
-    use fedimint_core::modules::ln::{Lightning, LightningGateway, LightningGen, LightningOutput};
+    use fedimint_core::modules::ln::{
+        GatewayFees, Lightning, LightningGateway, LightningGen, LightningOutput, RoutingFee,
+    };
     use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
     use fedimint_testing::FakeFed;
     use lightning_invoice::Invoice;
@@ -415,7 +670,7 @@ mod tests {
         let fed = Arc::new(tokio::sync::Mutex::new(
             FakeFed::<Lightning>::new(
                 4,
-                |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+                |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
                 &ConfigGenParams::new(),
                 &LightningGen,
                 module_id,
@@ -475,7 +730,17 @@ mod tests {
                 api: Url::parse("http://example.com")
                     .expect("Could not parse URL to generate GatewayClientConfig API endpoint"),
                 route_hints: vec![],
+                fees: GatewayFees {
+                    incoming: Default::default(),
+                    outgoing: RoutingFee {
+                        base_msat: 1000,
+                        proportional_millionths: 2000,
+                    },
+                },
                 valid_until: SystemTime::now(),
+                liquidity: None,
+                issues_invoices: false,
+                supports_mpp: false,
             }
         };
         let timelock = 42;
@@ -504,13 +769,14 @@ mod tests {
             .unwrap();
 
         assert_eq!(contract_acc.contract.contract_id(), contract.contract_id());
-        assert_eq!(contract_acc.contract.invoice, invoice);
+        assert_eq!(contract_acc.contract.invoice, PrunedInvoice::from(&invoice));
         assert_eq!(contract_acc.contract.timelock, timelock);
         assert_eq!(contract_acc.contract.hash, *invoice.payment_hash());
         assert_eq!(contract_acc.contract.gateway_key, gateway.mint_pub_key);
         // TODO: test that the client has its key
 
-        let expected_amount_msat = invoice_amt_msat + (invoice_amt_msat / 100);
+        let expected_amount_msat =
+            invoice_amt_msat + (invoice_amt_msat / 100) + 1000 + (invoice_amt_msat / 500);
         let expected_amount = Amount::from_msats(expected_amount_msat);
         assert_eq!(contract_acc.amount, expected_amount);
 
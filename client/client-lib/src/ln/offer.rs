@@ -0,0 +1,434 @@
+//! Reusable offers that let a user receive any number of payments with a single static payment
+//! code.
+//!
+//! The user registers an offer with its gateway together with a batch of [`OfferPayment`]s, each
+//! a payment hash and its preimage threshold-encrypted to the federation, derived from the offer's
+//! secret key and a sequential index. For every invoice request the gateway hands out the next
+//! payment, registers an
+//! [`IncomingContractOffer`](fedimint_core::modules::ln::contracts::incoming::IncomingContractOffer)
+//! selling its encrypted preimage and answers with an invoice for its hash. Just like for a single
+//! invoice the gateway only learns the preimage once it funded the contract and the federation
+//! decrypted it. The user later scans the indices handed to the gateway to find and claim the
+//! funded incoming contracts and tops up the gateway's payments.
+//!
+//! Our Lightning dependencies can't encode BOLT12 offers or receive invoice requests via onion
+//! messages yet, so for now invoice requests are answered through the gateway's HTTP API with
+//! BOLT11 invoices.
+
+use bitcoin_hashes::sha256::Hash as Sha256;
+use bitcoin_hashes::{hash_newtype, Hash as BitcoinHash, HashEngine};
+use fedimint_api::config::FederationId;
+use fedimint_api::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_api::Amount;
+use fedimint_core::modules::ln::contracts::{EncryptedPreimage, Preimage};
+use fedimint_core::modules::ln::LightningGateway;
+use secp256k1_zkp::schnorr::Signature;
+use secp256k1_zkp::{
+    KeyPair, Message, PublicKey, Scalar, Secp256k1, SecretKey, Signing, Verification,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Number of unused payments a gateway holds for a reusable offer, limiting how many invoices it
+/// can issue for it until the user tops them up again
+pub const OFFER_PAYMENT_BATCH: u64 = 20;
+
+const REGISTER_OFFER_TAG: &str = "register reusable offer";
+const REGISTER_LIGHTNING_ADDRESS_TAG: &str = "register lightning address";
+
+hash_newtype!(
+    ReusableOfferId,
+    Sha256,
+    32,
+    doc = "The hash of the public key of a reusable offer"
+);
+
+impl ReusableOfferId {
+    pub fn from_offer_key(offer_key: &PublicKey) -> Self {
+        ReusableOfferId::hash(&offer_key.serialize())
+    }
+}
+
+impl Encodable for ReusableOfferId {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        self.as_inner().consensus_encode(writer)
+    }
+}
+
+impl Decodable for ReusableOfferId {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(ReusableOfferId::from_inner(Decodable::consensus_decode(
+            d, modules,
+        )?))
+    }
+}
+
+/// Reusable offer created by a user, stored in their database
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ReusableOffer {
+    /// Keypair the payment keys of all payments to this offer are derived from
+    #[serde(with = "crate::serde_keypair")]
+    pub keypair: KeyPair,
+    pub description: String,
+    /// Gateway answering invoice requests for this offer
+    pub gateway: LightningGateway,
+    /// All payments below this index have already been claimed
+    pub next_claim_index: u64,
+    /// All payments below this index were handed to the gateway
+    pub next_payment_index: u64,
+}
+
+/// Payment hash and threshold-encrypted preimage of one payment to a reusable offer, prepared by
+/// the user so the gateway never learns the preimage before funding the incoming contract
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct OfferPayment {
+    pub index: u64,
+    pub hash: Sha256,
+    pub encrypted_preimage: EncryptedPreimage,
+}
+
+/// Reusable offer a gateway answers invoice requests for, stored in the gateway's database
+#[derive(Debug, Clone, Encodable, Decodable, Serialize, Deserialize)]
+pub struct GatewayOffer {
+    pub offer_key: PublicKey,
+    pub description: String,
+    /// Payments not handed out yet, in the order of their indices
+    pub payments: Vec<OfferPayment>,
+    /// Only payments from this index on are accepted when topping up, so no payment is ever handed
+    /// out twice
+    pub next_index: u64,
+}
+
+impl GatewayOffer {
+    /// Queues the `payments` we didn't see yet, up to [`OFFER_PAYMENT_BATCH`] unused ones
+    pub fn top_up(&mut self, mut payments: Vec<OfferPayment>) {
+        payments.sort_by_key(|payment| payment.index);
+        for payment in payments {
+            if self.payments.len() as u64 >= OFFER_PAYMENT_BATCH {
+                break;
+            }
+            if payment.index >= self.next_index {
+                self.next_index = payment.index + 1;
+                self.payments.push(payment);
+            }
+        }
+    }
+}
+
+/// Everything a payer needs to request invoices for a reusable offer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticPaymentCode {
+    pub gateway_api: Url,
+    pub federation_id: FederationId,
+    pub offer_id: ReusableOfferId,
+}
+
+/// Registers a reusable offer with a gateway or tops up its payments if it is already registered
+///
+/// Signed with the offer key, so only the offer's owner can hand payments to the gateway.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterOfferPayload {
+    pub federation_id: FederationId,
+    pub offer_key: PublicKey,
+    pub description: String,
+    pub payments: Vec<OfferPayment>,
+    pub signature: Signature,
+}
+
+impl RegisterOfferPayload {
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        federation_id: FederationId,
+        offer_keypair: &KeyPair,
+        description: String,
+        payments: Vec<OfferPayment>,
+    ) -> Self {
+        let offer_key = PublicKey::from_keypair(offer_keypair);
+        let message = register_offer_message(&federation_id, &offer_key, &description, &payments);
+        RegisterOfferPayload {
+            federation_id,
+            offer_key,
+            description,
+            payments,
+            signature: secp.sign_schnorr(&message, offer_keypair),
+        }
+    }
+
+    /// Whether the payload was signed by the owner of the offer key
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>) -> bool {
+        let message = register_offer_message(
+            &self.federation_id,
+            &self.offer_key,
+            &self.description,
+            &self.payments,
+        );
+        secp.verify_schnorr(
+            &self.signature,
+            &message,
+            &self.offer_key.x_only_public_key().0,
+        )
+        .is_ok()
+    }
+}
+
+fn register_offer_message(
+    federation_id: &FederationId,
+    offer_key: &PublicKey,
+    description: &str,
+    payments: &[OfferPayment],
+) -> Message {
+    let mut engine = Sha256::engine();
+    REGISTER_OFFER_TAG
+        .as_bytes()
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    federation_id
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    engine.input(&offer_key.serialize());
+    description
+        .as_bytes()
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    payments
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    Sha256::from_engine(engine).into()
+}
+
+/// Answer to a [`RegisterOfferPayload`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterOfferResponse {
+    pub offer_id: ReusableOfferId,
+    /// Number of payments the gateway can still issue invoices for
+    pub unused_payments: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfferInvoicePayload {
+    pub federation_id: FederationId,
+    pub offer_id: ReusableOfferId,
+    /// Amount the payer pays, the gateway's incoming fee is deducted from it
+    pub amount: Amount,
+}
+
+/// Maps a username to a reusable offer, signed by the offer key so only the offer's owner can
+/// register Lightning addresses for it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegisterLightningAddressPayload {
+    pub federation_id: FederationId,
+    pub username: String,
+    pub offer_id: ReusableOfferId,
+    pub signature: Signature,
+}
+
+impl RegisterLightningAddressPayload {
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        federation_id: FederationId,
+        username: String,
+        offer_keypair: &KeyPair,
+    ) -> Self {
+        let offer_id = ReusableOfferId::from_offer_key(&PublicKey::from_keypair(offer_keypair));
+        let message = register_lightning_address_message(&federation_id, &username, &offer_id);
+        RegisterLightningAddressPayload {
+            federation_id,
+            username,
+            offer_id,
+            signature: secp.sign_schnorr(&message, offer_keypair),
+        }
+    }
+
+    /// Whether the payload was signed by the owner of `offer_key`, which has to be the key of the
+    /// offer the username is mapped to
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, offer_key: &PublicKey) -> bool {
+        if ReusableOfferId::from_offer_key(offer_key) != self.offer_id {
+            return false;
+        }
+        let message =
+            register_lightning_address_message(&self.federation_id, &self.username, &self.offer_id);
+        secp.verify_schnorr(&self.signature, &message, &offer_key.x_only_public_key().0)
+            .is_ok()
+    }
+}
+
+fn register_lightning_address_message(
+    federation_id: &FederationId,
+    username: &str,
+    offer_id: &ReusableOfferId,
+) -> Message {
+    let mut engine = Sha256::engine();
+    REGISTER_LIGHTNING_ADDRESS_TAG
+        .as_bytes()
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    federation_id
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    username
+        .as_bytes()
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    offer_id
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    Sha256::from_engine(engine).into()
+}
+
+/// Tweak of the payment with the given `index`, derived from the offer's secret key so only the
+/// user knows the payment keys
+fn payment_tweak(offer_keypair: &KeyPair, index: u64) -> Scalar {
+    let mut engine = Sha256::engine();
+    engine.input(&offer_keypair.secret_bytes());
+    engine.input(&index.to_be_bytes());
+    Scalar::from_be_bytes(Sha256::from_engine(engine).into_inner())
+        .expect("hash is a valid scalar with overwhelming probability")
+}
+
+/// Payment keypair of the payment with the given `index`, used by the user to claim it
+pub fn payment_keypair<C: Signing>(
+    secp: &Secp256k1<C>,
+    offer_keypair: &KeyPair,
+    index: u64,
+) -> KeyPair {
+    let secret_key = SecretKey::from_keypair(offer_keypair)
+        .add_tweak(&payment_tweak(offer_keypair, index))
+        .expect("tweak is random");
+    KeyPair::from_secret_key(secp, &secret_key)
+}
+
+/// Preimage of a payment, the hash of the x-only payment public key as in
+/// [`IncomingContractVersion::V1`](fedimint_core::modules::ln::contracts::incoming::IncomingContractVersion::V1)
+/// contracts
+pub fn payment_preimage(payment_key: &PublicKey) -> Preimage {
+    Preimage::from_claim_key(&payment_key.x_only_public_key().0)
+}
+
+/// Prepares the payment with the given `index` for the gateway, encrypting its preimage to the
+/// federation's `threshold_pub_key`
+pub fn offer_payment<C: Signing>(
+    secp: &Secp256k1<C>,
+    offer_keypair: &KeyPair,
+    threshold_pub_key: &threshold_crypto::PublicKey,
+    index: u64,
+) -> OfferPayment {
+    let payment_keypair = payment_keypair(secp, offer_keypair, index);
+    let preimage = payment_preimage(&PublicKey::from_keypair(&payment_keypair));
+    OfferPayment {
+        index,
+        hash: Sha256::hash(&preimage.0),
+        encrypted_preimage: EncryptedPreimage::new(preimage, threshold_pub_key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payments(offer_keypair: &KeyPair, indices: std::ops::Range<u64>) -> Vec<OfferPayment> {
+        let secp = Secp256k1::new();
+        let threshold_key = threshold_crypto::SecretKey::random().public_key();
+        indices
+            .map(|index| offer_payment(&secp, offer_keypair, &threshold_key, index))
+            .collect()
+    }
+
+    #[test]
+    fn offer_payments_only_reveal_preimages_to_the_federation() {
+        let secp = Secp256k1::new();
+        let offer_keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        let threshold_secret = threshold_crypto::SecretKey::random();
+
+        for index in 0..10 {
+            let payment =
+                offer_payment(&secp, &offer_keypair, &threshold_secret.public_key(), index);
+            let preimage = threshold_secret
+                .decrypt(&payment.encrypted_preimage.0)
+                .unwrap();
+            let user_keypair = payment_keypair(&secp, &offer_keypair, index);
+
+            assert_eq!(Sha256::hash(&preimage), payment.hash);
+            assert_eq!(
+                Preimage(preimage.try_into().unwrap()),
+                Preimage::from_claim_key(&user_keypair.x_only_public_key().0)
+            );
+            // The offer key the gateway knows doesn't reveal the payment keys
+            assert_ne!(
+                user_keypair.public_key(),
+                PublicKey::from_keypair(&offer_keypair)
+            );
+        }
+    }
+
+    #[test]
+    fn register_offer_payload_is_signed_by_offer_key() {
+        let secp = Secp256k1::new();
+        let offer_keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        let mut payload = RegisterOfferPayload::new(
+            &secp,
+            FederationId::dummy(),
+            &offer_keypair,
+            "offer".to_string(),
+            payments(&offer_keypair, 0..2),
+        );
+        assert!(payload.verify(&secp));
+
+        payload.payments.pop();
+        assert!(!payload.verify(&secp));
+    }
+
+    #[test]
+    fn register_lightning_address_payload_is_signed_by_offer_key() {
+        let secp = Secp256k1::new();
+        let offer_keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        let offer_key = PublicKey::from_keypair(&offer_keypair);
+        let mut payload = RegisterLightningAddressPayload::new(
+            &secp,
+            FederationId::dummy(),
+            "alice".to_string(),
+            &offer_keypair,
+        );
+        assert!(payload.verify(&secp, &offer_key));
+
+        // Someone else's offer key can't map usernames to our offer
+        let other_keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        assert!(!payload.verify(&secp, &PublicKey::from_keypair(&other_keypair)));
+
+        payload.username = "mallory".to_string();
+        assert!(!payload.verify(&secp, &offer_key));
+    }
+
+    #[test]
+    fn gateway_offer_never_hands_out_a_payment_twice() {
+        let secp = Secp256k1::new();
+        let offer_keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        let mut offer = GatewayOffer {
+            offer_key: PublicKey::from_keypair(&offer_keypair),
+            description: "offer".to_string(),
+            payments: vec![],
+            next_index: 0,
+        };
+
+        offer.top_up(payments(&offer_keypair, 0..3));
+        let handed_out = offer.payments.remove(0);
+        assert_eq!(handed_out.index, 0);
+
+        // Replaying an old top up doesn't queue any payment again
+        offer.top_up(payments(&offer_keypair, 0..5));
+        let indices = offer
+            .payments
+            .iter()
+            .map(|payment| payment.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![1, 2, 3, 4]);
+
+        // The gateway holds a limited number of unused payments
+        offer.top_up(payments(&offer_keypair, 5..(5 + 2 * OFFER_PAYMENT_BATCH)));
+        assert_eq!(offer.payments.len() as u64, OFFER_PAYMENT_BATCH);
+        assert_eq!(offer.next_index, OFFER_PAYMENT_BATCH + 1);
+    }
+}
@@ -1,9 +1,9 @@
 use fedimint_api::encoding::{Decodable, Encodable};
-use fedimint_api::Amount;
+use fedimint_api::{Amount, OutPoint};
 use fedimint_core::modules::ln::contracts::{
-    outgoing::OutgoingContract, IdentifyableContract, Preimage,
+    outgoing::OutgoingContract, ContractId, IdentifyableContract, Preimage,
 };
-use fedimint_core::modules::ln::LightningInput;
+use fedimint_core::modules::ln::{LightningGateway, LightningInput};
 use serde::Serialize;
 
 #[derive(Debug, Encodable, Decodable, Serialize)]
@@ -12,6 +12,15 @@ pub struct OutgoingContractData {
     pub contract_account: OutgoingContractAccount,
 }
 
+/// Payment of an invoice issued by a member of our federation, made by funding its incoming
+/// contract ourselves
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct InternalPaymentData {
+    /// Key that can claim back the incoming contract if the payee's preimage turns out invalid
+    pub funder_key: bitcoin::KeyPair,
+    pub invoice: lightning_invoice::Invoice,
+}
+
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
 pub struct OutgoingContractAccount {
     pub amount: Amount,
@@ -35,3 +44,172 @@ impl OutgoingContractAccount {
         }
     }
 }
+
+/// Outgoing payment a gateway processes on behalf of a user, persisted so it can be resumed after
+/// a crash
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct GatewayPayment {
+    pub contract_account: OutgoingContractAccount,
+    /// Amount the gateway pays of a multi-part payment, `None` if it pays the whole invoice
+    pub part_amount: Option<Amount>,
+    pub state: GatewayPaymentState,
+}
+
+/// Progress of a [`GatewayPayment`], updated before every step that can't be undone
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize)]
+pub enum GatewayPaymentState {
+    /// The contract was validated and the invoice is being paid over Lightning
+    PayingInvoice,
+    /// The contract was validated and the invoice pays one of the federation's offers, whose
+    /// preimage is being bought
+    BuyingOffer,
+    /// The incoming contract of an internal payment was funded, its preimage is being decrypted
+    AwaitingDecryption {
+        out_point: OutPoint,
+        contract_id: ContractId,
+    },
+    /// The preimage was obtained, the outgoing contract is claimed next
+    Claiming { preimage: Preimage },
+    /// The claim transaction was built and submitted, its notes are being issued
+    ///
+    /// The preimage is kept in case the transaction never reached the federation.
+    Claimed {
+        preimage: Preimage,
+        out_point: OutPoint,
+    },
+}
+
+/// A part of a multi-part payment, paid by `gateway` from its own outgoing contract
+#[derive(Debug, Clone)]
+pub struct OutgoingPaymentPart {
+    pub gateway: LightningGateway,
+    pub contract_id: ContractId,
+    /// Amount of the invoice this part pays, excluding fees
+    pub amount: Amount,
+}
+
+/// Reported by the refund watcher for every outgoing contract it settled
+#[derive(Debug, Clone)]
+pub enum OutgoingRefundEvent {
+    /// The contract was cancelled by the gateway or timed out and we claimed back its funds
+    Refunded {
+        contract_id: ContractId,
+        outpoint: OutPoint,
+    },
+    /// The gateway claimed the contract after paying the invoice, so there is nothing to refund
+    Claimed { contract_id: ContractId },
+    /// Claiming back the funds failed, the watcher will retry in its next round
+    RefundFailed {
+        contract_id: ContractId,
+        error: String,
+    },
+}
+
+/// Splits `total` into `parts` amounts that differ by at most one msat
+pub fn split_payment(total: Amount, parts: usize) -> Vec<Amount> {
+    let parts = parts as u64;
+    (0..parts)
+        .map(|index| {
+            let remainder = u64::from(index < total.msats % parts);
+            Amount::from_msats(total.msats / parts + remainder)
+        })
+        .collect()
+}
+
+/// Splits `total` across gateways in proportion to their reported outbound `liquidity`, so no part
+/// exceeds what its gateway reported as long as they can send `total` together
+///
+/// Gateways that didn't report their liquidity are weighted as if they could send an even share,
+/// gateways without any liquidity get a zero part. Falls back to an even split if no gateway has
+/// any liquidity.
+pub fn split_payment_by_liquidity(total: Amount, liquidity: &[Option<Amount>]) -> Vec<Amount> {
+    let even_share = Amount::from_msats(total.msats / liquidity.len().max(1) as u64);
+    let weights = liquidity
+        .iter()
+        .map(|liquidity| liquidity.unwrap_or(even_share).msats as u128)
+        .collect::<Vec<_>>();
+    let total_weight = weights.iter().sum::<u128>();
+    if total_weight == 0 {
+        return split_payment(total, liquidity.len());
+    }
+
+    let mut parts = weights
+        .iter()
+        .map(|weight| (total.msats as u128 * weight / total_weight) as u64)
+        .collect::<Vec<_>>();
+    // Rounding down leaves less than one msat per part, which we add to parts with liquidity
+    let mut remainder = total.msats - parts.iter().sum::<u64>();
+    for (part, weight) in parts.iter_mut().zip(&weights) {
+        if remainder == 0 {
+            break;
+        }
+        if *weight != 0 {
+            *part += 1;
+            remainder -= 1;
+        }
+    }
+    parts.into_iter().map(Amount::from_msats).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_payment_preserves_total() {
+        let total = Amount::from_msats(1_000_001);
+        let parts = split_payment(total, 3);
+
+        assert_eq!(
+            parts,
+            vec![
+                Amount::from_msats(333_334),
+                Amount::from_msats(333_334),
+                Amount::from_msats(333_333),
+            ]
+        );
+        assert_eq!(parts.into_iter().sum::<Amount>(), total);
+    }
+
+    #[test]
+    fn split_payment_by_liquidity_respects_reported_liquidity() {
+        let total = Amount::from_msats(1_000_001);
+        let parts = split_payment_by_liquidity(
+            total,
+            &[
+                Some(Amount::from_msats(1_000_000)),
+                Some(Amount::from_msats(100_000)),
+                Some(Amount::ZERO),
+                Some(Amount::from_msats(100_000)),
+            ],
+        );
+
+        assert_eq!(
+            parts,
+            vec![
+                Amount::from_msats(833_335),
+                Amount::from_msats(83_333),
+                Amount::ZERO,
+                Amount::from_msats(83_333),
+            ]
+        );
+        assert_eq!(parts.into_iter().sum::<Amount>(), total);
+    }
+
+    #[test]
+    fn split_payment_by_liquidity_assumes_even_share_if_unreported() {
+        let total = Amount::from_msats(900);
+        assert_eq!(
+            split_payment_by_liquidity(total, &[None, Some(Amount::from_msats(600)), None]),
+            vec![
+                Amount::from_msats(225),
+                Amount::from_msats(450),
+                Amount::from_msats(225),
+            ]
+        );
+        assert_eq!(
+            split_payment_by_liquidity(total, &[Some(Amount::ZERO), Some(Amount::ZERO)]),
+            split_payment(total, 2)
+        );
+    }
+}
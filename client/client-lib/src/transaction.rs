@@ -35,6 +35,7 @@ impl Final for OutputOutcome {
                 ContractOutcome::Incoming(DecryptedPreimage::Some(_)) => true,
                 ContractOutcome::Incoming(_) => false,
                 ContractOutcome::Outgoing(_) => true,
+                ContractOutcome::Htlc(_) => true,
             },
         }
     }
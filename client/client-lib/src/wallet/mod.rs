@@ -12,7 +12,7 @@ use fedimint_core::modules::wallet::config::WalletClientConfig;
 use fedimint_core::modules::wallet::tweakable::Tweakable;
 use fedimint_core::modules::wallet::txoproof::{PegInProof, PegInProofError, TxOutProof};
 use fedimint_core::modules::wallet::WalletInput;
-use fedimint_core::modules::wallet::WalletOutput;
+use fedimint_core::modules::wallet::{PegInRegistration, WalletOutput};
 use fedimint_core::modules::wallet::{Wallet, WalletOutputOutcome};
 use rand::{CryptoRng, RngCore};
 use thiserror::Error;
@@ -50,9 +50,15 @@ impl ClientModule for WalletClient {
     }
 
     fn output_amount(&self, output: &WalletOutput) -> TransactionItemAmount {
-        TransactionItemAmount {
-            amount: (output.amount + output.fees.amount()).into(),
-            fee: self.config.fee_consensus.peg_out_abs,
+        match output {
+            WalletOutput::PegOut(peg_out) => TransactionItemAmount {
+                amount: (peg_out.amount + peg_out.fees.amount()).into(),
+                fee: self.config.fee_consensus.peg_out_abs,
+            },
+            WalletOutput::RegisterPegIn(_) => TransactionItemAmount {
+                amount: Amount::ZERO,
+                fee: self.config.fee_consensus.peg_in_registration_abs,
+            },
         }
     }
 }
@@ -70,8 +76,32 @@ impl WalletClient {
     pub async fn get_new_pegin_address<'a, R: RngCore + CryptoRng>(
         &self,
         dbtx: &mut DatabaseTransaction<'a>,
-        mut rng: R,
+        rng: R,
     ) -> Address {
+        self.new_pegin_key(dbtx, rng).await.0
+    }
+
+    /// Like [`Self::get_new_pegin_address`], but additionally returns an output registering the
+    /// address with the federation
+    ///
+    /// Once the registration is accepted the guardians watch confirmed blocks for deposits to the
+    /// address, so the peg-in can later be claimed by outpoint alone without supplying a
+    /// [`TxOutProof`].
+    pub async fn create_pegin_registration_output<'a, R: RngCore + CryptoRng>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        rng: R,
+    ) -> (Address, WalletOutput) {
+        let (address, tweak_contract_key) = self.new_pegin_key(dbtx, rng).await;
+        let output = WalletOutput::RegisterPegIn(PegInRegistration { tweak_contract_key });
+        (address, output)
+    }
+
+    async fn new_pegin_key<'a, R: RngCore + CryptoRng>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        mut rng: R,
+    ) -> (Address, secp256k1_zkp::XOnlyPublicKey) {
         let peg_in_keypair = bitcoin::KeyPair::new(&self.context.secp, &mut rng);
         let peg_in_pub_key = secp256k1_zkp::XOnlyPublicKey::from_keypair(&peg_in_keypair).0;
 
@@ -95,7 +125,7 @@ impl WalletClient {
         .await
         .expect("DB Error");
 
-        address
+        (address, peg_in_pub_key)
     }
 
     pub async fn create_pegin_input(
@@ -155,6 +185,46 @@ impl WalletClient {
         Ok((secret_tweak_key, peg_in_proof))
     }
 
+    /// Creates a peg-in input from a proof the federation built for a registered address
+    ///
+    /// The proof isn't trusted blindly, it has to belong to one of our peg-in keys and verify
+    /// against the peg-in descriptor just like a proof we built ourselves.
+    pub async fn create_registered_pegin_input(
+        &self,
+        peg_in_proof: PegInProof,
+    ) -> Result<(KeyPair, PegInProof)> {
+        let secret_tweak_key_bytes = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&PegInKey {
+                peg_in_script: peg_in_proof.tx_output().script_pubkey.clone(),
+            })
+            .await
+            .expect("DB error")
+            .ok_or(WalletClientError::NoMatchingPegInFound)?;
+
+        let secret_tweak_key =
+            bitcoin::KeyPair::from_seckey_slice(&self.context.secp, &secret_tweak_key_bytes)
+                .expect("sec key was generated and saved by us");
+        if secret_tweak_key.x_only_public_key().0 != *peg_in_proof.tweak_contract_key() {
+            return Err(WalletClientError::NoMatchingPegInFound);
+        }
+
+        peg_in_proof
+            .verify(&self.context.secp, &self.config.peg_in_descriptor)
+            .map_err(WalletClientError::PegInProofError)?;
+
+        let amount = Amount::from_sats(peg_in_proof.tx_output().value)
+            .saturating_sub(self.config.fee_consensus.peg_in_abs);
+        if amount == Amount::ZERO {
+            return Err(WalletClientError::PegInAmountTooSmall);
+        }
+
+        Ok((secret_tweak_key, peg_in_proof))
+    }
+
     pub async fn await_peg_out_outcome(
         &self,
         out_point: fedimint_api::OutPoint,
@@ -166,7 +236,24 @@ impl WalletClient {
             .api
             .await_output_outcome(out_point, timeout, &self.context.decoders)
             .await?;
-        Ok(outcome.0)
+        match outcome {
+            WalletOutputOutcome::PegOut(txid) => Ok(txid),
+            WalletOutputOutcome::PegInRegistered => Err(WalletClientError::WrongOutcomeType),
+        }
+    }
+
+    pub async fn await_peg_in_registration(&self, out_point: fedimint_api::OutPoint) -> Result<()> {
+        // TODO: define timeout centrally
+        let timeout = std::time::Duration::from_secs(15);
+        let outcome: WalletOutputOutcome = self
+            .context
+            .api
+            .await_output_outcome(out_point, timeout, &self.context.decoders)
+            .await?;
+        match outcome {
+            WalletOutputOutcome::PegInRegistered => Ok(()),
+            WalletOutputOutcome::PegOut(_) => Err(WalletClientError::WrongOutcomeType),
+        }
     }
 }
 
@@ -176,6 +263,12 @@ type Result<T> = std::result::Result<T, WalletClientError>;
 pub enum WalletClientError {
     #[error("Could not find an ongoing matching peg-in")]
     NoMatchingPegInFound,
+    #[error("The federation has not detected a confirmed peg-in at this outpoint yet")]
+    RegisteredPegInNotFound,
+    #[error("The federation doesn't accept peg-in registrations")]
+    PegInRegistrationDisabled,
+    #[error("Output outcome has unexpected type")]
+    WrongOutcomeType,
     #[error("Peg-in amount must be greater than peg-in fee")]
     PegInAmountTooSmall,
     #[error("Inconsistent peg-in proof: {0}")]
@@ -193,7 +286,9 @@ mod tests {
     use std::time::Duration;
 
     use bitcoin::hashes::sha256;
-    use bitcoin::{Address, Txid};
+    use bitcoin::secp256k1::{schnorr, Message, XOnlyPublicKey};
+    use bitcoin::util::sighash::{Prevouts, SighashCache};
+    use bitcoin::{Address, SchnorrSighashType, Transaction, TxOut, Txid};
     use bitcoin_hashes::Hash;
     use fedimint_api::config::ConfigGenParams;
     use fedimint_api::core::{
@@ -205,9 +300,11 @@ mod tests {
     use fedimint_api::task::TaskGroup;
     use fedimint_api::{Feerate, OutPoint, TransactionId};
     use fedimint_core::modules::wallet::common::WalletDecoder;
-    use fedimint_core::modules::wallet::config::WalletClientConfig;
+    use fedimint_core::modules::wallet::config::{PegInDescriptorType, WalletClientConfig};
+    use fedimint_core::modules::wallet::tweakable::Tweakable;
     use fedimint_core::modules::wallet::{
-        PegOut, PegOutFees, Wallet, WalletGen, WalletGenParams, WalletOutput, WalletOutputOutcome,
+        PegInDescriptor, PegOut, PegOutFees, Wallet, WalletGen, WalletGenParams, WalletOutput,
+        WalletOutputOutcome,
     };
     use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
     use fedimint_testing::btc::bitcoind::{FakeBitcoindRpc, FakeBitcoindRpcController};
@@ -245,7 +342,7 @@ mod tests {
                     epoch: 0,
                     outputs: vec![SerdeOutputOutcome::from(&DynOutputOutcome::from_typed(
                         module_id,
-                        WalletOutputOutcome(Txid::from_slice([0; 32].as_slice()).unwrap()),
+                        WalletOutputOutcome::PegOut(Txid::from_slice([0; 32].as_slice()).unwrap()),
                     ))],
                 })
             },
@@ -254,6 +351,7 @@ mod tests {
 
     async fn new_mint_and_client(
         task_group: &mut TaskGroup,
+        descriptor_type: PegInDescriptorType,
     ) -> (
         Arc<tokio::sync::Mutex<Fed>>,
         WalletClientConfig,
@@ -283,6 +381,7 @@ mod tests {
                 &ConfigGenParams::new().attach(WalletGenParams {
                     network: bitcoin::network::constants::Network::Regtest,
                     finality_delay: 10,
+                    descriptor_type,
                 }),
                 &WalletGen,
                 module_id,
@@ -312,9 +411,19 @@ mod tests {
 
     #[test_log::test(tokio::test)]
     async fn create_output() {
+        peg_out_and_recognize_change(PegInDescriptorType::Wsh).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn create_output_taproot() {
+        peg_out_and_recognize_change(PegInDescriptorType::Tr).await;
+    }
+
+    async fn peg_out_and_recognize_change(descriptor_type: PegInDescriptorType) {
         let mut task_group = TaskGroup::new();
         let (fed, client_config, client_context, btc_rpc) =
-            new_mint_and_client(&mut task_group).await;
+            new_mint_and_client(&mut task_group, descriptor_type).await;
+        let peg_in_descriptor = client_config.peg_in_descriptor.clone();
         let _client = WalletClient {
             config: client_config,
             context: Arc::new(client_context),
@@ -348,7 +457,7 @@ mod tests {
         btc_rpc.set_block_height(100).await;
         fed.lock()
             .await
-            .consensus_round(&[], &[(out_point, WalletOutput(output))])
+            .consensus_round(&[], &[(out_point, WalletOutput::PegOut(output))])
             .await;
 
         // begin pegout
@@ -366,6 +475,11 @@ mod tests {
         fedimint_api::task::sleep(Duration::from_secs(12)).await;
         assert!(btc_rpc.is_btc_sent_to(amount, addr).await);
 
+        if descriptor_type == PegInDescriptorType::Tr {
+            let peg_out_tx = btc_rpc.pending_transactions().await.pop().unwrap();
+            assert_key_path_spend(&peg_out_tx, &peg_in_descriptor);
+        }
+
         let wallet_value = fed
             .lock()
             .await
@@ -403,4 +517,35 @@ mod tests {
             .await;
         assert!(wallet_value > bitcoin::Amount::from_sat(0));
     }
+
+    /// Checks that the fake UTXO was spent with a single schnorr signature for the output key
+    fn assert_key_path_spend(tx: &Transaction, peg_in_descriptor: &PegInDescriptor) {
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let prevout = TxOut {
+            value: 48000,
+            script_pubkey: peg_in_descriptor.tweak(&[42u8; 32], &secp).script_pubkey(),
+        };
+
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, bitcoin::OutPoint::default());
+        let witness = tx.input[0].witness.to_vec();
+        assert_eq!(witness.len(), 1, "Peg-out wasn't signed via the key path");
+
+        let sighash = SighashCache::new(tx)
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout.clone()]),
+                SchnorrSighashType::Default,
+            )
+            .unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey[2..34]).unwrap();
+        let signature = schnorr::Signature::from_slice(&witness[0]).unwrap();
+
+        secp.verify_schnorr(
+            &signature,
+            &Message::from_slice(&sighash[..]).unwrap(),
+            &output_key,
+        )
+        .expect("Invalid key path signature");
+    }
 }
@@ -8,6 +8,17 @@ pub const FM_BITCOIND_RPC_ENV: &str = "FM_BITCOIND_RPC";
 /// Name of the env value used for passing electrum rpc url to modules that need it
 pub const FM_ELECTRUM_RPC_ENV: &str = "FM_ELECTRUM_RPC";
 
+/// Name of the env value used for passing esplora rest api url to modules that need it
+pub const FM_ESPLORA_RPC_ENV: &str = "FM_ESPLORA_RPC";
+
+/// Name of the env value used for setting how many of the configured bitcoin rpc backends have to
+/// agree on the chain state
+///
+/// Either a number smaller than the number of configured backends or `all`. Defaults to a simple
+/// majority, which has to be opted into with `all` if it would require every backend (e.g. with two
+/// backends) since a single unreachable backend would then stall the federation.
+pub const FM_BITCOIN_RPC_QUORUM_ENV: &str = "FM_BITCOIN_RPC_QUORUM";
+
 /// Default url that will be used if [`FM_BITCOIND_RPC_ENV`] is not set
 pub const FM_BITCOIND_RPC_DEFAULT_FALLBACK: &str = "http://127.0.0.1:8332";
 
@@ -17,6 +28,15 @@ pub enum BitcoindRpcBackend {
     Bitcoind(Url),
     /// Electrum RPC
     Electrum(Url),
+    /// Esplora REST API
+    Esplora(Url),
+    /// Multiple backends that have to agree on the chain state, used if more than one backend is
+    /// configured
+    Quorum {
+        backends: Vec<BitcoindRpcBackend>,
+        /// Number of backends that have to agree, see [`FM_BITCOIN_RPC_QUORUM_ENV`]
+        threshold: usize,
+    },
 }
 
 pub enum BitcoinRpcBackendType {
@@ -31,22 +51,74 @@ pub fn read_bitcoin_backend_from_global_env() -> anyhow::Result<BitcoindRpcBacke
     select_bitcoin_backend_from_envs(
         std::env::var_os(FM_BITCOIND_RPC_ENV).as_deref(),
         std::env::var_os(FM_ELECTRUM_RPC_ENV).as_deref(),
+        std::env::var_os(FM_ESPLORA_RPC_ENV).as_deref(),
+        std::env::var_os(FM_BITCOIN_RPC_QUORUM_ENV).as_deref(),
     )
 }
 
 pub fn select_bitcoin_backend_from_envs(
     bitcoind_rpc: Option<&OsStr>,
     electrum_rpc: Option<&OsStr>,
+    esplora_rpc: Option<&OsStr>,
+    quorum: Option<&OsStr>,
 ) -> anyhow::Result<BitcoindRpcBackend> {
-    Ok(if let Some(val) = bitcoind_rpc {
-        BitcoindRpcBackend::Bitcoind(fm_bitcoind_rpc_env_value_to_url(Some(val))?)
-    } else if let Some(val) = electrum_rpc {
-        BitcoindRpcBackend::Electrum(fm_electrum_rpc_env_value_to_url(val)?)
-    } else {
-        BitcoindRpcBackend::Bitcoind(fm_bitcoind_rpc_env_value_to_url(None)?)
+    let mut backends = vec![];
+    if let Some(val) = bitcoind_rpc {
+        backends.push(BitcoindRpcBackend::Bitcoind(
+            fm_bitcoind_rpc_env_value_to_url(Some(val))?,
+        ));
+    }
+    if let Some(val) = electrum_rpc {
+        backends.push(BitcoindRpcBackend::Electrum(
+            fm_electrum_rpc_env_value_to_url(val)?,
+        ));
+    }
+    if let Some(val) = esplora_rpc {
+        backends.push(BitcoindRpcBackend::Esplora(
+            fm_esplora_rpc_env_value_to_url(val)?,
+        ));
+    }
+
+    Ok(match backends.len() {
+        0 => BitcoindRpcBackend::Bitcoind(fm_bitcoind_rpc_env_value_to_url(None)?),
+        1 => backends.pop().expect("checked length"),
+        _ => BitcoindRpcBackend::Quorum {
+            threshold: fm_bitcoin_rpc_quorum_env_value_to_threshold(quorum, backends.len())?,
+            backends,
+        },
     })
 }
 
+/// Get the number of backends that have to agree from the value of [`FM_BITCOIN_RPC_QUORUM_ENV`]
+///
+/// Requiring all `backends` has to be opted into explicitly with `all`.
+fn fm_bitcoin_rpc_quorum_env_value_to_threshold(
+    value: Option<&OsStr>,
+    backends: usize,
+) -> anyhow::Result<usize> {
+    let threshold = match value.map(OsStr::to_str) {
+        None => backends / 2 + 1,
+        Some(Some("all")) => return Ok(backends),
+        Some(Some(threshold)) => threshold.parse::<usize>().map_err(|e| {
+            anyhow::format_err!("Invalid {FM_BITCOIN_RPC_QUORUM_ENV} {threshold:?}: {e}")
+        })?,
+        Some(None) => anyhow::bail!("{FM_BITCOIN_RPC_QUORUM_ENV} not ascii text"),
+    };
+
+    if threshold == 0 || backends < threshold {
+        anyhow::bail!(
+            "{FM_BITCOIN_RPC_QUORUM_ENV} must be between 1 and the number of bitcoin rpc backends ({backends})"
+        );
+    }
+    if threshold == backends {
+        anyhow::bail!(
+            "A quorum of {threshold} requires all bitcoin rpc backends to be reachable, set {FM_BITCOIN_RPC_QUORUM_ENV} to `all` to confirm that or to a smaller threshold"
+        );
+    }
+
+    Ok(threshold)
+}
+
 /// Get the value of bitcoin rpc url to use, from the value of env variable
 ///
 /// Useful in places where variable value is already available.
@@ -67,3 +139,55 @@ pub fn fm_electrum_rpc_env_value_to_url(value: &OsStr) -> anyhow::Result<Url> {
         anyhow::format_err!("Url not ascii text")
     })?)?)
 }
+
+pub fn fm_esplora_rpc_env_value_to_url(value: &OsStr) -> anyhow::Result<Url> {
+    Ok(Url::parse(value.to_str().ok_or_else(|| {
+        anyhow::format_err!("Url not ascii text")
+    })?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend_urls(quorum: Option<&str>) -> anyhow::Result<BitcoindRpcBackend> {
+        select_bitcoin_backend_from_envs(
+            Some(OsStr::new("http://bitcoind:8332")),
+            Some(OsStr::new("tcp://electrum:50001")),
+            None,
+            quorum.map(OsStr::new),
+        )
+    }
+
+    fn threshold(backend: BitcoindRpcBackend) -> usize {
+        match backend {
+            BitcoindRpcBackend::Quorum { threshold, .. } => threshold,
+            _ => panic!("Expected a quorum of backends"),
+        }
+    }
+
+    #[test]
+    fn quorum_of_all_backends_requires_opt_in() {
+        // A majority of two backends is both of them
+        assert!(backend_urls(None).is_err());
+        assert!(backend_urls(Some("2")).is_err());
+        assert_eq!(threshold(backend_urls(Some("all")).unwrap()), 2);
+        assert_eq!(threshold(backend_urls(Some("1")).unwrap()), 1);
+
+        assert!(backend_urls(Some("0")).is_err());
+        assert!(backend_urls(Some("3")).is_err());
+        assert!(backend_urls(Some("most")).is_err());
+    }
+
+    #[test]
+    fn quorum_defaults_to_majority() {
+        let backend = select_bitcoin_backend_from_envs(
+            Some(OsStr::new("http://bitcoind:8332")),
+            Some(OsStr::new("tcp://electrum:50001")),
+            Some(OsStr::new("https://esplora.example.com/api")),
+            None,
+        )
+        .unwrap();
+        assert_eq!(threshold(backend), 2);
+    }
+}
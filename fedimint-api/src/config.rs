@@ -505,6 +505,11 @@ pub trait TypedClientModuleConfig: DeserializeOwned + Serialize + Encodable {
 pub enum DkgPeerMsg {
     PublicKey(secp256k1::PublicKey),
     DistributedGen((String, SupportedDkgMessage)),
+    /// Commitment to a FROST DKG polynomial, sent in the first round
+    FrostCommitment(Vec<secp256k1::PublicKey>),
+    /// Secret share of a FROST DKG polynomial with a proof of possession of its constant term,
+    /// sent in the second round and only to the peer the share is meant for
+    FrostShare(secp256k1::SecretKey, secp256k1::schnorr::Signature),
     // Dkg completed on our side
     Done,
 }
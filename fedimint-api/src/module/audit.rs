@@ -7,6 +7,7 @@ use crate::db::{DatabaseKeyPrefix, DatabaseKeyPrefixConst, DatabaseTransaction};
 #[derive(Default)]
 pub struct Audit {
     items: Vec<AuditItem>,
+    summaries: Vec<AuditSummary>,
 }
 
 impl Audit {
@@ -44,6 +45,14 @@ impl Audit {
             .await;
         self.items.append(&mut new_items);
     }
+
+    /// Adds a value that is shown alongside the balance sheet but doesn't count towards it
+    pub fn add_summary(&mut self, name: impl Into<String>, value: u64) {
+        self.summaries.push(AuditSummary {
+            name: name.into(),
+            value,
+        });
+    }
 }
 
 impl Display for Audit {
@@ -52,7 +61,11 @@ impl Display for Audit {
         for item in &self.items {
             formatter.write_fmt(format_args!("\n{item}"))?;
         }
-        formatter.write_fmt(format_args!("\n{}", self.sum()))
+        formatter.write_fmt(format_args!("\n{}", self.sum()))?;
+        for summary in &self.summaries {
+            formatter.write_fmt(format_args!("\n{summary}"))?;
+        }
+        Ok(())
     }
 }
 
@@ -67,3 +80,14 @@ impl Display for AuditItem {
         formatter.write_fmt(format_args!("{:>+15.3}|{}", sats, self.name))
     }
 }
+
+pub struct AuditSummary {
+    pub name: String,
+    pub value: u64,
+}
+
+impl Display for AuditSummary {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_fmt(format_args!("{:>15}|{}", self.value, self.name))
+    }
+}
@@ -376,6 +376,17 @@ mod imp {
         tokio::task::block_in_place(f)
     }
 
+    /// Runs CPU heavy work on a thread meant for blocking tasks, so it doesn't stall the executor
+    pub async fn spawn_blocking<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .expect("Blocking task panicked")
+    }
+
     pub async fn sleep(duration: Duration) {
         tokio::time::sleep(duration).await
     }
@@ -426,6 +437,15 @@ mod imp {
         f()
     }
 
+    pub async fn spawn_blocking<F, R>(f: F) -> R
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        // no threads to offload to on wasm
+        f()
+    }
+
     pub async fn sleep(duration: Duration) {
         gloo_timers::future::sleep(duration).await
     }
@@ -4,7 +4,9 @@ use ::bitcoincore_rpc::bitcoincore_rpc_json::EstimateMode;
 use ::bitcoincore_rpc::jsonrpc::error::RpcError;
 use ::bitcoincore_rpc::{jsonrpc, Auth, RpcApi};
 use anyhow::{bail, format_err, Context};
-use bitcoin::consensus::Encodable;
+use bitcoin::consensus::{deserialize, Encodable};
+use bitcoin::hashes::{Hash as BitcoinHash, HashEngine};
+use bitcoin::{TxMerkleNode, Txid};
 use bitcoin_hashes::hex::ToHex;
 use electrum_client::ElectrumApi;
 use fedimint_api::bitcoin_rpc::BitcoindRpcBackend;
@@ -15,6 +17,9 @@ use tracing::warn;
 use url::Url;
 
 use super::*;
+#[cfg(feature = "esplora")]
+use crate::esplora::{make_esplora_rpc, EsploraClient};
+use crate::quorum::{QuorumClient, DEFAULT_CALL_TIMEOUT};
 
 // <https://github.com/bitcoin/bitcoin/blob/ec0a4ad67769109910e3685da9c56c1b9f42414e/src/rpc/protocol.h#L48>
 const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
@@ -56,9 +61,54 @@ pub fn make_bitcoin_rpc_backend(
             .context("bitcoind rpc backend initialization failed"),
         BitcoindRpcBackend::Electrum(url) => make_electrum_rpc(url, task_handle)
             .context("electrum rpc backend initialization failed"),
+        #[cfg(feature = "esplora")]
+        BitcoindRpcBackend::Esplora(url) => make_esplora_rpc(url, task_handle)
+            .context("esplora rpc backend initialization failed"),
+        #[cfg(not(feature = "esplora"))]
+        BitcoindRpcBackend::Esplora(_) => bail!("esplora rpc backend support is not enabled"),
+        BitcoindRpcBackend::Quorum {
+            backends,
+            threshold,
+        } => Ok(RetryClient::new(make_quorum_rpc(backends, *threshold)?, task_handle).into()),
     }
 }
 
+/// Builds a [`QuorumClient`] from backends that don't retry on their own
+///
+/// A member retrying forever would stall every call to the quorum, so members give up after
+/// [`DEFAULT_CALL_TIMEOUT`] and only the quorum as a whole is retried.
+fn make_quorum_rpc(backends: &[BitcoindRpcBackend], threshold: usize) -> Result<QuorumClient> {
+    let members = backends
+        .iter()
+        .map(|backend| -> Result<DynBitcoindRpc> {
+            Ok(match backend {
+                BitcoindRpcBackend::Bitcoind(url) => {
+                    let (url, auth) = from_url_to_url_auth(url)?;
+                    let bitcoind_client =
+                        ::bitcoincore_rpc::Client::new(&url, auth).map_err(anyhow::Error::from)?;
+                    Client(ErrorReporting::new(url, bitcoind_client)).into()
+                }
+                BitcoindRpcBackend::Electrum(url) => {
+                    ElectrumClient::with_timeout(url, DEFAULT_CALL_TIMEOUT)?.into()
+                }
+                #[cfg(feature = "esplora")]
+                BitcoindRpcBackend::Esplora(url) => EsploraClient::new(url)?.into(),
+                #[cfg(not(feature = "esplora"))]
+                BitcoindRpcBackend::Esplora(_) => {
+                    bail!("esplora rpc backend support is not enabled")
+                }
+                BitcoindRpcBackend::Quorum {
+                    backends,
+                    threshold,
+                } => make_quorum_rpc(backends, *threshold)?.into(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("quorum member rpc backend initialization failed")?;
+
+    Ok(QuorumClient::with_threshold(members, threshold).with_call_timeout(DEFAULT_CALL_TIMEOUT))
+}
+
 pub fn make_bitcoind_rpc(url: &Url, task_handle: TaskHandle) -> Result<DynBitcoindRpc> {
     let (url, auth) = from_url_to_url_auth(url)?;
     let bitcoind_client =
@@ -164,8 +214,7 @@ where
             self.0
                 .estimate_smart_fee(confirmation_target, Some(EstimateMode::Conservative))
                 .map_err(anyhow::Error::from)
-        })
-        .expect("Bitcoind returned an error") // TODO: implement retry logic in case bitcoind is temporarily unreachable
+        })?
         .fee_rate
         .map(|per_kb| Feerate {
             sats_per_kvb: per_kb.to_sat(),
@@ -191,6 +240,18 @@ impl ElectrumClient {
     fn new(url: &Url) -> anyhow::Result<Self> {
         Ok(Self(electrum_client::Client::new(url.as_str())?))
     }
+
+    /// Creates a client whose socket operations fail after `timeout`, since blocking calls can't
+    /// be interrupted from the outside
+    fn with_timeout(url: &Url, timeout: Duration) -> anyhow::Result<Self> {
+        let config = electrum_client::ConfigBuilder::new()
+            .timeout(Some(timeout.as_secs().try_into().unwrap_or(u8::MAX)))?
+            .build();
+        Ok(Self(electrum_client::Client::from_config(
+            url.as_str(),
+            config,
+        )?))
+    }
 }
 
 impl fmt::Debug for ElectrumClient {
@@ -291,4 +352,214 @@ impl IBitcoindRpc for ElectrumClient {
                 }))
         })
     }
+
+    async fn get_script_payments_in(
+        &self,
+        script: &Script,
+        height: u64,
+    ) -> Result<Vec<ScriptPayment>> {
+        if self.get_block_height().await? <= height {
+            bail!("Electrum backend does not contain the block at {height}H yet");
+        }
+
+        fedimint_api::task::block_in_place(|| {
+            let block_header = self.0.block_header(height as usize)?;
+
+            self.0
+                .script_get_history(script)?
+                .into_iter()
+                .filter(|history_item| (history_item.height as u64) == height)
+                .map(|history_item| -> Result<ScriptPayment> {
+                    let merkle = self
+                        .0
+                        .transaction_get_merkle(&history_item.tx_hash, height as usize)?;
+                    // Electrum serves merkle branches in RPC byte order
+                    let branch = merkle
+                        .merkle
+                        .into_iter()
+                        .map(|mut hash| {
+                            hash.reverse();
+                            TxMerkleNode::from_inner(hash)
+                        })
+                        .collect::<Vec<_>>();
+
+                    Ok(ScriptPayment {
+                        transaction: self.0.transaction_get(&history_item.tx_hash)?,
+                        block_header,
+                        merkle_proof: partial_merkle_tree_from_branch(
+                            history_item.tx_hash,
+                            merkle.pos,
+                            &branch,
+                        )?,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// Builds the partial merkle tree proving that `txid` is the `pos`-th transaction of a block from
+/// the merkle branch an electrum server returned for it
+///
+/// Partial merkle trees also encode the number of transactions in the block which electrum doesn't
+/// tell us. Bitcoin pads levels with an odd number of nodes by duplicating the last one, so a left
+/// node whose sibling equals itself is the last one of its level. Any transaction count matching
+/// these edges results in the same tree shape along our path and thus a valid proof.
+fn partial_merkle_tree_from_branch(
+    txid: Txid,
+    pos: usize,
+    branch: &[TxMerkleNode],
+) -> Result<PartialMerkleTree> {
+    let height = branch.len();
+    if height >= usize::BITS as usize || pos >> height != 0 {
+        bail!("Merkle branch of length {height} can't prove position {pos}");
+    }
+
+    let mut has_right_sibling = Vec::with_capacity(height);
+    let mut node = TxMerkleNode::from_inner(txid.into_inner());
+    for (level, sibling) in branch.iter().enumerate() {
+        let is_left = (pos >> level) % 2 == 0;
+        has_right_sibling.push(!is_left || *sibling != node);
+
+        let mut engine = TxMerkleNode::engine();
+        if is_left {
+            engine.input(&node[..]);
+            engine.input(&sibling[..]);
+        } else {
+            engine.input(&sibling[..]);
+            engine.input(&node[..]);
+        }
+        node = TxMerkleNode::from_engine(engine);
+    }
+
+    let width = |tx_count: usize, level: usize| (tx_count + (1 << level) - 1) >> level;
+    let tx_count = (pos + 1..=(1 << height))
+        .find(|&tx_count| {
+            (height == 0 || width(tx_count, height - 1) > 1)
+                && has_right_sibling
+                    .iter()
+                    .enumerate()
+                    .all(|(level, &has_right)| {
+                        has_right == (((pos >> level) | 1) < width(tx_count, level))
+                    })
+        })
+        .ok_or_else(|| format_err!("No block layout matches the merkle branch"))?;
+
+    // Traverse the tree depth first like `PartialMerkleTree::from_txids` does: nodes on our path
+    // are descended into, their siblings only contribute their hash. Right siblings are visited
+    // after the subtree containing our transaction, so deeper ones come first.
+    let mut bits = vec![];
+    let mut hashes = vec![];
+    let mut right_siblings = vec![];
+    for level in (0..height).rev() {
+        bits.push(true);
+        if (pos >> level) % 2 == 0 {
+            if has_right_sibling[level] {
+                right_siblings.push(branch[level]);
+            }
+        } else {
+            bits.push(false);
+            hashes.push(branch[level]);
+        }
+    }
+    bits.push(true);
+    hashes.push(TxMerkleNode::from_inner(txid.into_inner()));
+    for sibling in right_siblings.into_iter().rev() {
+        bits.push(false);
+        hashes.push(sibling);
+    }
+
+    let mut bytes = vec![];
+    (tx_count as u32).consensus_encode(&mut bytes)?;
+    hashes.consensus_encode(&mut bytes)?;
+    let mut flags = vec![0u8; (bits.len() + 7) / 8];
+    for (idx, bit) in bits.into_iter().enumerate() {
+        flags[idx / 8] |= u8::from(bit) << (idx % 8);
+    }
+    flags.consensus_encode(&mut bytes)?;
+
+    let merkle_proof: PartialMerkleTree = deserialize(&bytes)?;
+    let mut matches = vec![];
+    let mut indices = vec![];
+    let root = merkle_proof
+        .extract_matches(&mut matches, &mut indices)
+        .map_err(|error| format_err!("Invalid partial merkle tree: {error:?}"))?;
+    if root != node || matches != [txid] {
+        bail!("Reconstructed partial merkle tree doesn't match the merkle branch");
+    }
+
+    Ok(merkle_proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::sha256d;
+
+    use super::*;
+
+    /// Returns the merkle branch of the `pos`-th of `txids` like electrum servers do
+    fn merkle_branch(txids: &[Txid], pos: usize) -> Vec<TxMerkleNode> {
+        let mut level = txids
+            .iter()
+            .map(|txid| TxMerkleNode::from_inner(txid.into_inner()))
+            .collect::<Vec<_>>();
+        let mut idx = pos;
+        let mut branch = vec![];
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+            branch.push(level[idx ^ 1]);
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let mut engine = TxMerkleNode::engine();
+                    engine.input(&pair[0][..]);
+                    engine.input(&pair[1][..]);
+                    TxMerkleNode::from_engine(engine)
+                })
+                .collect();
+            idx /= 2;
+        }
+        branch
+    }
+
+    #[test]
+    fn partial_merkle_tree_from_electrum_branch() {
+        for tx_count in 1..=20usize {
+            let txids = (0..tx_count)
+                .map(|idx| Txid::from_hash(sha256d::Hash::hash(&idx.to_le_bytes())))
+                .collect::<Vec<_>>();
+
+            for pos in 0..tx_count {
+                let matches = (0..tx_count).map(|idx| idx == pos).collect::<Vec<_>>();
+                let expected = PartialMerkleTree::from_txids(&txids, &matches);
+                let mut expected_matches = vec![];
+                let expected_root = expected
+                    .extract_matches(&mut expected_matches, &mut vec![])
+                    .unwrap();
+
+                let proof =
+                    partial_merkle_tree_from_branch(txids[pos], pos, &merkle_branch(&txids, pos))
+                        .unwrap();
+                let mut proof_matches = vec![];
+                let mut proof_indices = vec![];
+                assert_eq!(
+                    proof
+                        .extract_matches(&mut proof_matches, &mut proof_indices)
+                        .unwrap(),
+                    expected_root,
+                    "{pos} of {tx_count}"
+                );
+                assert_eq!(proof_matches, vec![txids[pos]]);
+                assert_eq!(proof_indices, vec![pos as u32]);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_merkle_tree_rejects_short_branch() {
+        let txid = Txid::from_hash(sha256d::Hash::hash(b"tx"));
+        assert!(partial_merkle_tree_from_branch(txid, 2, &[]).is_err());
+    }
 }
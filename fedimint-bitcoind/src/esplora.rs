@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use anyhow::{format_err, Context};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use serde::Deserialize;
+use tracing::warn;
+use url::Url;
+
+use super::*;
+
+pub fn make_esplora_rpc(url: &Url, task_handle: TaskHandle) -> Result<DynBitcoindRpc> {
+    Ok(RetryClient::new(EsploraClient::new(url)?, task_handle).into())
+}
+
+/// Client for the REST API of an [Esplora](https://github.com/Blockstream/esplora) server
+#[derive(Debug)]
+pub struct EsploraClient {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct TxStatus {
+    confirmed: bool,
+    block_height: Option<u64>,
+}
+
+impl EsploraClient {
+    pub fn new(url: &Url) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_url: url.as_str().trim_end_matches('/').to_owned(),
+            client: reqwest::Client::builder().build()?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.base_url)
+    }
+
+    async fn get(&self, path: &str) -> Result<reqwest::Response> {
+        self.client
+            .get(self.url(path))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("esplora request GET /{path} failed"))
+    }
+
+    async fn get_text(&self, path: &str) -> Result<String> {
+        Ok(self.get(path).await?.text().await?.trim().to_owned())
+    }
+}
+
+#[async_trait]
+impl IBitcoindRpc for EsploraClient {
+    async fn get_network(&self) -> Result<Network> {
+        let genesis_hash = self.get_block_hash(0).await?;
+        Ok(
+            match [Network::Bitcoin, Network::Testnet, Network::Signet]
+                .into_iter()
+                .find(|network| genesis_block(*network).block_hash() == genesis_hash)
+            {
+                Some(network) => network,
+                None => {
+                    warn!("Unknown genesis hash {genesis_hash} - assuming regtest");
+                    Network::Regtest
+                }
+            },
+        )
+    }
+
+    async fn get_block_height(&self) -> Result<u64> {
+        Ok(self.get_text("blocks/tip/height").await?.parse()?)
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        Ok(BlockHash::from_hex(
+            &self.get_text(&format!("block-height/{height}")).await?,
+        )?)
+    }
+
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        let bytes = self
+            .get(&format!("block/{hash}/raw"))
+            .await?
+            .bytes()
+            .await?;
+        let block: Block = deserialize(&bytes)?;
+
+        if block.block_hash() != *hash {
+            return Err(format_err!(
+                "esplora returned block {} when asked for {hash}",
+                block.block_hash()
+            ));
+        }
+
+        Ok(block)
+    }
+
+    async fn get_fee_rate(&self, confirmation_target: u16) -> Result<Option<Feerate>> {
+        // Maps confirmation targets in blocks to fee rates in sat/vB, esplora only returns a
+        // subset of all possible targets
+        let estimates: HashMap<String, f64> = self.get("fee-estimates").await?.json().await?;
+
+        Ok(estimates
+            .into_iter()
+            .filter_map(|(target, fee_rate)| Some((target.parse::<u16>().ok()?, fee_rate)))
+            .filter(|(target, _)| *target <= confirmation_target)
+            .max_by_key(|(target, _)| *target)
+            .map(|(_, sats_per_vb)| Feerate {
+                sats_per_kvb: (sats_per_vb * 1000f64).ceil() as u64,
+            }))
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) -> Result<()> {
+        self.client
+            .post(self.url("tx"))
+            .body(serialize(&transaction).to_hex())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("esplora request POST /tx failed")?;
+        Ok(())
+    }
+
+    async fn was_transaction_confirmed_in(
+        &self,
+        transaction: &Transaction,
+        height: u64,
+    ) -> Result<bool> {
+        let status: TxStatus = self
+            .get(&format!("tx/{}/status", transaction.txid()))
+            .await?
+            .json()
+            .await?;
+        Ok(status.confirmed && status.block_height == Some(height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Bytes;
+    use axum::extract::Path;
+    use axum::routing::{get, post};
+    use axum::Router;
+
+    use super::*;
+
+    /// Serves canned esplora responses for a chain only consisting of the regtest genesis block
+    async fn spawn_mock_esplora(submitted: Arc<Mutex<Vec<String>>>) -> Url {
+        let genesis = genesis_block(Network::Regtest);
+        let genesis_hash = genesis.block_hash().to_string();
+        let raw_genesis = serialize(&genesis);
+
+        let app = Router::new()
+            .route("/blocks/tip/height", get(|| async { "0" }))
+            .route(
+                "/block-height/:height",
+                get(move |Path(height): Path<u64>| {
+                    let genesis_hash = genesis_hash.clone();
+                    async move {
+                        assert_eq!(height, 0);
+                        genesis_hash
+                    }
+                }),
+            )
+            .route(
+                "/block/:hash/raw",
+                get(move || {
+                    let raw_genesis = raw_genesis.clone();
+                    async move { raw_genesis }
+                }),
+            )
+            .route(
+                "/fee-estimates",
+                get(|| async { r#"{"1": 20.5, "6": 10.0, "144": 1.0}"# }),
+            )
+            .route(
+                "/tx",
+                post(move |body: Bytes| {
+                    submitted
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(body.to_vec()).unwrap());
+                    async { "txid" }
+                }),
+            );
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        Url::parse(&format!("http://{addr}/")).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn esplora_client_against_mock_server() {
+        let submitted = Arc::new(Mutex::new(vec![]));
+        let url = spawn_mock_esplora(submitted.clone()).await;
+        let client = EsploraClient::new(&url).unwrap();
+        let genesis = genesis_block(Network::Regtest);
+
+        assert_eq!(client.get_network().await.unwrap(), Network::Regtest);
+        assert_eq!(client.get_block_height().await.unwrap(), 0);
+        assert_eq!(
+            client.get_block_hash(0).await.unwrap(),
+            genesis.block_hash()
+        );
+        assert_eq!(
+            client.get_block(&genesis.block_hash()).await.unwrap(),
+            genesis
+        );
+
+        assert_eq!(
+            client.get_fee_rate(10).await.unwrap(),
+            Some(Feerate {
+                sats_per_kvb: 10_000
+            })
+        );
+        assert_eq!(
+            client.get_fee_rate(1).await.unwrap(),
+            Some(Feerate {
+                sats_per_kvb: 20_500
+            })
+        );
+        assert_eq!(client.get_fee_rate(0).await.unwrap(), None);
+
+        let transaction = genesis.txdata[0].clone();
+        client
+            .submit_transaction(transaction.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            *submitted.lock().unwrap(),
+            vec![serialize(&transaction).to_hex()]
+        );
+    }
+}
@@ -1,19 +1,25 @@
 use std::fmt::Debug;
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::bail;
 pub use anyhow::Result;
 use async_trait::async_trait;
-use bitcoin::{Block, BlockHash, Network, Transaction};
+use bitcoin::util::merkleblock::PartialMerkleTree;
+use bitcoin::{Block, BlockHash, BlockHeader, Network, Script, Transaction};
 use fedimint_api::{
     bitcoin_rpc::BitcoinRpcBackendType, dyn_newtype_define, task::TaskHandle, Feerate,
 };
-use tracing::info;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
 #[cfg(feature = "bitcoincore-rpc")]
 pub mod bitcoincore_rpc;
+#[cfg(feature = "esplora")]
+pub mod esplora;
+pub mod quorum;
 
 /// Trait that allows interacting with the Bitcoin blockchain
 ///
@@ -66,6 +72,21 @@ pub trait IBitcoindRpc: Debug + Send + Sync {
     ) -> Result<bool> {
         bail!("is_transaction_confirmed_in call not supported in standard (non-electrum) backends")
     }
+
+    /// Returns all transactions confirmed at `height` that pay to `script` together with proofs of
+    /// their inclusion (only electrum, other backends can scan the full block instead)
+    async fn get_script_payments_in(
+        &self,
+        _script: &Script,
+        _height: u64,
+    ) -> Result<Vec<ScriptPayment>> {
+        bail!("get_script_payments_in call not supported in standard (non-electrum) backends")
+    }
+
+    /// Statistics about calls made to the backend, if it keeps track of them
+    fn health(&self) -> Option<BitcoinRpcHealth> {
+        None
+    }
 }
 
 dyn_newtype_define! {
@@ -73,19 +94,103 @@ dyn_newtype_define! {
     pub DynBitcoindRpc(Arc<IBitcoindRpc>)
 }
 
-/// Wrapper around [`IBitcoindRpc`] that will retry failed calls
+/// Transaction paying to a watched script, see [`IBitcoindRpc::get_script_payments_in`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptPayment {
+    pub transaction: Transaction,
+    /// Header of the block the transaction was confirmed in
+    pub block_header: BlockHeader,
+    /// Proof that the transaction is committed to by `block_header`
+    pub merkle_proof: PartialMerkleTree,
+}
+
+/// Statistics about the calls made through a [`RetryClient`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BitcoinRpcHealth {
+    /// Calls that succeeded, possibly after retrying
+    pub successful_calls: u64,
+    /// Calls that were given up on
+    pub failed_calls: u64,
+    /// All failed attempts, including ones that were retried successfully
+    pub failed_attempts: u64,
+    /// Failed attempts since the last successful one
+    pub consecutive_failures: u64,
+    /// Error returned by the last failed attempt
+    pub last_error: Option<String>,
+    /// Calls to a quorum of backends that got different responses
+    #[serde(default)]
+    pub disagreements: u64,
+    /// Call and responses of the last disagreement among a quorum of backends
+    #[serde(default)]
+    pub last_disagreement: Option<String>,
+    /// Health of the individual backends of a quorum
+    #[serde(default)]
+    pub backends: Vec<BitcoinRpcHealth>,
+}
+
+/// Determines how often and how fast [`RetryClient`] retries failed calls
+///
+/// The default policy retries until the task is shut down: the wallet module treats failed calls
+/// as fatal since it can't make progress on consensus without the backend, so giving up during an
+/// outage would take the guardian down with it.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between retries
+    pub max_delay: Duration,
+    /// Factor the delay grows by after each failed attempt
+    pub multiplier: u32,
+    /// Give up after this many attempts, retry forever if `None`
+    pub max_attempts: Option<u32>,
+    /// Give up once retrying would take longer than this, retry forever if `None`
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2,
+            max_attempts: None,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the retry following the `attempt`-th failed attempt (starting at 1)
+    ///
+    /// Uses exponential backoff with up to 50% jitter so multiple clients don't retry in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(self.multiplier.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(backoff / 2..=backoff)
+    }
+}
+
+/// Wrapper around [`IBitcoindRpc`] that will retry failed calls according to a [`RetryPolicy`]
 #[derive(Debug)]
 pub struct RetryClient<C> {
     inner: C,
-    sleep: Duration,
+    policy: RetryPolicy,
+    health: Mutex<BitcoinRpcHealth>,
     task_handle: TaskHandle,
 }
 
 impl<C> RetryClient<C> {
     pub fn new(inner: C, task_handle: TaskHandle) -> Self {
+        Self::with_policy(inner, RetryPolicy::default(), task_handle)
+    }
+
+    pub fn with_policy(inner: C, policy: RetryPolicy, task_handle: TaskHandle) -> Self {
         Self {
             inner,
-            sleep: Duration::from_millis(10),
+            policy,
+            health: Mutex::new(BitcoinRpcHealth::default()),
             task_handle,
         }
     }
@@ -95,22 +200,55 @@ impl<C> RetryClient<C> {
         F: Fn() -> R,
         R: Future<Output = Result<T>>,
     {
-        let ret = loop {
+        let start = fedimint_api::time::SystemTime::now();
+        let mut attempt = 0;
+        loop {
             match call_fn().await {
                 Ok(ret) => {
-                    break ret;
+                    let mut health = self.health.lock().expect("poisoned");
+                    health.successful_calls += 1;
+                    health.consecutive_failures = 0;
+                    return Ok(ret);
                 }
                 Err(e) => {
-                    if self.task_handle.is_shutting_down() {
+                    attempt += 1;
+                    {
+                        let mut health = self.health.lock().expect("poisoned");
+                        health.failed_attempts += 1;
+                        health.consecutive_failures += 1;
+                        health.last_error = Some(e.to_string());
+                    }
+
+                    let delay = self.policy.delay(attempt);
+                    let elapsed = fedimint_api::time::SystemTime::now()
+                        .duration_since(start)
+                        .unwrap_or_default();
+                    let out_of_attempts = self
+                        .policy
+                        .max_attempts
+                        .map_or(false, |max_attempts| max_attempts <= attempt);
+                    let out_of_time = self
+                        .policy
+                        .deadline
+                        .map_or(false, |deadline| deadline < elapsed + delay);
+
+                    if self.task_handle.is_shutting_down() || out_of_attempts || out_of_time {
+                        warn!(attempt, "Bitcoind error {:?}, giving up", e);
+                        self.health.lock().expect("poisoned").failed_calls += 1;
                         return Err(e);
                     }
 
-                    info!("Bitcoind error {:?}, retrying", e);
-                    std::thread::sleep(self.sleep);
+                    // Only log the first failure loudly to not flood the logs while bitcoind is
+                    // unreachable
+                    if attempt == 1 {
+                        info!("Bitcoind error {:?}, retrying", e);
+                    } else {
+                        debug!(attempt, ?delay, "Bitcoind error {:?}, retrying", e);
+                    }
+                    fedimint_api::task::sleep(delay).await;
                 }
             }
-        };
-        Ok(ret)
+        }
     }
 }
 
@@ -165,4 +303,119 @@ where
         })
         .await
     }
+
+    async fn get_script_payments_in(
+        &self,
+        script: &Script,
+        height: u64,
+    ) -> Result<Vec<ScriptPayment>> {
+        self.retry_call(|| async { self.inner.get_script_payments_in(script, height).await })
+            .await
+    }
+
+    fn health(&self) -> Option<BitcoinRpcHealth> {
+        let mut health = self.health.lock().expect("poisoned").clone();
+        if let Some(inner) = self.inner.health() {
+            health.disagreements = inner.disagreements;
+            health.last_disagreement = inner.last_disagreement;
+            health.backends = inner.backends;
+        }
+        Some(health)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Unreachable;
+
+    #[async_trait]
+    impl IBitcoindRpc for Unreachable {
+        async fn get_network(&self) -> Result<Network> {
+            bail!("connection refused")
+        }
+
+        async fn get_block_height(&self) -> Result<u64> {
+            bail!("connection refused")
+        }
+
+        async fn get_block_hash(&self, _height: u64) -> Result<BlockHash> {
+            bail!("connection refused")
+        }
+
+        async fn get_block(&self, _hash: &BlockHash) -> Result<Block> {
+            bail!("connection refused")
+        }
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> Result<Option<Feerate>> {
+            bail!("connection refused")
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) -> Result<()> {
+            bail!("connection refused")
+        }
+    }
+
+    #[test]
+    fn backoff_grows_up_to_max_delay() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            multiplier: 3,
+            max_attempts: None,
+            deadline: None,
+        };
+        for (attempt, backoff_ms) in [(1, 100), (2, 300), (3, 900), (4, 1_000), (30, 1_000)] {
+            let backoff = Duration::from_millis(backoff_ms);
+            for _ in 0..10 {
+                let delay = policy.delay(attempt);
+                assert!(
+                    backoff / 2 <= delay && delay <= backoff,
+                    "attempt {attempt}: {delay:?} not within jitter of {backoff:?}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let client = RetryClient::with_policy(
+            Unreachable,
+            RetryPolicy {
+                max_attempts: Some(3),
+                ..RetryPolicy::default()
+            },
+            fedimint_api::task::TaskGroup::new().make_handle(),
+        );
+
+        assert!(client.get_block_height().await.is_err());
+        assert_eq!(
+            client.health(),
+            Some(BitcoinRpcHealth {
+                successful_calls: 0,
+                failed_calls: 1,
+                failed_attempts: 3,
+                consecutive_failures: 3,
+                last_error: Some("connection refused".to_string()),
+                ..BitcoinRpcHealth::default()
+            })
+        );
+    }
+    #[tokio::test]
+    async fn default_policy_retries_until_shutdown() {
+        let task_group = fedimint_api::task::TaskGroup::new();
+        let client = RetryClient::new(Unreachable, task_group.make_handle());
+
+        assert!(
+            fedimint_api::task::timeout(Duration::from_secs(1), client.get_block_height())
+                .await
+                .is_err(),
+            "Gave up retrying before shutdown"
+        );
+
+        task_group.shutdown().await;
+        assert!(client.get_block_height().await.is_err());
+    }
 }
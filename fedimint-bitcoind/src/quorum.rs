@@ -0,0 +1,573 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use anyhow::format_err;
+use futures::future::join_all;
+use tracing::warn;
+
+use super::*;
+
+/// Default upper bound for a single call to a backend of a [`QuorumClient`]
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// [`IBitcoindRpc`] that queries several backends and only returns data at least `threshold` of
+/// them agree on
+///
+/// This way a single compromised or lagging backend can't feed bad chain data to the wallet
+/// module. Disagreements are logged, counted in [`IBitcoindRpc::health`] and turned into errors if
+/// no `threshold` backends agree.
+///
+/// Backends should not retry failed calls themselves since a single unreachable backend would
+/// then stall the whole quorum. Instead every call is bounded by a timeout and the quorum as a
+/// whole should be wrapped in a [`RetryClient`].
+#[derive(Debug)]
+pub struct QuorumClient {
+    backends: Vec<DynBitcoindRpc>,
+    threshold: usize,
+    call_timeout: Duration,
+    health: Mutex<BitcoinRpcHealth>,
+}
+
+impl QuorumClient {
+    /// Creates a client requiring a simple majority of `backends` to agree
+    ///
+    /// Note that a majority of two backends is both of them, so neither of them may fail.
+    pub fn new(backends: Vec<DynBitcoindRpc>) -> Self {
+        let threshold = backends.len() / 2 + 1;
+        Self::with_threshold(backends, threshold)
+    }
+
+    /// Creates a client requiring `threshold` of `backends` to agree
+    ///
+    /// With a `threshold` equal to the number of backends a single unreachable backend makes every
+    /// call fail. With a `threshold` of at most half of them different responses may each reach
+    /// it, which is treated as the backends not agreeing.
+    pub fn with_threshold(backends: Vec<DynBitcoindRpc>, threshold: usize) -> Self {
+        assert!(
+            0 < threshold && threshold <= backends.len(),
+            "Quorum threshold must be between 1 and the number of backends"
+        );
+        let health = BitcoinRpcHealth {
+            backends: vec![BitcoinRpcHealth::default(); backends.len()],
+            ..BitcoinRpcHealth::default()
+        };
+        Self {
+            backends,
+            threshold,
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            health: Mutex::new(health),
+        }
+    }
+
+    /// Sets the upper bound for a single call to a backend
+    ///
+    /// Note that backends using blocking IO can't be interrupted, they need to be configured with
+    /// a timeout of their own.
+    pub fn with_call_timeout(mut self, call_timeout: Duration) -> Self {
+        self.call_timeout = call_timeout;
+        self
+    }
+
+    /// Awaits the `response` of the `idx`-th backend to `call`, giving up after the call timeout,
+    /// and records the result in the backend's health
+    async fn call_backend<T, R>(&self, idx: usize, call: &str, response: R) -> Result<T>
+    where
+        R: Future<Output = Result<T>>,
+    {
+        let response = fedimint_api::task::timeout(self.call_timeout, response)
+            .await
+            .unwrap_or_else(|_| Err(format_err!("Timed out after {:?}", self.call_timeout)));
+
+        let mut health = self.health.lock().expect("poisoned");
+        let backend_health = &mut health.backends[idx];
+        match &response {
+            Ok(_) => {
+                backend_health.successful_calls += 1;
+                backend_health.consecutive_failures = 0;
+            }
+            Err(error) => {
+                warn!(backend = idx, %error, "Bitcoin rpc backend failed on {call}");
+                backend_health.failed_calls += 1;
+                backend_health.failed_attempts += 1;
+                backend_health.consecutive_failures += 1;
+                backend_health.last_error = Some(error.to_string());
+            }
+        }
+
+        response
+    }
+
+    /// Logs and counts backends returning different responses to `call`
+    fn record_disagreement(&self, call: &str, responses: &impl Debug) {
+        warn!(?responses, "Bitcoin rpc backends disagree on {call}");
+        let mut health = self.health.lock().expect("poisoned");
+        health.disagreements += 1;
+        health.last_disagreement = Some(format!("{call}: {responses:?}"));
+    }
+
+    /// Runs `call_fn` against all backends, returning the successful responses if there are at
+    /// least `threshold` of them
+    async fn query_all<'a, T, F, R>(&'a self, call: &str, call_fn: F) -> Result<Vec<T>>
+    where
+        F: Fn(&'a DynBitcoindRpc) -> R,
+        R: Future<Output = Result<T>>,
+    {
+        let responses = join_all(
+            self.backends
+                .iter()
+                .enumerate()
+                .map(|(idx, backend)| self.call_backend(idx, call, call_fn(backend))),
+        )
+        .await
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+
+        if responses.len() < self.threshold {
+            return Err(format_err!(
+                "Only {} of {} bitcoin rpc backends answered {call}, {} required",
+                responses.len(),
+                self.backends.len(),
+                self.threshold
+            ));
+        }
+
+        Ok(responses)
+    }
+
+    /// Returns the response given by at least `threshold` backends
+    async fn query_agreeing<'a, T, F, R>(&'a self, call: &str, call_fn: F) -> Result<T>
+    where
+        T: Debug + Eq + Hash,
+        F: Fn(&'a DynBitcoindRpc) -> R,
+        R: Future<Output = Result<T>>,
+    {
+        let mut counts = HashMap::<T, usize>::new();
+        for response in self.query_all(call, call_fn).await? {
+            *counts.entry(response).or_default() += 1;
+        }
+
+        if counts.len() > 1 {
+            self.record_disagreement(call, &counts);
+        }
+
+        let mut agreeing = counts
+            .into_iter()
+            .filter(|(_, count)| *count >= self.threshold)
+            .map(|(response, _)| response);
+
+        match (agreeing.next(), agreeing.next()) {
+            (Some(response), None) => Ok(response),
+            (Some(_), Some(_)) => Err(format_err!(
+                "Several responses to {call} reached the quorum of {} bitcoin rpc backends",
+                self.threshold
+            )),
+            (None, _) => Err(format_err!(
+                "Fewer than {} bitcoin rpc backends agree on {call}",
+                self.threshold
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl IBitcoindRpc for QuorumClient {
+    fn backend_type(&self) -> BitcoinRpcBackendType {
+        if self
+            .backends
+            .iter()
+            .any(|backend| matches!(backend.backend_type(), BitcoinRpcBackendType::Bitcoind))
+        {
+            BitcoinRpcBackendType::Bitcoind
+        } else {
+            BitcoinRpcBackendType::Electrum
+        }
+    }
+
+    async fn get_network(&self) -> Result<Network> {
+        self.query_agreeing("get_network", |backend| backend.get_network())
+            .await
+    }
+
+    /// Returns the highest block height at least `threshold` backends have reached, so lagging
+    /// backends hold the height back instead of breaking the quorum
+    async fn get_block_height(&self) -> Result<u64> {
+        let mut heights = self
+            .query_all("get_block_height", |backend| backend.get_block_height())
+            .await?;
+        heights.sort_unstable_by(|a, b| b.cmp(a));
+
+        if heights.first() != heights.last() {
+            self.record_disagreement("get_block_height", &heights);
+        }
+
+        Ok(heights[self.threshold - 1])
+    }
+
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
+        self.query_agreeing("get_block_hash", |backend| backend.get_block_hash(height))
+            .await
+    }
+
+    /// Blocks commit to their content, so it is enough for a single backend to return a block
+    /// matching the requested hash
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
+        for (idx, backend) in self.backends.iter().enumerate() {
+            if !matches!(backend.backend_type(), BitcoinRpcBackendType::Bitcoind) {
+                continue;
+            }
+
+            match self
+                .call_backend(idx, "get_block", backend.get_block(hash))
+                .await
+            {
+                Ok(block) if block.block_hash() == *hash && block.check_merkle_root() => {
+                    return Ok(block)
+                }
+                Ok(block) => {
+                    warn!(
+                        backend = idx,
+                        requested = %hash,
+                        returned = %block.block_hash(),
+                        "Bitcoin rpc backend returned invalid block"
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+
+        bail!("No bitcoin rpc backend returned a valid block {hash}")
+    }
+
+    /// Returns the median of all fee rate estimates if at least `threshold` backends returned one
+    async fn get_fee_rate(&self, confirmation_target: u16) -> Result<Option<Feerate>> {
+        let mut fee_rates = self
+            .query_all("get_fee_rate", |backend| {
+                backend.get_fee_rate(confirmation_target)
+            })
+            .await?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if fee_rates.len() < self.threshold {
+            return Ok(None);
+        }
+
+        fee_rates.sort_unstable();
+        Ok(Some(fee_rates[fee_rates.len() / 2]))
+    }
+
+    /// Submits the transaction to all backends, succeeding if any of them accepted it
+    async fn submit_transaction(&self, transaction: Transaction) -> Result<()> {
+        let results = join_all(self.backends.iter().enumerate().map(|(idx, backend)| {
+            self.call_backend(
+                idx,
+                "submit_transaction",
+                backend.submit_transaction(transaction.clone()),
+            )
+        }))
+        .await;
+
+        let mut last_error = None;
+        for result in results {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.expect("There is at least one backend"))
+    }
+
+    async fn was_transaction_confirmed_in(
+        &self,
+        transaction: &Transaction,
+        height: u64,
+    ) -> Result<bool> {
+        self.query_agreeing("was_transaction_confirmed_in", |backend| {
+            backend.was_transaction_confirmed_in(transaction, height)
+        })
+        .await
+    }
+
+    /// Payments come with inclusion proofs the caller checks against the block hash it has
+    /// consensus on, so the responses of all electrum backends are merged instead of requiring
+    /// them to agree. That way a single backend can't hide a payment.
+    async fn get_script_payments_in(
+        &self,
+        script: &Script,
+        height: u64,
+    ) -> Result<Vec<ScriptPayment>> {
+        let mut payments = Vec::<ScriptPayment>::new();
+        let mut answered = 0;
+        for (idx, backend) in self.backends.iter().enumerate() {
+            if !matches!(backend.backend_type(), BitcoinRpcBackendType::Electrum) {
+                continue;
+            }
+
+            match self
+                .call_backend(
+                    idx,
+                    "get_script_payments_in",
+                    backend.get_script_payments_in(script, height),
+                )
+                .await
+            {
+                Ok(response) => {
+                    answered += 1;
+                    for payment in response {
+                        if !payments.iter().any(|known| {
+                            known.transaction.txid() == payment.transaction.txid()
+                                && known.block_header == payment.block_header
+                        }) {
+                            payments.push(payment);
+                        }
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+
+        if answered == 0 {
+            bail!("No electrum backend answered get_script_payments_in");
+        }
+
+        Ok(payments)
+    }
+
+    fn health(&self) -> Option<BitcoinRpcHealth> {
+        Some(self.health.lock().expect("poisoned").clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash as BitcoinHash;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct FixedBackend {
+        height: u64,
+        hash: BlockHash,
+        fee_rate: Option<Feerate>,
+    }
+
+    #[async_trait]
+    impl IBitcoindRpc for FixedBackend {
+        async fn get_network(&self) -> Result<Network> {
+            Ok(Network::Regtest)
+        }
+
+        async fn get_block_height(&self) -> Result<u64> {
+            Ok(self.height)
+        }
+
+        async fn get_block_hash(&self, _height: u64) -> Result<BlockHash> {
+            Ok(self.hash)
+        }
+
+        async fn get_block(&self, _hash: &BlockHash) -> Result<Block> {
+            bail!("not implemented")
+        }
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> Result<Option<Feerate>> {
+            Ok(self.fee_rate)
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn backend(height: u64, hash_byte: u8, sats_per_kvb: Option<u64>) -> DynBitcoindRpc {
+        FixedBackend {
+            height,
+            hash: BlockHash::from_inner([hash_byte; 32]),
+            fee_rate: sats_per_kvb.map(|sats_per_kvb| Feerate { sats_per_kvb }),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn majority_wins_on_block_hash() {
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, None),
+            backend(100, 1, None),
+            backend(100, 2, None),
+        ]);
+        assert_eq!(
+            quorum.get_block_hash(100).await.unwrap(),
+            BlockHash::from_inner([1; 32])
+        );
+
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, None),
+            backend(100, 2, None),
+            backend(100, 3, None),
+        ]);
+        assert!(quorum.get_block_hash(100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn disagreements_are_reported_in_health() {
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, None),
+            backend(100, 1, None),
+            backend(100, 2, None),
+        ]);
+        quorum.get_block_hash(100).await.unwrap();
+
+        let health = quorum.health().unwrap();
+        assert_eq!(health.disagreements, 1);
+        assert!(health
+            .last_disagreement
+            .unwrap()
+            .starts_with("get_block_hash"));
+        assert_eq!(health.backends.len(), 3);
+        assert!(health
+            .backends
+            .iter()
+            .all(|backend| backend.successful_calls == 1));
+    }
+
+    #[derive(Debug)]
+    struct HangingBackend;
+
+    #[async_trait]
+    impl IBitcoindRpc for HangingBackend {
+        async fn get_network(&self) -> Result<Network> {
+            futures::future::pending().await
+        }
+
+        async fn get_block_height(&self) -> Result<u64> {
+            futures::future::pending().await
+        }
+
+        async fn get_block_hash(&self, _height: u64) -> Result<BlockHash> {
+            futures::future::pending().await
+        }
+
+        async fn get_block(&self, _hash: &BlockHash) -> Result<Block> {
+            futures::future::pending().await
+        }
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> Result<Option<Feerate>> {
+            futures::future::pending().await
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) -> Result<()> {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn hanging_backend_times_out() {
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, None),
+            backend(100, 1, None),
+            HangingBackend.into(),
+        ])
+        .with_call_timeout(Duration::from_millis(10));
+        assert_eq!(
+            quorum.get_block_hash(100).await.unwrap(),
+            BlockHash::from_inner([1; 32])
+        );
+
+        let health = quorum.health().unwrap();
+        assert_eq!(health.backends[2].failed_calls, 1);
+        assert!(health.backends[2]
+            .last_error
+            .as_ref()
+            .unwrap()
+            .starts_with("Timed out"));
+    }
+
+    #[derive(Debug)]
+    struct FailingBackend;
+
+    #[async_trait]
+    impl IBitcoindRpc for FailingBackend {
+        async fn get_network(&self) -> Result<Network> {
+            bail!("unreachable")
+        }
+
+        async fn get_block_height(&self) -> Result<u64> {
+            bail!("unreachable")
+        }
+
+        async fn get_block_hash(&self, _height: u64) -> Result<BlockHash> {
+            bail!("unreachable")
+        }
+
+        async fn get_block(&self, _hash: &BlockHash) -> Result<Block> {
+            bail!("unreachable")
+        }
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> Result<Option<Feerate>> {
+            bail!("unreachable")
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) -> Result<()> {
+            bail!("unreachable")
+        }
+    }
+
+    #[tokio::test]
+    async fn one_of_two_backends_failing() {
+        let quorum =
+            QuorumClient::with_threshold(vec![backend(100, 1, None), FailingBackend.into()], 1);
+        assert_eq!(quorum.get_block_height().await.unwrap(), 100);
+        assert_eq!(
+            quorum.get_block_hash(100).await.unwrap(),
+            BlockHash::from_inner([1; 32])
+        );
+        assert_eq!(quorum.health().unwrap().backends[1].failed_calls, 2);
+
+        // A majority of two backends needs both of them
+        let quorum = QuorumClient::new(vec![backend(100, 1, None), FailingBackend.into()]);
+        assert!(quorum.get_block_height().await.is_err());
+        assert!(quorum.get_block_hash(100).await.is_err());
+
+        // Backends answering differently can't both be trusted
+        let quorum =
+            QuorumClient::with_threshold(vec![backend(100, 1, None), backend(100, 2, None)], 1);
+        assert!(quorum.get_block_hash(100).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lagging_backend_holds_back_height() {
+        let quorum = QuorumClient::with_threshold(
+            vec![
+                backend(105, 1, None),
+                backend(100, 1, None),
+                backend(90, 1, None),
+            ],
+            2,
+        );
+        assert_eq!(quorum.get_block_height().await.unwrap(), 100);
+    }
+
+    #[tokio::test]
+    async fn fee_rate_is_median() {
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, Some(1_000)),
+            backend(100, 1, Some(1_000_000)),
+            backend(100, 1, Some(2_000)),
+        ]);
+        assert_eq!(
+            quorum.get_fee_rate(6).await.unwrap(),
+            Some(Feerate {
+                sats_per_kvb: 2_000
+            })
+        );
+
+        let quorum = QuorumClient::new(vec![
+            backend(100, 1, Some(1_000)),
+            backend(100, 1, None),
+            backend(100, 1, None),
+        ]);
+        assert_eq!(quorum.get_fee_rate(6).await.unwrap(), None);
+    }
+}
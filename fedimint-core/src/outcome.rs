@@ -25,8 +25,8 @@ pub mod legacy {
     use fedimint_api::ServerModule;
     use fedimint_ln::contracts::incoming::OfferId;
     use fedimint_ln::contracts::{
-        AccountContractOutcome, ContractOutcome, DecryptedPreimage, OutgoingContractOutcome,
-        Preimage,
+        AccountContractOutcome, ContractOutcome, DecryptedPreimage, HtlcContractOutcome,
+        OutgoingContractOutcome, Preimage,
     };
     use fedimint_ln::{Lightning, LightningOutputOutcome};
     use fedimint_mint::{Mint, MintOutputOutcome};
@@ -164,4 +164,18 @@ pub mod legacy {
             }
         }
     }
+
+    impl TryIntoOutcome for HtlcContractOutcome {
+        fn try_into_outcome(common_outcome: OutputOutcome) -> Result<Self, CoreError> {
+            if let OutputOutcome::LN(fedimint_ln::LightningOutputOutcome::Contract {
+                outcome: ContractOutcome::Htlc(o),
+                ..
+            }) = common_outcome
+            {
+                Ok(o)
+            } else {
+                Err(CoreError::MismatchingVariant("ln::htlc", "other"))
+            }
+        }
+    }
 }
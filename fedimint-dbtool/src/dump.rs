@@ -262,16 +262,6 @@ impl<'a> DatabaseDump<'a> {
                         "Lightning Gateways"
                     );
                 }
-                ClientLightningRange::DbKeyPrefix::OutgoingContractAccount => {
-                    push_db_pair_items!(
-                        dbtx,
-                        ClientLightningRange::OutgoingContractAccountKeyPrefix,
-                        ClientLightningRange::OutgoingContractAccountKey,
-                        mint_client::ln::outgoing::OutgoingContractAccount,
-                        ln_client,
-                        "Outgoing Contract Accounts"
-                    );
-                }
                 ClientLightningRange::DbKeyPrefix::OutgoingPayment => {
                     push_db_pair_items!(
                         dbtx,
@@ -291,6 +281,86 @@ impl<'a> DatabaseDump<'a> {
                         "Outgoing Payment Claims"
                     );
                 }
+                ClientLightningRange::DbKeyPrefix::OutgoingContractAccount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::OutgoingContractAccountKeyPrefix,
+                        ClientLightningRange::OutgoingContractAccountKey,
+                        mint_client::ln::outgoing::OutgoingContractAccount,
+                        ln_client,
+                        "Outgoing Contract Accounts"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::ReusableOffer => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::ReusableOfferKeyPrefix,
+                        ClientLightningRange::ReusableOfferKey,
+                        mint_client::ln::offer::ReusableOffer,
+                        ln_client,
+                        "Reusable Offers"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::GatewayOffer => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::GatewayOfferKeyPrefix,
+                        ClientLightningRange::GatewayOfferKey,
+                        mint_client::ln::offer::GatewayOffer,
+                        ln_client,
+                        "Gateway Offers"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::LightningAddress => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::LightningAddressKeyPrefix,
+                        ClientLightningRange::LightningAddressKey,
+                        mint_client::ln::offer::ReusableOfferId,
+                        ln_client,
+                        "Lightning Addresses"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::GatewayPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::GatewayPaymentKeyPrefix,
+                        ClientLightningRange::GatewayPaymentKey,
+                        mint_client::ln::outgoing::GatewayPayment,
+                        ln_client,
+                        "Gateway Payments"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::InternalPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::InternalPaymentKeyPrefix,
+                        ClientLightningRange::InternalPaymentKey,
+                        mint_client::ln::outgoing::InternalPaymentData,
+                        ln_client,
+                        "Internal Payments"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::LightningPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::LightningPaymentKeyPrefix,
+                        ClientLightningRange::LightningPaymentKey,
+                        mint_client::ln::history::LightningPayment,
+                        ln_client,
+                        "Lightning Payments"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::OutgoingRefund => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::OutgoingRefundKeyPrefix,
+                        ClientLightningRange::OutgoingRefundKey,
+                        fedimint_api::OutPoint,
+                        ln_client,
+                        "Outgoing Refunds"
+                    );
+                }
             }
         }
 
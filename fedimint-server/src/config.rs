@@ -17,6 +17,7 @@ use fedimint_api::task::{timeout, Elapsed, TaskGroup};
 use fedimint_api::{Amount, PeerId};
 pub use fedimint_core::config::*;
 use fedimint_core::modules::mint::MintGenParams;
+use fedimint_wallet::config::PegInDescriptorType;
 use fedimint_wallet::WalletGenParams;
 use hbbft::crypto::serde_impl::SerdeSecret;
 use hbbft::NetworkInfo;
@@ -631,6 +632,7 @@ impl ServerConfigParams {
         federation_name: String,
         network: bitcoin::network::constants::Network,
         finality_delay: u32,
+        descriptor_type: PegInDescriptorType,
     ) -> ServerConfigParams {
         let peer_certs: HashMap<PeerId, rustls::Certificate> = peers
             .iter()
@@ -659,6 +661,7 @@ impl ServerConfigParams {
                     network,
                     // TODO this is not very elegant, but I'm planning to get rid of it in a next commit anyway
                     finality_delay,
+                    descriptor_type,
                 })
                 .attach(MintGenParams {
                     mint_amounts: ServerConfigParams::gen_denominations(max_denomination),
@@ -691,6 +694,7 @@ impl ServerConfigParams {
         max_denomination: Amount,
         base_port: u16,
         federation_name: &str,
+        descriptor_type: PegInDescriptorType,
     ) -> anyhow::Result<HashMap<PeerId, ServerConfigParams>> {
         let keys: HashMap<PeerId, (rustls::Certificate, rustls::PrivateKey)> = peers
             .iter()
@@ -733,6 +737,7 @@ impl ServerConfigParams {
                     federation_name.to_string(),
                     bitcoin::network::constants::Network::Regtest,
                     10,
+                    descriptor_type,
                 );
                 Ok((*peer, params))
             })
@@ -112,6 +112,17 @@ impl FakeBitcoindRpcController {
             })
     }
 
+    /// Transactions that were submitted but not added to a block yet
+    pub async fn pending_transactions(&self) -> Vec<Transaction> {
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     pub async fn add_pending_tx_to_block(&self, block: u64) {
         let block_hash = height_hash(block);
         let mut state = self.state.lock().unwrap();
@@ -10,6 +10,7 @@ use fedimint_api::task::TaskGroup;
 use fedimint_api::Amount;
 use fedimint_ln::LightningGen;
 use fedimint_mint::MintGen;
+use fedimint_wallet::config::PegInDescriptorType;
 use fedimint_wallet::WalletGen;
 use fedimintd::distributedgen::{create_cert, run_dkg};
 use fedimintd::encrypt::*;
@@ -87,6 +88,11 @@ enum Command {
         #[arg(long = "finalty", default_value = "10")]
         finality_delay: u32,
 
+        /// Script type of the peg-in descriptor, either `wsh` or `tr` (taproot with threshold
+        /// key path signing)
+        #[arg(long = "peg-in-descriptor", default_value = "wsh")]
+        descriptor_type: PegInDescriptorType,
+
         /// The password that encrypts the configs, will prompt if not passed in
         #[arg(env = "FM_PASSWORD")]
         password: Option<String>,
@@ -160,6 +166,7 @@ async fn main() -> anyhow::Result<()> {
             max_denomination,
             network,
             finality_delay,
+            descriptor_type,
             password,
         } => {
             let key = get_key(password, dir_out_path.join(SALT_FILE))?;
@@ -173,6 +180,7 @@ async fn main() -> anyhow::Result<()> {
                 certs,
                 network,
                 finality_delay,
+                descriptor_type,
                 rustls::PrivateKey(pk_bytes),
                 &mut task_group,
             )
@@ -13,6 +13,7 @@ use fedimint_ln::LightningGen;
 use fedimint_mint::MintGen;
 use fedimint_server::config::{PeerServerParams, ServerConfig, ServerConfigParams};
 use fedimint_server::multiplexed::PeerConnectionMultiplexer;
+use fedimint_wallet::config::PegInDescriptorType;
 use fedimint_wallet::WalletGen;
 use itertools::Itertools;
 use rand::rngs::OsRng;
@@ -46,6 +47,7 @@ pub async fn run_dkg(
     certs: Vec<String>,
     network: bitcoin::network::constants::Network,
     finality_delay: u32,
+    descriptor_type: PegInDescriptorType,
     pk: rustls::PrivateKey,
     task_group: &mut TaskGroup,
 ) -> anyhow::Result<ServerConfig> {
@@ -73,6 +75,7 @@ pub async fn run_dkg(
         federation_name,
         network,
         finality_delay,
+        descriptor_type,
     );
     let peer_ids: Vec<PeerId> = peers.keys().cloned().collect();
     let server_conn = fedimint_server::config::connect(
@@ -17,6 +17,7 @@ use fedimint_api::config::{ClientConfig, ModuleGenRegistry};
 use fedimint_api::task::TaskGroup;
 use fedimint_api::Amount;
 use fedimint_core::util::SanitizedUrl;
+use fedimint_wallet::config::PegInDescriptorType;
 use http::StatusCode;
 use mint_client::api::WsFederationConnect;
 use qrcode_generator::QrCodeEcc;
@@ -183,6 +184,7 @@ async fn post_guardians(
                 connection_strings,
                 params.network,
                 params.finality_delay,
+                PegInDescriptorType::default(),
                 rustls::PrivateKey(pk_bytes),
                 &mut dkg_task_group,
             )
@@ -240,6 +242,28 @@ async fn params_page(
                 let url_str = format!("{}", SanitizedUrl::new_borrowed(&url));
                 ("electrum", url_str)
             }
+            Ok(BitcoindRpcBackend::Esplora(url)) => {
+                let url_str = format!("{}", SanitizedUrl::new_borrowed(&url));
+                ("esplora", url_str)
+            }
+            Ok(BitcoindRpcBackend::Quorum {
+                backends,
+                threshold,
+            }) => {
+                let url_str = backends
+                    .iter()
+                    .filter_map(|backend| match backend {
+                        BitcoindRpcBackend::Bitcoind(url)
+                        | BitcoindRpcBackend::Electrum(url)
+                        | BitcoindRpcBackend::Esplora(url) => {
+                            Some(format!("{}", SanitizedUrl::new_borrowed(url)))
+                        }
+                        BitcoindRpcBackend::Quorum { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                ("quorum", format!("{threshold} of {url_str}"))
+            }
             Err(e) => ("error", e.to_string()),
         };
     UrlConnection {
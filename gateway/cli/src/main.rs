@@ -1,14 +1,16 @@
 use std::{net::SocketAddr, path::PathBuf};
 
-use bitcoin::{Address, Amount, Transaction};
+use bitcoin::{Address, Amount, Network, Transaction};
 use clap::{Parser, Subcommand};
 use fedimint_api::config::FederationId;
+use fedimint_server::modules::ln::{GatewayFees, RoutingFee};
 use fedimint_server::modules::wallet::txoproof::TxOutProof;
 use ln_gateway::{
-    config::GatewayConfig,
+    config::{GatewayConfig, LdkConfig, LightningBackend, LndConfig},
     rpc::{
-        rpc_client::RpcClient, BackupPayload, BalancePayload, ConnectFedPayload,
-        DepositAddressPayload, DepositPayload, RestorePayload, WithdrawPayload,
+        rpc_client::RpcClient, BackupPayload, BalancePayload, CloseChannelPayload,
+        ConnectFedPayload, DepositAddressPayload, DepositPayload, ListChannelsPayload,
+        OnchainAddressPayload, OpenChannelPayload, RestorePayload, WithdrawPayload,
     },
 };
 use mint_client::utils::from_hex;
@@ -38,6 +40,34 @@ pub enum Commands {
         announce_address: Url,
         /// The gateway configuration directory
         out_dir: PathBuf,
+        /// Base fee in msat charged for receiving payments
+        #[clap(long, default_value_t = 0)]
+        incoming_fee_base_msat: u32,
+        /// Proportional fee in millionths charged for receiving payments
+        #[clap(long, default_value_t = 0)]
+        incoming_fee_proportional_millionths: u32,
+        /// Base fee in msat charged for paying invoices
+        #[clap(long, default_value_t = 0)]
+        outgoing_fee_base_msat: u32,
+        /// Proportional fee in millionths charged for paying invoices
+        #[clap(long, default_value_t = 0)]
+        outgoing_fee_proportional_millionths: u32,
+        /// gRPC endpoint of LND, makes the gateway use LND instead of running as a CLN plugin
+        #[clap(long)]
+        lnd_rpc_address: Option<Url>,
+        /// LND's TLS certificate, omit to connect to LND without TLS
+        #[clap(long, requires = "lnd_rpc_address")]
+        lnd_tls_cert: Option<PathBuf>,
+        /// Macaroon authorizing the gateway's calls to LND, omit if LND runs with `--no-macaroons`
+        #[clap(long, requires = "lnd_rpc_address")]
+        lnd_macaroon: Option<PathBuf>,
+        /// Address the embedded LDK node accepts peer connections on, makes the gateway run its
+        /// own LDK node instead of running as a CLN plugin
+        #[clap(long, conflicts_with = "lnd_rpc_address")]
+        ldk_listen_address: Option<SocketAddr>,
+        /// Bitcoin network of the embedded LDK node
+        #[clap(long, requires = "ldk_listen_address", default_value_t = Network::Regtest)]
+        ldk_network: Network,
     },
     /// Display CLI version hash
     VersionHash,
@@ -73,6 +103,27 @@ pub enum Commands {
     Backup { federation_id: FederationId },
     /// Restore ecash from last available snapshot or from scratch
     Restore { federation_id: FederationId },
+    /// Generate an address to fund the embedded LDK node's on-chain wallet
+    OnchainAddress,
+    /// Open a channel funded by the embedded LDK node's on-chain wallet
+    OpenChannel {
+        /// Public key of the node to open the channel to
+        node_id: bitcoin::secp256k1::PublicKey,
+        /// Address the node accepts peer connections on
+        address: SocketAddr,
+        /// The amount to fund the channel with
+        amount: Amount,
+    },
+    /// Close a channel of the embedded LDK node
+    CloseChannel {
+        /// Hex encoded id of the channel, as shown by `list-channels`
+        channel_id: String,
+        /// Close unilaterally, e.g. if the peer is offline
+        #[clap(long)]
+        force: bool,
+    },
+    /// List the embedded LDK node's channels and on-chain balance
+    ListChannels,
 }
 
 #[tokio::main]
@@ -85,12 +136,34 @@ async fn main() {
             bind_address: address,
             announce_address,
             mut out_dir,
+            incoming_fee_base_msat,
+            incoming_fee_proportional_millionths,
+            outgoing_fee_base_msat,
+            outgoing_fee_proportional_millionths,
+            lnd_rpc_address,
+            lnd_tls_cert,
+            lnd_macaroon,
+            ldk_listen_address,
+            ldk_network,
         } => {
             // Recursively create config directory if it doesn't exist
             std::fs::create_dir_all(&out_dir).expect("Failed to create config directory");
             // Create config file
             out_dir.push("gateway.config");
 
+            let lightning = match (lnd_rpc_address, ldk_listen_address) {
+                (Some(rpc_address), _) => LightningBackend::Lnd(LndConfig {
+                    rpc_address,
+                    tls_cert: lnd_tls_cert,
+                    macaroon: lnd_macaroon,
+                }),
+                (None, Some(listen_address)) => LightningBackend::Ldk(LdkConfig {
+                    network: ldk_network,
+                    listen_address,
+                }),
+                (None, None) => LightningBackend::Cln,
+            };
+
             let cfg_file =
                 std::fs::File::create(out_dir).expect("Failed to create gateway config file");
             serde_json::to_writer_pretty(
@@ -100,6 +173,17 @@ async fn main() {
                     announce_address,
                     // TODO: Generate a strong random password
                     password: source_password(cli.rpcpassword),
+                    fees: GatewayFees {
+                        incoming: RoutingFee {
+                            base_msat: incoming_fee_base_msat,
+                            proportional_millionths: incoming_fee_proportional_millionths,
+                        },
+                        outgoing: RoutingFee {
+                            base_msat: outgoing_fee_base_msat,
+                            proportional_millionths: outgoing_fee_proportional_millionths,
+                        },
+                    },
+                    lightning,
                 },
             )
             .expect("Failed to write gateway configs to file");
@@ -208,6 +292,52 @@ async fn main() {
 
             print_response(response).await;
         }
+        Commands::OnchainAddress => {
+            let response = client
+                .get_onchain_address(source_password(cli.rpcpassword), OnchainAddressPayload)
+                .await
+                .expect("Failed to get on-chain address");
+
+            print_response(response).await;
+        }
+        Commands::OpenChannel {
+            node_id,
+            address,
+            amount,
+        } => {
+            let response = client
+                .open_channel(
+                    source_password(cli.rpcpassword),
+                    OpenChannelPayload {
+                        node_id,
+                        address,
+                        amount,
+                    },
+                )
+                .await
+                .expect("Failed to open channel");
+
+            print_response(response).await;
+        }
+        Commands::CloseChannel { channel_id, force } => {
+            let response = client
+                .close_channel(
+                    source_password(cli.rpcpassword),
+                    CloseChannelPayload { channel_id, force },
+                )
+                .await
+                .expect("Failed to close channel");
+
+            print_response(response).await;
+        }
+        Commands::ListChannels => {
+            let response = client
+                .list_channels(source_password(cli.rpcpassword), ListChannelsPayload)
+                .await
+                .expect("Failed to list channels");
+
+            print_response(response).await;
+        }
     }
 }
 
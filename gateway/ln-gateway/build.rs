@@ -8,7 +8,21 @@ fn main() {
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
-        .compile(&[proto_path], &[include_path])
+        .compile(&[proto_path], &[include_path.clone()])
         .unwrap_or_else(|e| panic!("failed to compile gateway proto files: {e}"));
+
+    // Subset of LND's gRPC API used by the LND backend, the server is only used by tests
+    let lnd_include_path = include_path.join("lnd");
+    tonic_build::configure()
+        .build_server(true)
+        .build_client(true)
+        .compile(
+            &[
+                lnd_include_path.join("lightning.proto"),
+                lnd_include_path.join("router.proto"),
+            ],
+            &[lnd_include_path],
+        )
+        .unwrap_or_else(|e| panic!("failed to compile LND proto files: {e}"));
     fedimint_build::print_git_hash();
 }
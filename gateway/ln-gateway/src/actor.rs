@@ -3,22 +3,40 @@ use std::{sync::Arc, time::Duration};
 use bitcoin::{Address, Transaction};
 use bitcoin_hashes::sha256;
 use fedimint_api::{task::TaskGroup, Amount, OutPoint, TransactionId};
+use fedimint_server::modules::ln::contracts::outgoing::PrunedInvoice;
 use fedimint_server::modules::ln::route_hints::RouteHint;
+use fedimint_server::modules::ln::GatewayLiquidity;
 use fedimint_server::modules::{
     ln::contracts::{ContractId, Preimage},
     wallet::txoproof::TxOutProof,
 };
-use mint_client::{GatewayClient, PaymentParameters};
+use lightning_invoice::Invoice;
+use mint_client::ln::offer::{
+    GatewayOffer, RegisterLightningAddressPayload, RegisterOfferPayload, RegisterOfferResponse,
+    ReusableOfferId,
+};
+use mint_client::ln::outgoing::{GatewayPayment, GatewayPaymentState};
+use mint_client::{ClientError, GatewayClient, PaymentParameters};
 use rand::{CryptoRng, RngCore};
 use tracing::{debug, info, instrument, warn};
 
-use crate::{ln::LnRpc, rpc::FederationInfo, utils::retry, LnGatewayError, Result};
+use crate::ln::{LnRpc, PaymentStatus};
+use crate::{
+    rpc::{FederationInfo, FederationProbe},
+    utils::retry,
+    LnGatewayError, Result,
+};
 
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
 
+/// How often we ask the lightning node for the outcome of a payment that was in flight when we
+/// stopped
+const PAYMENT_STATUS_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct GatewayActor {
     client: Arc<GatewayClient>,
+    route_hints: Vec<RouteHint>,
 }
 
 #[derive(Debug, Clone)]
@@ -28,18 +46,42 @@ pub enum BuyPreimage {
 }
 
 impl GatewayActor {
-    pub async fn new(client: Arc<GatewayClient>, route_hints: Vec<RouteHint>) -> Result<Self> {
+    pub async fn new(
+        client: Arc<GatewayClient>,
+        route_hints: Vec<RouteHint>,
+        ln_rpc: Arc<dyn LnRpc>,
+    ) -> Result<Self> {
         let register_client = client.clone();
+        let register_route_hints = route_hints.clone();
+        let issues_invoices = ln_rpc.issues_invoices();
+        let supports_mpp = ln_rpc.supports_mpp();
         tokio::spawn(async move {
             loop {
+                // Publish our current liquidity with every registration
+                let liquidity = match ln_rpc.channel_balances().await {
+                    Ok(balances) => Some(GatewayLiquidity::from_balances(
+                        balances.outbound,
+                        balances.inbound,
+                    )),
+                    Err(e) => {
+                        warn!("Failed to fetch channel balances: {:?}", e);
+                        None
+                    }
+                };
+
                 // Retry gateway registration
                 match retry(
                     String::from("Register With Federation"),
                     #[allow(clippy::unit_arg)]
                     || async {
-                        let gateway_registration = register_client
-                            .config()
-                            .to_gateway_registration_info(route_hints.clone(), GW_ANNOUNCEMENT_TTL);
+                        let gateway_registration =
+                            register_client.config().to_gateway_registration_info(
+                                register_route_hints.clone(),
+                                liquidity,
+                                issues_invoices,
+                                supports_mpp,
+                                GW_ANNOUNCEMENT_TTL,
+                            );
                         Ok(register_client
                             .register_with_federation(gateway_registration.clone())
                             .await?)
@@ -61,7 +103,10 @@ impl GatewayActor {
             }
         });
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            route_hints,
+        })
     }
 
     async fn fetch_all_notes(&self) {
@@ -97,10 +142,12 @@ impl GatewayActor {
         &self,
         ln_rpc: Arc<dyn LnRpc>,
         contract_id: ContractId,
-    ) -> Result<OutPoint> {
-        self.pay_invoice_buy_preimage_finalize_and_claim(
+        part_amount: Option<Amount>,
+    ) -> Result<(OutPoint, Preimage)> {
+        self.finalize_and_claim(
             contract_id,
-            self.pay_invoice_buy_preimage(ln_rpc, contract_id).await?,
+            self.pay_invoice_buy_preimage(ln_rpc, contract_id, part_amount)
+                .await?,
         )
         .await
     }
@@ -110,13 +157,14 @@ impl GatewayActor {
         &self,
         ln_rpc: Arc<dyn LnRpc>,
         contract_id: ContractId,
+        part_amount: Option<Amount>,
     ) -> Result<BuyPreimage> {
         info!("Fetching contract");
         let contract_account = self.client.fetch_outgoing_contract(contract_id).await?;
 
         let payment_params = match self
             .client
-            .validate_outgoing_account(&contract_account)
+            .validate_outgoing_account(&contract_account, part_amount)
             .await
         {
             Ok(payment_params) => payment_params,
@@ -133,10 +181,6 @@ impl GatewayActor {
             "Fetched and validated contract account"
         );
 
-        self.client
-            .save_outgoing_payment(contract_account.clone())
-            .await;
-
         let is_internal_payment = payment_params.maybe_internal
             && self
                 .client
@@ -145,24 +189,59 @@ impl GatewayActor {
                 .await
                 .unwrap_or(false);
 
-        Ok(if is_internal_payment {
-            BuyPreimage::Internal(
-                self.buy_preimage_internal(
-                    &payment_params.payment_hash,
-                    &payment_params.invoice_amount,
-                )
-                .await?,
-            )
+        let state = if is_internal_payment {
+            GatewayPaymentState::BuyingOffer
         } else {
-            BuyPreimage::External(
-                self.buy_preimage_external(
-                    ln_rpc,
-                    contract_account.contract.invoice,
-                    &payment_params,
-                )
-                .await?,
-            )
-        })
+            GatewayPaymentState::PayingInvoice
+        };
+        self.client
+            .save_outgoing_payment(contract_account.clone(), part_amount, state)
+            .await;
+
+        let buy_preimage = if is_internal_payment {
+            self.buy_preimage_internal(&payment_params.payment_hash, &payment_params.invoice_amount)
+                .await
+                .map(BuyPreimage::Internal)
+        } else {
+            self.buy_preimage_external(ln_rpc, contract_account.contract.invoice, &payment_params)
+                .await
+                .map(BuyPreimage::External)
+        };
+
+        match buy_preimage {
+            Ok(buy_preimage) => {
+                self.save_buy_preimage(contract_id, &buy_preimage).await?;
+                Ok(buy_preimage)
+            }
+            Err(e) => {
+                warn!("Buying preimage failed. Aborting");
+                self.client.abort_outgoing_payment(contract_id).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Records the progress of buying the preimage, so a crash doesn't make us pay again
+    async fn save_buy_preimage(
+        &self,
+        contract_id: ContractId,
+        buy_preimage: &BuyPreimage,
+    ) -> Result<()> {
+        let state = match buy_preimage {
+            BuyPreimage::Internal((out_point, incoming_contract_id)) => {
+                GatewayPaymentState::AwaitingDecryption {
+                    out_point: *out_point,
+                    contract_id: *incoming_contract_id,
+                }
+            }
+            BuyPreimage::External(preimage) => GatewayPaymentState::Claiming {
+                preimage: preimage.clone(),
+            },
+        };
+        Ok(self
+            .client
+            .update_outgoing_payment(contract_id, state)
+            .await?)
     }
 
     pub async fn pay_invoice_buy_preimage_finalize(
@@ -184,16 +263,34 @@ impl GatewayActor {
         contract_id: ContractId,
         buy_preimage: BuyPreimage,
     ) -> Result<OutPoint> {
+        Ok(self.finalize_and_claim(contract_id, buy_preimage).await?.0)
+    }
+
+    /// Obtains the preimage and claims the contract with it, returning the claim's out point and
+    /// the preimage
+    async fn finalize_and_claim(
+        &self,
+        contract_id: ContractId,
+        buy_preimage: BuyPreimage,
+    ) -> Result<(OutPoint, Preimage)> {
         let rng = rand::rngs::OsRng;
 
         match self.pay_invoice_buy_preimage_finalize(buy_preimage).await {
             Ok(preimage) => {
+                self.client
+                    .update_outgoing_payment(
+                        contract_id,
+                        GatewayPaymentState::Claiming {
+                            preimage: preimage.clone(),
+                        },
+                    )
+                    .await?;
                 let outpoint = self
                     .client
-                    .claim_outgoing_contract(contract_id, preimage, rng)
+                    .claim_outgoing_contract(contract_id, preimage.clone(), rng)
                     .await?;
 
-                Ok(outpoint)
+                Ok((outpoint, preimage))
             }
             Err(e) => {
                 warn!("Invoice payment failed. Aborting");
@@ -204,6 +301,144 @@ impl GatewayActor {
         }
     }
 
+    /// Drives the payments that were in flight when the gateway stopped to completion
+    pub async fn resume_pending_payments(&self, ln_rpc: Arc<dyn LnRpc>) {
+        if let Err(e) = self.client.migrate_legacy_outgoing_payments().await {
+            warn!("Failed to migrate legacy outgoing payments: {}", e);
+        }
+
+        let payments = self.client.list_pending_outgoing().await;
+        if !payments.is_empty() {
+            info!("Resuming {} pending payments", payments.len());
+        }
+
+        futures::future::join_all(payments.into_iter().map(|payment| async {
+            let contract_id = payment.contract_account.contract.contract_id();
+            if let Err(e) = self.resume_payment(ln_rpc.clone(), payment).await {
+                warn!(%contract_id, "Failed to resume payment: {}", e);
+            }
+        }))
+        .await;
+    }
+
+    #[instrument(skip_all, fields(state = ?payment.state))]
+    async fn resume_payment(&self, ln_rpc: Arc<dyn LnRpc>, payment: GatewayPayment) -> Result<()> {
+        let contract_id = payment.contract_account.contract.contract_id();
+        let out_point = match payment.state {
+            GatewayPaymentState::PayingInvoice | GatewayPaymentState::BuyingOffer => {
+                let buy_preimage = match self.resume_buying(ln_rpc, &payment).await {
+                    Ok(buy_preimage) => buy_preimage,
+                    Err(e) => {
+                        warn!("Buying preimage failed. Aborting");
+                        self.client.abort_outgoing_payment(contract_id).await?;
+                        return Err(e);
+                    }
+                };
+                self.save_buy_preimage(contract_id, &buy_preimage).await?;
+                self.pay_invoice_buy_preimage_finalize_and_claim(contract_id, buy_preimage)
+                    .await?
+            }
+            GatewayPaymentState::AwaitingDecryption {
+                out_point,
+                contract_id: incoming_contract_id,
+            } => {
+                self.pay_invoice_buy_preimage_finalize_and_claim(
+                    contract_id,
+                    BuyPreimage::Internal((out_point, incoming_contract_id)),
+                )
+                .await?
+            }
+            GatewayPaymentState::Claiming { preimage } => {
+                self.pay_invoice_buy_preimage_finalize_and_claim(
+                    contract_id,
+                    BuyPreimage::External(preimage),
+                )
+                .await?
+            }
+            GatewayPaymentState::Claimed {
+                preimage,
+                out_point,
+            } => match self
+                .await_outgoing_contract_claimed(contract_id, out_point)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    // We may have stopped before submitting the claim transaction
+                    let account = self.client.fetch_outgoing_contract(contract_id).await?;
+                    if account.amount == Amount::ZERO {
+                        return Err(e);
+                    }
+                    info!("Claim transaction was lost, claiming again");
+                    self.client
+                        .claim_outgoing_contract(contract_id, preimage, rand::rngs::OsRng)
+                        .await?
+                }
+            },
+        };
+
+        self.await_outgoing_contract_claimed(contract_id, out_point)
+            .await
+    }
+
+    /// Finds out whether we obtained the preimage of a payment we stopped buying
+    ///
+    /// Offers are bought again if they still exist. For invoices we ask the lightning node for the
+    /// outcome of the payment, waiting for it while it's in flight. If the node has no record of a
+    /// successful or pending attempt, e.g. since we stopped before handing it the payment, we pay
+    /// the contract's invoice again.
+    async fn resume_buying(
+        &self,
+        ln_rpc: Arc<dyn LnRpc>,
+        payment: &GatewayPayment,
+    ) -> Result<BuyPreimage> {
+        let invoice = &payment.contract_account.contract.invoice;
+        let payment_hash = invoice.payment_hash;
+
+        if payment.state == GatewayPaymentState::BuyingOffer {
+            let invoice_amount = invoice.amount.ok_or(ClientError::InvoiceMissingAmount)?;
+            // FIXME: if we funded the offer's contract before stopping the offer is gone and we
+            // lost track of the contract, see `buy_preimage_offer`
+            return Ok(BuyPreimage::Internal(
+                self.buy_preimage_internal(&payment_hash, &invoice_amount)
+                    .await?,
+            ));
+        }
+
+        loop {
+            match ln_rpc.payment_status(payment_hash).await {
+                Ok(PaymentStatus::Succeeded(preimage)) => {
+                    return Ok(BuyPreimage::External(preimage))
+                }
+                Ok(PaymentStatus::Failed) => {
+                    info!("Lightning node didn't complete the payment, paying again");
+                    let payment_params = self
+                        .client
+                        .validate_outgoing_account(&payment.contract_account, payment.part_amount)
+                        .await?;
+                    return self
+                        .buy_preimage_external(ln_rpc, invoice.clone(), &payment_params)
+                        .await
+                        .map(BuyPreimage::External);
+                }
+                Ok(PaymentStatus::Pending) => debug!("Payment still in flight"),
+                Err(e) => warn!("Failed to fetch payment status: {:?}", e),
+            }
+
+            match self
+                .client
+                .is_outgoing_contract_timed_out(&payment.contract_account)
+                .await
+            {
+                Ok(true) => return Err(ClientError::TimeoutTooClose.into()),
+                Ok(false) => {}
+                // The payment may still succeed, so we don't give up on it while offline
+                Err(e) => warn!("Failed to check contract timeout: {}", e),
+            }
+            tokio::time::sleep(PAYMENT_STATUS_POLL_INTERVAL).await;
+        }
+    }
+
     #[instrument(skip(self), ret, err)]
     pub async fn buy_preimage_internal(
         &self,
@@ -229,8 +464,16 @@ impl GatewayActor {
     ) -> Result<Preimage> {
         let rng = rand::rngs::OsRng;
         info!("Awaiting decryption of preimage");
-        match self.client.await_preimage_decryption(out_point).await {
+        match self
+            .client
+            .await_incoming_contract_preimage(out_point, contract_id, rng)
+            .await
+        {
             Ok(preimage) => Ok(preimage),
+            // We already claimed back the funds of held contracts that weren't accepted
+            Err(error @ ClientError::HoldInvoiceNotAccepted) => {
+                Err(LnGatewayError::ClientError(error))
+            }
             Err(error) => {
                 warn!(%error, "Failed to decrypt preimage. Now requesting a refund");
                 self.client
@@ -245,17 +488,29 @@ impl GatewayActor {
     pub async fn buy_preimage_external(
         &self,
         ln_rpc: Arc<dyn LnRpc>,
-        invoice: lightning_invoice::Invoice,
+        invoice: PrunedInvoice,
         payment_params: &PaymentParameters,
     ) -> Result<Preimage> {
-        match ln_rpc
-            .pay(
-                invoice,
-                payment_params.max_delay,
-                payment_params.max_fee_percent(),
-            )
-            .await
-        {
+        let payment = if payment_params.payment_amount < payment_params.invoice_amount {
+            ln_rpc
+                .pay_part(
+                    invoice,
+                    payment_params.payment_amount,
+                    payment_params.max_delay,
+                    payment_params.max_fee_percent(),
+                )
+                .await
+        } else {
+            ln_rpc
+                .pay(
+                    invoice,
+                    payment_params.max_delay,
+                    payment_params.max_fee_percent(),
+                )
+                .await
+        };
+
+        match payment {
             Ok(preimage) => Ok(preimage),
             Err(e) => {
                 warn!("LN payment failed, aborting");
@@ -344,17 +599,74 @@ impl GatewayActor {
         Ok(())
     }
 
+    pub async fn register_offer(
+        &self,
+        payload: RegisterOfferPayload,
+    ) -> Result<RegisterOfferResponse> {
+        Ok(self.client.register_reusable_offer(payload).await?)
+    }
+
+    #[instrument(skip(self), err)]
+    pub async fn offer_invoice(
+        &self,
+        offer_id: ReusableOfferId,
+        amount: Amount,
+        description_hash: Option<sha256::Hash>,
+    ) -> Result<Invoice> {
+        let rng = rand::rngs::OsRng;
+
+        Ok(self
+            .client
+            .create_offer_invoice(
+                offer_id,
+                amount,
+                description_hash,
+                self.route_hints.clone(),
+                rng,
+            )
+            .await?)
+    }
+
+    pub async fn register_lightning_address(
+        &self,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<()> {
+        Ok(self.client.register_lightning_address(payload).await?)
+    }
+
+    pub async fn lookup_lightning_address(
+        &self,
+        username: &str,
+    ) -> Option<(ReusableOfferId, GatewayOffer)> {
+        self.client.lookup_lightning_address(username).await
+    }
+
     pub async fn get_balance(&self) -> Result<Amount> {
         self.fetch_all_notes().await;
 
         Ok(self.client.notes().await.total_amount())
     }
 
-    pub fn get_info(&self) -> Result<FederationInfo> {
+    pub async fn get_info(&self) -> Result<FederationInfo> {
         let cfg = self.client.config();
         Ok(FederationInfo {
             federation_id: cfg.client_config.federation_id.clone(),
             mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            balance: self.client.notes().await.total_amount(),
         })
     }
+
+    /// Fake short channel id the route hints of our federation's invoices point to
+    pub fn mint_channel_id(&self) -> u64 {
+        self.client.config().mint_channel_id
+    }
+
+    pub fn get_probe(&self) -> FederationProbe {
+        let cfg = self.client.config();
+        FederationProbe {
+            federation_id: cfg.client_config.federation_id.clone(),
+            mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            fees: cfg.fees,
+        }
+    }
 }
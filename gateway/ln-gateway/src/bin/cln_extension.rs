@@ -1,6 +1,6 @@
 use std::{
     array::TryFromSliceError, collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr,
-    sync::Arc, time::Duration,
+    sync::Arc,
 };
 
 use anyhow::anyhow;
@@ -16,6 +16,7 @@ use ln_gateway::gatewaylnrpc::{
     PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
     SubscribeInterceptHtlcsResponse,
 };
+use ln_gateway::ln::htlc_processing_timeout;
 use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 use tokio::{
@@ -364,6 +365,7 @@ impl ClnHtlcInterceptor {
 
     async fn intercept_htlc(&self, payload: HtlcAccepted) -> serde_json::Value {
         let htlc_expiry = payload.htlc.cltv_expiry;
+        let processing_timeout = htlc_processing_timeout(payload.htlc.cltv_expiry_relative);
 
         let short_channel_id = match payload.onion.short_channel_id {
             Some(scid) => scid,
@@ -399,9 +401,10 @@ impl ClnHtlcInterceptor {
                         .await
                         .insert(intercepted_htlc_id, sender);
 
-                    // If the gateway does not respond within the HTLC expiry,
-                    // Automatically respond with a failure message.
-                    return tokio::time::timeout(Duration::from_secs(30), async {
+                    // If the gateway does not respond well before the HTLC expiry,
+                    // Automatically respond with a failure message. HTLCs paying hold invoices
+                    // are held until the recipient accepted the payment.
+                    return tokio::time::timeout(processing_timeout, async {
                         receiver.await.unwrap_or_else(|e| {
                             error!("Failed to receive outcome of intercepted htlc: {:?}", e);
                             htlc_processing_failure()
@@ -1,4 +1,11 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use cln_plugin::Error;
+use fedimint_api::bitcoin_rpc::{
+    select_bitcoin_backend_from_envs, FM_BITCOIND_RPC_ENV, FM_BITCOIN_RPC_QUORUM_ENV,
+    FM_ELECTRUM_RPC_ENV, FM_ESPLORA_RPC_ENV,
+};
 use fedimint_api::config::ModuleGenRegistry;
 use fedimint_api::module::DynModuleGen;
 use fedimint_api::{
@@ -9,6 +16,7 @@ use fedimint_api::{
     module::registry::ModuleDecoderRegistry,
     task::TaskGroup,
 };
+use fedimint_bitcoind::bitcoincore_rpc::make_bitcoin_rpc_backend;
 use fedimint_server::modules::ln::LightningGen;
 use fedimint_server::modules::mint::MintGen;
 use fedimint_server::modules::wallet::WalletGen;
@@ -21,14 +29,22 @@ use fedimint_server::{
 use ln_gateway::{
     client::{DynGatewayClientBuilder, RocksDbFactory, StandardGatewayClientBuilder},
     cln::{build_cln_rpc, ClnRpcRef},
-    config::GatewayConfig,
+    config::{GatewayConfig, LightningBackend},
+    ldk::LdkNode,
+    ln::LnRpc,
+    lnd::LndRpc,
     rpc::{GatewayRequest, GatewayRpcSender},
     LnGateway,
 };
 use tokio::sync::mpsc;
 use tracing::error;
 
-/// Fedimint gateway packaged as a CLN plugin
+/// Data directory of a gateway that uses LND or an embedded LDK node instead of running as a CLN
+/// plugin
+const DATA_DIR_ENV: &str = "FM_GATEWAY_DATA_DIR";
+
+/// Fedimint gateway packaged as a CLN plugin, or run standalone against LND or with an embedded LDK
+/// node if `FM_GATEWAY_DATA_DIR` is set
 // Use CLN_PLUGIN_LOG=<log-level> to enable debug logging from within cln-plugin
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -43,11 +59,68 @@ async fn main() -> Result<(), Error> {
 
     // Create message channels
     let (tx, rx) = mpsc::channel::<GatewayRequest>(100);
+    let task_group = TaskGroup::new();
+
+    let (ln_rpc, work_dir, gw_cfg) = match std::env::var_os(DATA_DIR_ENV) {
+        Some(work_dir) => {
+            let work_dir = PathBuf::from(work_dir);
+            let gw_cfg: GatewayConfig =
+                load_from_file(&work_dir.join("gateway.config")).expect("Failed to parse config");
+
+            let ln_rpc: Arc<dyn LnRpc> = match &gw_cfg.lightning {
+                LightningBackend::Lnd(lnd_cfg) => {
+                    let lnd = LndRpc::connect(lnd_cfg).await?;
+
+                    let interceptor = lnd.clone();
+                    let sender = GatewayRpcSender::new(tx.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = interceptor.run_htlc_interceptor(sender).await {
+                            error!("LND HTLC interceptor stopped with error: {:?}", e);
+                        }
+                    });
 
-    let ClnRpcRef { ln_rpc, work_dir } = build_cln_rpc(GatewayRpcSender::new(tx.clone())).await?;
+                    Arc::new(lnd)
+                }
+                LightningBackend::Ldk(ldk_cfg) => {
+                    let bitcoin_backend = select_bitcoin_backend_from_envs(
+                        std::env::var_os(FM_BITCOIND_RPC_ENV).as_deref(),
+                        std::env::var_os(FM_ELECTRUM_RPC_ENV).as_deref(),
+                        std::env::var_os(FM_ESPLORA_RPC_ENV).as_deref(),
+                        std::env::var_os(FM_BITCOIN_RPC_QUORUM_ENV).as_deref(),
+                    )?;
+                    let bitcoind =
+                        make_bitcoin_rpc_backend(&bitcoin_backend, task_group.make_handle())?;
 
-    let gw_cfg_path = work_dir.clone().join("gateway.config");
-    let gw_cfg: GatewayConfig = load_from_file(&gw_cfg_path).expect("Failed to parse config");
+                    Arc::new(
+                        LdkNode::start(
+                            ldk_cfg,
+                            work_dir.join("ldk"),
+                            bitcoind,
+                            GatewayRpcSender::new(tx.clone()),
+                        )
+                        .await?,
+                    )
+                }
+                LightningBackend::Cln => {
+                    return Err(anyhow::anyhow!(
+                        "A gateway using CLN runs as a plugin, unset {DATA_DIR_ENV}"
+                    ))
+                }
+            };
+
+            (ln_rpc, work_dir, gw_cfg)
+        }
+        None => {
+            let ClnRpcRef { ln_rpc, work_dir } =
+                build_cln_rpc(GatewayRpcSender::new(tx.clone())).await?;
+
+            let gw_cfg_path = work_dir.clone().join("gateway.config");
+            let gw_cfg: GatewayConfig =
+                load_from_file(&gw_cfg_path).expect("Failed to parse config");
+
+            (ln_rpc as Arc<dyn LnRpc>, work_dir, gw_cfg)
+        }
+    };
 
     // Create federation client builder
     let client_builder: DynGatewayClientBuilder =
@@ -64,7 +137,6 @@ async fn main() -> Result<(), Error> {
     ]);
 
     // Create gateway instance
-    let task_group = TaskGroup::new();
     let gateway = LnGateway::new(
         gw_cfg,
         decoders,
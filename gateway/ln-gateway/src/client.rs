@@ -13,6 +13,7 @@ use fedimint_api::{
     dyn_newtype_define,
 };
 use fedimint_server::config::load_from_file;
+use fedimint_server::modules::ln::GatewayFees;
 use mint_client::{
     api::{DynFederationApi, GlobalFederationApi, WsFederationApi, WsFederationConnect},
     module_decode_stubs, Client, GatewayClientConfig,
@@ -88,6 +89,7 @@ pub trait IGatewayClientBuilder: Debug {
         mint_channel_id: u64,
         node_pubkey: PublicKey,
         announce_address: Url,
+        fees: GatewayFees,
     ) -> Result<GatewayClientConfig>;
 
     /// Save and persist the configuration of the gateway federation client
@@ -144,6 +146,7 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
         mint_channel_id: u64,
         node_pubkey: PublicKey,
         announce_address: Url,
+        fees: GatewayFees,
     ) -> Result<GatewayClientConfig> {
         let api: DynFederationApi = WsFederationApi::new(connect.members).into();
 
@@ -163,6 +166,7 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
             timelock_delta: 10,
             node_pub_key: node_pubkey,
             api: announce_address,
+            fees,
         })
     }
 
@@ -1,16 +1,17 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
 
 use async_trait::async_trait;
+use bitcoin_hashes::hex::{FromHex, ToHex};
 use cln_plugin::{anyhow, options, Builder, Error, Plugin};
 use cln_rpc::model::{ListchannelsRequest, ListpeersPeersChannelsState, ListpeersRequest};
-use cln_rpc::primitives::ShortChannelId;
+use cln_rpc::primitives::{Amount as ClnAmount, Secret, ShortChannelId};
 use cln_rpc::{model, Request, Response};
 use fedimint_api::Amount;
+use fedimint_server::modules::ln::contracts::outgoing::PrunedInvoice;
 use fedimint_server::modules::ln::contracts::Preimage;
 use fedimint_server::modules::ln::route_hints::{RouteHint, RouteHintHop};
-use lightning_invoice::Invoice;
 use secp256k1::PublicKey;
 use serde::{Deserialize, Deserializer, Serialize};
 use tokio::io::{stdin, stdout};
@@ -18,7 +19,10 @@ use tracing::{debug, error, instrument, trace, warn};
 
 use crate::ReceivePaymentPayload;
 use crate::{
-    ln::{LightningError, LnRpc},
+    ln::{
+        format_short_channel_id, htlc_processing_timeout, is_mint_channel_id,
+        parse_short_channel_id, ChannelBalances, LightningError, LnRpc, PaymentStatus,
+    },
     rpc::GatewayRpcSender,
 };
 
@@ -39,7 +43,6 @@ where
 pub struct Htlc {
     #[serde(deserialize_with = "as_fedimint_amount")]
     pub amount_msat: Amount,
-    // TODO: use these to validate we can actually redeem the HTLC in time
     pub cltv_expiry: u32,
     pub cltv_expiry_relative: u32,
     pub payment_hash: bitcoin_hashes::sha256::Hash,
@@ -57,6 +60,20 @@ pub struct HtlcAccepted {
     pub onion: Onion,
 }
 
+/// The parts of core-lightning's `listpays` response we need
+#[derive(Clone, Deserialize, Debug)]
+struct ListpaysResponse {
+    pays: Vec<ListpaysPay>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+struct ListpaysPay {
+    /// One of "pending", "failed" or "complete"
+    status: String,
+    #[serde(default)]
+    preimage: Option<String>,
+}
+
 pub struct ClnRpcRef {
     // CLN RPC client
     pub ln_rpc: Arc<ClnRpc>,
@@ -85,6 +102,180 @@ impl ClnRpc {
             LightningError(None)
         })
     }
+
+    /// Pays `amount` of the invoice along a single route, without retrying other routes if it
+    /// fails
+    ///
+    /// We only know the pruned invoice, so we can't use `pay` which needs the whole invoice.
+    /// Instead we find the route ourselves and send the payment along it. If `amount` is less
+    /// than the invoice amount others pay the remaining parts.
+    async fn send_to_route(
+        &self,
+        invoice: &PrunedInvoice,
+        amount: Amount,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        let invoice_amount = invoice.amount.ok_or(LightningError(None))?;
+        let route = self.find_route(invoice, amount).await?;
+
+        let first_hop = route.first().ok_or(LightningError(None))?;
+        let fee_msat = first_hop.amount_msat.msat() - amount.msats;
+        let max_fee_msat = (amount.msats as f64 * max_fee_percent) as u64;
+        if u64::from(first_hop.delay) > max_delay || fee_msat > max_fee_msat {
+            debug!(
+                delay = first_hop.delay,
+                fee_msat, max_delay, max_fee_msat, "Route exceeds our limits"
+            );
+            return Err(LightningError(None));
+        }
+
+        // Parts of a multi-part payment need a non-zero part id
+        let partid = (amount < invoice_amount).then_some(1);
+        let mut client = self.rpc_client().await?;
+        client
+            .call(Request::SendPay(model::SendpayRequest {
+                route,
+                payment_hash: invoice.payment_hash,
+                label: None,
+                amount_msat: Some(ClnAmount::from_msat(invoice_amount.msats)),
+                bolt11: None,
+                payment_secret: Some(
+                    Secret::try_from(invoice.payment_secret.to_vec())
+                        .expect("Payment secret has 32 bytes"),
+                ),
+                partid,
+                localinvreqid: None,
+                groupid: None,
+            }))
+            .await
+            .map_err(cln_error)?;
+
+        let response = client
+            .call(Request::WaitSendPay(model::WaitsendpayRequest {
+                payment_hash: invoice.payment_hash,
+                timeout: None,
+                partid: partid.map(u64::from),
+                groupid: None,
+            }))
+            .await
+            .map_err(cln_error)?;
+        match response {
+            Response::WaitSendPay(payment) => {
+                let preimage = payment.payment_preimage.ok_or_else(|| {
+                    error!("c-lightning returned no preimage for a complete payment");
+                    LightningError(None)
+                })?;
+                Ok(Preimage(preimage.into()))
+            }
+            _ => unreachable!("unexpected response from C-lightning"),
+        }
+    }
+
+    /// Finds a route delivering `amount` to the payee of the invoice, through the first of its
+    /// route hints that leads to a node we can reach
+    async fn find_route(
+        &self,
+        invoice: &PrunedInvoice,
+        amount: Amount,
+    ) -> Result<Vec<model::SendpayRoute>, LightningError> {
+        if invoice.route_hints.is_empty() {
+            return self
+                .route_to(
+                    invoice.destination,
+                    amount.msats,
+                    invoice.min_final_cltv_expiry,
+                    vec![],
+                )
+                .await;
+        }
+
+        for route_hint in &invoice.route_hints {
+            // Going backwards from the payee tells us what the first node of the hint has to
+            // receive to forward `amount` to the payee
+            let mut node = invoice.destination;
+            let mut amount_msat = amount.msats;
+            let mut delay = invoice.min_final_cltv_expiry;
+            let mut hint_route = vec![];
+            for hop in route_hint.0.iter().rev() {
+                hint_route.push(model::SendpayRoute {
+                    amount_msat: ClnAmount::from_msat(amount_msat),
+                    id: node,
+                    delay: delay.try_into().map_err(|_| LightningError(None))?,
+                    channel: ShortChannelId::from_str(&format_short_channel_id(
+                        hop.short_channel_id,
+                    ))
+                    .map_err(|_| LightningError(None))?,
+                });
+                amount_msat += u64::from(hop.base_msat)
+                    + amount_msat * u64::from(hop.proportional_millionths) / 1_000_000;
+                delay += u64::from(hop.cltv_expiry_delta);
+                node = hop.src_node_id;
+            }
+            hint_route.reverse();
+
+            match self.route_to(node, amount_msat, delay, hint_route).await {
+                Ok(route) => return Ok(route),
+                Err(_) => debug!(?route_hint, "Found no route to route hint"),
+            }
+        }
+        Err(LightningError(None))
+    }
+
+    /// Finds a route delivering `amount_msat` to `node` with a remaining CLTV delta of `delay`
+    /// and appends `tail` to it
+    async fn route_to(
+        &self,
+        node: PublicKey,
+        amount_msat: u64,
+        delay: u64,
+        tail: Vec<model::SendpayRoute>,
+    ) -> Result<Vec<model::SendpayRoute>, LightningError> {
+        // Route hints may start at one of our own channels
+        if node == self.pubkey().await? {
+            return Ok(tail);
+        }
+
+        let response = self
+            .rpc_client()
+            .await?
+            .call(Request::GetRoute(model::GetrouteRequest {
+                id: node,
+                amount_msat: ClnAmount::from_msat(amount_msat),
+                riskfactor: 10,
+                cltv: Some(delay as f64),
+                fromid: None,
+                fuzzpercent: None,
+                exclude: None,
+                maxhops: None,
+            }))
+            .await
+            .map_err(cln_error)?;
+        let route = match response {
+            Response::GetRoute(response) => response.route,
+            _ => unreachable!("unexpected response from C-lightning"),
+        };
+
+        let mut route = route
+            .into_iter()
+            .map(|hop| {
+                Ok(model::SendpayRoute {
+                    amount_msat: hop.amount_msat,
+                    id: hop.id,
+                    delay: hop.delay.try_into().map_err(|_| LightningError(None))?,
+                    channel: hop.channel,
+                })
+            })
+            .collect::<Result<Vec<_>, LightningError>>()?;
+        route.extend(tail);
+        Ok(route)
+    }
+}
+
+/// Logs an error returned by core-lightning and converts it into a [`LightningError`]
+fn cln_error(cln_rpc::RpcError { code, message }: cln_rpc::RpcError) -> LightningError {
+    debug!(?code, %message, "c-lightning returned error");
+    LightningError(code)
 }
 
 #[async_trait]
@@ -107,50 +298,80 @@ impl LnRpc for ClnRpc {
         }
     }
 
-    #[instrument(name = "LnRpc::route_hints", skip(self))]
+    #[instrument(name = "LnRpc::pay", skip(self))]
     async fn pay(
         &self,
-        invoice: Invoice,
+        invoice: PrunedInvoice,
         max_delay: u64,
         max_fee_percent: f64,
     ) -> Result<Preimage, LightningError> {
         debug!("Attempting to pay invoice");
+        let amount = invoice.amount.ok_or(LightningError(None))?;
+        let preimage = self
+            .send_to_route(&invoice, amount, max_delay, max_fee_percent)
+            .await?;
+        debug!("Successfully paid invoice");
+        Ok(preimage)
+    }
+
+    #[instrument(name = "LnRpc::pay_part", skip(self))]
+    async fn pay_part(
+        &self,
+        invoice: PrunedInvoice,
+        amount: Amount,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        debug!("Attempting to pay part of invoice");
+        let preimage = self
+            .send_to_route(&invoice, amount, max_delay, max_fee_percent)
+            .await?;
+        debug!("Successfully paid part of invoice");
+        Ok(preimage)
+    }
+
+    fn supports_mpp(&self) -> bool {
+        true
+    }
 
-        let pay_result = self
+    #[instrument(name = "LnRpc::payment_status", skip(self))]
+    async fn payment_status(
+        &self,
+        payment_hash: bitcoin_hashes::sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError> {
+        let listpays_result = self
             .rpc_client()
             .await?
-            .call(cln_rpc::Request::Pay(model::PayRequest {
-                bolt11: invoice.to_string(),
-                amount_msat: None,
-                label: None,
-                riskfactor: None,
-                maxfeepercent: Some(max_fee_percent),
-                retry_for: None,
-                maxdelay: Some(max_delay as u16),
-                exemptfee: None,
-                localinvreqid: None,
-                exclude: None,
-                maxfee: None,
-                description: None,
-            }))
+            .call_raw::<ListpaysResponse, _>(
+                "listpays",
+                serde_json::json!({ "payment_hash": payment_hash.to_string() }),
+            )
             .await;
 
-        match pay_result {
-            Ok(cln_rpc::Response::Pay(pay_success)) => {
-                debug!("Successfully paid invoice");
-                let slice: [u8; 32] = pay_success.payment_preimage.to_vec().try_into().unwrap();
-                Ok(Preimage(slice))
-            }
-            Ok(_) => unreachable!("unexpected response from C-lightning"),
+        let pays = match listpays_result {
+            Ok(response) => response.pays,
             Err(cln_rpc::RpcError { code, message }) => {
-                if let Some(code) = code {
-                    debug!(%code, %message, "c-lightning pay returned error");
-                } else {
-                    debug!(%message, "c-lightning pay returned error");
-                }
-                Err(LightningError(code))
+                debug!(?code, %message, "c-lightning listpays returned error");
+                return Err(LightningError(code));
             }
+        };
+
+        // Every retry after a failed attempt is listed as another pay of the same hash
+        if let Some(pay) = pays.iter().find(|pay| pay.status == "complete") {
+            let preimage = pay
+                .preimage
+                .as_deref()
+                .and_then(|preimage| <[u8; 32]>::from_hex(preimage).ok())
+                .ok_or_else(|| {
+                    error!("c-lightning returned invalid preimage");
+                    LightningError(None)
+                })?;
+            return Ok(PaymentStatus::Succeeded(Preimage(preimage)));
+        }
+        if pays.iter().any(|pay| pay.status == "pending") {
+            return Ok(PaymentStatus::Pending);
         }
+        Ok(PaymentStatus::Failed)
     }
 
     #[instrument(name = "LnRpc::route_hints", skip(self))]
@@ -241,6 +462,39 @@ impl LnRpc for ClnRpc {
 
         Ok(route_hints)
     }
+
+    #[instrument(name = "LnRpc::channel_balances", skip(self))]
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        let peers_response = self
+            .rpc_client()
+            .await?
+            .call(Request::ListPeers(ListpeersRequest {
+                id: None,
+                level: None,
+            }))
+            .await
+            .map_err(|cln_rpc::RpcError { code, message }| {
+                debug!(%message, "c-lightning listpeers returned error");
+                LightningError(code)
+            })?;
+        let peers = match peers_response {
+            Response::ListPeers(peers) => peers.peers,
+            _ => unreachable!("unexpected response from C-lightning"),
+        };
+
+        let mut outbound = Amount::ZERO;
+        let mut inbound = Amount::ZERO;
+        for chan in peers.into_iter().flat_map(|peer| peer.channels) {
+            if !matches!(chan.state, ListpeersPeersChannelsState::CHANNELD_NORMAL) {
+                continue;
+            }
+
+            outbound += Amount::from_msats(chan.spendable_msat.map_or(0, |amt| amt.msat()));
+            inbound += Amount::from_msats(chan.receivable_msat.map_or(0, |amt| amt.msat()));
+        }
+
+        Ok(ChannelBalances { outbound, inbound })
+    }
 }
 
 // TODO: upstream
@@ -270,25 +524,34 @@ async fn htlc_accepted_hook(
 ) -> Result<serde_json::Value, Error> {
     let htlc_accepted: HtlcAccepted = serde_json::from_value(value)?;
 
-    // Filter and process intercepted HTLCs based on `short_channel_id` value.
-    //
-    // After https://github.com/fedimint/fedimint/pull/1180,
-    // all HTLCs to Fedimint clients should have route hint with `short_channel_id = 0u64`,
-    // unless the gateway is serving multiple federations.
-    if htlc_accepted.onion.short_channel_id.as_deref() == Some("0x0x0") {
-        let preimage = match plugin
-            .state()
-            .send(ReceivePaymentPayload { htlc_accepted })
-            .await
+    // Filter and process intercepted HTLCs based on `short_channel_id` value, HTLCs to Fedimint
+    // clients are addressed to the fake channel of their federation
+    if htlc_accepted
+        .onion
+        .short_channel_id
+        .as_deref()
+        .and_then(parse_short_channel_id)
+        .map_or(false, is_mint_channel_id)
+    {
+        // FIXME: Test this potential fix for Issue 1018: Gateway channel force closures
+        //
+        // Timeout processing of intercepted HTLC before it expires. HTLCs paying hold invoices
+        // are held until the recipient accepted the payment, all others are failed after 30s.
+        let timeout = htlc_processing_timeout(htlc_accepted.htlc.cltv_expiry_relative);
+        let preimage = match tokio::time::timeout(
+            timeout,
+            plugin.state().send(ReceivePaymentPayload { htlc_accepted }),
+        )
+        .await
         {
-            Ok(preimage) => preimage,
-            Err(_) => return Ok(htlc_processing_failure()),
+            Ok(Ok(preimage)) => preimage,
+            Ok(Err(_)) => return Ok(htlc_processing_failure()),
+            Err(_) => return Err(anyhow!("htlc_accepted timeout")),
         };
 
-        let pk = preimage.to_public_key()?;
         Ok(serde_json::json!({
           "result": "resolve",
-          "payment_key": pk.to_string(),
+          "payment_key": preimage.0.to_hex(),
         }))
     } else {
         // HTLC is not relevant to fedimint
@@ -309,17 +572,10 @@ pub async fn build_cln_rpc(sender: GatewayRpcSender) -> Result<ClnRpcRef, Error>
         .hook("htlc_accepted", |plugin, value| async move {
             // This callback needs to be `Sync`, so we use tokio::spawn
             let handle = tokio::spawn(async move {
-                // FIXME: Test this potential fix for Issue 1018: Gateway channel force closures
-                //
-                // Timeout processing of intecepted HTLC after 30 seconds
-                // If the HTLC is not resolved, we continue and forward it to the next hop
-                tokio::time::timeout(Duration::from_secs(30), htlc_accepted_hook(plugin, value))
-                    .await
-                    .unwrap_or_else(|_| Err(anyhow!("htlc_accepted timeout")))
-                    .or_else(|e| {
-                        error!("htlc_accepted error {:?}", e);
-                        Ok(htlc_processing_failure())
-                    })
+                htlc_accepted_hook(plugin, value).await.or_else(|e| {
+                    error!("htlc_accepted error {:?}", e);
+                    Ok(htlc_processing_failure())
+                })
             });
             handle.await?
         })
@@ -1,5 +1,7 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
+use fedimint_server::modules::ln::GatewayFees;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -11,4 +13,40 @@ pub struct GatewayConfig {
     pub announce_address: Url,
     /// webserver authentication password
     pub password: String,
+    /// Fees charged for routing payments, advertised to all connected federations
+    #[serde(default)]
+    pub fees: GatewayFees,
+    /// Lightning node the gateway routes payments with
+    #[serde(default)]
+    pub lightning: LightningBackend,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LightningBackend {
+    /// Core Lightning, which runs the gateway as a plugin
+    #[default]
+    Cln,
+    /// LND, which the gateway connects to via gRPC
+    Lnd(LndConfig),
+    /// LDK node embedded into the gateway, storing its data in `ldk` within the data directory
+    Ldk(LdkConfig),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LndConfig {
+    /// gRPC endpoint of LND, e.g. `https://localhost:10009`
+    pub rpc_address: Url,
+    /// LND's TLS certificate, `None` to connect without TLS
+    pub tls_cert: Option<PathBuf>,
+    /// Macaroon authorizing our calls, `None` if LND runs with `--no-macaroons`
+    pub macaroon: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LdkConfig {
+    /// Bitcoin network the node's channels and on-chain wallet live on
+    pub network: bitcoin::Network,
+    /// Address to accept connections from Lightning peers on
+    pub listen_address: SocketAddr,
 }
@@ -5,37 +5,74 @@ use bitcoin_hashes::sha256;
 use fedimint_api::{task::TaskGroup, Amount, OutPoint, TransactionId};
 use fedimint_server::modules::{
     ln::{
-        contracts::{ContractId, Preimage},
+        contracts::{outgoing::PrunedInvoice, ContractId, Preimage},
         route_hints::RouteHint,
+        GatewayLiquidity,
     },
     wallet::txoproof::TxOutProof,
 };
-use mint_client::{GatewayClient, PaymentParameters};
+use lightning_invoice::Invoice;
+use mint_client::ln::offer::{
+    GatewayOffer, RegisterLightningAddressPayload, RegisterOfferPayload, RegisterOfferResponse,
+    ReusableOfferId,
+};
+use mint_client::ln::outgoing::GatewayPaymentState;
+use mint_client::{ClientError, GatewayClient, PaymentParameters};
 use rand::{CryptoRng, RngCore};
 use tracing::{debug, info, instrument, warn};
 
-use crate::{ln::LnRpc, rpc::FederationInfo, utils::retry, LnGatewayError, Result};
+use crate::{
+    ln::LnRpc,
+    rpc::{FederationInfo, FederationProbe},
+    utils::retry,
+    LnGatewayError, Result,
+};
 
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
 
 pub struct GatewayActor {
     client: Arc<GatewayClient>,
+    route_hints: Vec<RouteHint>,
 }
 
 impl GatewayActor {
-    pub async fn new(client: Arc<GatewayClient>, route_hints: Vec<RouteHint>) -> Result<Self> {
+    pub async fn new(
+        client: Arc<GatewayClient>,
+        route_hints: Vec<RouteHint>,
+        ln_rpc: Arc<dyn LnRpc>,
+    ) -> Result<Self> {
         let register_client = client.clone();
+        let register_route_hints = route_hints.clone();
+        let issues_invoices = ln_rpc.issues_invoices();
+        let supports_mpp = ln_rpc.supports_mpp();
         tokio::spawn(async move {
             loop {
+                // Publish our current liquidity with every registration
+                let liquidity = match ln_rpc.channel_balances().await {
+                    Ok(balances) => Some(GatewayLiquidity::from_balances(
+                        balances.outbound,
+                        balances.inbound,
+                    )),
+                    Err(e) => {
+                        warn!("Failed to fetch channel balances: {:?}", e);
+                        None
+                    }
+                };
+
                 // Retry gateway registration
                 match retry(
                     String::from("Register With Federation"),
                     #[allow(clippy::unit_arg)]
                     || async {
-                        let gateway_registration = register_client
-                            .config()
-                            .to_gateway_registration_info(route_hints.clone(), GW_ANNOUNCEMENT_TTL);
+                        let gateway_registration =
+                            register_client.config().to_gateway_registration_info(
+                                register_route_hints.clone(),
+                                liquidity,
+                                issues_invoices,
+                                supports_mpp,
+                                GW_ANNOUNCEMENT_TTL,
+                            );
                         Ok(register_client
                             .register_with_federation(gateway_registration.clone())
                             .await?)
@@ -57,7 +94,10 @@ impl GatewayActor {
             }
         });
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            route_hints,
+        })
     }
 
     async fn fetch_all_notes(&self) {
@@ -92,14 +132,15 @@ impl GatewayActor {
         &self,
         ln_rpc: Arc<dyn LnRpc>,
         contract_id: ContractId,
-    ) -> Result<OutPoint> {
+        part_amount: Option<Amount>,
+    ) -> Result<(OutPoint, Preimage)> {
         debug!("Fetching contract");
         let rng = rand::rngs::OsRng;
         let contract_account = self.client.fetch_outgoing_contract(contract_id).await?;
 
         let payment_params = match self
             .client
-            .validate_outgoing_account(&contract_account)
+            .validate_outgoing_account(&contract_account, part_amount)
             .await
         {
             Ok(payment_params) => payment_params,
@@ -116,10 +157,6 @@ impl GatewayActor {
             "Fetched and validated contract account"
         );
 
-        self.client
-            .save_outgoing_payment(contract_account.clone())
-            .await;
-
         let is_internal_payment = payment_params.maybe_internal
             && self
                 .client
@@ -128,6 +165,15 @@ impl GatewayActor {
                 .await
                 .unwrap_or(false);
 
+        let state = if is_internal_payment {
+            GatewayPaymentState::BuyingOffer
+        } else {
+            GatewayPaymentState::PayingInvoice
+        };
+        self.client
+            .save_outgoing_payment(contract_account.clone(), part_amount, state)
+            .await;
+
         let preimage_res = if is_internal_payment {
             self.buy_preimage_internal(&payment_params.payment_hash, &payment_params.invoice_amount)
                 .await
@@ -140,10 +186,10 @@ impl GatewayActor {
             Ok(preimage) => {
                 let outpoint = self
                     .client
-                    .claim_outgoing_contract(contract_id, preimage, rng)
+                    .claim_outgoing_contract(contract_id, preimage.clone(), rng)
                     .await?;
 
-                Ok(outpoint)
+                Ok((outpoint, preimage))
             }
             Err(e) => {
                 warn!("Invoice payment failed: {}. Aborting", e);
@@ -168,11 +214,17 @@ impl GatewayActor {
             .await?;
 
         debug!("Awaiting decryption of preimage of hash {}", payment_hash);
-        match self.client.await_preimage_decryption(out_point).await {
+        match self
+            .client
+            .await_incoming_contract_preimage(out_point, contract_id, rng)
+            .await
+        {
             Ok(preimage) => {
                 debug!("Decrypted preimage {:?}", preimage);
                 Ok(preimage)
             }
+            // We already claimed back the funds of held contracts that weren't accepted
+            Err(e @ ClientError::HoldInvoiceNotAccepted) => Err(LnGatewayError::ClientError(e)),
             Err(e) => {
                 warn!("Failed to decrypt preimage. Now requesting a refund: {}", e);
                 self.client
@@ -186,17 +238,29 @@ impl GatewayActor {
     pub async fn buy_preimage_external(
         &self,
         ln_rpc: Arc<dyn LnRpc>,
-        invoice: lightning_invoice::Invoice,
+        invoice: PrunedInvoice,
         payment_params: &PaymentParameters,
     ) -> Result<Preimage> {
-        match ln_rpc
-            .pay(
-                invoice,
-                payment_params.max_delay,
-                payment_params.max_fee_percent(),
-            )
-            .await
-        {
+        let payment = if payment_params.payment_amount < payment_params.invoice_amount {
+            ln_rpc
+                .pay_part(
+                    invoice,
+                    payment_params.payment_amount,
+                    payment_params.max_delay,
+                    payment_params.max_fee_percent(),
+                )
+                .await
+        } else {
+            ln_rpc
+                .pay(
+                    invoice,
+                    payment_params.max_delay,
+                    payment_params.max_fee_percent(),
+                )
+                .await
+        };
+
+        match payment {
             Ok(preimage) => {
                 debug!(?preimage, "Successfully paid LN invoice");
                 Ok(preimage)
@@ -287,17 +351,73 @@ impl GatewayActor {
         Ok(())
     }
 
+    pub async fn register_offer(
+        &self,
+        payload: RegisterOfferPayload,
+    ) -> Result<RegisterOfferResponse> {
+        Ok(self.client.register_reusable_offer(payload).await?)
+    }
+
+    pub async fn offer_invoice(
+        &self,
+        offer_id: ReusableOfferId,
+        amount: Amount,
+        description_hash: Option<sha256::Hash>,
+    ) -> Result<Invoice> {
+        let rng = rand::rngs::OsRng;
+
+        Ok(self
+            .client
+            .create_offer_invoice(
+                offer_id,
+                amount,
+                description_hash,
+                self.route_hints.clone(),
+                rng,
+            )
+            .await?)
+    }
+
+    pub async fn register_lightning_address(
+        &self,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<()> {
+        Ok(self.client.register_lightning_address(payload).await?)
+    }
+
+    pub async fn lookup_lightning_address(
+        &self,
+        username: &str,
+    ) -> Option<(ReusableOfferId, GatewayOffer)> {
+        self.client.lookup_lightning_address(username).await
+    }
+
     pub async fn get_balance(&self) -> Result<Amount> {
         self.fetch_all_notes().await;
 
         Ok(self.client.notes().await.total_amount())
     }
 
-    pub fn get_info(&self) -> Result<FederationInfo> {
+    pub async fn get_info(&self) -> Result<FederationInfo> {
         let cfg = self.client.config();
         Ok(FederationInfo {
             federation_id: cfg.client_config.federation_id.clone(),
             mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            balance: self.client.notes().await.total_amount(),
         })
     }
+
+    /// Fake short channel id the route hints of our federation's invoices point to
+    pub fn mint_channel_id(&self) -> u64 {
+        self.client.config().mint_channel_id
+    }
+
+    pub fn get_probe(&self) -> FederationProbe {
+        let cfg = self.client.config();
+        FederationProbe {
+            federation_id: cfg.client_config.federation_id.clone(),
+            mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            fees: cfg.fees,
+        }
+    }
 }
@@ -8,28 +8,41 @@ use std::{
 };
 
 use bitcoin::Address;
+use bitcoin_hashes::{hex::FromHex, sha256, Hash};
 use fedimint_api::{
     config::{FederationId, ModuleGenRegistry},
     module::registry::ModuleDecoderRegistry,
     task::TaskGroup,
     Amount, TransactionId,
 };
-use fedimint_server::modules::ln::{contracts::Preimage, route_hints::RouteHint};
-use mint_client::{api::WsFederationConnect, ln::PayInvoicePayload, GatewayClient};
-use tokio::sync::{mpsc, Mutex};
+use fedimint_server::modules::ln::{contracts::Preimage, route_hints::RouteHint, RoutingFee};
+use lightning_invoice::Invoice;
+use mint_client::ln::offer::{GatewayOffer, RegisterOfferResponse, ReusableOfferId};
+use mint_client::{
+    api::WsFederationConnect,
+    ln::{PayInvoicePayload, PayInvoiceResponse},
+    ClientError, GatewayClient,
+};
+use tokio::sync::{mpsc, Mutex, Semaphore};
 use tracing::{error, info, warn};
+use url::Url;
 
 use super::actor::GatewayActor;
 use crate::{
     client::DynGatewayClientBuilder,
     config::GatewayConfig,
-    ln::LnRpc,
+    ln::{self, LnRpc},
+    lnurl,
     rpc::{
-        rpc_server::run_webserver, BackupPayload, BalancePayload, ConnectFedPayload,
-        DepositAddressPayload, DepositPayload, GatewayInfo, GatewayRequest, GatewayRpcSender,
-        InfoPayload, ReceivePaymentPayload, RestorePayload, WithdrawPayload,
+        rpc_server::run_webserver, BackupPayload, BalancePayload, CloseChannelPayload,
+        ConnectFedPayload, CreateInvoicePayload, DepositAddressPayload, DepositPayload,
+        GatewayInfo, GatewayProbe, GatewayRequest, GatewayRpcSender, InfoPayload,
+        ListChannelsPayload, ListChannelsResponse, LnurlInvoicePayload, LnurlPayPayload,
+        OfferInvoicePayload, OnchainAddressPayload, OpenChannelPayload, ProbePayload,
+        ReceivePaymentPayload, RegisterLightningAddressPayload, RegisterOfferPayload,
+        RestorePayload, WithdrawPayload,
     },
-    LnGatewayError, Result,
+    LnGatewayError, Result, MAX_CONCURRENT_OFFER_INVOICES,
 };
 
 const ROUTE_HINT_RETRIES: usize = 10;
@@ -46,6 +59,7 @@ pub struct Gateway {
     client_builder: DynGatewayClientBuilder,
     task_group: TaskGroup,
     channel_id_generator: AtomicU64,
+    offer_invoice_permits: Arc<Semaphore>,
 }
 
 impl Gateway {
@@ -89,7 +103,8 @@ impl Gateway {
             receiver,
             client_builder,
             task_group,
-            channel_id_generator: AtomicU64::new(0),
+            channel_id_generator: AtomicU64::new(ln::FIRST_MINT_CHANNEL_ID),
+            offer_invoice_permits: Arc::new(Semaphore::new(MAX_CONCURRENT_OFFER_INVOICES)),
             decoders: decoders.clone(),
             module_gens: module_gens.clone(),
         };
@@ -124,9 +139,7 @@ impl Gateway {
                     error!("Failed to connect federation: {}", e);
                 }
 
-                if config.mint_channel_id > next_channel_id {
-                    next_channel_id = config.mint_channel_id + 1;
-                }
+                next_channel_id = next_channel_id.max(config.mint_channel_id + 1);
             }
             self.channel_id_generator
                 .store(next_channel_id, Ordering::SeqCst);
@@ -155,7 +168,7 @@ impl Gateway {
         route_hints: Vec<RouteHint>,
     ) -> Result<Arc<GatewayActor>> {
         let actor = Arc::new(
-            GatewayActor::new(client.clone(), route_hints)
+            GatewayActor::new(client.clone(), route_hints, self.ln_rpc.clone())
                 .await
                 .expect("Failed to create actor"),
         );
@@ -196,6 +209,7 @@ impl Gateway {
                 channel_id,
                 node_pub_key,
                 self.config.announce_address.clone(),
+                self.config.fees,
             )
             .await
             .expect("Failed to create gateway client config");
@@ -226,18 +240,27 @@ impl Gateway {
     }
 
     async fn handle_get_info(&self, _payload: InfoPayload) -> Result<GatewayInfo> {
+        let mut federations = vec![];
+        for actor in self.actors.lock().await.values() {
+            federations.push(actor.get_info().await.expect("Failed to get actor info"));
+        }
+
+        Ok(GatewayInfo {
+            federations,
+            version_hash: env!("GIT_HASH").to_string(),
+        })
+    }
+
+    async fn handle_probe(&self, _payload: ProbePayload) -> Result<GatewayProbe> {
         let federations = self
             .actors
             .lock()
             .await
-            .iter()
-            .map(|(_, actor)| actor.get_info().expect("Failed to get actor info"))
+            .values()
+            .map(|actor| actor.get_probe())
             .collect();
 
-        Ok(GatewayInfo {
-            federations,
-            version_hash: env!("GIT_HASH").to_string(),
-        })
+        Ok(GatewayProbe { federations })
     }
 
     /// Handles an intercepted HTLC that might be an incoming payment we are receiving on behalf of
@@ -248,18 +271,24 @@ impl Gateway {
         )))
     }
 
-    async fn handle_pay_invoice_msg(&self, payload: PayInvoicePayload) -> Result<()> {
+    async fn handle_pay_invoice_msg(
+        &self,
+        payload: PayInvoicePayload,
+    ) -> Result<PayInvoiceResponse> {
         let PayInvoicePayload {
             federation_id,
             contract_id,
+            part_amount,
         } = payload;
 
         let actor = self.select_actor(federation_id).await?;
-        let outpoint = actor.pay_invoice(self.ln_rpc.clone(), contract_id).await?;
+        let (outpoint, preimage) = actor
+            .pay_invoice(self.ln_rpc.clone(), contract_id, part_amount)
+            .await?;
         actor
             .await_outgoing_contract_claimed(contract_id, outpoint)
             .await?;
-        Ok(())
+        Ok(PayInvoiceResponse { preimage })
     }
 
     async fn handle_balance_msg(&self, payload: BalancePayload) -> Result<Amount> {
@@ -316,6 +345,177 @@ impl Gateway {
         self.select_actor(federation_id).await?.restore().await
     }
 
+    async fn handle_register_offer_msg(
+        &self,
+        payload: RegisterOfferPayload,
+    ) -> Result<RegisterOfferResponse> {
+        // Offer invoices are issued by the actor, which our node can't receive payments to
+        if self.ln_rpc.issues_invoices() {
+            return Err(LnGatewayError::Other(anyhow::anyhow!(
+                "Reusable offers are not supported by this gateway's lightning node"
+            )));
+        }
+
+        self.select_actor(payload.federation_id.clone())
+            .await?
+            .register_offer(payload)
+            .await
+    }
+
+    /// Runs outside of the main loop since registering the incoming contract offer waits for
+    /// consensus, see [`MAX_CONCURRENT_OFFER_INVOICES`]
+    async fn handle_offer_invoice_msg(
+        actors: HashMap<String, Arc<GatewayActor>>,
+        OfferInvoicePayload {
+            federation_id,
+            offer_id,
+            amount,
+        }: OfferInvoicePayload,
+    ) -> Result<Invoice> {
+        actors
+            .get(&federation_id.to_string())
+            .ok_or(LnGatewayError::UnknownFederation)?
+            .offer_invoice(offer_id, amount, None)
+            .await
+    }
+
+    /// Finds the federation and offer the Lightning address of `username` pays to
+    async fn find_lightning_address(
+        actors: &HashMap<String, Arc<GatewayActor>>,
+        username: &str,
+    ) -> Result<(Arc<GatewayActor>, ReusableOfferId, GatewayOffer)> {
+        for actor in actors.values() {
+            if let Some((offer_id, offer)) = actor.lookup_lightning_address(username).await {
+                return Ok((actor.clone(), offer_id, offer));
+            }
+        }
+        Err(LnGatewayError::UnknownLightningAddress)
+    }
+
+    /// Domain our Lightning addresses live under
+    fn lightning_address_domain(announce_address: &Url) -> Result<&str> {
+        announce_address
+            .host_str()
+            .ok_or_else(|| LnGatewayError::Other(anyhow::anyhow!("Announce address has no host")))
+    }
+
+    async fn handle_register_lightning_address_msg(
+        &self,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<()> {
+        if !lnurl::is_valid_username(&payload.username) {
+            return Err(LnGatewayError::InvalidUsername);
+        }
+
+        // Usernames are unique across all federations since the address doesn't contain any
+        let actors = self.actors.lock().await.clone();
+        if let Ok((_, registered, _)) =
+            Self::find_lightning_address(&actors, &payload.username).await
+        {
+            if registered != payload.offer_id {
+                return Err(ClientError::LightningAddressTaken.into());
+            }
+        }
+
+        self.select_actor(payload.federation_id.clone())
+            .await?
+            .register_lightning_address(payload)
+            .await
+    }
+
+    async fn handle_lnurl_pay_msg(
+        &self,
+        LnurlPayPayload { username }: LnurlPayPayload,
+    ) -> Result<lnurl::PayResponse> {
+        let actors = self.actors.lock().await.clone();
+        let (actor, _, offer) = Self::find_lightning_address(&actors, &username).await?;
+        let sendable = lnurl::sendable_range(self.config.fees.incoming, actor.get_balance().await?);
+
+        Ok(lnurl::PayResponse {
+            callback: self
+                .config
+                .announce_address
+                .join(&format!("lnurlp/{username}/callback"))
+                .map_err(anyhow::Error::from)?,
+            max_sendable: sendable.end().msats,
+            min_sendable: sendable.start().msats,
+            metadata: lnurl::metadata(
+                &username,
+                Self::lightning_address_domain(&self.config.announce_address)?,
+                &offer.description,
+            ),
+            tag: "payRequest".to_string(),
+        })
+    }
+
+    /// Runs outside of the main loop like [`Self::handle_offer_invoice_msg`]
+    async fn handle_lnurl_invoice_msg(
+        actors: HashMap<String, Arc<GatewayActor>>,
+        announce_address: Url,
+        incoming_fee: RoutingFee,
+        LnurlInvoicePayload { username, amount }: LnurlInvoicePayload,
+    ) -> Result<lnurl::InvoiceResponse> {
+        let (actor, offer_id, offer) = Self::find_lightning_address(&actors, &username).await?;
+        // Payers aren't bound by what we advertised, so we check the amount again
+        if !lnurl::sendable_range(incoming_fee, actor.get_balance().await?).contains(&amount) {
+            return Err(LnGatewayError::AmountNotSendable);
+        }
+        let metadata = lnurl::metadata(
+            &username,
+            Self::lightning_address_domain(&announce_address)?,
+            &offer.description,
+        );
+        let description_hash = sha256::Hash::hash(metadata.as_bytes());
+
+        let invoice = actor
+            .offer_invoice(offer_id, amount, Some(description_hash))
+            .await?;
+        Ok(lnurl::InvoiceResponse {
+            pr: invoice.to_string(),
+            routes: vec![],
+        })
+    }
+
+    async fn handle_create_invoice_msg(&self, payload: CreateInvoicePayload) -> Result<Invoice> {
+        // Only issue invoices for federations we can buy the preimage from
+        self.select_actor(payload.federation_id.clone()).await?;
+        Ok(self.ln_rpc.create_invoice(payload).await?)
+    }
+
+    async fn handle_onchain_address_msg(&self, _payload: OnchainAddressPayload) -> Result<Address> {
+        Ok(self.ln_rpc.onchain_address().await?)
+    }
+
+    async fn handle_open_channel_msg(
+        &self,
+        OpenChannelPayload {
+            node_id,
+            address,
+            amount,
+        }: OpenChannelPayload,
+    ) -> Result<String> {
+        Ok(self.ln_rpc.open_channel(node_id, address, amount).await?)
+    }
+
+    async fn handle_close_channel_msg(
+        &self,
+        CloseChannelPayload { channel_id, force }: CloseChannelPayload,
+    ) -> Result<()> {
+        let channel_id = <[u8; 32]>::from_hex(&channel_id)
+            .map_err(|e| LnGatewayError::Other(anyhow::anyhow!("Invalid channel id: {e}")))?;
+        Ok(self.ln_rpc.close_channel(channel_id, force).await?)
+    }
+
+    async fn handle_list_channels_msg(
+        &self,
+        _payload: ListChannelsPayload,
+    ) -> Result<ListChannelsResponse> {
+        Ok(ListChannelsResponse {
+            onchain_balance: self.ln_rpc.onchain_balance().await?,
+            channels: self.ln_rpc.list_channels().await?,
+        })
+    }
+
     pub async fn run(mut self) -> Result<()> {
         let mut tg = self.task_group.clone();
 
@@ -353,6 +553,9 @@ impl Gateway {
                     GatewayRequest::Info(inner) => {
                         inner.handle(|payload| self.handle_get_info(payload)).await;
                     }
+                    GatewayRequest::Probe(inner) => {
+                        inner.handle(|payload| self.handle_probe(payload)).await;
+                    }
                     GatewayRequest::ConnectFederation(inner) => {
                         let route_hints = self.ln_rpc.route_hints().await?;
                         inner
@@ -401,6 +604,89 @@ impl Gateway {
                             .handle(|payload| self.handle_restore_msg(payload))
                             .await;
                     }
+                    GatewayRequest::RegisterOffer(inner) => {
+                        inner
+                            .handle(|payload| self.handle_register_offer_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::OfferInvoice(inner) => {
+                        let Ok(permit) = self.offer_invoice_permits.clone().try_acquire_owned()
+                        else {
+                            inner
+                                .handle(|_| async { Err(LnGatewayError::TooManyRequests) })
+                                .await;
+                            continue;
+                        };
+                        let actors = self.actors.lock().await.clone();
+                        tokio::spawn(async move {
+                            inner
+                                .handle(|payload| {
+                                    Self::handle_offer_invoice_msg(actors.clone(), payload)
+                                })
+                                .await;
+                            drop(permit);
+                        });
+                    }
+                    GatewayRequest::RegisterLightningAddress(inner) => {
+                        inner
+                            .handle(|payload| self.handle_register_lightning_address_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::LnurlPay(inner) => {
+                        inner
+                            .handle(|payload| self.handle_lnurl_pay_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::LnurlInvoice(inner) => {
+                        let Ok(permit) = self.offer_invoice_permits.clone().try_acquire_owned()
+                        else {
+                            inner
+                                .handle(|_| async { Err(LnGatewayError::TooManyRequests) })
+                                .await;
+                            continue;
+                        };
+                        let actors = self.actors.lock().await.clone();
+                        let announce_address = self.config.announce_address.clone();
+                        let incoming_fee = self.config.fees.incoming;
+                        tokio::spawn(async move {
+                            inner
+                                .handle(|payload| {
+                                    Self::handle_lnurl_invoice_msg(
+                                        actors.clone(),
+                                        announce_address.clone(),
+                                        incoming_fee,
+                                        payload,
+                                    )
+                                })
+                                .await;
+                            drop(permit);
+                        });
+                    }
+                    GatewayRequest::CreateInvoice(inner) => {
+                        inner
+                            .handle(|payload| self.handle_create_invoice_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::OnchainAddress(inner) => {
+                        inner
+                            .handle(|payload| self.handle_onchain_address_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::OpenChannel(inner) => {
+                        inner
+                            .handle(|payload| self.handle_open_channel_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::CloseChannel(inner) => {
+                        inner
+                            .handle(|payload| self.handle_close_channel_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::ListChannels(inner) => {
+                        inner
+                            .handle(|payload| self.handle_list_channels_msg(payload))
+                            .await;
+                    }
                 }
             }
 
@@ -0,0 +1,155 @@
+//! Connects the LDK node to the blockchain through the gateway's bitcoin backend
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use bitcoin::{Block, BlockHeader, Transaction};
+use fedimint_bitcoind::DynBitcoindRpc;
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{BestBlock, Listen};
+use tokio::runtime::Handle;
+use tracing::{debug, warn};
+
+/// Lowest fee rate LDK accepts, corresponds to 1 sat/vbyte
+const MIN_FEERATE_SAT_PER_KW: u32 = 253;
+
+/// Estimates fee rates with the bitcoin backend
+///
+/// LDK queries fee rates synchronously, so they are cached and refreshed by [`Self::update`].
+pub struct LdkFeeEstimator {
+    bitcoind: DynBitcoindRpc,
+    background: AtomicU32,
+    normal: AtomicU32,
+    high_priority: AtomicU32,
+}
+
+impl LdkFeeEstimator {
+    pub fn new(bitcoind: DynBitcoindRpc) -> Self {
+        Self {
+            bitcoind,
+            background: AtomicU32::new(MIN_FEERATE_SAT_PER_KW),
+            normal: AtomicU32::new(2_000),
+            high_priority: AtomicU32::new(5_000),
+        }
+    }
+
+    /// Refreshes the cached fee rates, keeping the old ones if the backend can't estimate them
+    pub async fn update(&self) -> anyhow::Result<()> {
+        for (feerate, confirmation_target) in [
+            (&self.background, 144),
+            (&self.normal, 18),
+            (&self.high_priority, 6),
+        ] {
+            if let Some(estimate) = self.bitcoind.get_fee_rate(confirmation_target).await? {
+                // Weight units are a quarter of a virtual byte
+                let sat_per_kw = (estimate.sats_per_kvb / 4)
+                    .try_into()
+                    .unwrap_or(u32::MAX)
+                    .max(MIN_FEERATE_SAT_PER_KW);
+                feerate.store(sat_per_kw, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FeeEstimator for LdkFeeEstimator {
+    fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+        match confirmation_target {
+            ConfirmationTarget::Background => &self.background,
+            ConfirmationTarget::Normal => &self.normal,
+            ConfirmationTarget::HighPriority => &self.high_priority,
+        }
+        .load(Ordering::Relaxed)
+    }
+}
+
+/// Broadcasts LDK's transactions with the bitcoin backend
+pub struct LdkBroadcaster {
+    bitcoind: DynBitcoindRpc,
+    runtime: Handle,
+}
+
+impl LdkBroadcaster {
+    /// LDK broadcasts from its background thread, so the broadcaster spawns on `runtime`
+    pub fn new(bitcoind: DynBitcoindRpc, runtime: Handle) -> Self {
+        Self { bitcoind, runtime }
+    }
+}
+
+impl BroadcasterInterface for LdkBroadcaster {
+    fn broadcast_transaction(&self, tx: &Transaction) {
+        let bitcoind = self.bitcoind.clone();
+        let tx = tx.clone();
+        self.runtime.spawn(async move {
+            let txid = tx.txid();
+            match bitcoind.submit_transaction(tx).await {
+                Ok(()) => debug!(%txid, "Broadcast transaction"),
+                Err(e) => warn!(%txid, "Failed to broadcast transaction: {:?}", e),
+            }
+        });
+    }
+}
+
+/// Passes blocks on to several chain listeners that are synced to the same block
+pub struct ChainListeners(pub Vec<Arc<dyn Listen + Send + Sync>>);
+
+impl Listen for ChainListeners {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &lightning::chain::transaction::TransactionData,
+        height: u32,
+    ) {
+        for listener in &self.0 {
+            listener.filtered_block_connected(header, txdata, height);
+        }
+    }
+
+    fn block_connected(&self, block: &Block, height: u32) {
+        for listener in &self.0 {
+            listener.block_connected(block, height);
+        }
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+        for listener in &self.0 {
+            listener.block_disconnected(header, height);
+        }
+    }
+}
+
+/// Brings `listener` from its `best_block` to the chain tip at `tip_height`, returning its new best
+/// block
+///
+/// If the listener is on a stale fork its blocks are disconnected first, so the bitcoin backend
+/// has to still know them.
+pub async fn sync_listener(
+    bitcoind: &DynBitcoindRpc,
+    listener: &(dyn Listen + Sync),
+    best_block: BestBlock,
+    tip_height: u32,
+) -> anyhow::Result<BestBlock> {
+    let mut hash = best_block.block_hash();
+    let mut height = best_block.height();
+
+    // Heights above the tip must not be queried, so we check them first
+    while height > tip_height
+        || (height > 0 && bitcoind.get_block_hash(height.into()).await? != hash)
+    {
+        let header = bitcoind.get_block(&hash).await?.header;
+        debug!(%hash, height, "Disconnecting block");
+        listener.block_disconnected(&header, height);
+        hash = header.prev_blockhash;
+        height -= 1;
+    }
+
+    while height < tip_height {
+        height += 1;
+        hash = bitcoind.get_block_hash(height.into()).await?;
+        let block = bitcoind.get_block(&hash).await?;
+        listener.block_connected(&block, height);
+    }
+
+    Ok(BestBlock::new(hash, height))
+}
@@ -0,0 +1,1063 @@
+//! LDK node embedded into the gateway
+//!
+//! Unlike CLN and LND, LDK can't settle HTLCs it intercepted, so the node has to be the recipient
+//! of the payments to our federations' users. Clients request invoices from the gateway, which
+//! the node issues for the payment hash of their offer, see [`LnRpc::issues_invoices`]. Once a
+//! payment to such an invoice is claimable the gateway buys the preimage from the federation.
+//! LDK's intercepted HTLCs (`Event::HTLCIntercepted`) don't help here either, since they can only
+//! be forwarded or failed, not claimed.
+
+pub mod chain;
+pub mod wallet;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_api::Amount;
+use fedimint_bitcoind::DynBitcoindRpc;
+use fedimint_server::modules::ln::contracts::outgoing::PrunedInvoice;
+use fedimint_server::modules::ln::contracts::Preimage;
+use fedimint_server::modules::ln::route_hints::{RouteHint, RouteHintHop};
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::chainmonitor;
+use lightning::chain::channelmonitor::ChannelMonitor;
+use lightning::chain::keysinterface::{
+    InMemorySigner, KeysInterface, KeysManager, Recipient, SpendableOutputDescriptor,
+};
+use lightning::chain::{Access, BestBlock, ChannelMonitorUpdateStatus, Filter, Listen, Watch};
+use lightning::ln::channelmanager::{
+    ChainParameters, ChannelManagerReadArgs, PaymentId, PaymentSendFailure,
+    SimpleArcChannelManager, MIN_FINAL_CLTV_EXPIRY,
+};
+use lightning::ln::features::InvoiceFeatures;
+use lightning::ln::peer_handler::{IgnoringMessageHandler, MessageHandler};
+use lightning::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
+use lightning::routing::gossip::{self, P2PGossipSync};
+use lightning::routing::router::{self, DefaultRouter, PaymentParameters, RouteParameters};
+use lightning::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringParameters};
+use lightning::util::config::UserConfig;
+use lightning::util::events::{Event, EventHandler};
+use lightning::util::logger::{Level, Logger, Record};
+use lightning::util::ser::ReadableArgs;
+use lightning_background_processor::{BackgroundProcessor, GossipSync};
+use lightning_invoice::payment::{self, Retry};
+use lightning_invoice::{Invoice, InvoiceBuilder, DEFAULT_EXPIRY_TIME};
+use lightning_net_tokio::SocketDescriptor;
+use lightning_persister::FilesystemPersister;
+use mint_client::ln::CreateInvoicePayload;
+use mint_client::utils::network_to_currency;
+use rand::RngCore;
+use secp256k1::PublicKey;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use self::chain::{sync_listener, ChainListeners, LdkBroadcaster, LdkFeeEstimator};
+use self::wallet::LdkWallet;
+use crate::cln::{Htlc, HtlcAccepted, Onion};
+use crate::config::LdkConfig;
+use crate::ln::{
+    htlc_processing_timeout, ChannelBalances, ChannelInfo, LightningError, LnRpc, PaymentStatus,
+};
+use crate::rpc::{GatewayRpcSender, ReceivePaymentPayload};
+
+/// Most unpaid invoices issued by the node we keep track of before refusing to issue more
+const MAX_PENDING_INVOICES: usize = 10_000;
+
+/// How often we look for new blocks and fee rates
+const CHAIN_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often we reconnect to channel peers we lost the connection to
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long we wait for the handshake with a peer we connected to
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ChainMonitor = chainmonitor::ChainMonitor<
+    InMemorySigner,
+    Arc<dyn Filter + Send + Sync>,
+    Arc<LdkBroadcaster>,
+    Arc<LdkFeeEstimator>,
+    Arc<LdkLogger>,
+    Arc<FilesystemPersister>,
+>;
+
+type ChannelManager =
+    SimpleArcChannelManager<ChainMonitor, LdkBroadcaster, LdkFeeEstimator, LdkLogger>;
+
+type NetworkGraph = gossip::NetworkGraph<Arc<LdkLogger>>;
+
+type LdkGossipSync =
+    P2PGossipSync<Arc<NetworkGraph>, Arc<dyn Access + Send + Sync>, Arc<LdkLogger>>;
+
+type Scorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<LdkLogger>>;
+
+type Router = DefaultRouter<Arc<NetworkGraph>, Arc<LdkLogger>, Arc<Mutex<Scorer>>>;
+
+type InvoicePayer =
+    payment::InvoicePayer<Arc<ChannelManager>, Router, Arc<LdkLogger>, Arc<LdkEventHandler>>;
+
+type PeerManager = lightning::ln::peer_handler::PeerManager<
+    SocketDescriptor,
+    Arc<ChannelManager>,
+    Arc<LdkGossipSync>,
+    IgnoringMessageHandler,
+    Arc<LdkLogger>,
+    IgnoringMessageHandler,
+>;
+
+/// Minimum final CLTV expiries of the unpaid invoices we issued, along with when they expire
+type InvoiceCltvExpiries = Arc<Mutex<HashMap<PaymentHash, (u32, SystemTime)>>>;
+
+/// Payments we wait for the outcome of, by payment hash
+type PendingPayments =
+    Arc<Mutex<HashMap<PaymentHash, oneshot::Sender<Result<Preimage, LightningError>>>>>;
+
+/// Outcomes of the payments the node made, persisted so they can be looked up after a restart
+///
+/// LDK keeps tracking the payments that were in flight when we stopped and reports their outcomes
+/// with the same events after the restart.
+#[derive(Clone)]
+pub struct PaymentStore {
+    path: PathBuf,
+    payments: Arc<Mutex<HashMap<sha256::Hash, PaymentStatus>>>,
+}
+
+impl PaymentStore {
+    fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let payments = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path,
+            payments: Arc::new(Mutex::new(payments)),
+        })
+    }
+
+    fn get(&self, payment_hash: &sha256::Hash) -> Option<PaymentStatus> {
+        self.payments
+            .lock()
+            .expect("poisoned")
+            .get(payment_hash)
+            .cloned()
+    }
+
+    fn set(&self, payment_hash: &PaymentHash, status: PaymentStatus) {
+        let mut payments = self.payments.lock().expect("poisoned");
+        payments.insert(sha256::Hash::from_inner(payment_hash.0), status);
+
+        let tmp_path = self.path.with_extension("tmp");
+        let result = serde_json::to_vec(&*payments)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(std::fs::write(&tmp_path, bytes)?))
+            .and_then(|_| Ok(std::fs::rename(&tmp_path, &self.path)?));
+        if let Err(e) = result {
+            error!("Failed to persist payment outcomes: {:?}", e);
+        }
+    }
+}
+
+/// Forwards LDK's logs to `tracing`
+pub struct LdkLogger;
+
+impl Logger for LdkLogger {
+    fn log(&self, record: &Record) {
+        match record.level {
+            Level::Gossip | Level::Trace => trace!(target: "ldk", "{}", record.args),
+            Level::Debug => debug!(target: "ldk", "{}", record.args),
+            Level::Info => info!(target: "ldk", "{}", record.args),
+            Level::Warn => warn!(target: "ldk", "{}", record.args),
+            Level::Error => error!(target: "ldk", "{}", record.args),
+        }
+    }
+}
+
+/// LDK node that routes the gateway's payments and holds its channels
+pub struct LdkNode {
+    network: bitcoin::Network,
+    data_dir: PathBuf,
+    keys_manager: Arc<KeysManager>,
+    channel_manager: Arc<ChannelManager>,
+    peer_manager: Arc<PeerManager>,
+    network_graph: Arc<NetworkGraph>,
+    scorer: Arc<Mutex<Scorer>>,
+    logger: Arc<LdkLogger>,
+    wallet: Arc<LdkWallet>,
+    pending_payments: PendingPayments,
+    payment_store: PaymentStore,
+    invoice_cltv_expiries: InvoiceCltvExpiries,
+    _background_processor: BackgroundProcessor,
+}
+
+impl LdkNode {
+    /// Starts the node with its data in `data_dir`, syncing it to the chain tip of `bitcoind`
+    ///
+    /// Payments to invoices issued by the node are passed on to the gateway through `sender`.
+    pub async fn start(
+        config: &LdkConfig,
+        data_dir: PathBuf,
+        bitcoind: DynBitcoindRpc,
+        sender: GatewayRpcSender,
+    ) -> anyhow::Result<LdkNode> {
+        std::fs::create_dir_all(&data_dir)?;
+        let runtime = Handle::current();
+
+        let node_network = bitcoind.get_network().await?;
+        if node_network != config.network {
+            return Err(anyhow!(
+                "Bitcoin backend is on {node_network}, but the node is configured for {}",
+                config.network
+            ));
+        }
+
+        let logger = Arc::new(LdkLogger);
+        let fee_estimator = Arc::new(LdkFeeEstimator::new(bitcoind.clone()));
+        fee_estimator.update().await?;
+        let broadcaster = Arc::new(LdkBroadcaster::new(bitcoind.clone(), runtime.clone()));
+        let persister = Arc::new(FilesystemPersister::new(
+            data_dir.to_string_lossy().into_owned(),
+        ));
+        let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
+            None,
+            broadcaster.clone(),
+            logger.clone(),
+            fee_estimator.clone(),
+            persister.clone(),
+        ));
+
+        let seed = load_or_create_seed(&data_dir.join("keys_seed"))?;
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let keys_manager = Arc::new(KeysManager::new(&seed, now.as_secs(), now.subsec_nanos()));
+
+        let tip_height: u32 = bitcoind.get_block_height().await?.try_into()?;
+        let tip = BestBlock::new(
+            bitcoind.get_block_hash(tip_height.into()).await?,
+            tip_height,
+        );
+
+        // Restore our channels or start from the chain tip without any
+        let mut channel_monitors = persister.read_channelmonitors(keys_manager.clone())?;
+        let user_config = UserConfig::default();
+        let manager_path = data_dir.join("manager");
+        let channel_manager = if manager_path.exists() {
+            let read_args = ChannelManagerReadArgs::new(
+                keys_manager.clone(),
+                fee_estimator.clone(),
+                chain_monitor.clone(),
+                broadcaster.clone(),
+                logger.clone(),
+                user_config,
+                channel_monitors
+                    .iter_mut()
+                    .map(|(_, monitor)| monitor)
+                    .collect(),
+            );
+            let (_, channel_manager) = <(bitcoin::BlockHash, ChannelManager)>::read(
+                &mut File::open(&manager_path)?,
+                read_args,
+            )
+            .map_err(|e| anyhow!("Failed to read channel manager: {e:?}"))?;
+            channel_manager
+        } else {
+            ChannelManager::new(
+                fee_estimator.clone(),
+                chain_monitor.clone(),
+                broadcaster.clone(),
+                logger.clone(),
+                keys_manager.clone(),
+                user_config,
+                ChainParameters {
+                    network: config.network,
+                    best_block: tip,
+                },
+            )
+        };
+        let channel_manager = Arc::new(channel_manager);
+
+        let wallet = Arc::new(LdkWallet::load(
+            &seed,
+            config.network,
+            data_dir.join("wallet.json"),
+            tip,
+        )?);
+
+        // Sync everything to the tip separately before handing the monitors to the chain monitor
+        info!("Syncing LDK node to block {}", tip_height);
+        for (_, monitor) in channel_monitors {
+            let best_block = monitor.current_best_block();
+            let listener = (
+                monitor,
+                broadcaster.clone(),
+                fee_estimator.clone(),
+                logger.clone(),
+            );
+            sync_listener(&bitcoind, &listener, best_block, tip_height).await?;
+            watch_monitor(&chain_monitor, listener.0)?;
+        }
+        sync_listener(
+            &bitcoind,
+            channel_manager.as_ref(),
+            channel_manager.current_best_block(),
+            tip_height,
+        )
+        .await?;
+        sync_listener(&bitcoind, wallet.as_ref(), wallet.best_block(), tip_height).await?;
+
+        let network_graph = Arc::new(read_or_default(
+            &data_dir.join("network_graph"),
+            logger.clone(),
+            || {
+                NetworkGraph::new(
+                    genesis_block(config.network).header.block_hash(),
+                    logger.clone(),
+                )
+            },
+        ));
+        let gossip_sync = Arc::new(P2PGossipSync::new(
+            network_graph.clone(),
+            None::<Arc<dyn Access + Send + Sync>>,
+            logger.clone(),
+        ));
+        let scoring_params = ProbabilisticScoringParameters::default();
+        let scorer = Arc::new(Mutex::new(read_or_default(
+            &data_dir.join("scorer"),
+            (
+                scoring_params.clone(),
+                network_graph.clone(),
+                logger.clone(),
+            ),
+            || {
+                ProbabilisticScorer::new(
+                    scoring_params.clone(),
+                    network_graph.clone(),
+                    logger.clone(),
+                )
+            },
+        )));
+
+        let pending_payments = PendingPayments::default();
+        let payment_store = PaymentStore::load(data_dir.join("payments.json"))?;
+        let invoice_cltv_expiries = InvoiceCltvExpiries::default();
+        let event_handler = Arc::new(LdkEventHandler {
+            channel_manager: channel_manager.clone(),
+            keys_manager: keys_manager.clone(),
+            fee_estimator: fee_estimator.clone(),
+            broadcaster: broadcaster.clone(),
+            wallet: wallet.clone(),
+            sender,
+            runtime: runtime.clone(),
+            pending_payments: pending_payments.clone(),
+            payment_store: payment_store.clone(),
+            invoice_cltv_expiries: invoice_cltv_expiries.clone(),
+        });
+
+        let router = DefaultRouter::new(
+            network_graph.clone(),
+            logger.clone(),
+            keys_manager.get_secure_random_bytes(),
+            scorer.clone(),
+        );
+        // We route payments ourselves to respect their fee and CLTV limits, which the invoice
+        // payer's retries wouldn't, so it only handles the events of our payments
+        let invoice_payer = Arc::new(InvoicePayer::new(
+            channel_manager.clone(),
+            router,
+            logger.clone(),
+            event_handler,
+            Retry::Attempts(0),
+        ));
+
+        let mut ephemeral_bytes = [0; 32];
+        rand::thread_rng().fill_bytes(&mut ephemeral_bytes);
+        let peer_manager = Arc::new(PeerManager::new(
+            MessageHandler {
+                chan_handler: channel_manager.clone(),
+                route_handler: gossip_sync.clone(),
+                onion_message_handler: IgnoringMessageHandler {},
+            },
+            keys_manager
+                .get_node_secret(Recipient::Node)
+                .map_err(|()| anyhow!("Failed to get node secret"))?,
+            now.as_secs().try_into()?,
+            &ephemeral_bytes,
+            logger.clone(),
+            IgnoringMessageHandler {},
+        ));
+
+        let background_processor = BackgroundProcessor::start(
+            persister,
+            invoice_payer,
+            chain_monitor.clone(),
+            channel_manager.clone(),
+            GossipSync::p2p(gossip_sync),
+            peer_manager.clone(),
+            logger.clone(),
+            Some(scorer.clone()),
+        );
+
+        let listener = tokio::net::TcpListener::bind(config.listen_address).await?;
+        info!("LDK node listening on {}", config.listen_address);
+        let inbound_peer_manager = peer_manager.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept peer connection: {:?}", e);
+                        continue;
+                    }
+                };
+                let peer_manager = inbound_peer_manager.clone();
+                tokio::spawn(async move {
+                    match stream.into_std() {
+                        Ok(stream) => {
+                            lightning_net_tokio::setup_inbound(peer_manager, stream).await
+                        }
+                        Err(e) => warn!("Failed to set up peer connection: {:?}", e),
+                    }
+                });
+            }
+        });
+
+        let chain_listeners = ChainListeners(vec![
+            chain_monitor.clone() as Arc<dyn Listen + Send + Sync>,
+            channel_manager.clone() as Arc<dyn Listen + Send + Sync>,
+            wallet.clone() as Arc<dyn Listen + Send + Sync>,
+        ]);
+        tokio::spawn(async move {
+            let mut best_block = tip;
+            loop {
+                tokio::time::sleep(CHAIN_POLL_INTERVAL).await;
+                if let Err(e) = fee_estimator.update().await {
+                    warn!("Failed to update fee rates: {:?}", e);
+                }
+
+                let result = async {
+                    let tip_height = bitcoind.get_block_height().await?.try_into()?;
+                    sync_listener(&bitcoind, &chain_listeners, best_block, tip_height).await
+                };
+                match result.await {
+                    Ok(synced) => best_block = synced,
+                    Err(e) => warn!("Failed to sync LDK node to chain tip: {:?}", e),
+                }
+            }
+        });
+
+        let node = LdkNode {
+            network: config.network,
+            data_dir,
+            keys_manager,
+            channel_manager,
+            peer_manager,
+            network_graph,
+            scorer,
+            logger,
+            wallet,
+            pending_payments,
+            payment_store,
+            invoice_cltv_expiries,
+            _background_processor: background_processor,
+        };
+        node.spawn_reconnect_peers();
+        Ok(node)
+    }
+
+    /// Keeps reconnecting to the peers of our channels, whose addresses we saved when opening them
+    fn spawn_reconnect_peers(&self) {
+        let peer_manager = self.peer_manager.clone();
+        let channel_manager = self.channel_manager.clone();
+        let peers_path = self.data_dir.join("peers.json");
+        tokio::spawn(async move {
+            loop {
+                let peers = read_peers(&peers_path).unwrap_or_else(|e| {
+                    warn!("Failed to read peer addresses: {:?}", e);
+                    HashMap::new()
+                });
+                let connected = peer_manager.get_peer_node_ids();
+                for channel in channel_manager.list_channels() {
+                    let node_id = channel.counterparty.node_id;
+                    let Some(address) = peers.get(&node_id) else {
+                        continue;
+                    };
+                    if connected.contains(&node_id) {
+                        continue;
+                    }
+                    if let Err(e) = connect_peer(&peer_manager, node_id, *address).await {
+                        debug!("Failed to reconnect to {}: {:?}", node_id, e);
+                    }
+                }
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        });
+    }
+}
+
+/// Hands a channel monitor synced to the chain tip to the chain monitor
+fn watch_monitor(
+    chain_monitor: &ChainMonitor,
+    monitor: ChannelMonitor<InMemorySigner>,
+) -> anyhow::Result<()> {
+    let (funding_outpoint, _) = monitor.get_funding_txo();
+    match chain_monitor.watch_channel(funding_outpoint, monitor) {
+        ChannelMonitorUpdateStatus::Completed => Ok(()),
+        status => Err(anyhow!(
+            "Failed to watch channel {funding_outpoint:?}: {status:?}"
+        )),
+    }
+}
+
+/// Connects to the peer `node_id` at `address` unless we are already connected
+async fn connect_peer(
+    peer_manager: &Arc<PeerManager>,
+    node_id: PublicKey,
+    address: SocketAddr,
+) -> anyhow::Result<()> {
+    if peer_manager.get_peer_node_ids().contains(&node_id) {
+        return Ok(());
+    }
+
+    // The connection keeps running in the background, we don't need to wait till it's closed
+    let _connection_closed =
+        lightning_net_tokio::connect_outbound(peer_manager.clone(), node_id, address)
+            .await
+            .ok_or_else(|| anyhow!("Failed to connect to {node_id}@{address}"))?;
+
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        while !peer_manager.get_peer_node_ids().contains(&node_id) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("Handshake with {node_id} timed out"))
+}
+
+/// Loads the node's seed, generating a random one on first start
+fn load_or_create_seed(path: &Path) -> anyhow::Result<[u8; 32]> {
+    if path.exists() {
+        let seed = std::fs::read(path)?;
+        return seed
+            .try_into()
+            .map_err(|_| anyhow!("Seed file {} is corrupted", path.display()));
+    }
+
+    let mut seed = [0; 32];
+    rand::thread_rng().fill_bytes(&mut seed);
+    std::fs::write(path, seed)?;
+    Ok(seed)
+}
+
+/// Reads data persisted by the background processor, falling back to `default` if there is none
+/// or it can't be decoded
+fn read_or_default<T, A>(path: &Path, args: A, default: impl FnOnce() -> T) -> T
+where
+    T: ReadableArgs<A>,
+{
+    let Ok(mut file) = File::open(path) else {
+        return default();
+    };
+    match T::read(&mut file, args) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!(
+                "Failed to read {}, starting from scratch: {:?}",
+                path.display(),
+                e
+            );
+            default()
+        }
+    }
+}
+
+fn read_peers(path: &Path) -> anyhow::Result<HashMap<PublicKey, SocketAddr>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+/// Handles the events of the node's channel manager and chain monitor
+pub struct LdkEventHandler {
+    channel_manager: Arc<ChannelManager>,
+    keys_manager: Arc<KeysManager>,
+    fee_estimator: Arc<LdkFeeEstimator>,
+    broadcaster: Arc<LdkBroadcaster>,
+    wallet: Arc<LdkWallet>,
+    sender: GatewayRpcSender,
+    runtime: Handle,
+    pending_payments: PendingPayments,
+    payment_store: PaymentStore,
+    /// Minimum final CLTV expiries of the invoices we issued, to know how long we may hold their
+    /// payments
+    invoice_cltv_expiries: InvoiceCltvExpiries,
+}
+
+impl EventHandler for LdkEventHandler {
+    fn handle_event(&self, event: Event) {
+        match event {
+            Event::FundingGenerationReady {
+                temporary_channel_id,
+                counterparty_node_id,
+                channel_value_satoshis,
+                output_script,
+                ..
+            } => {
+                let feerate = self
+                    .fee_estimator
+                    .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+                let funding_tx = match self.wallet.create_funding_transaction(
+                    output_script,
+                    channel_value_satoshis,
+                    feerate,
+                ) {
+                    Ok(funding_tx) => funding_tx,
+                    Err(e) => {
+                        error!("Failed to fund channel: {:?}", e);
+                        let _ = self.channel_manager.force_close_without_broadcasting_txn(
+                            &temporary_channel_id,
+                            &counterparty_node_id,
+                        );
+                        return;
+                    }
+                };
+                if let Err(e) = self.channel_manager.funding_transaction_generated(
+                    &temporary_channel_id,
+                    &counterparty_node_id,
+                    funding_tx.clone(),
+                ) {
+                    error!("Channel rejected funding transaction: {:?}", e);
+                    self.wallet.unreserve(&funding_tx);
+                }
+            }
+            Event::PaymentClaimable {
+                payment_hash,
+                amount_msat,
+                ..
+            } => self.buy_preimage(payment_hash, amount_msat),
+            Event::PaymentSent {
+                payment_hash,
+                payment_preimage,
+                ..
+            } => {
+                let preimage = Preimage(payment_preimage.0);
+                self.payment_store
+                    .set(&payment_hash, PaymentStatus::Succeeded(preimage.clone()));
+                if let Some(sender) = self.pending_payment(&payment_hash) {
+                    let _ = sender.send(Ok(preimage));
+                }
+            }
+            Event::PaymentFailed { payment_hash, .. } => {
+                self.payment_store.set(&payment_hash, PaymentStatus::Failed);
+                if let Some(sender) = self.pending_payment(&payment_hash) {
+                    let _ = sender.send(Err(LightningError(None)));
+                }
+            }
+            Event::PendingHTLCsForwardable { time_forwardable } => {
+                let channel_manager = self.channel_manager.clone();
+                self.runtime.spawn(async move {
+                    tokio::time::sleep(time_forwardable).await;
+                    channel_manager.process_pending_htlc_forwards();
+                });
+            }
+            Event::SpendableOutputs { outputs } => {
+                // Outputs paid to our destination script are already picked up by the wallet
+                let outputs = outputs
+                    .iter()
+                    .filter(|output| {
+                        !matches!(output, SpendableOutputDescriptor::StaticOutput { .. })
+                    })
+                    .collect::<Vec<_>>();
+                if outputs.is_empty() {
+                    return;
+                }
+
+                let feerate = self
+                    .fee_estimator
+                    .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+                match self.keys_manager.spend_spendable_outputs(
+                    &outputs,
+                    vec![],
+                    self.wallet.destination_script(),
+                    feerate,
+                    &Secp256k1::new(),
+                ) {
+                    Ok(tx) => self.broadcaster.broadcast_transaction(&tx),
+                    Err(()) => error!("Failed to sweep spendable outputs"),
+                }
+            }
+            Event::DiscardFunding { transaction, .. } => self.wallet.unreserve(&transaction),
+            event => trace!("Ignoring LDK event {:?}", event),
+        }
+    }
+}
+
+impl LdkEventHandler {
+    fn pending_payment(
+        &self,
+        payment_hash: &PaymentHash,
+    ) -> Option<oneshot::Sender<Result<Preimage, LightningError>>> {
+        self.pending_payments
+            .lock()
+            .expect("poisoned")
+            .remove(payment_hash)
+    }
+
+    /// Buys the preimage of a payment to one of our invoices from the federation and claims the
+    /// payment with it, failing it if that's not possible
+    fn buy_preimage(&self, payment_hash: PaymentHash, amount_msat: u64) {
+        let cltv_expiry_relative = self
+            .invoice_cltv_expiries
+            .lock()
+            .expect("poisoned")
+            .remove(&payment_hash)
+            .map_or(MIN_FINAL_CLTV_EXPIRY, |(cltv_expiry, _)| cltv_expiry);
+        let htlc_accepted = HtlcAccepted {
+            htlc: Htlc {
+                amount_msat: Amount::from_msats(amount_msat),
+                cltv_expiry: self.channel_manager.current_best_block().height()
+                    + cltv_expiry_relative,
+                cltv_expiry_relative,
+                payment_hash: sha256::Hash::from_inner(payment_hash.0),
+            },
+            onion: Onion {
+                short_channel_id: Some("0x0x0".to_string()),
+            },
+        };
+
+        let channel_manager = self.channel_manager.clone();
+        let sender = self.sender.clone();
+        self.runtime.spawn(async move {
+            let result = tokio::time::timeout(
+                htlc_processing_timeout(cltv_expiry_relative),
+                sender.send(ReceivePaymentPayload { htlc_accepted }),
+            )
+            .await;
+            match result {
+                Ok(Ok(preimage)) => channel_manager.claim_funds(PaymentPreimage(preimage.0)),
+                Ok(Err(e)) => {
+                    warn!("Failed to buy preimage of incoming payment: {:?}", e);
+                    channel_manager.fail_htlc_backwards(&payment_hash);
+                }
+                Err(_) => {
+                    warn!("Buying preimage of incoming payment timed out");
+                    channel_manager.fail_htlc_backwards(&payment_hash);
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl LnRpc for LdkNode {
+    #[instrument(name = "LnRpc::pubkey", skip(self))]
+    async fn pubkey(&self) -> Result<PublicKey, LightningError> {
+        Ok(self.channel_manager.get_our_node_id())
+    }
+
+    /// Pays the invoice along a single route whose total CLTV delta is at most `max_delay` and
+    /// whose fees are at most `max_fee_percent` of the invoice amount, without retrying other
+    /// routes if it fails
+    #[instrument(name = "LnRpc::pay", skip(self))]
+    async fn pay(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        debug!("Attempting to pay invoice");
+
+        let amount_msat = invoice.amount.ok_or(LightningError(None))?.msats;
+        // The pruned invoice has no features, but it has a payment secret which requires them
+        let mut features = InvoiceFeatures::empty();
+        features.set_variable_length_onion_required();
+        features.set_payment_secret_required();
+        let mut payment_params = PaymentParameters::from_node_id(invoice.destination)
+            .with_route_hints(
+                invoice
+                    .route_hints
+                    .iter()
+                    .map(RouteHint::to_ldk_route_hint)
+                    .collect(),
+            )
+            .with_features(features)
+            .with_expiry_time(invoice.expiry_timestamp);
+        payment_params.max_total_cltv_expiry_delta = max_delay.try_into().unwrap_or(u32::MAX);
+        let route_params = RouteParameters {
+            payment_params,
+            final_value_msat: amount_msat,
+            final_cltv_expiry_delta: invoice
+                .min_final_cltv_expiry
+                .try_into()
+                .map_err(|_| LightningError(None))?,
+        };
+
+        let first_hops = self.channel_manager.list_usable_channels();
+        let route = router::find_route(
+            &self.channel_manager.get_our_node_id(),
+            &route_params,
+            &self.network_graph,
+            Some(&first_hops.iter().collect::<Vec<_>>()),
+            self.logger.clone(),
+            &*self.scorer.lock().expect("poisoned"),
+            &self.keys_manager.get_secure_random_bytes(),
+        )
+        .map_err(|e| {
+            debug!("LDK found no route within our limits: {}", e.err);
+            LightningError(None)
+        })?;
+        let max_fee_msat = (amount_msat as f64 * max_fee_percent / 100.0) as u64;
+        if route.get_total_fees() > max_fee_msat {
+            debug!(
+                fees_msat = route.get_total_fees(),
+                max_fee_msat, "Cheapest route exceeds our fee limit"
+            );
+            return Err(LightningError(None));
+        }
+
+        let payment_hash = PaymentHash(invoice.payment_hash.into_inner());
+        let (sender, receiver) = oneshot::channel();
+        self.pending_payments
+            .lock()
+            .expect("poisoned")
+            .insert(payment_hash, sender);
+        self.payment_store
+            .set(&payment_hash, PaymentStatus::Pending);
+
+        match self.channel_manager.send_payment(
+            &route,
+            payment_hash,
+            &Some(PaymentSecret(invoice.payment_secret)),
+            PaymentId(payment_hash.0),
+        ) {
+            // Some paths are in flight, LDK reports the outcome of the payment once they resolve
+            Ok(()) | Err(PaymentSendFailure::PartialFailure { .. }) => {}
+            Err(e) => {
+                debug!("LDK failed to start payment: {:?}", e);
+                self.pending_payments
+                    .lock()
+                    .expect("poisoned")
+                    .remove(&payment_hash);
+                self.payment_store.set(&payment_hash, PaymentStatus::Failed);
+                return Err(LightningError(None));
+            }
+        }
+
+        // LDK always reports the outcome of a payment, unless it shuts down before
+        receiver.await.unwrap_or(Err(LightningError(None)))
+    }
+
+    #[instrument(name = "LnRpc::pay_part", skip(self))]
+    async fn pay_part(
+        &self,
+        _invoice: PrunedInvoice,
+        _amount: Amount,
+        _max_delay: u64,
+        _max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        warn!("LDK can't pay a part of an invoice whose remaining amount is paid by others");
+        Err(LightningError(None))
+    }
+
+    #[instrument(name = "LnRpc::payment_status", skip(self))]
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError> {
+        Ok(self
+            .payment_store
+            .get(&payment_hash)
+            .unwrap_or(PaymentStatus::Failed))
+    }
+
+    #[instrument(name = "LnRpc::route_hints", skip(self))]
+    async fn route_hints(&self) -> Result<Vec<RouteHint>, anyhow::Error> {
+        let our_node_id = self.channel_manager.get_our_node_id();
+        let route_hints = self
+            .channel_manager
+            .list_usable_channels()
+            .into_iter()
+            .filter_map(|channel| {
+                // Payers route through the channel from our peer to us, so we need its policy
+                let policy = channel.counterparty.forwarding_info?;
+                Some(RouteHint(vec![RouteHintHop {
+                    src_node_id: channel.counterparty.node_id,
+                    short_channel_id: channel.get_inbound_payment_scid()?,
+                    base_msat: policy.fee_base_msat,
+                    proportional_millionths: policy.fee_proportional_millionths,
+                    cltv_expiry_delta: policy.cltv_expiry_delta,
+                    htlc_minimum_msat: channel.inbound_htlc_minimum_msat,
+                    htlc_maximum_msat: channel.inbound_htlc_maximum_msat,
+                }]))
+            })
+            .collect::<Vec<_>>();
+
+        debug!(%our_node_id, "Found {} usable channels to use as route hints", route_hints.len());
+        Ok(route_hints)
+    }
+
+    #[instrument(name = "LnRpc::channel_balances", skip(self))]
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        let channels = self.channel_manager.list_usable_channels();
+        Ok(ChannelBalances {
+            outbound: Amount::from_msats(
+                channels
+                    .iter()
+                    .map(|channel| channel.outbound_capacity_msat)
+                    .sum(),
+            ),
+            inbound: Amount::from_msats(
+                channels
+                    .iter()
+                    .map(|channel| channel.inbound_capacity_msat)
+                    .sum(),
+            ),
+        })
+    }
+
+    fn issues_invoices(&self) -> bool {
+        true
+    }
+
+    #[instrument(name = "LnRpc::create_invoice", skip(self))]
+    async fn create_invoice(&self, payload: CreateInvoicePayload) -> anyhow::Result<Invoice> {
+        let CreateInvoicePayload {
+            payment_hash,
+            amount,
+            description,
+            expiry_time,
+            min_final_cltv_expiry,
+            ..
+        } = payload;
+        let expiry_time = expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME);
+        let min_final_cltv_expiry = min_final_cltv_expiry.max(MIN_FINAL_CLTV_EXPIRY.into());
+
+        let ldk_payment_hash = PaymentHash(payment_hash.into_inner());
+        let now = SystemTime::now();
+        {
+            // Forget the invoices nobody paid before they expired
+            let mut invoice_cltv_expiries = self.invoice_cltv_expiries.lock().expect("poisoned");
+            invoice_cltv_expiries.retain(|_, (_, expires_at)| *expires_at > now);
+            if invoice_cltv_expiries.len() >= MAX_PENDING_INVOICES {
+                return Err(anyhow!("Too many unpaid invoices"));
+            }
+        }
+
+        let payment_secret = self
+            .channel_manager
+            .create_inbound_payment_for_hash(
+                ldk_payment_hash,
+                Some(amount.msats),
+                expiry_time.try_into()?,
+            )
+            .map_err(|()| anyhow!("Invoice parameters are out of bounds"))?;
+
+        let mut invoice_builder = InvoiceBuilder::new(network_to_currency(self.network))
+            .amount_milli_satoshis(amount.msats)
+            .description(description)
+            .payment_hash(payment_hash)
+            .payment_secret(payment_secret)
+            .current_timestamp()
+            .min_final_cltv_expiry(min_final_cltv_expiry)
+            .expiry_time(Duration::from_secs(expiry_time))
+            .basic_mpp();
+        for route_hint in self.route_hints().await? {
+            invoice_builder = invoice_builder.private_route(route_hint.to_ldk_route_hint());
+        }
+
+        let node_secret = self
+            .keys_manager
+            .get_node_secret(Recipient::Node)
+            .map_err(|()| anyhow!("Failed to get node secret"))?;
+        let invoice = invoice_builder
+            .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &node_secret))?;
+
+        self.invoice_cltv_expiries.lock().expect("poisoned").insert(
+            ldk_payment_hash,
+            (
+                min_final_cltv_expiry.try_into()?,
+                now + Duration::from_secs(expiry_time),
+            ),
+        );
+        Ok(invoice)
+    }
+
+    async fn onchain_address(&self) -> anyhow::Result<bitcoin::Address> {
+        Ok(self.wallet.address())
+    }
+
+    async fn onchain_balance(&self) -> anyhow::Result<bitcoin::Amount> {
+        Ok(self.wallet.balance())
+    }
+
+    /// Opens the channel, returning its temporary id, it's only known by its final id derived from
+    /// the funding transaction once the peer accepted it
+    #[instrument(name = "LnRpc::open_channel", skip(self))]
+    async fn open_channel(
+        &self,
+        node_id: PublicKey,
+        address: SocketAddr,
+        amount: bitcoin::Amount,
+    ) -> anyhow::Result<String> {
+        connect_peer(&self.peer_manager, node_id, address).await?;
+
+        // Remember the peer's address to reconnect after restarts
+        let peers_path = self.data_dir.join("peers.json");
+        let mut peers = read_peers(&peers_path)?;
+        peers.insert(node_id, address);
+        std::fs::write(&peers_path, serde_json::to_vec(&peers)?)?;
+
+        let channel_id = self
+            .channel_manager
+            .create_channel(node_id, amount.to_sat(), 0, 0, None)
+            .map_err(|e| anyhow!("Failed to open channel: {e:?}"))?;
+        Ok(channel_id.to_hex())
+    }
+
+    #[instrument(name = "LnRpc::close_channel", skip(self))]
+    async fn close_channel(&self, channel_id: [u8; 32], force: bool) -> anyhow::Result<()> {
+        let counterparty_node_id = self
+            .channel_manager
+            .list_channels()
+            .into_iter()
+            .find(|channel| channel.channel_id == channel_id)
+            .context("Unknown channel")?
+            .counterparty
+            .node_id;
+
+        if force {
+            self.channel_manager
+                .force_close_broadcasting_latest_txn(&channel_id, &counterparty_node_id)
+        } else {
+            self.channel_manager
+                .close_channel(&channel_id, &counterparty_node_id)
+        }
+        .map_err(|e| anyhow!("Failed to close channel: {e:?}"))
+    }
+
+    async fn list_channels(&self) -> anyhow::Result<Vec<ChannelInfo>> {
+        Ok(self
+            .channel_manager
+            .list_channels()
+            .into_iter()
+            .map(|channel| ChannelInfo {
+                channel_id: channel.channel_id.to_hex(),
+                peer: channel.counterparty.node_id,
+                capacity: bitcoin::Amount::from_sat(channel.channel_value_satoshis),
+                outbound: Amount::from_msats(channel.outbound_capacity_msat),
+                inbound: Amount::from_msats(channel.inbound_capacity_msat),
+                is_usable: channel.is_usable,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,341 @@
+//! On-chain wallet of the LDK node, funding its channels and receiving the funds of closed ones
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail};
+use bitcoin::secp256k1::{All, Message, Secp256k1, SecretKey};
+use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{
+    Address, BlockHash, BlockHeader, EcdsaSighashType, Network, OutPoint, PackedLockTime,
+    PublicKey, Script, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::{BestBlock, Listen};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+/// Weight of a transaction without inputs and outputs, including the segwit marker and flag
+const TX_BASE_WEIGHT: u64 = 4 * (4 + 1 + 1 + 4) + 2;
+
+/// Weight of a P2WPKH input, assuming the largest possible signature
+const P2WPKH_INPUT_WEIGHT: u64 = 4 * (32 + 4 + 1 + 4) + (1 + 1 + 73 + 1 + 33);
+
+/// Change below this amount is left to the miners
+const DUST_LIMIT_SAT: u64 = 546;
+
+/// A key of the wallet, paid to as P2WPKH
+struct WalletKey {
+    secret_key: SecretKey,
+    public_key: PublicKey,
+    script_pubkey: Script,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WalletUtxo {
+    outpoint: OutPoint,
+    txout: TxOut,
+    /// Spent by a funding transaction that hasn't confirmed yet
+    reserved: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WalletState {
+    best_block_hash: BlockHash,
+    best_block_height: u32,
+    utxos: Vec<WalletUtxo>,
+}
+
+/// Wallet owning the keys LDK's `KeysManager` sends our funds from closed channels to
+///
+/// The destination key receives non-cooperative closes and deposits, the shutdown key cooperative
+/// closes. Reorged out transactions are not rolled back, their coins stay in the wallet until
+/// spending them fails.
+pub struct LdkWallet {
+    secp: Secp256k1<All>,
+    network: Network,
+    destination_key: WalletKey,
+    shutdown_key: WalletKey,
+    path: PathBuf,
+    state: Mutex<WalletState>,
+}
+
+impl LdkWallet {
+    /// Loads the wallet of the node with `seed` from `path`, creating it at `tip` if it doesn't
+    /// exist yet
+    pub fn load(
+        seed: &[u8; 32],
+        network: Network,
+        path: PathBuf,
+        tip: BestBlock,
+    ) -> anyhow::Result<Self> {
+        let secp = Secp256k1::new();
+        // `KeysManager` derives its keys from a testnet master key on every network
+        let master_key = ExtendedPrivKey::new_master(Network::Testnet, seed)?;
+        let derive_key = |index| -> anyhow::Result<WalletKey> {
+            let secret_key = master_key
+                .ckd_priv(&secp, ChildNumber::from_hardened_idx(index)?)?
+                .private_key;
+            let public_key = PublicKey::new(secret_key.public_key(&secp));
+            let script_pubkey = Script::new_v0_p2wpkh(
+                &public_key
+                    .wpubkey_hash()
+                    .expect("derived public keys are compressed"),
+            );
+            Ok(WalletKey {
+                secret_key,
+                public_key,
+                script_pubkey,
+            })
+        };
+        let destination_key = derive_key(1)?;
+        let shutdown_key = derive_key(2)?;
+
+        let state = if path.exists() {
+            serde_json::from_slice(&std::fs::read(&path)?)?
+        } else {
+            WalletState {
+                best_block_hash: tip.block_hash(),
+                best_block_height: tip.height(),
+                utxos: vec![],
+            }
+        };
+
+        let wallet = Self {
+            secp,
+            network,
+            destination_key,
+            shutdown_key,
+            path,
+            state: Mutex::new(state),
+        };
+        wallet.persist(&wallet.state.lock().expect("poisoned"));
+        Ok(wallet)
+    }
+
+    pub fn best_block(&self) -> BestBlock {
+        let state = self.state.lock().expect("poisoned");
+        BestBlock::new(state.best_block_hash, state.best_block_height)
+    }
+
+    /// Address to deposit funds for opening channels to
+    pub fn address(&self) -> Address {
+        Address::p2wpkh(&self.destination_key.public_key, self.network)
+            .expect("derived public keys are compressed")
+    }
+
+    /// Script the funds of LDK's spendable outputs are swept to
+    pub fn destination_script(&self) -> Script {
+        self.destination_key.script_pubkey.clone()
+    }
+
+    /// Sum of all coins not spent by unconfirmed funding transactions
+    pub fn balance(&self) -> bitcoin::Amount {
+        let state = self.state.lock().expect("poisoned");
+        bitcoin::Amount::from_sat(
+            state
+                .utxos
+                .iter()
+                .filter(|utxo| !utxo.reserved)
+                .map(|utxo| utxo.txout.value)
+                .sum(),
+        )
+    }
+
+    /// Creates and signs a transaction paying `amount_sat` to `output_script`, reserving the coins
+    /// it spends until it confirms or is discarded with [`Self::unreserve`]
+    pub fn create_funding_transaction(
+        &self,
+        output_script: Script,
+        amount_sat: u64,
+        feerate_sat_per_kw: u32,
+    ) -> anyhow::Result<Transaction> {
+        let fee = |weight: u64| weight * u64::from(feerate_sat_per_kw) / 1000;
+        let output_weight = |script: &Script| 4 * (8 + 1 + script.len() as u64);
+
+        let mut state = self.state.lock().expect("poisoned");
+        let mut candidates = state
+            .utxos
+            .iter()
+            .enumerate()
+            .filter(|(_, utxo)| !utxo.reserved)
+            .map(|(idx, utxo)| (idx, utxo.txout.value))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(_, value)| Reverse(*value));
+
+        // Select the largest coins until they cover the amount and the fee of spending them
+        let mut weight = TX_BASE_WEIGHT + output_weight(&output_script);
+        let mut selected = vec![];
+        let mut total_sat = 0;
+        for (idx, value) in candidates {
+            if total_sat >= amount_sat + fee(weight) {
+                break;
+            }
+            selected.push(idx);
+            total_sat += value;
+            weight += P2WPKH_INPUT_WEIGHT;
+        }
+        if total_sat < amount_sat + fee(weight) {
+            bail!(
+                "Insufficient on-chain funds, need {} sat but only have {total_sat} sat",
+                amount_sat + fee(weight)
+            );
+        }
+
+        let mut output = vec![TxOut {
+            value: amount_sat,
+            script_pubkey: output_script,
+        }];
+        let change_script = self.destination_script();
+        let change_fee = fee(weight + output_weight(&change_script));
+        if let Some(change_sat) = total_sat
+            .checked_sub(amount_sat + change_fee)
+            .filter(|change_sat| *change_sat > DUST_LIMIT_SAT)
+        {
+            output.push(TxOut {
+                value: change_sat,
+                script_pubkey: change_script,
+            });
+        }
+
+        let utxos = selected
+            .iter()
+            .map(|idx| state.utxos[*idx].clone())
+            .collect::<Vec<_>>();
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::default(),
+                })
+                .collect(),
+            output,
+        };
+        let witnesses = self.sign(&tx, &utxos)?;
+        for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+            input.witness = witness;
+        }
+
+        for idx in selected {
+            state.utxos[idx].reserved = true;
+        }
+        self.persist(&state);
+
+        debug!(txid = %tx.txid(), "Created funding transaction");
+        Ok(tx)
+    }
+
+    /// Releases the coins spent by a funding transaction that will never be broadcast
+    pub fn unreserve(&self, tx: &Transaction) {
+        let spent = tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<HashSet<_>>();
+
+        let mut state = self.state.lock().expect("poisoned");
+        for utxo in &mut state.utxos {
+            if spent.contains(&utxo.outpoint) {
+                utxo.reserved = false;
+            }
+        }
+        self.persist(&state);
+    }
+
+    fn sign(&self, tx: &Transaction, utxos: &[WalletUtxo]) -> anyhow::Result<Vec<Witness>> {
+        let mut sighash_cache = SighashCache::new(tx);
+        utxos
+            .iter()
+            .enumerate()
+            .map(|(idx, utxo)| {
+                let key = self.key_for(&utxo.txout.script_pubkey).ok_or_else(|| {
+                    anyhow!("Coin {} is not paid to one of our keys", utxo.outpoint)
+                })?;
+                let script_code = Script::new_p2pkh(&key.public_key.pubkey_hash());
+                let sighash = sighash_cache.segwit_signature_hash(
+                    idx,
+                    &script_code,
+                    utxo.txout.value,
+                    EcdsaSighashType::All,
+                )?;
+                let signature = self
+                    .secp
+                    .sign_ecdsa(&Message::from_slice(&sighash[..])?, &key.secret_key);
+
+                let mut signature = signature.serialize_der().to_vec();
+                signature.push(EcdsaSighashType::All as u8);
+                Ok(Witness::from_vec(vec![
+                    signature,
+                    key.public_key.to_bytes(),
+                ]))
+            })
+            .collect()
+    }
+
+    fn key_for(&self, script_pubkey: &Script) -> Option<&WalletKey> {
+        [&self.destination_key, &self.shutdown_key]
+            .into_iter()
+            .find(|key| &key.script_pubkey == script_pubkey)
+    }
+
+    fn persist(&self, state: &WalletState) {
+        let tmp_path = self.path.with_extension("tmp");
+        let result = serde_json::to_vec(state)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(std::fs::write(&tmp_path, bytes)?))
+            .and_then(|_| Ok(std::fs::rename(&tmp_path, &self.path)?));
+        if let Err(e) = result {
+            error!("Failed to persist on-chain wallet: {:?}", e);
+        }
+    }
+}
+
+impl Listen for LdkWallet {
+    fn filtered_block_connected(
+        &self,
+        header: &BlockHeader,
+        txdata: &TransactionData,
+        height: u32,
+    ) {
+        let mut state = self.state.lock().expect("poisoned");
+        for (_, tx) in txdata {
+            let spent = tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<HashSet<_>>();
+            state.utxos.retain(|utxo| !spent.contains(&utxo.outpoint));
+
+            let txid = tx.txid();
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if self.key_for(&txout.script_pubkey).is_some() {
+                    debug!(%txid, vout, value = txout.value, "Received on-chain funds");
+                    state.utxos.push(WalletUtxo {
+                        outpoint: OutPoint::new(txid, vout as u32),
+                        txout: txout.clone(),
+                        reserved: false,
+                    });
+                }
+            }
+        }
+
+        state.best_block_hash = header.block_hash();
+        state.best_block_height = height;
+        self.persist(&state);
+    }
+
+    fn block_disconnected(&self, header: &BlockHeader, height: u32) {
+        let mut state = self.state.lock().expect("poisoned");
+        state.best_block_hash = header.prev_blockhash;
+        state.best_block_height = height - 1;
+        self.persist(&state);
+    }
+}
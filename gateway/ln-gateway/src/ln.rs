@@ -1,7 +1,17 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::anyhow;
 use async_trait::async_trait;
+use bitcoin_hashes::sha256;
+use fedimint_api::Amount;
+use fedimint_server::modules::ln::contracts::outgoing::PrunedInvoice;
 use fedimint_server::modules::ln::contracts::Preimage;
 use fedimint_server::modules::ln::route_hints::RouteHint;
+use lightning_invoice::Invoice;
+use mint_client::ln::CreateInvoicePayload;
 use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
 
 #[async_trait]
 pub trait LnRpc: Send + Sync + 'static {
@@ -9,16 +19,215 @@ pub trait LnRpc: Send + Sync + 'static {
     async fn pubkey(&self) -> Result<PublicKey, LightningError>;
 
     /// Attempt to pay an invoice and block till it succeeds, fails or times out
+    ///
+    /// We only know the pruned invoice of the outgoing contract, so the payment has to be built
+    /// from its destination, payment hash and secret, route hints and final CLTV delta.
     async fn pay(
         &self,
-        invoice: lightning_invoice::Invoice,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError>;
+
+    /// Attempt to pay `amount` of an invoice whose remaining amount is paid by others, e.g. other
+    /// gateways, and block till it succeeds, fails or times out
+    ///
+    /// The recipient only settles once it received the whole amount, so this succeeds if and only
+    /// if all other parts succeed as well.
+    async fn pay_part(
+        &self,
+        invoice: PrunedInvoice,
+        amount: Amount,
         max_delay: u64,
         max_fee_percent: f64,
     ) -> Result<Preimage, LightningError>;
 
+    /// Look up the outcome of our earlier attempts to pay the invoice with `payment_hash`, used to
+    /// resume payments after a restart
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError>;
+
     /// List peer channels that should be used as route hints in invoices
     async fn route_hints(&self) -> Result<Vec<RouteHint>, anyhow::Error>;
+
+    /// Get the total balances of the lightning node's active channels
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError>;
+
+    /// Whether the lightning node can pay a part of an invoice via [`Self::pay_part`], which we
+    /// advertise so clients only split payments across gateways that can
+    fn supports_mpp(&self) -> bool {
+        false
+    }
+
+    /// Whether the lightning node can only receive payments to invoices it issued itself instead
+    /// of intercepting HTLCs for invoices issued by clients
+    fn issues_invoices(&self) -> bool {
+        false
+    }
+
+    /// Issue an invoice paying to the lightning node, only called if [`Self::issues_invoices`]
+    async fn create_invoice(&self, _payload: CreateInvoicePayload) -> anyhow::Result<Invoice> {
+        Err(anyhow!(
+            "Issuing invoices is not supported by this lightning node"
+        ))
+    }
+
+    /// Get an address of the lightning node's on-chain wallet to fund channels with
+    async fn onchain_address(&self) -> anyhow::Result<bitcoin::Address> {
+        Err(channel_management_unsupported())
+    }
+
+    /// Get the spendable balance of the lightning node's on-chain wallet
+    async fn onchain_balance(&self) -> anyhow::Result<bitcoin::Amount> {
+        Err(channel_management_unsupported())
+    }
+
+    /// Open a channel of `amount` to the node `node_id` listening on `address`, funded by the
+    /// on-chain wallet, returning the hex encoded id of the new channel
+    async fn open_channel(
+        &self,
+        _node_id: PublicKey,
+        _address: SocketAddr,
+        _amount: bitcoin::Amount,
+    ) -> anyhow::Result<String> {
+        Err(channel_management_unsupported())
+    }
+
+    /// Close the channel with id `channel_id`, unilaterally if `force` is set
+    async fn close_channel(&self, _channel_id: [u8; 32], _force: bool) -> anyhow::Result<()> {
+        Err(channel_management_unsupported())
+    }
+
+    /// List all channels of the lightning node, including pending ones
+    async fn list_channels(&self) -> anyhow::Result<Vec<ChannelInfo>> {
+        Err(channel_management_unsupported())
+    }
+}
+
+fn channel_management_unsupported() -> anyhow::Error {
+    anyhow!("Channel management is only supported by the embedded LDK node, use your node's tools")
+}
+
+/// Total balances of a lightning node's active channels
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelBalances {
+    /// Amount we can send over our channels
+    pub outbound: Amount,
+    /// Amount we can receive over our channels
+    pub inbound: Amount,
+}
+
+/// Outcome of a payment as reported by [`LnRpc::payment_status`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentStatus {
+    /// The payment is in flight and may still succeed or fail
+    Pending,
+    /// The payment succeeded and revealed the preimage
+    Succeeded(Preimage),
+    /// The payment failed or was never attempted
+    Failed,
+}
+
+/// A channel of the lightning node as reported by [`LnRpc::list_channels`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelInfo {
+    /// Hex encoded channel id
+    pub channel_id: String,
+    pub peer: PublicKey,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub capacity: bitcoin::Amount,
+    /// Amount we can currently send over the channel
+    pub outbound: Amount,
+    /// Amount we can currently receive over the channel
+    pub inbound: Amount,
+    /// Whether the channel is confirmed and the peer connected
+    pub is_usable: bool,
 }
 
 #[derive(Debug)]
 pub struct LightningError(pub Option<i32>);
+
+/// First of the fake short channel ids the gateway assigns to its federations, which the route
+/// hints in the invoices of their users point to
+///
+/// They all lie in block 0, so they can't collide with real channels. Zero is skipped since LND
+/// treats it as no channel at all.
+pub const FIRST_MINT_CHANNEL_ID: u64 = 1;
+
+/// Whether HTLCs to `short_channel_id` are addressed to one of our federations, including zero
+/// which federations connected before ids started at [`FIRST_MINT_CHANNEL_ID`] still use
+pub fn is_mint_channel_id(short_channel_id: u64) -> bool {
+    short_channel_id >> 40 == 0
+}
+
+/// Formats a short channel id as `BLOCKxTXxOUTPUT`, like CLN does
+pub fn format_short_channel_id(short_channel_id: u64) -> String {
+    format!(
+        "{}x{}x{}",
+        short_channel_id >> 40,
+        (short_channel_id >> 16) & 0xff_ffff,
+        short_channel_id & 0xffff
+    )
+}
+
+/// Parses a short channel id formatted as `BLOCKxTXxOUTPUT`
+pub fn parse_short_channel_id(short_channel_id: &str) -> Option<u64> {
+    let parts = short_channel_id
+        .split('x')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [block, tx, output] if block < 1 << 24 && tx < 1 << 24 && output < 1 << 16 => {
+            Some(block << 40 | tx << 16 | output)
+        }
+        _ => None,
+    }
+}
+
+/// Blocks before the expiry of an intercepted HTLC at which we stop holding it, so it can be failed
+/// without risking a force close
+const HTLC_EXPIRY_SAFETY_MARGIN: u32 = 72;
+
+/// How long we process an intercepted HTLC before failing it
+///
+/// Most HTLCs are settled within seconds, but those paying hold invoices have to be held until the
+/// recipient accepted the payment. Their invoices require a long enough CLTV expiry to allow that.
+pub fn htlc_processing_timeout(cltv_expiry_relative: u32) -> Duration {
+    const MIN_TIMEOUT: Duration = Duration::from_secs(30);
+    const AVERAGE_BLOCK_TIME: Duration = Duration::from_secs(600);
+
+    MIN_TIMEOUT
+        .max(AVERAGE_BLOCK_TIME * cltv_expiry_relative.saturating_sub(HTLC_EXPIRY_SAFETY_MARGIN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_channel_ids_roundtrip() {
+        let real_channel = 750_000 << 40 | 1_234 << 16 | 1;
+        assert_eq!(format_short_channel_id(real_channel), "750000x1234x1");
+        for short_channel_id in [0, FIRST_MINT_CHANNEL_ID, 70_000, real_channel] {
+            assert_eq!(
+                parse_short_channel_id(&format_short_channel_id(short_channel_id)),
+                Some(short_channel_id)
+            );
+        }
+
+        assert_eq!(parse_short_channel_id("0x0"), None);
+        assert_eq!(parse_short_channel_id("0x0x65536"), None);
+        assert_eq!(parse_short_channel_id("1x2x3x4"), None);
+    }
+
+    #[test]
+    fn mint_channel_ids_never_collide_with_real_channels() {
+        assert!(is_mint_channel_id(0));
+        assert!(is_mint_channel_id(FIRST_MINT_CHANNEL_ID));
+        assert!(is_mint_channel_id((1 << 40) - 1));
+        assert!(!is_mint_channel_id(1 << 40));
+        assert!(!is_mint_channel_id(750_000 << 40 | 1_234 << 16 | 1));
+    }
+}
@@ -0,0 +1,438 @@
+//! LND backend of the gateway
+//!
+//! Payments are routed through LND's router and HTLCs paying invoices of our federations' users
+//! are captured with its HTLC interceptor, both via gRPC.
+
+pub mod lnrpc {
+    tonic::include_proto!("lnrpc");
+}
+
+pub mod routerrpc {
+    tonic::include_proto!("routerrpc");
+}
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_api::Amount;
+use fedimint_server::modules::ln::contracts::outgoing::PrunedInvoice;
+use fedimint_server::modules::ln::contracts::Preimage;
+use fedimint_server::modules::ln::route_hints::{RouteHint, RouteHintHop};
+use secp256k1::PublicKey;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use self::lnrpc::lightning_client::LightningClient;
+use self::lnrpc::payment::PaymentStatus;
+use self::routerrpc::router_client::RouterClient;
+use self::routerrpc::{
+    ForwardHtlcInterceptRequest, ForwardHtlcInterceptResponse, ResolveHoldForwardAction,
+};
+use crate::cln::{Htlc, HtlcAccepted, Onion};
+use crate::config::LndConfig;
+use crate::ln::{self, htlc_processing_timeout, ChannelBalances, LightningError, LnRpc};
+use crate::rpc::{GatewayRpcSender, ReceivePaymentPayload};
+
+/// How long LND may try to route a payment before giving up
+const PAYMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Adds the macaroon authorizing our calls to every request
+#[derive(Debug, Clone)]
+pub struct MacaroonInterceptor {
+    macaroon: Option<AsciiMetadataValue>,
+}
+
+impl Interceptor for MacaroonInterceptor {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if let Some(macaroon) = &self.macaroon {
+            request.metadata_mut().insert("macaroon", macaroon.clone());
+        }
+        Ok(request)
+    }
+}
+
+type LndChannel = InterceptedService<Channel, MacaroonInterceptor>;
+
+/// LND RPC client
+#[derive(Clone)]
+pub struct LndRpc {
+    lightning: LightningClient<LndChannel>,
+    router: RouterClient<LndChannel>,
+}
+
+impl LndRpc {
+    pub async fn connect(config: &LndConfig) -> anyhow::Result<LndRpc> {
+        let mut endpoint = Endpoint::from_shared(config.rpc_address.to_string())?;
+        if let Some(tls_cert) = &config.tls_cert {
+            let pem = tokio::fs::read(tls_cert).await?;
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().ca_certificate(Certificate::from_pem(pem)))?;
+        }
+
+        let macaroon = match &config.macaroon {
+            Some(macaroon) => Some(tokio::fs::read(macaroon).await?.to_hex().parse()?),
+            None => None,
+        };
+        let interceptor = MacaroonInterceptor { macaroon };
+
+        let channel = endpoint.connect().await?;
+        Ok(LndRpc {
+            lightning: LightningClient::with_interceptor(channel.clone(), interceptor.clone()),
+            router: RouterClient::with_interceptor(channel, interceptor),
+        })
+    }
+
+    /// Intercepts the HTLCs paying invoices of our federations' users and settles them with the
+    /// preimage the gateway bought from the federation, all other HTLCs are forwarded as usual
+    pub async fn run_htlc_interceptor(self, sender: GatewayRpcSender) -> anyhow::Result<()> {
+        let (responses, responses_receiver) = mpsc::channel(100);
+        let mut htlcs = self
+            .router
+            .clone()
+            .htlc_interceptor(ReceiverStream::new(responses_receiver))
+            .await?
+            .into_inner();
+
+        info!("Intercepting HTLCs");
+        while let Some(htlc) = htlcs.message().await? {
+            let lnd = self.clone();
+            let sender = sender.clone();
+            let responses = responses.clone();
+            tokio::spawn(async move {
+                let response = lnd.handle_intercepted_htlc(htlc, &sender).await;
+                if responses.send(response).await.is_err() {
+                    error!("HTLC interceptor stopped before we could resolve an HTLC");
+                }
+            });
+        }
+
+        Err(anyhow!("LND closed the HTLC interceptor stream"))
+    }
+
+    async fn handle_intercepted_htlc(
+        &self,
+        htlc: ForwardHtlcInterceptRequest,
+        sender: &GatewayRpcSender,
+    ) -> ForwardHtlcInterceptResponse {
+        let incoming_circuit_key = htlc.incoming_circuit_key.clone();
+
+        if !ln::is_mint_channel_id(htlc.outgoing_requested_chan_id) {
+            // HTLC is not relevant to fedimint
+            return ForwardHtlcInterceptResponse {
+                incoming_circuit_key,
+                action: ResolveHoldForwardAction::Resume as i32,
+                preimage: vec![],
+            };
+        }
+
+        match self.buy_preimage(htlc, sender).await {
+            Ok(preimage) => ForwardHtlcInterceptResponse {
+                incoming_circuit_key,
+                action: ResolveHoldForwardAction::Settle as i32,
+                preimage: preimage.0.to_vec(),
+            },
+            Err(e) => {
+                warn!("Failed to process intercepted HTLC: {:?}", e);
+                ForwardHtlcInterceptResponse {
+                    incoming_circuit_key,
+                    action: ResolveHoldForwardAction::Fail as i32,
+                    preimage: vec![],
+                }
+            }
+        }
+    }
+
+    async fn buy_preimage(
+        &self,
+        htlc: ForwardHtlcInterceptRequest,
+        sender: &GatewayRpcSender,
+    ) -> anyhow::Result<Preimage> {
+        let block_height = self
+            .lightning
+            .clone()
+            .get_info(lnrpc::GetInfoRequest {})
+            .await?
+            .into_inner()
+            .block_height;
+        let cltv_expiry_relative = htlc.incoming_expiry.saturating_sub(block_height);
+
+        let htlc_accepted = HtlcAccepted {
+            htlc: Htlc {
+                amount_msat: Amount::from_msats(htlc.incoming_amount_msat),
+                cltv_expiry: htlc.incoming_expiry,
+                cltv_expiry_relative,
+                payment_hash: sha256::Hash::from_slice(&htlc.payment_hash)?,
+            },
+            onion: Onion {
+                short_channel_id: Some(ln::format_short_channel_id(
+                    htlc.outgoing_requested_chan_id,
+                )),
+            },
+        };
+
+        // Timeout processing of intercepted HTLC before it expires, same as for CLN
+        tokio::time::timeout(
+            htlc_processing_timeout(cltv_expiry_relative),
+            sender.send(ReceivePaymentPayload { htlc_accepted }),
+        )
+        .await
+        .map_err(|_| anyhow!("Intercepted HTLC timed out"))?
+    }
+}
+
+/// Logs an error returned by LND and converts it into a [`LightningError`]
+fn lnd_error(status: tonic::Status) -> LightningError {
+    debug!(code = ?status.code(), message = %status.message(), "LND returned error");
+    LightningError(Some(status.code() as i32))
+}
+
+/// Parses the hex encoded preimage of a successful payment
+fn parse_preimage(payment: &lnrpc::Payment) -> Result<Preimage, LightningError> {
+    Vec::<u8>::from_hex(&payment.payment_preimage)
+        .ok()
+        .and_then(|preimage| <[u8; 32]>::try_from(preimage).ok())
+        .map(Preimage)
+        .ok_or_else(|| {
+            error!("LND returned invalid preimage");
+            LightningError(None)
+        })
+}
+
+/// Converts a route hint of an invoice into the one LND expects
+fn lnd_route_hint(route_hint: &RouteHint) -> lnrpc::RouteHint {
+    lnrpc::RouteHint {
+        hop_hints: route_hint
+            .0
+            .iter()
+            .map(|hop| lnrpc::HopHint {
+                node_id: hop.src_node_id.to_string(),
+                chan_id: hop.short_channel_id,
+                fee_base_msat: hop.base_msat,
+                fee_proportional_millionths: hop.proportional_millionths,
+                cltv_expiry_delta: hop.cltv_expiry_delta.into(),
+            })
+            .collect(),
+    }
+}
+
+#[async_trait]
+impl LnRpc for LndRpc {
+    #[instrument(name = "LnRpc::pubkey", skip(self))]
+    async fn pubkey(&self) -> Result<PublicKey, LightningError> {
+        let info = self
+            .lightning
+            .clone()
+            .get_info(lnrpc::GetInfoRequest {})
+            .await
+            .map_err(lnd_error)?
+            .into_inner();
+
+        PublicKey::from_str(&info.identity_pubkey).map_err(|e| {
+            error!("LND returned invalid node pubkey: {e}");
+            LightningError(None)
+        })
+    }
+
+    #[instrument(name = "LnRpc::pay", skip(self))]
+    async fn pay(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        debug!("Attempting to pay invoice");
+
+        let invoice_amount_msat = invoice.amount.ok_or(LightningError(None))?.msats;
+        let mut payment_updates = self
+            .router
+            .clone()
+            .send_payment_v2(routerrpc::SendPaymentRequest {
+                dest: invoice.destination.serialize().to_vec(),
+                amt_msat: invoice_amount_msat as i64,
+                payment_hash: invoice.payment_hash.into_inner().to_vec(),
+                final_cltv_delta: invoice
+                    .min_final_cltv_expiry
+                    .try_into()
+                    .map_err(|_| LightningError(None))?,
+                payment_addr: invoice.payment_secret.to_vec(),
+                payment_request: String::new(),
+                timeout_seconds: PAYMENT_TIMEOUT.as_secs() as i32,
+                fee_limit_msat: (invoice_amount_msat as f64 * max_fee_percent) as i64,
+                cltv_limit: max_delay.try_into().unwrap_or(i32::MAX),
+                route_hints: invoice.route_hints.iter().map(lnd_route_hint).collect(),
+                // The pruned invoice has no features, but it has a payment secret which requires
+                // them
+                dest_features: vec![
+                    lnrpc::FeatureBit::TlvOnionReq as i32,
+                    lnrpc::FeatureBit::PaymentAddrReq as i32,
+                ],
+                no_inflight_updates: true,
+            })
+            .await
+            .map_err(lnd_error)?
+            .into_inner();
+
+        while let Some(payment) = payment_updates.message().await.map_err(lnd_error)? {
+            match payment.status() {
+                PaymentStatus::Succeeded => {
+                    debug!("Successfully paid invoice");
+                    return parse_preimage(&payment);
+                }
+                PaymentStatus::Failed => {
+                    debug!("LND failed to pay invoice");
+                    return Err(LightningError(None));
+                }
+                PaymentStatus::Unknown | PaymentStatus::InFlight => {
+                    trace!("Payment still in flight");
+                }
+            }
+        }
+
+        debug!("LND closed the payment stream before the payment completed");
+        Err(LightningError(None))
+    }
+
+    #[instrument(name = "LnRpc::pay_part", skip(self))]
+    async fn pay_part(
+        &self,
+        _invoice: PrunedInvoice,
+        _amount: Amount,
+        _max_delay: u64,
+        _max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        // LND always sets the total amount of a payment's MPP record to the amount it sends itself
+        warn!("LND can't pay a part of an invoice whose remaining amount is paid by others");
+        Err(LightningError(None))
+    }
+
+    #[instrument(name = "LnRpc::payment_status", skip(self))]
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<ln::PaymentStatus, LightningError> {
+        let payment_updates = self
+            .router
+            .clone()
+            .track_payment_v2(routerrpc::TrackPaymentRequest {
+                payment_hash: payment_hash.into_inner().to_vec(),
+                no_inflight_updates: false,
+            })
+            .await;
+        let mut payment_updates = match payment_updates {
+            Ok(payment_updates) => payment_updates.into_inner(),
+            Err(status) if status.code() == tonic::Code::NotFound => {
+                debug!("LND never attempted the payment");
+                return Ok(ln::PaymentStatus::Failed);
+            }
+            Err(status) => return Err(lnd_error(status)),
+        };
+
+        // The first update is the current state of the payment
+        let payment = payment_updates
+            .message()
+            .await
+            .map_err(lnd_error)?
+            .ok_or_else(|| {
+                debug!("LND closed the payment stream without an update");
+                LightningError(None)
+            })?;
+        Ok(match payment.status() {
+            PaymentStatus::Succeeded => ln::PaymentStatus::Succeeded(parse_preimage(&payment)?),
+            PaymentStatus::Failed => ln::PaymentStatus::Failed,
+            PaymentStatus::Unknown | PaymentStatus::InFlight => ln::PaymentStatus::Pending,
+        })
+    }
+
+    #[instrument(name = "LnRpc::route_hints", skip(self))]
+    async fn route_hints(&self) -> Result<Vec<RouteHint>, anyhow::Error> {
+        let mut lightning = self.lightning.clone();
+        let channels = lightning
+            .list_channels(lnrpc::ListChannelsRequest {
+                active_only: true,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+            })
+            .await?
+            .into_inner()
+            .channels;
+
+        debug!(
+            "Found {} active channels to use as route hints",
+            channels.len()
+        );
+
+        let mut route_hints = vec![];
+        for channel in channels {
+            let edge = match lightning
+                .get_chan_info(lnrpc::ChanInfoRequest {
+                    chan_id: channel.chan_id,
+                })
+                .await
+            {
+                Ok(edge) => edge.into_inner(),
+                Err(status) => {
+                    warn!("Channel {} not found in graph: {}", channel.chan_id, status);
+                    continue;
+                }
+            };
+
+            // Payers route through the channel from our peer to us, so we need our peer's policy
+            let peer_policy = if edge.node1_pub == channel.remote_pubkey {
+                edge.node1_policy
+            } else {
+                edge.node2_policy
+            };
+            let Some(policy) = peer_policy else {
+                warn!("Channel {} has no policy of our peer", channel.chan_id);
+                continue;
+            };
+
+            let route_hint_hop = RouteHintHop {
+                src_node_id: PublicKey::from_str(&channel.remote_pubkey)?,
+                short_channel_id: channel.chan_id,
+                base_msat: policy.fee_base_msat.try_into()?,
+                proportional_millionths: policy.fee_rate_milli_msat.try_into()?,
+                cltv_expiry_delta: policy
+                    .time_lock_delta
+                    .try_into()
+                    .expect("LND returned too big cltv expiry delta"),
+                htlc_minimum_msat: Some(policy.min_htlc.try_into()?),
+                htlc_maximum_msat: Some(policy.max_htlc_msat),
+            };
+
+            trace!("Constructed route hint {:?}", route_hint_hop);
+            route_hints.push(RouteHint(vec![route_hint_hop]))
+        }
+
+        Ok(route_hints)
+    }
+
+    #[instrument(name = "LnRpc::channel_balances", skip(self))]
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        let balance = self
+            .lightning
+            .clone()
+            .channel_balance(lnrpc::ChannelBalanceRequest {})
+            .await
+            .map_err(lnd_error)?
+            .into_inner();
+
+        Ok(ChannelBalances {
+            outbound: Amount::from_msats(balance.local_balance.map_or(0, |amount| amount.msat)),
+            inbound: Amount::from_msats(balance.remote_balance.map_or(0, |amount| amount.msat)),
+        })
+    }
+}
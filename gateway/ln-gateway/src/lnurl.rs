@@ -0,0 +1,118 @@
+//! LNURL-pay (LUD-06) for Lightning addresses (LUD-16) served by the gateway
+//!
+//! A Lightning address `username@domain` resolves to `https://domain/.well-known/lnurlp/username`,
+//! so it only works if the gateway's API is served at the root of its announced domain. Every
+//! username is mapped to a reusable offer, which the invoices requested via the callback pay to.
+
+use std::ops::RangeInclusive;
+
+use fedimint_api::Amount;
+use fedimint_server::modules::ln::RoutingFee;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Smallest amount a payer can send on top of our incoming fee
+pub const MIN_RECEIVE_AMOUNT: Amount = Amount::from_sats(1);
+
+/// Describes the payment to a Lightning address, answering the first request of an LNURL-pay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayResponse {
+    /// URL the payer requests the invoice from
+    pub callback: Url,
+    /// Largest amount in msat the payer may send
+    pub max_sendable: u64,
+    /// Smallest amount in msat the payer may send
+    pub min_sendable: u64,
+    /// JSON-encoded metadata the invoice's description hash commits to
+    pub metadata: String,
+    pub tag: String,
+}
+
+/// Invoice returned by the callback of an LNURL-pay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvoiceResponse {
+    pub pr: String,
+    /// Always empty, required by LUD-06 for backwards compatibility
+    pub routes: Vec<serde_json::Value>,
+}
+
+/// Query parameters of the LNURL-pay callback
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    /// Amount in msat the payer wants to send
+    pub amount: u64,
+}
+
+/// Amounts a payer may send to a Lightning address, both advertised and enforced by the callback
+///
+/// We fund the incoming contract with our e-cash, so our `balance` is what we can receive at most.
+pub fn sendable_range(incoming_fee: RoutingFee, balance: Amount) -> RangeInclusive<Amount> {
+    let min_sendable = MIN_RECEIVE_AMOUNT + incoming_fee.amount(MIN_RECEIVE_AMOUNT);
+    min_sendable..=balance.max(min_sendable)
+}
+
+/// Usernames may only contain the characters allowed by LUD-16
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '-' | '_' | '.'))
+}
+
+/// Metadata of a payment to `username@domain`, invoices commit to its hash
+pub fn metadata(username: &str, domain: &str, description: &str) -> String {
+    serde_json::json!([
+        ["text/plain", description],
+        ["text/identifier", format!("{username}@{domain}")]
+    ])
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_only_contain_lud16_characters() {
+        for valid in ["alice", "bob-1", "c_d.e", "0"] {
+            assert!(is_valid_username(valid), "{valid}");
+        }
+        for invalid in ["", "Alice", "a b", "a@b", "a/b", "ä"] {
+            assert!(!is_valid_username(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn metadata_identifies_the_lightning_address() {
+        let metadata = metadata("alice", "gateway.example", "Tips \"welcome\"");
+        let entries: Vec<(String, String)> = serde_json::from_str(&metadata).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("text/plain".to_string(), "Tips \"welcome\"".to_string()),
+                (
+                    "text/identifier".to_string(),
+                    "alice@gateway.example".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn sendable_range_covers_the_fee_and_our_balance() {
+        let fee = RoutingFee {
+            base_msat: 1000,
+            proportional_millionths: 0,
+        };
+        let sendable = sendable_range(fee, Amount::from_sats(100));
+        assert_eq!(*sendable.start(), Amount::from_sats(2));
+        assert_eq!(*sendable.end(), Amount::from_sats(100));
+        assert!(!sendable.contains(&Amount::from_sats(1)));
+        assert!(!sendable.contains(&Amount::from_sats(101)));
+
+        // Without balance there is still a valid range, but only for the minimum
+        let sendable = sendable_range(fee, Amount::ZERO);
+        assert_eq!(sendable, Amount::from_sats(2)..=Amount::from_sats(2));
+    }
+}
@@ -3,19 +3,32 @@ pub mod rpc_server;
 
 use std::borrow::Cow;
 use std::io::Cursor;
+use std::net::SocketAddr;
 
 use anyhow::{anyhow, Error};
-use bitcoin::{Address, Transaction, XOnlyPublicKey};
+use bitcoin::{Address, Transaction};
 use bitcoin_hashes::hex::{FromHex, ToHex};
 use fedimint_api::config::FederationId;
 use fedimint_api::{Amount, TransactionId};
 use fedimint_server::{modules::ln::contracts::Preimage, modules::wallet::txoproof::TxOutProof};
 use futures::Future;
-use mint_client::ln::PayInvoicePayload;
+use lightning_invoice::Invoice;
+pub use mint_client::ln::gateway::{
+    FederationInfo, FederationProbe, GatewayInfo, GatewayProbe, InfoPayload, ProbePayload,
+};
+use mint_client::ln::offer::RegisterOfferResponse;
+pub use mint_client::ln::offer::{
+    OfferInvoicePayload, RegisterLightningAddressPayload, RegisterOfferPayload,
+};
+pub use mint_client::ln::CreateInvoicePayload;
+use mint_client::ln::{PayInvoicePayload, PayInvoiceResponse};
+use secp256k1::PublicKey;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
 
+use crate::ln::ChannelInfo;
+use crate::lnurl::{InvoiceResponse, PayResponse};
 use crate::{cln::HtlcAccepted, LnGatewayError, Result};
 
 #[derive(Debug, Clone)]
@@ -67,9 +80,6 @@ pub struct ReceivePaymentPayload {
     pub htlc_accepted: HtlcAccepted,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct InfoPayload;
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BackupPayload {
     pub federation_id: FederationId,
@@ -109,21 +119,53 @@ pub struct WithdrawPayload {
     pub address: Address,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FederationInfo {
-    pub federation_id: FederationId,
-    pub mint_pubkey: XOnlyPublicKey,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlPayPayload {
+    pub username: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GatewayInfo {
-    pub version_hash: String,
-    pub federations: Vec<FederationInfo>,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlInvoicePayload {
+    pub username: String,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OnchainAddressPayload;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenChannelPayload {
+    pub node_id: PublicKey,
+    /// Address the node accepts peer connections on
+    pub address: SocketAddr,
+    /// Amount the channel gets funded with by our on-chain wallet
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub amount: bitcoin::Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CloseChannelPayload {
+    /// Hex encoded channel id
+    pub channel_id: String,
+    /// Close unilaterally instead of cooperatively, e.g. if the peer is offline
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListChannelsPayload;
+
+#[derive(Debug, Serialize)]
+pub struct ListChannelsResponse {
+    /// Spendable balance of the on-chain wallet funding our channels
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub onchain_balance: bitcoin::Amount,
+    pub channels: Vec<ChannelInfo>,
 }
 
 #[derive(Debug)]
 pub enum GatewayRequest {
     Info(GatewayRequestInner<InfoPayload>),
+    Probe(GatewayRequestInner<ProbePayload>),
     ConnectFederation(GatewayRequestInner<ConnectFedPayload>),
     ReceivePayment(GatewayRequestInner<ReceivePaymentPayload>),
     PayInvoice(GatewayRequestInner<PayInvoicePayload>),
@@ -133,6 +175,16 @@ pub enum GatewayRequest {
     Withdraw(GatewayRequestInner<WithdrawPayload>),
     Backup(GatewayRequestInner<BackupPayload>),
     Restore(GatewayRequestInner<RestorePayload>),
+    RegisterOffer(GatewayRequestInner<RegisterOfferPayload>),
+    OfferInvoice(GatewayRequestInner<OfferInvoicePayload>),
+    RegisterLightningAddress(GatewayRequestInner<RegisterLightningAddressPayload>),
+    LnurlPay(GatewayRequestInner<LnurlPayPayload>),
+    LnurlInvoice(GatewayRequestInner<LnurlInvoicePayload>),
+    CreateInvoice(GatewayRequestInner<CreateInvoicePayload>),
+    OnchainAddress(GatewayRequestInner<OnchainAddressPayload>),
+    OpenChannel(GatewayRequestInner<OpenChannelPayload>),
+    CloseChannel(GatewayRequestInner<CloseChannelPayload>),
+    ListChannels(GatewayRequestInner<ListChannelsPayload>),
 }
 
 #[derive(Debug)]
@@ -162,13 +214,18 @@ macro_rules! impl_gateway_request_trait {
 }
 
 impl_gateway_request_trait!(InfoPayload, GatewayInfo, GatewayRequest::Info);
+impl_gateway_request_trait!(ProbePayload, GatewayProbe, GatewayRequest::Probe);
 impl_gateway_request_trait!(ConnectFedPayload, (), GatewayRequest::ConnectFederation);
 impl_gateway_request_trait!(
     ReceivePaymentPayload,
     Preimage,
     GatewayRequest::ReceivePayment
 );
-impl_gateway_request_trait!(PayInvoicePayload, (), GatewayRequest::PayInvoice);
+impl_gateway_request_trait!(
+    PayInvoicePayload,
+    PayInvoiceResponse,
+    GatewayRequest::PayInvoice
+);
 impl_gateway_request_trait!(BalancePayload, Amount, GatewayRequest::Balance);
 impl_gateway_request_trait!(
     DepositAddressPayload,
@@ -179,6 +236,36 @@ impl_gateway_request_trait!(DepositPayload, TransactionId, GatewayRequest::Depos
 impl_gateway_request_trait!(WithdrawPayload, TransactionId, GatewayRequest::Withdraw);
 impl_gateway_request_trait!(BackupPayload, (), GatewayRequest::Backup);
 impl_gateway_request_trait!(RestorePayload, (), GatewayRequest::Restore);
+impl_gateway_request_trait!(
+    RegisterOfferPayload,
+    RegisterOfferResponse,
+    GatewayRequest::RegisterOffer
+);
+impl_gateway_request_trait!(OfferInvoicePayload, Invoice, GatewayRequest::OfferInvoice);
+impl_gateway_request_trait!(
+    RegisterLightningAddressPayload,
+    (),
+    GatewayRequest::RegisterLightningAddress
+);
+impl_gateway_request_trait!(LnurlPayPayload, PayResponse, GatewayRequest::LnurlPay);
+impl_gateway_request_trait!(
+    LnurlInvoicePayload,
+    InvoiceResponse,
+    GatewayRequest::LnurlInvoice
+);
+impl_gateway_request_trait!(CreateInvoicePayload, Invoice, GatewayRequest::CreateInvoice);
+impl_gateway_request_trait!(
+    OnchainAddressPayload,
+    Address,
+    GatewayRequest::OnchainAddress
+);
+impl_gateway_request_trait!(OpenChannelPayload, String, GatewayRequest::OpenChannel);
+impl_gateway_request_trait!(CloseChannelPayload, (), GatewayRequest::CloseChannel);
+impl_gateway_request_trait!(
+    ListChannelsPayload,
+    ListChannelsResponse,
+    GatewayRequest::ListChannels
+);
 
 impl<T> GatewayRequestInner<T>
 where
@@ -5,8 +5,9 @@ use serde::Serialize;
 use url::Url;
 
 use super::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    RestorePayload, WithdrawPayload,
+    BackupPayload, BalancePayload, CloseChannelPayload, ConnectFedPayload, DepositAddressPayload,
+    DepositPayload, ListChannelsPayload, OnchainAddressPayload, OpenChannelPayload, RestorePayload,
+    WithdrawPayload,
 };
 
 pub struct RpcClient {
@@ -92,6 +93,54 @@ impl RpcClient {
         self.call(url, password, payload).await
     }
 
+    pub async fn get_onchain_address(
+        &self,
+        password: String,
+        payload: OnchainAddressPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/onchain_address")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn open_channel(
+        &self,
+        password: String,
+        payload: OpenChannelPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/open_channel")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn close_channel(
+        &self,
+        password: String,
+        payload: CloseChannelPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/close_channel")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn list_channels(
+        &self,
+        password: String,
+        payload: ListChannelsPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/list_channels")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
     async fn call<P>(
         &self,
         url: Url,
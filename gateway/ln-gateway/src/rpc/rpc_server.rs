@@ -1,16 +1,26 @@
 use std::net::SocketAddr;
 
-use axum::{response::IntoResponse, routing::post, Extension, Json, Router};
+use axum::extract::{Path, Query};
+use axum::{
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
 use axum_macros::debug_handler;
+use fedimint_api::Amount;
 use mint_client::ln::PayInvoicePayload;
 use serde_json::json;
 use tower_http::{auth::RequireAuthorizationLayer, cors::CorsLayer};
 use tracing::instrument;
 
 use super::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    GatewayRpcSender, InfoPayload, RestorePayload, WithdrawPayload,
+    BackupPayload, BalancePayload, CloseChannelPayload, ConnectFedPayload, CreateInvoicePayload,
+    DepositAddressPayload, DepositPayload, GatewayRpcSender, InfoPayload, ListChannelsPayload,
+    LnurlInvoicePayload, LnurlPayPayload, OfferInvoicePayload, OnchainAddressPayload,
+    OpenChannelPayload, ProbePayload, RegisterLightningAddressPayload, RegisterOfferPayload,
+    RestorePayload, WithdrawPayload,
 };
+use crate::lnurl::CallbackQuery;
 use crate::LnGatewayError;
 
 pub async fn run_webserver(
@@ -19,7 +29,18 @@ pub async fn run_webserver(
     sender: GatewayRpcSender,
 ) -> axum::response::Result<()> {
     // Public routes on gateway webserver
-    let routes = Router::new().route("/pay_invoice", post(pay_invoice));
+    let routes = Router::new()
+        .route("/probe", post(probe))
+        .route("/pay_invoice", post(pay_invoice))
+        .route("/register_offer", post(register_offer))
+        .route("/offer_invoice", post(offer_invoice))
+        .route("/create_invoice", post(create_invoice))
+        .route(
+            "/register_lightning_address",
+            post(register_lightning_address),
+        )
+        .route("/.well-known/lnurlp/:username", get(lnurl_pay))
+        .route("/lnurlp/:username/callback", get(lnurl_callback));
 
     // Authenticated, public routes used for gateway administration
     let admin_routes = Router::new()
@@ -31,6 +52,10 @@ pub async fn run_webserver(
         .route("/connect", post(connect))
         .route("/backup", post(backup))
         .route("/restore", post(restore))
+        .route("/onchain_address", post(onchain_address))
+        .route("/open_channel", post(open_channel))
+        .route("/close_channel", post(close_channel))
+        .route("/list_channels", post(list_channels))
         .layer(RequireAuthorizationLayer::bearer(&authkey));
 
     let app = Router::new()
@@ -47,7 +72,7 @@ pub async fn run_webserver(
     Ok(())
 }
 
-/// Display gateway ecash note balance
+/// Display gateway version, federations and their balances
 #[debug_handler]
 #[instrument(skip_all, err)]
 async fn info(
@@ -58,6 +83,17 @@ async fn info(
     Ok(Json(json!(info)))
 }
 
+/// Report the federations and fees of the gateway, used by clients to check it is alive
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn probe(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ProbePayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let probe = rpc.send(payload).await?;
+    Ok(Json(json!(probe)))
+}
+
 /// Display gateway ecash note balance
 #[debug_handler]
 #[instrument(skip_all, err)]
@@ -106,11 +142,84 @@ async fn withdraw(
 async fn pay_invoice(
     Extension(rpc): Extension<GatewayRpcSender>,
     Json(payload): Json<PayInvoicePayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let response = rpc.send(payload).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Start answering invoice requests for a user's reusable offer or top up its payments
+#[instrument(skip_all, err)]
+async fn register_offer(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<RegisterOfferPayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let response = rpc.send(payload).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Request an invoice paying to a reusable offer
+#[instrument(skip_all, err)]
+async fn offer_invoice(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<OfferInvoicePayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let invoice = rpc.send(payload).await?;
+    Ok(Json(json!({ "invoice": invoice.to_string() })))
+}
+
+/// Request an invoice paying to the gateway's Lightning node, only supported if the node can't
+/// intercept payments to invoices issued by clients
+#[instrument(skip_all, err)]
+async fn create_invoice(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<CreateInvoicePayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let invoice = rpc.send(payload).await?;
+    Ok(Json(json!({ "invoice": invoice.to_string() })))
+}
+
+/// Map a username to a reusable offer, making it payable via a Lightning address, requires a
+/// signature of the offer key
+#[instrument(skip_all, err)]
+async fn register_lightning_address(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<RegisterLightningAddressPayload>,
 ) -> Result<impl IntoResponse, LnGatewayError> {
     rpc.send(payload).await?;
     Ok(())
 }
 
+/// First step of an LNURL-pay to a Lightning address, describing the payment
+///
+/// LNURL wallets expect errors to be reported as JSON, so they are not turned into HTTP errors.
+#[instrument(skip_all)]
+async fn lnurl_pay(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Path(username): Path<String>,
+) -> impl IntoResponse {
+    match rpc.send(LnurlPayPayload { username }).await {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({ "status": "ERROR", "reason": e.to_string() })),
+    }
+}
+
+/// Second step of an LNURL-pay to a Lightning address, returning an invoice for the amount
+#[instrument(skip_all)]
+async fn lnurl_callback(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Path(username): Path<String>,
+    Query(CallbackQuery { amount }): Query<CallbackQuery>,
+) -> impl IntoResponse {
+    let payload = LnurlInvoicePayload {
+        username,
+        amount: Amount::from_msats(amount),
+    };
+    match rpc.send(payload).await {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({ "status": "ERROR", "reason": e.to_string() })),
+    }
+}
+
 /// Connect a new federation
 #[instrument(skip_all, err)]
 async fn connect(
@@ -140,3 +249,144 @@ async fn restore(
     rpc.send(payload).await?;
     Ok(())
 }
+
+/// Generate an address of the Lightning node's on-chain wallet
+#[instrument(skip_all, err)]
+async fn onchain_address(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<OnchainAddressPayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let address = rpc.send(payload).await?;
+    Ok(Json(json!({ "address": address })))
+}
+
+/// Open a channel funded by the Lightning node's on-chain wallet
+#[instrument(skip_all, err)]
+async fn open_channel(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<OpenChannelPayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let channel_id = rpc.send(payload).await?;
+    Ok(Json(json!({ "channel_id": channel_id })))
+}
+
+/// Close one of the Lightning node's channels
+#[instrument(skip_all, err)]
+async fn close_channel(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<CloseChannelPayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// List the Lightning node's channels and on-chain balance
+#[instrument(skip_all, err)]
+async fn list_channels(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ListChannelsPayload>,
+) -> Result<impl IntoResponse, LnGatewayError> {
+    let channels = rpc.send(payload).await?;
+    Ok(Json(json!(channels)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::HttpBody;
+    use axum::response::Response;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::lnurl::InvoiceResponse;
+    use crate::rpc::GatewayRequest;
+
+    /// Answers the LNURL requests of the handlers like a gateway that knows no username but
+    /// `alice`, who can receive 1 to 1000 sats
+    fn lnurl_gateway() -> GatewayRpcSender {
+        let (sender, mut receiver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            while let Some(request) = receiver.recv().await {
+                match request {
+                    GatewayRequest::LnurlPay(inner) => {
+                        inner
+                            .handle(|_| async { Err(LnGatewayError::UnknownLightningAddress) })
+                            .await
+                    }
+                    GatewayRequest::LnurlInvoice(inner) => {
+                        inner
+                            .handle(|LnurlInvoicePayload { username, amount }| async move {
+                                if username != "alice" {
+                                    return Err(LnGatewayError::UnknownLightningAddress);
+                                }
+                                if !(Amount::from_sats(1)..=Amount::from_sats(1000))
+                                    .contains(&amount)
+                                {
+                                    return Err(LnGatewayError::AmountNotSendable);
+                                }
+                                Ok(InvoiceResponse {
+                                    pr: format!("invoice for {}", amount.msats),
+                                    routes: vec![],
+                                })
+                            })
+                            .await
+                    }
+                    _ => panic!("Unexpected request"),
+                }
+            }
+        });
+        GatewayRpcSender::new(sender)
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let mut body = response.into_body();
+        let bytes = body
+            .data()
+            .await
+            .expect("Response has a body")
+            .expect("Body is readable");
+        serde_json::from_slice(&bytes).expect("Body is JSON")
+    }
+
+    #[tokio::test]
+    async fn lnurl_errors_are_reported_as_json() {
+        let rpc = lnurl_gateway();
+
+        let response = lnurl_pay(Extension(rpc.clone()), Path("bob".to_string()))
+            .await
+            .into_response();
+        assert!(response.status().is_success());
+        let body = json_body(response).await;
+        assert_eq!(body["status"], "ERROR");
+        assert!(body["reason"]
+            .as_str()
+            .unwrap()
+            .contains("No Lightning address"));
+
+        let response = lnurl_callback(
+            Extension(rpc),
+            Path("alice".to_string()),
+            Query(CallbackQuery {
+                amount: 1_000_001_000,
+            }),
+        )
+        .await
+        .into_response();
+        assert!(response.status().is_success());
+        assert_eq!(json_body(response).await["status"], "ERROR");
+    }
+
+    #[tokio::test]
+    async fn lnurl_callback_requests_the_amount_in_msat() {
+        let response = lnurl_callback(
+            Extension(lnurl_gateway()),
+            Path("alice".to_string()),
+            Query(CallbackQuery { amount: 1_500 }),
+        )
+        .await
+        .into_response();
+
+        let body = json_body(response).await;
+        assert_eq!(body["pr"], "invoice for 1500");
+        assert_eq!(body["routes"], serde_json::json!([]));
+    }
+}
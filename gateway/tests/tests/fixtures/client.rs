@@ -10,6 +10,7 @@ use fedimint_api::{
     module::registry::ModuleDecoderRegistry,
     PeerId,
 };
+use fedimint_ln::GatewayFees;
 use ln_gateway::{
     client::{DynDbFactory, IGatewayClientBuilder},
     LnGatewayError,
@@ -73,6 +74,7 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
         mint_channel_id: u64,
         node_pubkey: PublicKey,
         announce_address: Url,
+        fees: GatewayFees,
     ) -> Result<GatewayClientConfig, LnGatewayError> {
         // TODO: use the connect info urls to get the federation name?
         // Simulate clients in the same federation by seeding the generated `client_config`
@@ -97,6 +99,7 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
             timelock_delta: 10,
             node_pub_key: node_pubkey,
             api: announce_address,
+            fees,
         })
     }
 
@@ -1,10 +1,16 @@
 use std::{collections::BTreeSet, sync::Arc};
 
-use fedimint_api::{core::ModuleInstanceId, PeerId};
-use fedimint_ln::LightningGateway;
+use fedimint_api::{
+    core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET},
+    PeerId,
+};
+use fedimint_ln::{LightningGateway, SignedLightningGateway};
 use mint_client::api::fake::FederationApiFaker;
 use tokio::sync::Mutex;
 
+/// Block height the mock federation has reached consensus on
+pub const CONSENSUS_BLOCK_HEIGHT: u64 = 100;
+
 #[derive(Debug, Default)]
 pub struct MockApi {
     gateway: Option<LightningGateway>,
@@ -18,8 +24,8 @@ impl MockApi {
         FederationApiFaker::new(Arc::new(Mutex::new(MockApi::default())), members)
             .with(
                 format!("/module/{module_id}/register_gateway"),
-                |mint: Arc<Mutex<MockApi>>, gateway: LightningGateway| async move {
-                    mint.lock().await.gateway = Some(gateway);
+                |mint: Arc<Mutex<MockApi>>, gateway: SignedLightningGateway| async move {
+                    mint.lock().await.gateway = Some(gateway.gateway);
                     Ok(())
                 },
             )
@@ -35,5 +41,9 @@ impl MockApi {
                         .collect::<Vec<LightningGateway>>())
                 },
             )
+            .with(
+                format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_WALLET}/block_height"),
+                |_: Arc<Mutex<MockApi>>, _: ()| async move { Ok(CONSENSUS_BLOCK_HEIGHT) },
+            )
     }
 }
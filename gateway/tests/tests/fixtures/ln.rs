@@ -1,12 +1,15 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use anyhow::{Error, Result};
 use async_trait::async_trait;
+use bitcoin::hashes::sha256;
 use bitcoin::{secp256k1, KeyPair};
+use fedimint_api::Amount;
+use fedimint_ln::contracts::outgoing::PrunedInvoice;
 use fedimint_ln::contracts::Preimage;
 use fedimint_ln::route_hints::RouteHint;
-use lightning_invoice::Invoice;
-use ln_gateway::ln::{LightningError, LnRpc};
+use ln_gateway::ln::{ChannelBalances, LightningError, LnRpc, PaymentStatus};
 use rand::rngs::OsRng;
 use tokio::sync::Mutex;
 
@@ -14,6 +17,7 @@ pub struct MockLnRpc {
     pub preimage: Preimage,
     node_pubkey: secp256k1::PublicKey,
     amount_sent: Arc<Mutex<u64>>,
+    paid_hashes: Arc<Mutex<HashSet<sha256::Hash>>>,
 }
 
 impl MockLnRpc {
@@ -25,6 +29,7 @@ impl MockLnRpc {
             preimage: Preimage([1; 32]),
             node_pubkey: secp256k1::PublicKey::from_keypair(&kp),
             amount_sent: Arc::new(Mutex::new(0)),
+            paid_hashes: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 }
@@ -37,16 +42,48 @@ impl LnRpc for MockLnRpc {
 
     async fn pay(
         &self,
-        invoice: Invoice,
+        invoice: PrunedInvoice,
         _max_delay: u64,
         _max_fee_percent: f64,
     ) -> Result<Preimage, LightningError> {
-        *self.amount_sent.lock().await += invoice.amount_milli_satoshis().unwrap();
+        *self.amount_sent.lock().await += invoice.amount.unwrap().msats;
+        self.paid_hashes.lock().await.insert(invoice.payment_hash);
 
         Ok(self.preimage.clone())
     }
 
+    async fn pay_part(
+        &self,
+        invoice: PrunedInvoice,
+        amount: Amount,
+        _max_delay: u64,
+        _max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        *self.amount_sent.lock().await += amount.msats;
+        self.paid_hashes.lock().await.insert(invoice.payment_hash);
+
+        Ok(self.preimage.clone())
+    }
+
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError> {
+        if self.paid_hashes.lock().await.contains(&payment_hash) {
+            Ok(PaymentStatus::Succeeded(self.preimage.clone()))
+        } else {
+            Ok(PaymentStatus::Failed)
+        }
+    }
+
     async fn route_hints(&self) -> std::result::Result<Vec<RouteHint>, Error> {
         Ok(vec![RouteHint(vec![])])
     }
+
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        Ok(ChannelBalances {
+            outbound: Amount::from_sats(100_000_000),
+            inbound: Amount::from_sats(100_000_000),
+        })
+    }
 }
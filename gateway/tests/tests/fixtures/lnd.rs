@@ -0,0 +1,247 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::{secp256k1, KeyPair};
+use fedimint_ln::contracts::Preimage;
+use ln_gateway::config::LndConfig;
+use ln_gateway::lnd::lnrpc::lightning_server::{Lightning, LightningServer};
+use ln_gateway::lnd::lnrpc::payment::PaymentStatus;
+use ln_gateway::lnd::lnrpc::{
+    Amount, ChanInfoRequest, Channel, ChannelBalanceRequest, ChannelBalanceResponse, ChannelEdge,
+    GetInfoRequest, GetInfoResponse, ListChannelsRequest, ListChannelsResponse, Payment,
+    RoutingPolicy,
+};
+use ln_gateway::lnd::routerrpc::router_server::{Router, RouterServer};
+use ln_gateway::lnd::routerrpc::{
+    ForwardHtlcInterceptRequest, ForwardHtlcInterceptResponse, SendPaymentRequest,
+    TrackPaymentRequest,
+};
+use rand::rngs::OsRng;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+use url::Url;
+
+pub const BLOCK_HEIGHT: u32 = 100;
+pub const CHANNEL_ID: u64 = 42;
+pub const LOCAL_BALANCE_MSAT: u64 = 2_000_000;
+pub const REMOTE_BALANCE_MSAT: u64 = 1_000_000;
+
+/// Policy our channel peer advertises for forwarding payments to us
+pub fn peer_policy() -> RoutingPolicy {
+    RoutingPolicy {
+        time_lock_delta: 40,
+        min_htlc: 1_000,
+        fee_base_msat: 1_000,
+        fee_rate_milli_msat: 100,
+        disabled: false,
+        max_htlc_msat: 500_000,
+    }
+}
+
+/// Serves the parts of LND's gRPC API used by the gateway
+#[derive(Clone)]
+pub struct MockLnd {
+    pub node_pubkey: secp256k1::PublicKey,
+    pub peer_pubkey: secp256k1::PublicKey,
+    pub preimage: Preimage,
+    /// Payments the gateway asked us to send
+    pub payments: Arc<Mutex<Vec<SendPaymentRequest>>>,
+    htlcs: Arc<Mutex<Option<mpsc::Receiver<ForwardHtlcInterceptRequest>>>>,
+    resolutions: mpsc::Sender<ForwardHtlcInterceptResponse>,
+}
+
+/// Lets tests forward HTLCs to the interceptor and observe how they got resolved
+pub struct MockLndHandle {
+    pub config: LndConfig,
+    pub htlcs: mpsc::Sender<ForwardHtlcInterceptRequest>,
+    pub resolutions: mpsc::Receiver<ForwardHtlcInterceptResponse>,
+}
+
+impl MockLnd {
+    /// Starts a mock LND listening on a random local port
+    pub async fn start() -> Result<(MockLnd, MockLndHandle)> {
+        let ctx = secp256k1::Secp256k1::new();
+        let (htlcs_sender, htlcs) = mpsc::channel(10);
+        let (resolutions, resolutions_receiver) = mpsc::channel(10);
+
+        let lnd = MockLnd {
+            node_pubkey: secp256k1::PublicKey::from_keypair(&KeyPair::new(&ctx, &mut OsRng)),
+            peer_pubkey: secp256k1::PublicKey::from_keypair(&KeyPair::new(&ctx, &mut OsRng)),
+            preimage: Preimage([1; 32]),
+            payments: Arc::new(Mutex::new(vec![])),
+            htlcs: Arc::new(Mutex::new(Some(htlcs))),
+            resolutions,
+        };
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let address = listener.local_addr()?;
+        let server = tonic::transport::Server::builder()
+            .add_service(LightningServer::new(lnd.clone()))
+            .add_service(RouterServer::new(lnd.clone()))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+        tokio::spawn(server);
+
+        let handle = MockLndHandle {
+            config: LndConfig {
+                rpc_address: Url::parse(&format!("http://{address}"))?,
+                tls_cert: None,
+                macaroon: None,
+            },
+            htlcs: htlcs_sender,
+            resolutions: resolutions_receiver,
+        };
+        Ok((lnd, handle))
+    }
+}
+
+#[tonic::async_trait]
+impl Lightning for MockLnd {
+    async fn get_info(
+        &self,
+        _request: Request<GetInfoRequest>,
+    ) -> Result<Response<GetInfoResponse>, Status> {
+        Ok(Response::new(GetInfoResponse {
+            identity_pubkey: self.node_pubkey.to_string(),
+            block_height: BLOCK_HEIGHT,
+            synced_to_chain: true,
+        }))
+    }
+
+    async fn channel_balance(
+        &self,
+        _request: Request<ChannelBalanceRequest>,
+    ) -> Result<Response<ChannelBalanceResponse>, Status> {
+        Ok(Response::new(ChannelBalanceResponse {
+            local_balance: Some(Amount {
+                sat: LOCAL_BALANCE_MSAT / 1000,
+                msat: LOCAL_BALANCE_MSAT,
+            }),
+            remote_balance: Some(Amount {
+                sat: REMOTE_BALANCE_MSAT / 1000,
+                msat: REMOTE_BALANCE_MSAT,
+            }),
+        }))
+    }
+
+    async fn list_channels(
+        &self,
+        _request: Request<ListChannelsRequest>,
+    ) -> Result<Response<ListChannelsResponse>, Status> {
+        Ok(Response::new(ListChannelsResponse {
+            channels: vec![Channel {
+                active: true,
+                remote_pubkey: self.peer_pubkey.to_string(),
+                chan_id: CHANNEL_ID,
+            }],
+        }))
+    }
+
+    async fn get_chan_info(
+        &self,
+        request: Request<ChanInfoRequest>,
+    ) -> Result<Response<ChannelEdge>, Status> {
+        if request.get_ref().chan_id != CHANNEL_ID {
+            return Err(Status::not_found("edge not found"));
+        }
+
+        // Our peer is node 2, so its policy must not be confused with ours
+        Ok(Response::new(ChannelEdge {
+            channel_id: CHANNEL_ID,
+            node1_pub: self.node_pubkey.to_string(),
+            node2_pub: self.peer_pubkey.to_string(),
+            node1_policy: Some(RoutingPolicy {
+                time_lock_delta: 144,
+                ..Default::default()
+            }),
+            node2_policy: Some(peer_policy()),
+        }))
+    }
+}
+
+type HtlcStream =
+    Pin<Box<dyn Stream<Item = Result<ForwardHtlcInterceptRequest, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl Router for MockLnd {
+    type SendPaymentV2Stream = tokio_stream::Iter<std::vec::IntoIter<Result<Payment, Status>>>;
+
+    async fn send_payment_v2(
+        &self,
+        request: Request<SendPaymentRequest>,
+    ) -> Result<Response<Self::SendPaymentV2Stream>, Status> {
+        let request = request.into_inner();
+        let payment = Payment {
+            payment_hash: request.payment_hash.to_hex(),
+            payment_preimage: self.preimage.0.to_hex(),
+            fee_msat: 0,
+            ..Default::default()
+        };
+        self.payments.lock().await.push(request);
+
+        Ok(Response::new(tokio_stream::iter(vec![
+            Ok(Payment {
+                status: PaymentStatus::InFlight as i32,
+                ..payment.clone()
+            }),
+            Ok(Payment {
+                status: PaymentStatus::Succeeded as i32,
+                ..payment
+            }),
+        ])))
+    }
+
+    type TrackPaymentV2Stream = tokio_stream::Iter<std::vec::IntoIter<Result<Payment, Status>>>;
+
+    async fn track_payment_v2(
+        &self,
+        request: Request<TrackPaymentRequest>,
+    ) -> Result<Response<Self::TrackPaymentV2Stream>, Status> {
+        // We only ever succeed paying invoices for our preimage
+        let payment_hash = sha256::Hash::hash(&self.preimage.0);
+        if request.into_inner().payment_hash != payment_hash.into_inner().to_vec() {
+            return Err(Status::not_found("payment isn't initiated"));
+        }
+
+        Ok(Response::new(tokio_stream::iter(vec![Ok(Payment {
+            payment_hash: payment_hash.to_hex(),
+            payment_preimage: self.preimage.0.to_hex(),
+            status: PaymentStatus::Succeeded as i32,
+            ..Default::default()
+        })])))
+    }
+
+    type HtlcInterceptorStream = HtlcStream;
+
+    async fn htlc_interceptor(
+        &self,
+        request: Request<Streaming<ForwardHtlcInterceptResponse>>,
+    ) -> Result<Response<Self::HtlcInterceptorStream>, Status> {
+        let htlcs = self
+            .htlcs
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| Status::already_exists("interceptor already registered"))?;
+
+        let mut responses = request.into_inner();
+        let resolutions = self.resolutions.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(response)) = responses.next().await {
+                if resolutions.send(response).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(
+            ReceiverStream::new(htlcs).map(Ok::<_, Status>),
+        )))
+    }
+}
@@ -23,18 +23,47 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use anyhow::Result;
-use fedimint_api::config::FederationId;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{KeyPair, PublicKey, SecretKey};
+use bitcoin::{
+    Amount, Block, BlockHeader, Network, PackedLockTime, Script, Transaction, TxOut, WScriptHash,
+};
+use fedimint_api::config::{FederationId, ModuleGenRegistry};
+use fedimint_api::msats;
+use fedimint_ln::contracts::outgoing::{OutgoingContract, PrunedInvoice};
+use fedimint_ln::contracts::Preimage;
+use fedimint_ln::route_hints::{RouteHint, RouteHintHop};
+use fedimint_ln::{GatewayFees, RoutingFee};
+use fixtures::client::TestGatewayClientBuilder;
+use fixtures::fed::CONSENSUS_BLOCK_HEIGHT;
+use fixtures::lnd::{self, MockLnd};
 use fixtures::{fixtures, Fixtures};
+use lightning::chain::{BestBlock, Listen};
+use lightning::ln::PaymentSecret;
+use lightning_invoice::{Currency, InvoiceBuilder};
+use ln_gateway::client::{IGatewayClientBuilder, MemDbFactory};
+use ln_gateway::ldk::wallet::LdkWallet;
+use ln_gateway::ln::{LnRpc, PaymentStatus};
+use ln_gateway::lnd::routerrpc::{
+    CircuitKey, ForwardHtlcInterceptRequest, ResolveHoldForwardAction,
+};
+use ln_gateway::lnd::LndRpc;
 use ln_gateway::rpc::rpc_client::{Error, Response};
 use ln_gateway::{
     config::GatewayConfig,
     rpc::{
         rpc_client::RpcClient, BalancePayload, ConnectFedPayload, DepositAddressPayload,
-        DepositPayload, WithdrawPayload,
+        DepositPayload, GatewayRequest, GatewayRpcSender, WithdrawPayload,
     },
     utils::retry,
 };
 use mint_client::api::WsFederationConnect;
+use mint_client::ln::outgoing::OutgoingContractAccount;
+use mint_client::{module_decode_stubs, ClientError};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tokio::sync::mpsc;
 use tracing_subscriber::EnvFilter;
 use url::Url;
 
@@ -57,6 +86,8 @@ async fn test_gateway_authentication() -> Result<()> {
         password: gw_password.clone(),
         bind_address: gw_bind_address,
         announce_address: gw_announce_address.clone(),
+        fees: Default::default(),
+        lightning: Default::default(),
     };
 
     let Fixtures {
@@ -145,6 +176,310 @@ async fn test_gateway_authentication() -> Result<()> {
     task_group.shutdown_join_all(None).await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_refuses_contract_underpaying_fee() -> Result<()> {
+    let ctx = bitcoin::secp256k1::Secp256k1::new();
+    let fees = GatewayFees {
+        incoming: RoutingFee::default(),
+        outgoing: RoutingFee {
+            base_msat: 1_000,
+            proportional_millionths: 10_000,
+        },
+    };
+    let builder = TestGatewayClientBuilder::new(MemDbFactory.into());
+    let config = builder
+        .create_config(
+            WsFederationConnect { members: vec![] },
+            0,
+            PublicKey::from_secret_key(&ctx, &SecretKey::new(&mut OsRng)),
+            Url::parse("http://127.0.0.1:8080")?,
+            fees,
+        )
+        .await?;
+    let client = builder
+        .build(
+            config,
+            module_decode_stubs(),
+            ModuleGenRegistry::from(vec![]),
+        )
+        .await?;
+
+    let secret_key = SecretKey::new(&mut OsRng);
+    let invoice = InvoiceBuilder::new(Currency::Regtest)
+        .description("".to_string())
+        .payment_hash(sha256::Hash::hash(&[1; 32]))
+        .current_timestamp()
+        .min_final_cltv_expiry(0)
+        .payment_secret(PaymentSecret([0; 32]))
+        .amount_milli_satoshis(100_000)
+        .build_signed(|m| ctx.sign_ecdsa_recoverable(m, &secret_key))
+        .unwrap();
+    let contract = OutgoingContract {
+        hash: *invoice.payment_hash(),
+        gateway_key: client.config().redeem_key.x_only_public_key().0,
+        timelock: (CONSENSUS_BLOCK_HEIGHT + 100) as u32,
+        user_key: KeyPair::new(&ctx, &mut OsRng).x_only_public_key().0,
+        invoice: PrunedInvoice::from(&invoice),
+        cancelled: false,
+    };
+
+    // The advertised fee for 100 sat is 1 sat base plus 1%
+    let underpaying = OutgoingContractAccount {
+        amount: msats(101_999),
+        contract: contract.clone(),
+    };
+    assert!(matches!(
+        client.validate_outgoing_account(&underpaying, None).await,
+        Err(ClientError::Underfunded(required, _)) if required == msats(102_000)
+    ));
+
+    let paying = OutgoingContractAccount {
+        amount: msats(102_000),
+        contract,
+    };
+    let params = client.validate_outgoing_account(&paying, None).await?;
+    assert_eq!(params.max_send_amount, msats(100_000));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lnd_rpc_queries_node() -> Result<()> {
+    let (mock, handle) = MockLnd::start().await?;
+    let lnd_rpc = LndRpc::connect(&handle.config).await?;
+
+    assert_eq!(lnd_rpc.pubkey().await.unwrap(), mock.node_pubkey);
+
+    let balances = lnd_rpc.channel_balances().await.unwrap();
+    assert_eq!(balances.outbound, msats(lnd::LOCAL_BALANCE_MSAT));
+    assert_eq!(balances.inbound, msats(lnd::REMOTE_BALANCE_MSAT));
+
+    // The route hint must use the policy of our peer, not our own
+    let policy = lnd::peer_policy();
+    let route_hints = lnd_rpc.route_hints().await?;
+    assert_eq!(
+        route_hints,
+        vec![RouteHint(vec![RouteHintHop {
+            src_node_id: mock.peer_pubkey,
+            short_channel_id: lnd::CHANNEL_ID,
+            base_msat: policy.fee_base_msat as u32,
+            proportional_millionths: policy.fee_rate_milli_msat as u32,
+            cltv_expiry_delta: policy.time_lock_delta as u16,
+            htlc_minimum_msat: Some(policy.min_htlc as u64),
+            htlc_maximum_msat: Some(policy.max_htlc_msat),
+        }])]
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lnd_rpc_pays_invoice() -> Result<()> {
+    let (mock, handle) = MockLnd::start().await?;
+    let lnd_rpc = LndRpc::connect(&handle.config).await?;
+
+    let ctx = bitcoin::secp256k1::Secp256k1::new();
+    let secret_key = SecretKey::new(&mut OsRng);
+    let route_hint = RouteHint(vec![RouteHintHop {
+        src_node_id: mock.peer_pubkey,
+        short_channel_id: lnd::CHANNEL_ID,
+        base_msat: 1_000,
+        proportional_millionths: 100,
+        cltv_expiry_delta: 40,
+        htlc_minimum_msat: None,
+        htlc_maximum_msat: None,
+    }]);
+    let invoice = InvoiceBuilder::new(Currency::Regtest)
+        .description("".to_string())
+        .payment_hash(sha256::Hash::hash(&mock.preimage.0))
+        .current_timestamp()
+        .min_final_cltv_expiry(18)
+        .payment_secret(PaymentSecret([2; 32]))
+        .private_route(route_hint.to_ldk_route_hint())
+        .amount_milli_satoshis(1000)
+        .build_signed(|m| ctx.sign_ecdsa_recoverable(m, &secret_key))
+        .unwrap();
+
+    assert_eq!(
+        lnd_rpc
+            .pay(PrunedInvoice::from(&invoice), 100, 0.01)
+            .await
+            .unwrap(),
+        mock.preimage
+    );
+
+    // The payment is built from the pruned invoice alone
+    let payments = mock.payments.lock().await;
+    let payment = &payments[0];
+    assert!(payment.payment_request.is_empty());
+    assert_eq!(
+        payment.dest,
+        PublicKey::from_secret_key(&ctx, &secret_key)
+            .serialize()
+            .to_vec()
+    );
+    assert_eq!(payment.amt_msat, 1000);
+    assert_eq!(
+        payment.payment_hash,
+        sha256::Hash::hash(&mock.preimage.0).into_inner().to_vec()
+    );
+    assert_eq!(payment.payment_addr, vec![2; 32]);
+    assert_eq!(payment.final_cltv_delta, 18);
+    assert_eq!(payment.cltv_limit, 100);
+    assert_eq!(payment.fee_limit_msat, 10);
+    assert_eq!(payment.route_hints.len(), 1);
+    let hop = &payment.route_hints[0].hop_hints[0];
+    assert_eq!(hop.node_id, mock.peer_pubkey.to_string());
+    assert_eq!(hop.chan_id, lnd::CHANNEL_ID);
+    assert_eq!(hop.fee_base_msat, 1_000);
+    assert_eq!(hop.fee_proportional_millionths, 100);
+    assert_eq!(hop.cltv_expiry_delta, 40);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lnd_rpc_queries_payment_status() -> Result<()> {
+    let (mock, handle) = MockLnd::start().await?;
+    let lnd_rpc = LndRpc::connect(&handle.config).await?;
+
+    // Payments are recovered with the preimage LND learned
+    assert_eq!(
+        lnd_rpc
+            .payment_status(sha256::Hash::hash(&mock.preimage.0))
+            .await
+            .unwrap(),
+        PaymentStatus::Succeeded(mock.preimage.clone())
+    );
+
+    // Payments LND doesn't know were never attempted
+    assert_eq!(
+        lnd_rpc
+            .payment_status(sha256::Hash::hash(&[2; 32]))
+            .await
+            .unwrap(),
+        PaymentStatus::Failed
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_lnd_htlc_interceptor() -> Result<()> {
+    let (_mock, mut handle) = MockLnd::start().await?;
+    let lnd_rpc = LndRpc::connect(&handle.config).await?;
+
+    let (tx, mut rx) = mpsc::channel::<GatewayRequest>(10);
+    tokio::spawn(lnd_rpc.run_htlc_interceptor(GatewayRpcSender::new(tx)));
+
+    let htlc = |htlc_id, outgoing_requested_chan_id| ForwardHtlcInterceptRequest {
+        incoming_circuit_key: Some(CircuitKey {
+            chan_id: lnd::CHANNEL_ID,
+            htlc_id,
+        }),
+        incoming_amount_msat: 1000,
+        incoming_expiry: lnd::BLOCK_HEIGHT + 40,
+        payment_hash: [2; 32].to_vec(),
+        outgoing_requested_chan_id,
+        outgoing_amount_msat: 1000,
+        outgoing_expiry: lnd::BLOCK_HEIGHT + 40,
+    };
+
+    // HTLCs forwarded to other channels are resumed without involving the gateway
+    handle.htlcs.send(htlc(0, 7)).await?;
+    let resolution = handle.resolutions.recv().await.expect("mock LND stopped");
+    assert_eq!(resolution.action(), ResolveHoldForwardAction::Resume);
+    assert_eq!(resolution.incoming_circuit_key.unwrap().htlc_id, 0);
+
+    // HTLCs to our federations are settled with the preimage the gateway returns
+    handle.htlcs.send(htlc(1, 0)).await?;
+    let request = rx.recv().await.expect("interceptor stopped");
+    let GatewayRequest::ReceivePayment(request) = request else {
+        panic!("interceptor sent unexpected request");
+    };
+    request
+        .handle(|payload| async move {
+            assert_eq!(payload.htlc_accepted.htlc.cltv_expiry_relative, 40);
+            assert_eq!(payload.htlc_accepted.htlc.amount_msat, msats(1000));
+            Ok(Preimage([3; 32]))
+        })
+        .await;
+
+    let resolution = handle.resolutions.recv().await.expect("mock LND stopped");
+    assert_eq!(resolution.action(), ResolveHoldForwardAction::Settle);
+    assert_eq!(resolution.incoming_circuit_key.unwrap().htlc_id, 1);
+    assert_eq!(resolution.preimage, [3; 32].to_vec());
+
+    Ok(())
+}
+
+#[test]
+fn test_ldk_wallet_funds_channels() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("ldk-wallet-{}.json", OsRng.next_u64()));
+    let genesis = genesis_block(Network::Regtest);
+    let wallet = LdkWallet::load(
+        &[42; 32],
+        Network::Regtest,
+        path.clone(),
+        BestBlock::new(genesis.block_hash(), 0),
+    )?;
+
+    let deposit = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![],
+        output: vec![
+            TxOut {
+                value: 100_000,
+                script_pubkey: wallet.address().script_pubkey(),
+            },
+            TxOut {
+                value: 50_000,
+                script_pubkey: wallet.destination_script(),
+            },
+        ],
+    };
+    let block = Block {
+        header: BlockHeader {
+            prev_blockhash: genesis.block_hash(),
+            ..genesis.header
+        },
+        txdata: vec![deposit],
+    };
+    wallet.block_connected(&block, 1);
+    assert_eq!(wallet.best_block().height(), 1);
+    assert_eq!(wallet.balance(), Amount::from_sat(150_000));
+
+    // The largest coin covers the channel and its change goes back to the wallet
+    let channel_script = Script::new_v0_p2wsh(&WScriptHash::hash(&[1]));
+    let funding_tx = wallet.create_funding_transaction(channel_script.clone(), 80_000, 1_000)?;
+    assert_eq!(funding_tx.input.len(), 1);
+    assert_eq!(funding_tx.input[0].witness.len(), 2);
+    assert_eq!(funding_tx.output[0].script_pubkey, channel_script);
+    assert_eq!(
+        funding_tx.output[1].script_pubkey,
+        wallet.destination_script()
+    );
+    assert_eq!(wallet.balance(), Amount::from_sat(50_000));
+
+    // Reserved coins survive a restart and can't be spent twice
+    let wallet = LdkWallet::load(
+        &[42; 32],
+        Network::Regtest,
+        path,
+        BestBlock::new(genesis.block_hash(), 0),
+    )?;
+    assert_eq!(wallet.best_block().height(), 1);
+    assert!(wallet
+        .create_funding_transaction(channel_script, 80_000, 1_000)
+        .is_err());
+
+    wallet.unreserve(&funding_tx);
+    assert_eq!(wallet.balance(), Amount::from_sat(150_000));
+
+    Ok(())
+}
+
 /// Test that a given endpoint/functionality of func fails with the wrong password but works with the correct one
 async fn test_auth<Fut>(gw_password: &str, func: impl Fn(String) -> Fut) -> Result<()>
 where
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -7,11 +8,12 @@ use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::{PublicKey, SecretKey};
 use bitcoin::{secp256k1, KeyPair};
 use fedimint_api::Amount;
+use fedimint_ln::contracts::outgoing::PrunedInvoice;
 use fedimint_ln::route_hints::RouteHint;
 use fedimint_server::modules::ln::contracts::Preimage;
 use lightning::ln::PaymentSecret;
 use lightning_invoice::{Currency, Invoice, InvoiceBuilder, DEFAULT_EXPIRY_TIME};
-use ln_gateway::ln::{LightningError, LnRpc};
+use ln_gateway::ln::{ChannelBalances, LightningError, LnRpc, PaymentStatus};
 use rand::rngs::OsRng;
 
 use crate::fixtures::LightningTest;
@@ -22,6 +24,7 @@ pub struct FakeLightningTest {
     pub gateway_node_pub_key: secp256k1::PublicKey,
     gateway_node_sec_key: secp256k1::SecretKey,
     amount_sent: Arc<Mutex<u64>>,
+    paid_hashes: Arc<Mutex<HashSet<sha256::Hash>>>,
 }
 
 impl FakeLightningTest {
@@ -35,6 +38,7 @@ impl FakeLightningTest {
             gateway_node_sec_key: SecretKey::from_keypair(&kp),
             gateway_node_pub_key: PublicKey::from_keypair(&kp),
             amount_sent,
+            paid_hashes: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 }
@@ -50,6 +54,7 @@ impl LightningTest for FakeLightningTest {
             .current_timestamp()
             .min_final_cltv_expiry(0)
             .payment_secret(PaymentSecret([0; 32]))
+            .basic_mpp()
             .amount_milli_satoshis(amount.msats)
             .expiry_time(Duration::from_secs(
                 expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
@@ -75,16 +80,58 @@ impl LnRpc for FakeLightningTest {
 
     async fn pay(
         &self,
-        invoice: lightning_invoice::Invoice,
+        invoice: PrunedInvoice,
         _max_delay: u64,
         _max_fee_percent: f64,
     ) -> Result<Preimage, LightningError> {
-        *self.amount_sent.lock().unwrap() += invoice.amount_milli_satoshis().unwrap();
+        *self.amount_sent.lock().unwrap() += invoice.amount.unwrap().msats;
+        self.paid_hashes
+            .lock()
+            .unwrap()
+            .insert(invoice.payment_hash);
+
+        Ok(self.preimage.clone())
+    }
+
+    async fn pay_part(
+        &self,
+        invoice: PrunedInvoice,
+        amount: Amount,
+        _max_delay: u64,
+        _max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        *self.amount_sent.lock().unwrap() += amount.msats;
+        self.paid_hashes
+            .lock()
+            .unwrap()
+            .insert(invoice.payment_hash);
 
         Ok(self.preimage.clone())
     }
 
+    fn supports_mpp(&self) -> bool {
+        true
+    }
+
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError> {
+        if self.paid_hashes.lock().unwrap().contains(&payment_hash) {
+            Ok(PaymentStatus::Succeeded(self.preimage.clone()))
+        } else {
+            Ok(PaymentStatus::Failed)
+        }
+    }
+
     async fn route_hints(&self) -> Result<Vec<RouteHint>, Error> {
         Ok(vec![RouteHint(vec![])])
     }
+
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        Ok(ChannelBalances {
+            outbound: Amount::from_sats(100_000_000),
+            inbound: Amount::from_sats(100_000_000),
+        })
+    }
 }
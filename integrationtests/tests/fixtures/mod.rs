@@ -49,7 +49,7 @@ use fedimint_server::net::connect::{Connector, TlsTcpConnector};
 use fedimint_server::net::peers::PeerConnector;
 use fedimint_server::{consensus, EpochMessage, FedimintServer};
 use fedimint_testing::btc::{fixtures::FakeBitcoinTest, BitcoinTest};
-use fedimint_wallet::config::WalletConfig;
+use fedimint_wallet::config::{PegInDescriptorType, WalletConfig};
 use fedimint_wallet::db::UTXOKey;
 use fedimint_wallet::Wallet;
 use fedimint_wallet::WalletConsensusItem;
@@ -169,7 +169,14 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
     }
 
     let peers = (0..num_peers).map(PeerId::from).collect::<Vec<_>>();
-    let params = ServerConfigParams::gen_local(&peers, sats(100000), base_port, "test").unwrap();
+    // run the wallet tests against a taproot federation with `FM_TEST_PEG_IN_DESCRIPTOR=tr`
+    let descriptor_type = match env::var("FM_TEST_PEG_IN_DESCRIPTOR") {
+        Ok(s) => s.parse().expect("Invalid FM_TEST_PEG_IN_DESCRIPTOR"),
+        Err(_) => PegInDescriptorType::default(),
+    };
+    let params =
+        ServerConfigParams::gen_local(&peers, sats(100000), base_port, "test", descriptor_type)
+            .unwrap();
     let max_evil = hbbft::util::max_faulty(peers.len());
 
     let module_inits = ModuleGenRegistry::from(vec![
@@ -206,6 +213,12 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
                     fedimint_api::bitcoin_rpc::BitcoindRpcBackend::Electrum(_) => {
                         panic!("Electrum backend not supported for tests")
                     }
+                    fedimint_api::bitcoin_rpc::BitcoindRpcBackend::Esplora(_) => {
+                        panic!("Esplora backend not supported for tests")
+                    }
+                    fedimint_api::bitcoin_rpc::BitcoindRpcBackend::Quorum { .. } => {
+                        panic!("Quorum backend not supported for tests")
+                    }
                 };
             let bitcoin_rpc = fedimint_bitcoind::bitcoincore_rpc::make_bitcoind_rpc(
                 &bitcoin_rpc_url,
@@ -363,10 +376,10 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
         }
     };
 
-    // Wait till the gateway has registered itself
+    // Wait till the gateway has registered itself and the federation agreed on the registration
     while fixtures.user.client.fetch_active_gateway().await.is_err() {
-        tokio::time::sleep(Duration::from_millis(100)).await;
         info!("Waiting for gateway to register");
+        fixtures.fed.await_consensus_epochs(1).await?;
     }
 
     Ok(fixtures)
@@ -513,7 +526,11 @@ impl GatewayTest {
             api: Url::parse("http://example.com")
                 .expect("Could not parse URL to generate GatewayClientConfig API endpoint"),
             route_hints: vec![],
+            fees: Default::default(),
             valid_until: SystemTime::now(),
+            liquidity: None,
+            issues_invoices: false,
+            supports_mpp: true,
         };
 
         let bind_addr: SocketAddr = format!("127.0.0.1:{bind_port}").parse().unwrap();
@@ -526,6 +543,7 @@ impl GatewayTest {
             timelock_delta: 10,
             api: announce_addr.clone(),
             node_pub_key,
+            fees: Default::default(),
         };
 
         // Create federation client builder for the gateway
@@ -540,6 +558,8 @@ impl GatewayTest {
             bind_address: bind_addr,
             announce_address: announce_addr,
             password: "abc".into(),
+            fees: Default::default(),
+            lightning: Default::default(),
         };
 
         let gateway = LnGateway::new(
@@ -702,6 +722,35 @@ impl FederationTest {
         }
     }
 
+    /// Stops sending the custom proposal set by [`Self::override_proposal`]
+    pub fn clear_override_proposal(&self) {
+        for server in &self.servers {
+            server.borrow_mut().override_proposal = None;
+        }
+    }
+
+    /// Waits until every federation server proposes the transaction with the given id
+    #[allow(clippy::await_holding_refcell_ref)]
+    pub async fn await_proposed_transaction(&self, txid: fedimint_api::TransactionId) {
+        for server in &self.servers {
+            loop {
+                let proposal = server
+                    .borrow()
+                    .fedimint
+                    .consensus
+                    .get_consensus_proposal()
+                    .await;
+                let proposed = proposal.items.iter().any(
+                    |item| matches!(item, ConsensusItem::Transaction(tx) if tx.tx_hash() == txid),
+                );
+                if proposed {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+
     /// Submit a fedimint transaction to all federation servers
     #[allow(clippy::await_holding_refcell_ref)] // TODO: fix, it's just a test
     pub async fn submit_transaction(
@@ -3,10 +3,13 @@ use std::sync::Arc;
 
 use anyhow::Error;
 use async_trait::async_trait;
+use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::PublicKey;
+use fedimint_api::Amount;
+use fedimint_ln::contracts::outgoing::PrunedInvoice;
 use fedimint_ln::contracts::Preimage;
 use fedimint_ln::route_hints::RouteHint;
-use ln_gateway::ln::{LightningError, LnRpc};
+use ln_gateway::ln::{ChannelBalances, LightningError, LnRpc, PaymentStatus};
 use tokio::sync::Mutex;
 
 /// A proxy for the underlying LnRpc which can be used to add behavoir to it using the "Decorator pattern"
@@ -14,7 +17,7 @@ pub struct LnRpcAdapter {
     /// The actual LnRpc that we add behavior to.
     client: Box<dyn LnRpc>,
     /// A pair of <Invoice> and <Count> where client.pay() will fail <Count> times for each <Invoice>
-    fail_invoices: Arc<Mutex<HashMap<lightning_invoice::Invoice, u8>>>,
+    fail_invoices: Arc<Mutex<HashMap<PrunedInvoice, u8>>>,
 }
 
 impl LnRpcAdapter {
@@ -30,7 +33,10 @@ impl LnRpcAdapter {
     /// Register <invoice> to fail <times> before (attempt) succeeding. The invoice will be dropped from the HashMap after succeeding
     #[allow(dead_code)]
     pub async fn fail_invoice(&self, invoice: lightning_invoice::Invoice, times: u8) {
-        self.fail_invoices.lock().await.insert(invoice, times + 1);
+        self.fail_invoices
+            .lock()
+            .await
+            .insert(PrunedInvoice::from(&invoice), times + 1);
     }
 }
 
@@ -42,7 +48,7 @@ impl LnRpc for LnRpcAdapter {
 
     async fn pay(
         &self,
-        invoice: lightning_invoice::Invoice,
+        invoice: PrunedInvoice,
         max_delay: u64,
         max_fee_percent: f64,
     ) -> Result<Preimage, LightningError> {
@@ -62,7 +68,34 @@ impl LnRpc for LnRpcAdapter {
         self.client.pay(invoice, max_delay, max_fee_percent).await
     }
 
+    async fn pay_part(
+        &self,
+        invoice: PrunedInvoice,
+        amount: Amount,
+        max_delay: u64,
+        max_fee_percent: f64,
+    ) -> Result<Preimage, LightningError> {
+        self.client
+            .pay_part(invoice, amount, max_delay, max_fee_percent)
+            .await
+    }
+
+    fn supports_mpp(&self) -> bool {
+        self.client.supports_mpp()
+    }
+
+    async fn payment_status(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<PaymentStatus, LightningError> {
+        self.client.payment_status(payment_hash).await
+    }
+
     async fn route_hints(&self) -> Result<Vec<RouteHint>, Error> {
         Ok(vec![RouteHint(vec![])])
     }
+
+    async fn channel_balances(&self) -> Result<ChannelBalances, LightningError> {
+        self.client.channel_balances().await
+    }
 }
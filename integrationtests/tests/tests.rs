@@ -1,5 +1,6 @@
 mod fixtures;
 
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -10,18 +11,26 @@ use fedimint_api::core::{
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_api::task::TaskGroup;
-use fedimint_api::{msats, sats, TieredMulti};
+use fedimint_api::{msats, sats, OutPoint, TieredMulti};
+use fedimint_ln::contracts::incoming::IncomingContractVersion;
+use fedimint_ln::contracts::outgoing::PrunedInvoice;
 use fedimint_ln::contracts::{Preimage, PreimageDecryptionShare};
 use fedimint_ln::LightningConsensusItem;
 use fedimint_mint::{MintConsensusItem, MintOutputSignatureShare};
 use fedimint_server::consensus::TransactionSubmissionError::TransactionError;
 use fedimint_server::epoch::ConsensusItem;
-use fedimint_server::transaction::legacy::Output;
+use fedimint_server::transaction::legacy::{Input, Output};
 use fedimint_server::transaction::TransactionError::UnbalancedTransaction;
 use fedimint_wallet::PegOutSignatureItem;
 use fedimint_wallet::WalletConsensusItem::PegOutSignature;
 use fixtures::{rng, secp, sha256};
 use futures::future::{join_all, Either};
+use futures::StreamExt;
+use ln_gateway::ln::LnRpc;
+use mint_client::ln::db::{OutgoingPaymentKey, OutgoingRefundKey};
+use mint_client::ln::history::{PaymentDirection, PaymentStatus};
+use mint_client::ln::offer::OFFER_PAYMENT_BATCH;
+use mint_client::ln::outgoing::{GatewayPaymentState, OutgoingRefundEvent};
 use mint_client::transaction::TransactionBuilder;
 use mint_client::{ClientError, ConfigVerifyError};
 use threshold_crypto::{SecretKey, SecretKeyShare};
@@ -101,6 +110,64 @@ async fn peg_in_and_peg_out_with_fees() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn peg_in_registered_address_by_outpoint() -> Result<()> {
+    test(2, |fed, user, bitcoin, _, _| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
+        let peg_in_amount: u64 = 5000;
+
+        let registration_fee = fed.wallet.consensus.fee_consensus.peg_in_registration_abs;
+        fed.mint_notes_for_user(&user, registration_fee).await;
+        let (peg_in_address, registration) =
+            user.client.register_pegin_address(rng()).await.unwrap();
+        fed.run_consensus_epochs(1).await;
+        user.client
+            .wallet_client()
+            .await_peg_in_registration(registration)
+            .await
+            .unwrap();
+
+        let (_, tx) = bitcoin
+            .send_and_mine_block(&peg_in_address, Amount::from_sat(peg_in_amount))
+            .await;
+        let outpoint = bitcoin::OutPoint {
+            txid: tx.txid(),
+            vout: tx
+                .output
+                .iter()
+                .position(|out| out.script_pubkey == peg_in_address.script_pubkey())
+                .unwrap() as u32,
+        };
+
+        // Not claimable before the federation reached consensus on the block
+        assert!(user
+            .client
+            .peg_in_registered(outpoint, rng())
+            .await
+            .is_err());
+
+        bitcoin
+            .mine_blocks(fed.wallet.consensus.finality_delay as u64)
+            .await;
+        fed.run_consensus_epochs(1).await;
+
+        user.client
+            .peg_in_registered(outpoint, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await;
+        user.assert_total_notes(sats(peg_in_amount)).await;
+
+        // The proof is no longer served once the peg-in was claimed
+        assert!(user
+            .client
+            .peg_in_registered(outpoint, rng())
+            .await
+            .is_err());
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn peg_outs_are_rejected_if_fees_are_too_low() -> Result<()> {
     test(2, |fed, user, bitcoin, _, _| async move {
@@ -388,10 +455,10 @@ async fn drop_peers_who_dont_contribute_decryption_shares() -> Result<()> {
             .override_proposal(vec![ConsensusItem::Module(
                 fedimint_api::core::DynModuleConsensusItem::from_typed(
                     LEGACY_HARDCODED_INSTANCE_ID_LN,
-                    LightningConsensusItem {
+                    LightningConsensusItem::DecryptionShares(BTreeMap::from([(
                         contract_id,
-                        share: PreimageDecryptionShare(share),
-                    },
+                        PreimageDecryptionShare(share),
+                    )])),
                 ),
             )]);
         drop_peer_3_during_epoch(&fed).await.unwrap(); // preimage decryption
@@ -508,7 +575,7 @@ async fn lightning_gateway_pays_internal_invoice() -> Result<()> {
         let claim_outpoint = {
             let buy_preimage = gateway
                 .actor
-                .pay_invoice_buy_preimage(gateway.adapter.clone(), contract_id)
+                .pay_invoice_buy_preimage(gateway.adapter.clone(), contract_id, None)
                 .await
                 .unwrap();
 
@@ -560,6 +627,85 @@ async fn lightning_gateway_pays_internal_invoice() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_user_pays_internal_invoice_directly() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let receiving_user = user.new_user_with_peers(peers(&[0])).await;
+
+        let confirmed_invoice = {
+            let (txid, invoice, payment_keypair) = receiving_user
+                .client
+                .generate_unsigned_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+                .await
+                .unwrap();
+            fed.await_consensus_epochs(1).await.unwrap();
+
+            receiving_user
+                .client
+                .await_invoice_confirmation(txid, invoice, payment_keypair)
+                .await
+                .unwrap()
+        };
+        let invoice = confirmed_invoice.invoice.clone();
+
+        assert!(user.client.is_internal_invoice(&invoice).await.unwrap());
+        let (contract_id, funding_outpoint) = user
+            .client
+            .pay_internal_invoice(invoice, rng())
+            .await
+            .unwrap();
+        assert_eq!(contract_id, confirmed_invoice.contract_id());
+
+        // fund the incoming contract and decrypt its preimage
+        fed.await_consensus_epochs(2).await.unwrap();
+
+        user.client
+            .await_internal_payment(contract_id, funding_outpoint, rng())
+            .await
+            .unwrap();
+
+        let receiving_outpoint = receiving_user
+            .client
+            .claim_incoming_contract(contract_id, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // claim incoming contract and mint the notes
+
+        receiving_user
+            .client
+            .fetch_notes(receiving_outpoint)
+            .await
+            .unwrap();
+
+        user.assert_total_notes(sats(2000 - 1000)).await; // no gateway fees for internal payments
+        gateway.user.assert_total_notes(sats(0)).await; // gateway wasn't involved at all
+        receiving_user.assert_total_notes(sats(1000)).await;
+
+        // both sides keep a receipt of the payment
+        let sent = user.client.ln_payment_history().await;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].direction, PaymentDirection::Outgoing);
+        assert_eq!(sent[0].status, PaymentStatus::Succeeded);
+        assert_eq!(sent[0].fee, sats(0));
+        assert!(sent[0].gateways.is_empty());
+        let received = receiving_user.client.ln_payment_history().await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].direction, PaymentDirection::Incoming);
+        assert_eq!(received[0].status, PaymentStatus::Succeeded);
+        assert_eq!(received[0].amount, sats(1000));
+        assert_eq!(received[0].preimage, sent[0].preimage);
+        assert!(received[0].preimage.is_some());
+
+        if !lightning.is_shared() {
+            assert_eq!(lightning.amount_sent().await, sats(0));
+        }
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn lightning_gateway_pays_outgoing_invoice() -> Result<()> {
     test(2, |fed, user, bitcoin, gateway, lightning| async move {
@@ -589,9 +735,165 @@ async fn lightning_gateway_pays_outgoing_invoice() -> Result<()> {
             .await
             .unwrap();
 
-        let claim_outpoint = gateway
+        let (claim_outpoint, _) = gateway
             .actor
-            .pay_invoice(gateway.adapter.clone(), contract_id)
+            .pay_invoice(gateway.adapter.clone(), contract_id, None)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // contract to mint notes, sign notes
+
+        gateway
+            .actor
+            .await_outgoing_contract_claimed(contract_id, claim_outpoint)
+            .await
+            .unwrap();
+        user.assert_total_notes(sats(2000 - 1010)).await;
+        gateway.user.assert_total_notes(sats(1010)).await;
+
+        tokio::time::sleep(Duration::from_millis(500)).await; // FIXME need to wait for listfunds to update
+        if !lightning.is_shared() {
+            assert_eq!(lightning.amount_sent().await, sats(1000));
+        }
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_resumes_interrupted_payment() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
+
+        let invoice = lightning.invoice(sats(1000), None).await;
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let (contract_id, outpoint) = user
+            .client
+            .fund_outgoing_ln_contract(invoice.clone(), rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        user.client
+            .await_outgoing_contract_acceptance(outpoint)
+            .await
+            .unwrap();
+
+        // The gateway paid the invoice, but stopped before it claimed the contract
+        let contract_account = gateway
+            .client
+            .fetch_outgoing_contract(contract_id)
+            .await
+            .unwrap();
+        gateway
+            .client
+            .save_outgoing_payment(contract_account, None, GatewayPaymentState::PayingInvoice)
+            .await;
+        gateway
+            .adapter
+            .pay(PrunedInvoice::from(&invoice), 100, 0.01)
+            .await
+            .unwrap();
+
+        // After restarting it learns the preimage from the lightning node and claims the contract
+        let (_, epochs) = tokio::join!(
+            gateway
+                .actor
+                .resume_pending_payments(gateway.adapter.clone()),
+            fed.await_consensus_epochs(2) // contract to mint notes, sign notes
+        );
+        epochs.unwrap();
+
+        assert!(gateway.client.list_pending_outgoing().await.is_empty());
+        user.assert_total_notes(sats(2000 - 1010)).await;
+        gateway.user.assert_total_notes(sats(1010)).await;
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_pays_again_when_resuming_unattempted_payment() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
+
+        let invoice = lightning.invoice(sats(1000), None).await;
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let (contract_id, outpoint) = user
+            .client
+            .fund_outgoing_ln_contract(invoice, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        user.client
+            .await_outgoing_contract_acceptance(outpoint)
+            .await
+            .unwrap();
+
+        // The gateway stopped before it handed the payment to the lightning node
+        let contract_account = gateway
+            .client
+            .fetch_outgoing_contract(contract_id)
+            .await
+            .unwrap();
+        gateway
+            .client
+            .save_outgoing_payment(contract_account, None, GatewayPaymentState::PayingInvoice)
+            .await;
+
+        // After restarting it pays the pruned invoice of the contract and claims it
+        let (_, epochs) = tokio::join!(
+            gateway
+                .actor
+                .resume_pending_payments(gateway.adapter.clone()),
+            fed.await_consensus_epochs(2) // contract to mint notes, sign notes
+        );
+        epochs.unwrap();
+
+        assert!(gateway.client.list_pending_outgoing().await.is_empty());
+        user.assert_total_notes(sats(2000 - 1010)).await;
+        gateway.user.assert_total_notes(sats(1010)).await;
+        if !lightning.is_shared() {
+            assert_eq!(lightning.amount_sent().await, sats(1000));
+        }
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_pays_part_of_outgoing_invoice() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
+
+        let invoice = lightning.invoice(sats(1000), None).await;
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let (parts, txid) = user
+            .client
+            .fund_outgoing_ln_contract_parts(invoice, 2, rng())
+            .await
+            .unwrap();
+        // We only have a single gateway to pay through
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].amount, sats(1000));
+
+        fed.run_consensus_epochs(1).await;
+
+        user.client
+            .await_outgoing_contract_acceptance(OutPoint { txid, out_idx: 0 })
+            .await
+            .unwrap();
+
+        let contract_id = parts[0].contract_id;
+        let (claim_outpoint, _) = gateway
+            .actor
+            .pay_invoice(gateway.adapter.clone(), contract_id, Some(parts[0].amount))
             .await
             .unwrap();
         fed.run_consensus_epochs(2).await; // contract to mint notes, sign notes
@@ -668,7 +970,7 @@ async fn lightning_gateway_claims_refund_for_internal_invoice() -> Result<()> {
         let response = {
             let buy_preimage = gateway
                 .actor
-                .pay_invoice_buy_preimage(gateway.adapter.clone(), contract_id)
+                .pay_invoice_buy_preimage(gateway.adapter.clone(), contract_id, None)
                 .await
                 .unwrap();
 
@@ -754,10 +1056,10 @@ async fn receive_lightning_payment_valid_preimage() -> Result<()> {
             .await
             .unwrap();
 
-        // Check that the preimage matches user pubkey & lightning invoice preimage
+        // Check that the preimage commits to the user pubkey & matches the lightning invoice
         let pubkey = invoice.keypair.x_only_public_key().0;
-        assert_eq!(pubkey, preimage.to_public_key().unwrap());
-        assert_eq!(&sha256(&pubkey.serialize()), invoice.invoice.payment_hash());
+        assert_eq!(preimage, Preimage::from_claim_key(&pubkey));
+        assert_eq!(&sha256(&preimage.0), invoice.invoice.payment_hash());
 
         // User claims their ecash
         user.client
@@ -777,6 +1079,66 @@ async fn receive_lightning_payment_valid_preimage() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn receive_lightning_payment_via_reusable_offer() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, _| async move {
+        let starting_balance = sats(2000);
+        let invoice_amount = sats(1000);
+
+        fed.mine_and_mint(&gateway.user, &*bitcoin, starting_balance)
+            .await;
+
+        // The user hands the gateway a batch of payments whose preimages only the federation can
+        // decrypt
+        let payload = user
+            .client
+            .new_reusable_offer("reusable".into(), gateway.keys.clone(), rng())
+            .await;
+        let registration = gateway.actor.register_offer(payload).await.unwrap();
+        assert_eq!(registration.unused_payments, OFFER_PAYMENT_BATCH);
+
+        // A payer requests an invoice, so the gateway offers the first payment to the federation
+        let (invoice, epochs) = tokio::join!(
+            gateway
+                .actor
+                .offer_invoice(registration.offer_id, invoice_amount, None),
+            fed.await_consensus_epochs(1)
+        );
+        epochs.unwrap();
+        let invoice = invoice.unwrap();
+
+        // The gateway receives the payment and buys the preimage from the federation
+        let (outpoint, _) = gateway
+            .actor
+            .buy_preimage_offer(invoice.payment_hash(), &invoice_amount, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // 1 epoch to process contract, 1 for preimage decryption
+        let preimage = gateway
+            .actor
+            .await_preimage_decryption(outpoint)
+            .await
+            .unwrap();
+        assert_eq!(&sha256(&preimage.0), invoice.payment_hash());
+
+        // The user finds the funded contract by scanning the payments handed to the gateway
+        let claim = user.client.claim_offer_payments(rng()).await.unwrap();
+        assert!(claim.is_some());
+        fed.run_consensus_epochs(2).await; // 1 epoch to process contract, 1 to sweep ecash from contract
+
+        gateway
+            .user
+            .assert_total_notes(starting_balance - invoice_amount)
+            .await;
+        user.assert_total_notes(invoice_amount).await;
+        assert_eq!(fed.max_balance_sheet(), 0);
+
+        // Claimed payments are never claimed twice
+        assert_eq!(user.client.claim_offer_payments(rng()).await.unwrap(), None);
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn receive_lightning_payment_invalid_preimage() -> Result<()> {
     test(2, |fed, user, bitcoin, gateway, _| async move {
@@ -791,12 +1153,19 @@ async fn receive_lightning_payment_invalid_preimage() -> Result<()> {
         // Manually construct offer where sha256(preimage) != hash
         let kp = KeyPair::new(&secp(), &mut rng());
         let payment_hash = sha256(&[0]);
-        let offer_output = user.client.ln_client().create_offer_output(
-            payment_amount,
-            payment_hash,
-            Preimage(kp.x_only_public_key().0.serialize()),
-            None,
-        );
+        let offer_output = user
+            .client
+            .ln_client()
+            .create_offer_output(
+                payment_amount,
+                payment_hash,
+                Preimage(kp.x_only_public_key().0.serialize()),
+                None,
+                None,
+                IncomingContractVersion::V0,
+            )
+            .await
+            .unwrap();
         let mut builder = TransactionBuilder::default();
         builder.output(Output::LN(offer_output));
         user.client
@@ -906,6 +1275,8 @@ async fn lightning_gateway_can_abort_payment_to_return_user_funds() -> Result<()
                     .get_outgoing_contract(contract_id)
                     .await
                     .unwrap(),
+                None,
+                GatewayPaymentState::PayingInvoice,
             )
             .await;
 
@@ -916,19 +1287,187 @@ async fn lightning_gateway_can_abort_payment_to_return_user_funds() -> Result<()
             .await
             .unwrap();
         fed.run_consensus_epochs(1).await;
-        let outpoint = user
+        let (outpoint, epochs) = tokio::join!(
+            user.client.try_refund_outgoing_contract(contract_id, rng()),
+            fed.await_consensus_epochs(1) // process the refund
+        );
+        epochs.unwrap();
+        let outpoint = outpoint.unwrap();
+        fed.run_consensus_epochs(1).await; // sign the refunded notes
+        user.client.fetch_notes(outpoint).await.unwrap();
+        assert_eq!(user.total_notes().await, sats(1010));
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_refund_watcher_refunds_cancelled_contract() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let invoice = lightning.invoice(sats(1000), None);
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(1010)).await; // 1% LN fee
+        let (contract_id, _) = user
             .client
-            .try_refund_outgoing_contract(contract_id, rng())
+            .fund_outgoing_ln_contract(invoice.await, rng())
             .await
             .unwrap();
-        fed.run_consensus_epochs(2).await;
+        fed.run_consensus_epochs(1).await; // send notes to LN contract
+
+        gateway
+            .client
+            .save_outgoing_payment(
+                gateway
+                    .client
+                    .ln_client()
+                    .get_outgoing_contract(contract_id)
+                    .await
+                    .unwrap(),
+                None,
+                GatewayPaymentState::PayingInvoice,
+            )
+            .await;
+        gateway
+            .client
+            .abort_outgoing_payment(contract_id)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        // The watcher notices the cancellation without the user asking for a refund
+        let mut task_group = TaskGroup::new();
+        let mut events = Box::pin(
+            user.client
+                .clone()
+                .spawn_refund_watcher(&mut task_group)
+                .await,
+        );
+        let (event, epochs) = tokio::join!(events.next(), fed.await_consensus_epochs(1));
+        epochs.unwrap();
+        let outpoint = match event.unwrap() {
+            OutgoingRefundEvent::Refunded {
+                contract_id: refunded,
+                outpoint,
+            } => {
+                assert_eq!(refunded, contract_id);
+                outpoint
+            }
+            event => panic!("Unexpected refund event {event:?}"),
+        };
+        fed.run_consensus_epochs(1).await;
         user.client.fetch_notes(outpoint).await.unwrap();
+        task_group.shutdown().await;
+
         assert_eq!(user.total_notes().await, sats(1010));
+        assert_eq!(
+            user.client.ln_payment_history().await[0].status,
+            PaymentStatus::Refunded
+        );
         assert_eq!(fed.max_balance_sheet(), 0);
     })
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_rejected_refund_keeps_tracking_contract() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let invoice = lightning.invoice(sats(1000), None);
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(1010)).await; // 1% LN fee
+        let (contract_id, _) = user
+            .client
+            .fund_outgoing_ln_contract(invoice.await, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await; // send notes to LN contract
+
+        gateway
+            .client
+            .save_outgoing_payment(
+                gateway
+                    .client
+                    .ln_client()
+                    .get_outgoing_contract(contract_id)
+                    .await
+                    .unwrap(),
+                None,
+                GatewayPaymentState::PayingInvoice,
+            )
+            .await;
+        gateway
+            .client
+            .abort_outgoing_payment(contract_id)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        // A competing refund of the same contract gets processed before ours
+        let contract_data = user
+            .client
+            .db()
+            .begin_transaction()
+            .await
+            .get_value(&OutgoingPaymentKey(contract_id))
+            .await
+            .unwrap()
+            .unwrap();
+        let race_refund = async {
+            let our_refund = loop {
+                let refund = user
+                    .client
+                    .db()
+                    .begin_transaction()
+                    .await
+                    .get_value(&OutgoingRefundKey(contract_id))
+                    .await
+                    .unwrap();
+                match refund {
+                    Some(outpoint) => break outpoint,
+                    None => tokio::time::sleep(Duration::from_millis(100)).await,
+                }
+            };
+            fed.await_proposed_transaction(our_refund.txid).await;
+
+            let mut builder = TransactionBuilder::default();
+            let (refund_key, refund_input) = user
+                .client
+                .ln_client()
+                .create_refund_outgoing_contract_input(&contract_data);
+            builder.input(&mut vec![*refund_key], Input::LN(refund_input));
+            let mut dbtx = user.client.db().begin_transaction().await;
+            let competing_refund = builder.build(&user.client, &mut dbtx, rng()).await;
+            dbtx.commit_tx().await.unwrap();
+
+            fed.override_proposal(vec![ConsensusItem::Transaction(
+                competing_refund.into_type_erased(),
+            )]);
+            fed.run_consensus_epochs(1).await;
+            fed.clear_override_proposal();
+            fed.run_consensus_epochs(1).await; // our refund gets rejected
+        };
+        let (refund, _) = tokio::join!(
+            user.client.try_refund_outgoing_contract(contract_id, rng()),
+            race_refund
+        );
+
+        assert_matches!(refund, Err(ClientError::TransactionRejected(_)));
+        assert!(user
+            .client
+            .db()
+            .begin_transaction()
+            .await
+            .get_value(&OutgoingPaymentKey(contract_id))
+            .await
+            .unwrap()
+            .is_some());
+        assert_ne!(
+            user.client.ln_payment_history().await[0].status,
+            PaymentStatus::Refunded
+        );
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn runs_consensus_if_tx_submitted() -> Result<()> {
     test(2, |fed, user_send, bitcoin, _, _| async move {
@@ -25,6 +25,9 @@ pub struct LightningConfigConsensus {
     pub threshold_pub_keys: threshold_crypto::PublicKeySet,
     /// Fees charged for LN transactions
     pub fee_consensus: FeeConsensus,
+    /// Limits the creation of offers, which is free since they don't require any inputs
+    #[serde(default)]
+    pub offer_rate_limit: OfferRateLimit,
 }
 
 impl LightningConfigConsensus {
@@ -39,6 +42,8 @@ pub struct LightningConfigPrivate {
     // TODO: propose serde(with = "…") based protection upstream instead
     /// Our secret key for decrypting preimages
     pub threshold_sec_key: SerdeSecret<threshold_crypto::SecretKeyShare>,
+    /// Our id among the guardians, identifies the consensus items we proposed
+    pub our_peer_id: PeerId,
 }
 
 impl TypedClientModuleConfig for LightningClientConfig {
@@ -51,6 +56,8 @@ impl TypedClientModuleConfig for LightningClientConfig {
 pub struct LightningClientConfig {
     pub threshold_pub_key: threshold_crypto::PublicKey,
     pub fee_consensus: FeeConsensus,
+    #[serde(default)]
+    pub offer_rate_limit: OfferRateLimit,
 }
 
 impl TypedServerModuleConsensusConfig for LightningConfigConsensus {
@@ -60,6 +67,7 @@ impl TypedServerModuleConsensusConfig for LightningConfigConsensus {
             serde_json::to_value(&LightningClientConfig {
                 threshold_pub_key: self.threshold_pub_keys.public_key(),
                 fee_consensus: self.fee_consensus.clone(),
+                offer_rate_limit: self.offer_rate_limit.clone(),
             })
             .expect("Serialization can't fail"),
         )
@@ -80,6 +88,9 @@ impl TypedServerModuleConfig for LightningConfig {
     }
 
     fn validate_config(&self, identity: &PeerId) -> anyhow::Result<()> {
+        if self.private.our_peer_id != *identity {
+            bail!("Lightning config belongs to {}", self.private.our_peer_id);
+        }
         if self.private.threshold_sec_key.public_key_share()
             != self
                 .consensus
@@ -88,6 +99,16 @@ impl TypedServerModuleConfig for LightningConfig {
         {
             bail!("Lightning private key doesn't match pubkey share");
         }
+        if self.consensus.offer_rate_limit.proof_of_work_difficulty
+            > MAX_OFFER_PROOF_OF_WORK_DIFFICULTY
+        {
+            bail!(
+                "Offer proof of work difficulty exceeds {MAX_OFFER_PROOF_OF_WORK_DIFFICULTY} bits"
+            );
+        }
+        if self.consensus.offer_rate_limit.offers_per_difficulty_bit == Some(0) {
+            bail!("Offer proof of work difficulty can't rise every 0 offers");
+        }
         Ok(())
     }
 }
@@ -106,3 +127,50 @@ impl Default for FeeConsensus {
         }
     }
 }
+
+/// Proof of work difficulty of new federations, about 65k hashes per offer
+pub const DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY: u8 = 16;
+
+/// Highest proof of work difficulty a federation may demand, beyond it clients would spend hours
+/// solving a single offer
+pub const MAX_OFFER_PROOF_OF_WORK_DIFFICULTY: u8 = 32;
+
+/// Number of offers per consensus epoch after which new federations demand one more bit of proof
+/// of work, so the 100th offer of an epoch takes about 1000 times the work of the first
+pub const DEFAULT_OFFERS_PER_DIFFICULTY_BIT: u64 = 10;
+
+/// How the federation prevents offers from being spammed into its database
+///
+/// Offers usually expire with their invoice, so limiting the rate of their creation also bounds the
+/// number of active offers. The default doesn't limit offers at all, it's only used for configs
+/// generated before offers were rate limited, see [`OfferRateLimit::new_federation`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+pub struct OfferRateLimit {
+    /// Every offer has to contain a nonce such that its
+    /// [proof of work hash](crate::contracts::incoming::IncomingContractOffer::proof_of_work_hash)
+    /// starts with this many zero bits, at most [`MAX_OFFER_PROOF_OF_WORK_DIFFICULTY`]
+    pub proof_of_work_difficulty: u8,
+    /// If set, every time this many offers were created in the current consensus epoch further
+    /// offers need one more zero bit. Flooding an epoch with offers thus gets exponentially more
+    /// expensive, while offers with enough work still get in no matter how many others there are.
+    pub offers_per_difficulty_bit: Option<u64>,
+}
+
+impl OfferRateLimit {
+    /// Rate limit of newly generated configs
+    pub fn new_federation() -> Self {
+        OfferRateLimit {
+            proof_of_work_difficulty: DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY,
+            offers_per_difficulty_bit: Some(DEFAULT_OFFERS_PER_DIFFICULTY_BIT),
+        }
+    }
+
+    /// Proof of work difficulty of the next offer if `epoch_offer_count` offers were already
+    /// created in the current consensus epoch
+    pub fn difficulty(&self, epoch_offer_count: u64) -> u8 {
+        let load_bits = self
+            .offers_per_difficulty_bit
+            .map_or(0, |offers| epoch_offer_count / offers);
+        u8::try_from(u64::from(self.proof_of_work_difficulty) + load_bits).unwrap_or(u8::MAX)
+    }
+}
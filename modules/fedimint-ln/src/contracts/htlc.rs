@@ -0,0 +1,39 @@
+use bitcoin_hashes::Hash as BitcoinHash;
+use fedimint_api::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::{ContractId, IdentifyableContract, Preimage};
+
+/// General-purpose hash-timelocked contract, e.g. for atomic swaps between users or with a swap
+/// service
+///
+/// The sender locks up funds that the recipient can claim by revealing the preimage of the hash
+/// before the timelock expires. Once it expired the sender can claim the funds back. Since the
+/// federation keeps the revealed preimage the counterparty of a swap can use it to claim funds
+/// locked to the same hash elsewhere, e.g. on-chain or in another federation.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct HtlcContract {
+    /// Hash whose preimage allows the recipient to spend the contract before the timelock expires
+    pub hash: bitcoin_hashes::sha256::Hash,
+    /// Public key of the recipient that can claim the money by revealing the preimage
+    pub recipient_key: secp256k1::XOnlyPublicKey,
+    /// Federation block height at which the money will be spendable by the sender
+    pub timelock: u32,
+    /// Public key of the sender that can claim the money back after the timelock expires
+    pub sender_key: secp256k1::XOnlyPublicKey,
+}
+
+impl IdentifyableContract for HtlcContract {
+    fn contract_id(&self) -> ContractId {
+        let mut engine = ContractId::engine();
+        Encodable::consensus_encode(self, &mut engine).expect("Hashing never fails");
+        ContractId::from_engine(engine)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct FundedHtlcContract {
+    pub contract: HtlcContract,
+    /// Preimage revealed when the recipient claimed the contract
+    pub revealed_preimage: Option<Preimage>,
+}
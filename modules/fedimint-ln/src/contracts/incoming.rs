@@ -8,35 +8,287 @@ use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::OutPoint;
 use serde::{Deserialize, Serialize};
 
-use crate::contracts::{ContractId, DecryptedPreimage, EncryptedPreimage, IdentifyableContract};
+use crate::contracts::{
+    ContractId, DecryptedPreimage, EncryptedPreimage, IdentifyableContract, Preimage,
+};
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+const ACCEPTANCE_TAG: &str = "incoming contract acceptance";
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct IncomingContractOffer {
     /// Amount for which the user is willing to sell the preimage
     pub amount: fedimint_api::Amount,
     pub hash: bitcoin_hashes::sha256::Hash,
     pub encrypted_preimage: EncryptedPreimage,
+    /// Unix time in seconds from which on the offer can't be funded anymore and gets removed by
+    /// the federation, usually the expiry of the corresponding invoice
     pub expiry_time: Option<u64>,
+    /// If set the offer is a hold invoice: the federation holds the funded contract until the
+    /// payment is accepted with a signature of this key, see [`IncomingContractHold`]
+    pub hold_key: Option<secp256k1::XOnlyPublicKey>,
+    /// How the encrypted preimage relates to the key claiming the funded contract
+    #[serde(default)]
+    pub version: IncomingContractVersion,
+    /// Varied to meet the federation's proof of work difficulty if it requires any, see
+    /// [`OfferRateLimit`](crate::config::OfferRateLimit)
+    #[serde(default)]
+    pub nonce: u64,
 }
 
 impl IncomingContractOffer {
     pub fn id(&self) -> OfferId {
         OfferId::from_hash(self.hash)
     }
+
+    /// Whether the offer expired at the given unix time in seconds
+    pub fn is_expired(&self, time: u64) -> bool {
+        self.expiry_time
+            .map_or(false, |expiry_time| expiry_time <= time)
+    }
+
+    /// Hash committing to the entire offer, including its nonce
+    pub fn proof_of_work_hash(&self) -> Sha256 {
+        let mut engine = Sha256::engine();
+        self.consensus_encode(&mut engine)
+            .expect("Hashing never fails");
+        Sha256::from_engine(engine)
+    }
+
+    /// Whether the proof of work hash starts with at least `difficulty` zero bits
+    pub fn meets_proof_of_work(&self, difficulty: u8) -> bool {
+        let mut zero_bits = 0;
+        for byte in self.proof_of_work_hash().into_inner() {
+            zero_bits += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        zero_bits >= u32::from(difficulty)
+    }
+
+    /// Increments the nonce until the offer meets the proof of work difficulty
+    ///
+    /// This takes about `2^difficulty` hashes, so it should run on a thread meant for blocking work
+    /// and the difficulty must be checked against
+    /// [`MAX_OFFER_PROOF_OF_WORK_DIFFICULTY`](crate::config::MAX_OFFER_PROOF_OF_WORK_DIFFICULTY).
+    pub fn solve_proof_of_work(&mut self, difficulty: u8) {
+        while !self.meets_proof_of_work(difficulty) {
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+    }
+}
+
+/// How the preimage sold by an incoming contract relates to the key that can claim the contract
+///
+/// The version isn't encoded on its own but as a flag of the trailing optional field of offers and
+/// contracts, see `encode_option_with_flags` and `LegacyEncoding`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum IncomingContractVersion {
+    /// The preimage is the x-only claim key itself. This is bad for privacy since public keys are
+    /// distinguishable from randomness and the payer learns the recipient uses a federated mint.
+    #[default]
+    V0,
+    /// The preimage is the hash of the claim key, see [`Preimage::from_claim_key`]. Claiming the
+    /// contract reveals the key in the input's witness and proves knowledge of it by signing.
+    V1,
+}
+
+impl IncomingContractVersion {
+    /// Whether a contract of this version selling `preimage` can be claimed by its recipient
+    pub fn is_valid_preimage(&self, preimage: &Preimage) -> bool {
+        match self {
+            IncomingContractVersion::V0 => preimage.to_public_key().is_ok(),
+            // Any preimage may be the hash of a key, the recipient has to prove it when claiming
+            IncomingContractVersion::V1 => true,
+        }
+    }
+}
+
+/// Flag set in the option's flag byte for [`IncomingContractVersion::V1`]
+const VERSION_V1_FLAG: u8 = 0b10;
+
+/// Flag set in the offer's option flag byte if a proof of work nonce follows the option
+const NONCE_FLAG: u8 = 0b100;
+
+/// Encodes an option like [`Option`]'s own encoding but with additional `flags` in its flag byte
+fn encode_option_with_flags<T: Encodable, W: std::io::Write>(
+    option: &Option<T>,
+    flags: u8,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let mut len = (flags | u8::from(option.is_some())).consensus_encode(writer)?;
+    if let Some(inner) = option {
+        len += inner.consensus_encode(writer)?;
+    }
+    Ok(len)
+}
+
+/// Decodes an option encoded by [`encode_option_with_flags`] given its already read flag byte,
+/// rejecting flags that aren't in `known_flags`
+fn decode_option_with_flags<T: Decodable, D: std::io::Read>(
+    flags: u8,
+    d: &mut D,
+    modules: &ModuleDecoderRegistry,
+    known_flags: u8,
+) -> Result<(Option<T>, u8), DecodeError> {
+    if flags & !(known_flags | 1) != 0 {
+        return Err(DecodeError::from_str("Unknown flags in versioned option"));
+    }
+    let option = if flags & 1 == 1 {
+        Some(T::consensus_decode(d, modules)?)
+    } else {
+        None
+    };
+    Ok((option, flags & !1))
+}
+
+impl IncomingContractVersion {
+    fn flags(&self) -> u8 {
+        match self {
+            IncomingContractVersion::V0 => 0,
+            IncomingContractVersion::V1 => VERSION_V1_FLAG,
+        }
+    }
+
+    fn from_flags(flags: u8) -> Self {
+        if flags & VERSION_V1_FLAG == 0 {
+            IncomingContractVersion::V0
+        } else {
+            IncomingContractVersion::V1
+        }
+    }
+}
+
+/// Encoding of offers and contracts from before they had a flag byte, see
+/// `encode_option_with_flags`
+///
+/// Offers and contracts that don't use any of the fields added since, i.e. version 0 ones without
+/// hold or proof of work, are still encoded this way inside the enums containing them, so anything
+/// encoded before stays valid. Since the encoding can't tell whether a flag byte follows, the enums
+/// use their original variant index for it and a new one for the encoding with the flag byte.
+pub(crate) trait LegacyEncoding: Encodable + Decodable {
+    /// Whether the value can be encoded without the flag byte
+    fn has_legacy_encoding(&self) -> bool;
+
+    fn consensus_encode_legacy<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error>;
+
+    fn consensus_decode_legacy<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError>;
+}
+
+/// Encodes a value as enum variant, using `legacy_variant` if it has a [`LegacyEncoding`] and
+/// `variant` otherwise
+pub(crate) fn encode_legacy_variant<T: LegacyEncoding, W: std::io::Write>(
+    value: &T,
+    legacy_variant: u64,
+    variant: u64,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    if value.has_legacy_encoding() {
+        Ok(legacy_variant.consensus_encode(writer)? + value.consensus_encode_legacy(writer)?)
+    } else {
+        Ok(variant.consensus_encode(writer)? + value.consensus_encode(writer)?)
+    }
+}
+
+/// Decodes a value encoded by [`encode_legacy_variant`] after its non-legacy variant index,
+/// rejecting values that should have used the legacy variant to keep the encoding unique
+pub(crate) fn decode_non_legacy_variant<T: LegacyEncoding, D: std::io::Read>(
+    d: &mut D,
+    modules: &ModuleDecoderRegistry,
+) -> Result<T, DecodeError> {
+    let value = T::consensus_decode(d, modules)?;
+    if value.has_legacy_encoding() {
+        return Err(DecodeError::from_str(
+            "Offer or contract should use legacy encoding",
+        ));
+    }
+    Ok(value)
+}
+
+impl Encodable for IncomingContractOffer {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = self.consensus_encode_legacy(writer)?;
+        let nonce_flag = if self.nonce == 0 { 0 } else { NONCE_FLAG };
+        len += encode_option_with_flags(&self.hold_key, self.version.flags() | nonce_flag, writer)?;
+        if self.nonce != 0 {
+            len += self.nonce.consensus_encode(writer)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for IncomingContractOffer {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let offer = Self::consensus_decode_legacy(d, modules)?;
+        // Offers stored in the database before the flag byte was introduced end here
+        let mut flags = [0; 1];
+        if d.read(&mut flags).map_err(DecodeError::from_err)? == 0 {
+            return Ok(offer);
+        }
+        let (hold_key, flags) =
+            decode_option_with_flags(flags[0], d, modules, VERSION_V1_FLAG | NONCE_FLAG)?;
+        let nonce = if flags & NONCE_FLAG == 0 {
+            0
+        } else {
+            match u64::consensus_decode(d, modules)? {
+                // A zero nonce is never encoded, keeping the encoding unique
+                0 => return Err(DecodeError::from_str("Encoded zero nonce")),
+                nonce => nonce,
+            }
+        };
+        Ok(IncomingContractOffer {
+            hold_key,
+            version: IncomingContractVersion::from_flags(flags),
+            nonce,
+            ..offer
+        })
+    }
 }
 
-// FIXME: the protocol currently envisions the use of a pub key as preimage. This is bad for privacy
-// though since pub keys are distinguishable from randomness and the payer would learn the recipient
-// is using a federated mint. Probably best to just hash the key before.
+impl LegacyEncoding for IncomingContractOffer {
+    fn has_legacy_encoding(&self) -> bool {
+        self.hold_key.is_none() && self.version == IncomingContractVersion::V0 && self.nonce == 0
+    }
+
+    fn consensus_encode_legacy<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.amount.consensus_encode(writer)?;
+        len += self.hash.consensus_encode(writer)?;
+        len += self.encrypted_preimage.consensus_encode(writer)?;
+        len += self.expiry_time.consensus_encode(writer)?;
+        Ok(len)
+    }
+
+    fn consensus_decode_legacy<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(IncomingContractOffer {
+            amount: Decodable::consensus_decode(d, modules)?,
+            hash: Decodable::consensus_decode(d, modules)?,
+            encrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            expiry_time: Decodable::consensus_decode(d, modules)?,
+            hold_key: None,
+            version: IncomingContractVersion::V0,
+            nonce: 0,
+        })
+    }
+}
 
 // FIXME: encrypt preimage to LN gateway?
 
 /// Specialized smart contract for incoming payments
 ///
 /// A user generates a private/public keypair that can later be used to claim the incoming funds.
-/// The public key is the defined as the preimage of a payment has and threshold-encrypted to the
-/// federation's public key. They then put up the encrypted preimage for sale by creating an
-/// [`IncomingContractOffer`].
+/// The public key, or its hash depending on the [`IncomingContractVersion`], is the defined as the
+/// preimage of a payment has and threshold-encrypted to the federation's public key. They then put
+/// up the encrypted preimage for sale by creating an [`IncomingContractOffer`].
 ///
 /// A lightning gateway wanting to claim an incoming HTLC can now use the offer to buy the preimage
 /// by transferring funds into the corresponding contract. This activates the threshold decryption
@@ -45,22 +297,112 @@ impl IncomingContractOffer {
 ///
 ///   1. The decryption results in a valid preimage which is given to the lightning gateway. The
 ///      user can in return claim the funds from the contract. For this they need to be able to sign
-///      with the private key corresponding to the public key which they used as preimage.
+///      with the private key corresponding to the public key which they derived the preimage from.
 ///   2. The decryption results in an invalid preimage, the gateway can claim back the money. For
 ///      this to work securely they have to specify a public key when creating the actual contract.
+///
+/// Contracts funded for hold invoice offers are special: the decryption only starts once the user
+/// accepts the payment, see [`IncomingContractHold`].
 // TODO: don't duplicate offer, include id instead and fetch offer on mint side
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct IncomingContract {
     /// Payment hash which's corresponding preimage is being sold
     pub hash: bitcoin_hashes::sha256::Hash,
     /// Encrypted preimage as specified in offer
     pub encrypted_preimage: EncryptedPreimage,
     /// Status of preimage decryption, will either end in failure or contain the preimage
-    /// eventually. In case decryption was successful the preimage is also the public key locking
+    /// eventually. In case decryption was successful the preimage determines the public key locking
     /// the contract, allowing the offer creator to redeem their money.
     pub decrypted_preimage: DecryptedPreimage,
     /// Key that can unlock contract in case the decrypted preimage was invalid
     pub gateway_key: secp256k1::XOnlyPublicKey,
+    /// Set iff the offer was a hold invoice
+    pub hold: Option<IncomingContractHold>,
+    /// Version as specified in offer
+    #[serde(default)]
+    pub version: IncomingContractVersion,
+}
+
+impl Encodable for IncomingContract {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = self.consensus_encode_legacy(writer)?;
+        len += encode_option_with_flags(&self.hold, self.version.flags(), writer)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for IncomingContract {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let contract = Self::consensus_decode_legacy(d, modules)?;
+        let flags = u8::consensus_decode(d, modules)?;
+        let (hold, flags) = decode_option_with_flags(flags, d, modules, VERSION_V1_FLAG)?;
+        Ok(IncomingContract {
+            hold,
+            version: IncomingContractVersion::from_flags(flags),
+            ..contract
+        })
+    }
+}
+
+impl LegacyEncoding for IncomingContract {
+    fn has_legacy_encoding(&self) -> bool {
+        self.hold.is_none() && self.version == IncomingContractVersion::V0
+    }
+
+    fn consensus_encode_legacy<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.hash.consensus_encode(writer)?;
+        len += self.encrypted_preimage.consensus_encode(writer)?;
+        len += self.decrypted_preimage.consensus_encode(writer)?;
+        len += self.gateway_key.consensus_encode(writer)?;
+        Ok(len)
+    }
+
+    fn consensus_decode_legacy<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(IncomingContract {
+            hash: Decodable::consensus_decode(d, modules)?,
+            encrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            decrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            gateway_key: Decodable::consensus_decode(d, modules)?,
+            hold: None,
+            version: IncomingContractVersion::V0,
+        })
+    }
+}
+
+/// Terms under which the federation holds a funded incoming contract for a hold invoice
+///
+/// Instead of decrypting the preimage right away the federation waits for the user to accept the
+/// payment, e.g. once the goods of an escrow were delivered. If the user doesn't accept it before
+/// the timelock expires the gateway can claim its money back and fail the HTLC. The gateway has to
+/// choose the timelock well before the HTLC's expiry, since acceptance remains possible until it
+/// actually claimed the refund.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct IncomingContractHold {
+    /// Key the user signs the acceptance of the payment with, as specified in the offer
+    pub accept_key: secp256k1::XOnlyPublicKey,
+    /// Block height from which on the gateway can claim back the funds if the payment wasn't
+    /// accepted
+    pub timelock: u32,
+    /// Flag that can be set by the user to start the preimage decryption
+    pub accepted: bool,
+}
+
+impl IncomingContract {
+    /// Message the user signs with the hold's `accept_key` to accept the payment
+    pub fn acceptance_message(&self) -> bitcoin_hashes::sha256::Hash {
+        let mut engine = bitcoin_hashes::sha256::Hash::engine();
+        Encodable::consensus_encode(&ACCEPTANCE_TAG.as_bytes(), &mut engine)
+            .expect("Hashing never fails");
+        Encodable::consensus_encode(&self.contract_id(), &mut engine).expect("Hashing never fails");
+        bitcoin_hashes::sha256::Hash::from_engine(engine)
+    }
 }
 
 /// The funded version of an [`IncomingContract`] contains the [`OutPoint`] of it's creation. Since
@@ -76,6 +418,27 @@ pub struct FundedIncomingContract {
     pub out_point: OutPoint,
 }
 
+impl LegacyEncoding for FundedIncomingContract {
+    fn has_legacy_encoding(&self) -> bool {
+        self.contract.has_legacy_encoding()
+    }
+
+    fn consensus_encode_legacy<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        Ok(self.contract.consensus_encode_legacy(writer)?
+            + self.out_point.consensus_encode(writer)?)
+    }
+
+    fn consensus_decode_legacy<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(FundedIncomingContract {
+            contract: IncomingContract::consensus_decode_legacy(d, modules)?,
+            out_point: Decodable::consensus_decode(d, modules)?,
+        })
+    }
+}
+
 hash_newtype!(
     OfferId,
     Sha256,
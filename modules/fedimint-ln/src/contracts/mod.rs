@@ -1,4 +1,5 @@
 pub mod account;
+pub mod htlc;
 pub mod incoming;
 pub mod outgoing;
 
@@ -12,6 +13,8 @@ use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::OutPoint;
 use serde::{Deserialize, Serialize};
 
+use crate::contracts::incoming::LegacyEncoding;
+
 /// Anything representing a contract which thus has an associated [`ContractId`]
 pub trait IdentifyableContract: Encodable {
     fn contract_id(&self) -> ContractId;
@@ -25,22 +28,29 @@ hash_newtype!(
 );
 
 /// A contract before execution as found in transaction outputs
+///
+/// Incoming contracts that can't use the legacy encoding without a flag byte, see `LegacyEncoding`,
+/// are encoded as an additional variant after the others.
 // TODO: investigate if this is actually a problem
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum Contract {
     Account(account::AccountContract),
     Incoming(incoming::IncomingContract),
     Outgoing(outgoing::OutgoingContract),
+    Htlc(htlc::HtlcContract),
 }
 
 /// A contract after execution as saved in the database
+///
+/// Encoded like [`Contract`].
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum FundedContract {
     Account(account::AccountContract),
     Incoming(incoming::FundedIncomingContract),
     Outgoing(outgoing::OutgoingContract),
+    Htlc(htlc::FundedHtlcContract),
 }
 
 /// Outcome of a contract. Only incoming contracts currently need to communicate anything back to
@@ -50,6 +60,7 @@ pub enum ContractOutcome {
     Account(AccountContractOutcome),
     Incoming(DecryptedPreimage),
     Outgoing(OutgoingContractOutcome),
+    Htlc(HtlcContractOutcome),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
@@ -58,12 +69,91 @@ pub struct AccountContractOutcome {}
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct OutgoingContractOutcome {}
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct HtlcContractOutcome {}
+
+/// Variant index of incoming contracts that don't have a legacy encoding
+const INCOMING_FLAGGED_VARIANT: u64 = 4;
+
+impl Encodable for Contract {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        match self {
+            Contract::Account(c) => {
+                Ok(0u64.consensus_encode(writer)? + c.consensus_encode(writer)?)
+            }
+            Contract::Incoming(c) => {
+                incoming::encode_legacy_variant(c, 1, INCOMING_FLAGGED_VARIANT, writer)
+            }
+            Contract::Outgoing(c) => {
+                Ok(2u64.consensus_encode(writer)? + c.consensus_encode(writer)?)
+            }
+            Contract::Htlc(c) => Ok(3u64.consensus_encode(writer)? + c.consensus_encode(writer)?),
+        }
+    }
+}
+
+impl Decodable for Contract {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(match u64::consensus_decode(d, modules)? {
+            0 => Contract::Account(Decodable::consensus_decode(d, modules)?),
+            1 => Contract::Incoming(LegacyEncoding::consensus_decode_legacy(d, modules)?),
+            2 => Contract::Outgoing(Decodable::consensus_decode(d, modules)?),
+            3 => Contract::Htlc(Decodable::consensus_decode(d, modules)?),
+            INCOMING_FLAGGED_VARIANT => {
+                Contract::Incoming(incoming::decode_non_legacy_variant(d, modules)?)
+            }
+            _ => return Err(DecodeError::from_str("invalid enum variant")),
+        })
+    }
+}
+
+impl Encodable for FundedContract {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        match self {
+            FundedContract::Account(c) => {
+                Ok(0u64.consensus_encode(writer)? + c.consensus_encode(writer)?)
+            }
+            FundedContract::Incoming(c) => {
+                incoming::encode_legacy_variant(c, 1, INCOMING_FLAGGED_VARIANT, writer)
+            }
+            FundedContract::Outgoing(c) => {
+                Ok(2u64.consensus_encode(writer)? + c.consensus_encode(writer)?)
+            }
+            FundedContract::Htlc(c) => {
+                Ok(3u64.consensus_encode(writer)? + c.consensus_encode(writer)?)
+            }
+        }
+    }
+}
+
+impl Decodable for FundedContract {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(match u64::consensus_decode(d, modules)? {
+            0 => FundedContract::Account(Decodable::consensus_decode(d, modules)?),
+            1 => FundedContract::Incoming(LegacyEncoding::consensus_decode_legacy(d, modules)?),
+            2 => FundedContract::Outgoing(Decodable::consensus_decode(d, modules)?),
+            3 => FundedContract::Htlc(Decodable::consensus_decode(d, modules)?),
+            INCOMING_FLAGGED_VARIANT => {
+                FundedContract::Incoming(incoming::decode_non_legacy_variant(d, modules)?)
+            }
+            _ => return Err(DecodeError::from_str("invalid enum variant")),
+        })
+    }
+}
+
 impl IdentifyableContract for Contract {
     fn contract_id(&self) -> ContractId {
         match self {
             Contract::Account(c) => c.contract_id(),
             Contract::Incoming(c) => c.contract_id(),
             Contract::Outgoing(c) => c.contract_id(),
+            Contract::Htlc(c) => c.contract_id(),
         }
     }
 }
@@ -74,6 +164,7 @@ impl IdentifyableContract for FundedContract {
             FundedContract::Account(c) => c.contract_id(),
             FundedContract::Incoming(c) => c.contract.contract_id(),
             FundedContract::Outgoing(c) => c.contract_id(),
+            FundedContract::Htlc(c) => c.contract.contract_id(),
         }
     }
 }
@@ -86,6 +177,7 @@ impl Contract {
             Contract::Account(_) => ContractOutcome::Account(AccountContractOutcome {}),
             Contract::Incoming(_) => ContractOutcome::Incoming(DecryptedPreimage::Pending),
             Contract::Outgoing(_) => ContractOutcome::Outgoing(OutgoingContractOutcome {}),
+            Contract::Htlc(_) => ContractOutcome::Htlc(HtlcContractOutcome {}),
         }
     }
 
@@ -100,6 +192,10 @@ impl Contract {
                 })
             }
             Contract::Outgoing(outgoing) => FundedContract::Outgoing(outgoing),
+            Contract::Htlc(htlc) => FundedContract::Htlc(htlc::FundedHtlcContract {
+                contract: htlc,
+                revealed_preimage: None,
+            }),
         }
     }
 }
@@ -133,6 +229,12 @@ impl Preimage {
     pub fn to_public_key(&self) -> Result<secp256k1::XOnlyPublicKey, secp256k1::Error> {
         secp256k1::XOnlyPublicKey::from_slice(&self.0)
     }
+
+    /// Preimage of a [`incoming::IncomingContractVersion::V1`] contract that can be claimed with
+    /// `claim_key`
+    pub fn from_claim_key(claim_key: &secp256k1::XOnlyPublicKey) -> Preimage {
+        Preimage(Sha256::hash(&claim_key.serialize()).into_inner())
+    }
 }
 
 /// Possible outcomes of preimage decryption
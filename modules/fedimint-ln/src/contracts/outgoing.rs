@@ -1,8 +1,11 @@
 use bitcoin_hashes::Hash as BitcoinHash;
 use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::Amount;
+use lightning_invoice::Invoice;
 use serde::{Deserialize, Serialize};
 
 use crate::contracts::{ContractId, IdentifyableContract};
+use crate::route_hints::RouteHint;
 
 const CANCELLATION_TAG: &str = "outgoing contract cancellation";
 
@@ -21,9 +24,9 @@ pub struct OutgoingContract {
     pub timelock: u32,
     /// Public key of the user that can claim the money back after the timelock expires
     pub user_key: secp256k1::XOnlyPublicKey,
-    // FIXME: use pruned, privacy friendly version without description etc.
-    /// Invoice containing metadata on how to obtain the preimage
-    pub invoice: lightning_invoice::Invoice,
+    /// Invoice containing metadata on how to obtain the preimage, pruned of everything the gateway
+    /// doesn't need to pay it since every guardian stores it
+    pub invoice: PrunedInvoice,
     /// Flag that can be set by the gateway and allows the client to claim an early refund
     pub cancelled: bool,
 }
@@ -49,3 +52,42 @@ impl OutgoingContract {
         bitcoin_hashes::sha256::Hash::from_engine(engine)
     }
 }
+
+/// Privacy friendly version of an invoice that leaves out its description and everything else
+/// that isn't needed to pay it
+///
+/// The gateway builds the payment from it alone, so it never learns the full invoice.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PrunedInvoice {
+    pub payment_hash: bitcoin_hashes::sha256::Hash,
+    /// Node public key of the payee
+    pub destination: secp256k1::PublicKey,
+    /// `None` if the payer decides how much to pay
+    pub amount: Option<Amount>,
+    pub payment_secret: [u8; 32],
+    pub route_hints: Vec<RouteHint>,
+    pub min_final_cltv_expiry: u64,
+    /// Unix time in seconds at which the invoice expires
+    pub expiry_timestamp: u64,
+}
+
+impl From<&Invoice> for PrunedInvoice {
+    fn from(invoice: &Invoice) -> Self {
+        PrunedInvoice {
+            payment_hash: *invoice.payment_hash(),
+            destination: invoice
+                .payee_pub_key()
+                .cloned()
+                .unwrap_or_else(|| invoice.recover_payee_pub_key()),
+            amount: invoice.amount_milli_satoshis().map(Amount::from_msats),
+            payment_secret: invoice.payment_secret().0,
+            route_hints: invoice
+                .route_hints()
+                .iter()
+                .map(RouteHint::from_ldk_route_hint)
+                .collect(),
+            min_final_cltv_expiry: invoice.min_final_cltv_expiry(),
+            expiry_timestamp: (invoice.duration_since_epoch() + invoice.expiry_time()).as_secs(),
+        }
+    }
+}
@@ -1,12 +1,12 @@
 use fedimint_api::db::DatabaseKeyPrefixConst;
 use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::{OutPoint, PeerId};
-use secp256k1::PublicKey;
+use secp256k1::XOnlyPublicKey;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
 use crate::contracts::{incoming::IncomingContractOffer, ContractId, PreimageDecryptionShare};
-use crate::{ContractAccount, LightningGateway, LightningOutputOutcome};
+use crate::{ContractAccount, LightningOutputOutcome, SignedLightningGateway};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -17,6 +17,10 @@ pub enum DbKeyPrefix {
     AgreedDecryptionShare = 0x43,
     ContractUpdate = 0x44,
     LightningGateway = 0x45,
+    ProposeGateway = 0x46,
+    PeerTime = 0x47,
+    ExpiredOfferCount = 0x48,
+    EpochOfferCount = 0x49,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -119,13 +123,14 @@ impl DatabaseKeyPrefixConst for AgreedDecryptionShareKeyPrefix {
     type Value = PreimageDecryptionShare;
 }
 
+/// Gateway registrations the federation agreed on, keyed by the gateway's `mint_pub_key`
 #[derive(Debug, Encodable, Decodable, Serialize)]
-pub struct LightningGatewayKey(pub PublicKey);
+pub struct LightningGatewayKey(pub XOnlyPublicKey);
 
 impl DatabaseKeyPrefixConst for LightningGatewayKey {
     const DB_PREFIX: u8 = DbKeyPrefix::LightningGateway as u8;
     type Key = Self;
-    type Value = LightningGateway;
+    type Value = SignedLightningGateway;
 }
 
 #[derive(Debug, Encodable, Decodable)]
@@ -134,5 +139,82 @@ pub struct LightningGatewayKeyPrefix;
 impl DatabaseKeyPrefixConst for LightningGatewayKeyPrefix {
     const DB_PREFIX: u8 = DbKeyPrefix::LightningGateway as u8;
     type Key = LightningGatewayKey;
-    type Value = LightningGateway;
+    type Value = SignedLightningGateway;
+}
+
+/// Gateway registrations we received through the API that still need to be broadcasted
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ProposeGatewayKey(pub XOnlyPublicKey);
+
+impl DatabaseKeyPrefixConst for ProposeGatewayKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::ProposeGateway as u8;
+    type Key = Self;
+    type Value = SignedLightningGateway;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ProposeGatewayKeyPrefix;
+
+impl DatabaseKeyPrefixConst for ProposeGatewayKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::ProposeGateway as u8;
+    type Key = ProposeGatewayKey;
+    type Value = SignedLightningGateway;
+}
+
+/// Latest unix time in seconds each guardian proposed, used to agree on the expiry of offers
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PeerTimeKey(pub PeerId);
+
+impl DatabaseKeyPrefixConst for PeerTimeKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PeerTime as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerTimeKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PeerTimeKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PeerTime as u8;
+    type Key = PeerTimeKey;
+    type Value = u64;
+}
+
+/// Number of offers that were removed because they expired before being funded
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ExpiredOfferCountKey;
+
+impl DatabaseKeyPrefixConst for ExpiredOfferCountKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::ExpiredOfferCount as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ExpiredOfferCountKeyPrefix;
+
+impl DatabaseKeyPrefixConst for ExpiredOfferCountKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::ExpiredOfferCount as u8;
+    type Key = ExpiredOfferCountKey;
+    type Value = u64;
+}
+
+/// Number of offers created in the current consensus epoch, see
+/// [`OfferRateLimit`](crate::config::OfferRateLimit)
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct EpochOfferCountKey;
+
+impl DatabaseKeyPrefixConst for EpochOfferCountKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::EpochOfferCount as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EpochOfferCountKeyPrefix;
+
+impl DatabaseKeyPrefixConst for EpochOfferCountKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::EpochOfferCount as u8;
+    type Key = EpochOfferCountKey;
+    type Value = u64;
 }
@@ -17,11 +17,16 @@ pub mod db;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsString;
 use std::ops::Sub;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bitcoin_hashes::Hash as BitcoinHash;
 use config::FeeConsensus;
-use db::{DbKeyPrefix, LightningGatewayKey, LightningGatewayKeyPrefix};
+use db::{
+    DbKeyPrefix, EpochOfferCountKey, EpochOfferCountKeyPrefix, ExpiredOfferCountKey,
+    ExpiredOfferCountKeyPrefix, LightningGatewayKey, LightningGatewayKeyPrefix, PeerTimeKey,
+    PeerTimeKeyPrefix, ProposeGatewayKey, ProposeGatewayKeyPrefix,
+};
 use fedimint_api::cancellable::{Cancellable, Cancelled};
 use fedimint_api::config::{
     ConfigGenParams, DkgPeerMsg, DkgRunner, ServerModuleConfig, TypedServerModuleConfig,
@@ -29,16 +34,17 @@ use fedimint_api::config::{
 use fedimint_api::config::{ModuleConfigResponse, TypedServerModuleConsensusConfig};
 use fedimint_api::core::{ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
 use fedimint_api::db::{Database, DatabaseTransaction};
-use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_api::module::audit::Audit;
 use fedimint_api::module::interconnect::ModuleInterconect;
+use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::module::{
     api_endpoint, ApiEndpoint, ApiError, InputMeta, IntoModuleError, ModuleError, ModuleGen,
     TransactionItemAmount,
 };
 use fedimint_api::net::peers::MuxPeerConnections;
 use fedimint_api::server::DynServerModule;
-use fedimint_api::task::TaskGroup;
+use fedimint_api::task::{sleep, TaskGroup};
 use fedimint_api::time::SystemTime;
 use fedimint_api::{plugin_types_trait_impl, push_db_pair_items, Amount, NumPeers, PeerId};
 use fedimint_api::{OutPoint, ServerModule};
@@ -54,9 +60,13 @@ use url::Url;
 use crate::common::LightningDecoder;
 use crate::config::{
     LightningClientConfig, LightningConfig, LightningConfigConsensus, LightningConfigPrivate,
+    OfferRateLimit,
 };
 use crate::contracts::{
-    incoming::{IncomingContractOffer, OfferId},
+    incoming::{
+        decode_non_legacy_variant, encode_legacy_variant, IncomingContract, IncomingContractOffer,
+        IncomingContractVersion, LegacyEncoding, OfferId,
+    },
     Contract, ContractId, ContractOutcome, DecryptedPreimage, EncryptedPreimage, FundedContract,
     IdentifyableContract, Preimage, PreimageDecryptionShare,
 };
@@ -68,8 +78,14 @@ use crate::db::{
 
 const KIND: ModuleKind = ModuleKind::from_static_str("ln");
 
+const GATEWAY_REGISTRATION_TAG: &str = "gateway registration";
+
+/// How often we check for gateway registrations received through the API and for expired offers
+/// while waiting for something to propose
+const GATEWAY_PROPOSAL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 /// The lightning module implements an account system. It does not have the privacy guarantees of
-/// the e-cash mint module but instead allows for smart contracting. There exist three contract
+/// the e-cash mint module but instead allows for smart contracting. There exist four contract
 /// types that can be used to "lock" accounts:
 ///
 ///   * [Account]: an account locked with a schnorr public key
@@ -80,16 +96,20 @@ const KIND: ModuleKind = ModuleKind::from_static_str("ln");
 ///     for the preimage to a particular hash. It also contains some threshold-encrypted data. Once
 ///     the contract is funded the data is decrypted. If it is a valid preimage the contract's funds
 ///     are now accessible to the creator of the offer, if not they are accessible to the funder.
+///   * [Htlc]: a general-purpose hash-timelocked contract that the recipient can claim by revealing
+///     the preimage and the sender can claim back after a timeout, e.g. for atomic swaps
 ///
-/// These three primitives allow to integrate the federation with the wider Lightning network
+/// The first three primitives allow to integrate the federation with the wider Lightning network
 /// through a centralized but untrusted (except for availability) Lightning gateway server.
 ///
 /// [Account]: contracts::account::AccountContract
 /// [Outgoing]: contracts::outgoing::OutgoingContract
 /// [Incoming]: contracts::incoming::IncomingContract
+/// [Htlc]: contracts::htlc::HtlcContract
 #[derive(Debug)]
 pub struct Lightning {
     cfg: LightningConfig,
+    db: Database,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
@@ -97,9 +117,10 @@ pub struct LightningInput {
     pub contract_id: contracts::ContractId,
     /// While for now we only support spending the entire contract we need to avoid
     pub amount: Amount,
-    /// Of the three contract types only the outgoing one needs any other witness data than a
-    /// signature. The signature is aggregated on the transaction level, so only the optional
-    /// preimage remains.
+    /// Only outgoing contracts and incoming contracts of version
+    /// [`IncomingContractVersion::V1`] need any other witness data than a signature. The signature
+    /// is aggregated on the transaction level, so only the optional preimage, or the claim key
+    /// whose hash is the incoming contract's preimage, remains.
     pub witness: Option<Preimage>,
 }
 
@@ -119,12 +140,16 @@ impl std::fmt::Display for LightningInput {
 ///   * Normal contracts users may lock funds in
 ///   * Offers to buy preimages (see `contracts::incoming` docs)
 ///   * Early cancellation of outgoing contracts before their timeout
+///   * Acceptance of held incoming contracts by their recipient
 ///
 /// The offer type exists to register `IncomingContractOffer`s. Instead of patching in a second way
 /// of letting clients submit consensus items outside of transactions we let offers be a 0-amount
 /// output. We need to take care to allow 0-input, 1-output transactions for that to allow users
 /// to receive their fist notes via LN without already having notes.
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+///
+/// Offers that can't use the legacy encoding without a flag byte, see `LegacyEncoding`, are encoded
+/// as an additional variant after the others.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum LightningOutput {
     /// Fund contract
     Contract(ContractOutput),
@@ -137,6 +162,13 @@ pub enum LightningOutput {
         /// Signature of gateway
         gateway_signature: secp256k1::schnorr::Signature,
     },
+    /// Accept the payment of a held incoming contract, starting its preimage decryption
+    AcceptIncoming {
+        /// Contract to update
+        contract: ContractId,
+        /// Signature of the hold's accept key
+        signature: secp256k1::schnorr::Signature,
+    },
 }
 
 impl std::fmt::Display for LightningOutput {
@@ -160,6 +192,9 @@ impl std::fmt::Display for LightningOutput {
                         amount, outgoing.hash
                     )
                 }
+                Contract::Htlc(htlc) => {
+                    write!(f, "LN HTLC Contract for {} hash {}", amount, htlc.hash)
+                }
             },
             LightningOutput::Offer(offer) => {
                 write!(f, "LN offer for {} with hash {}", offer.amount, offer.hash)
@@ -167,10 +202,72 @@ impl std::fmt::Display for LightningOutput {
             LightningOutput::CancelOutgoing { contract, .. } => {
                 write!(f, "LN outgoing contract cancellation {contract}")
             }
+            LightningOutput::AcceptIncoming { contract, .. } => {
+                write!(f, "LN incoming contract acceptance {contract}")
+            }
         }
     }
 }
 
+/// Variant index of offers that don't have a legacy encoding
+const OFFER_FLAGGED_VARIANT: u64 = 4;
+
+impl Encodable for LightningOutput {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        match self {
+            LightningOutput::Contract(contract) => {
+                len += 0u64.consensus_encode(writer)?;
+                len += contract.consensus_encode(writer)?;
+            }
+            LightningOutput::Offer(offer) => {
+                len += encode_legacy_variant(offer, 1, OFFER_FLAGGED_VARIANT, writer)?;
+            }
+            LightningOutput::CancelOutgoing {
+                contract,
+                gateway_signature,
+            } => {
+                len += 2u64.consensus_encode(writer)?;
+                len += contract.consensus_encode(writer)?;
+                len += gateway_signature.consensus_encode(writer)?;
+            }
+            LightningOutput::AcceptIncoming {
+                contract,
+                signature,
+            } => {
+                len += 3u64.consensus_encode(writer)?;
+                len += contract.consensus_encode(writer)?;
+                len += signature.consensus_encode(writer)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for LightningOutput {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(match u64::consensus_decode(d, modules)? {
+            0 => LightningOutput::Contract(Decodable::consensus_decode(d, modules)?),
+            1 => {
+                LightningOutput::Offer(IncomingContractOffer::consensus_decode_legacy(d, modules)?)
+            }
+            2 => LightningOutput::CancelOutgoing {
+                contract: Decodable::consensus_decode(d, modules)?,
+                gateway_signature: Decodable::consensus_decode(d, modules)?,
+            },
+            3 => LightningOutput::AcceptIncoming {
+                contract: Decodable::consensus_decode(d, modules)?,
+                signature: Decodable::consensus_decode(d, modules)?,
+            },
+            OFFER_FLAGGED_VARIANT => LightningOutput::Offer(decode_non_legacy_variant(d, modules)?),
+            _ => return Err(DecodeError::from_str("invalid enum variant")),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct ContractOutput {
     pub amount: fedimint_api::Amount,
@@ -183,6 +280,13 @@ pub struct ContractAccount {
     pub contract: contracts::FundedContract,
 }
 
+/// Offers that can still be funded and offers that were removed since they expired unfunded
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub struct OfferCounts {
+    pub active: u64,
+    pub expired: u64,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub enum LightningOutputOutcome {
     Contract {
@@ -221,19 +325,164 @@ pub struct LightningGateway {
     /// These will be appended with the route hint of the recipient's virtual channel. To keeps
     /// invoices small these should be used sparingly.
     pub route_hints: Vec<route_hints::RouteHint>,
+    /// Fees the gateway charges for routing payments, gateways that registered before advertising
+    /// fees are treated as charging none
+    #[serde(default)]
+    pub fees: GatewayFees,
     /// Limits the validity of the announcement to allow updates
     pub valid_until: SystemTime,
+    /// Coarse channel balances of the gateway, `None` if its Lightning node doesn't report them
+    #[serde(default)]
+    pub liquidity: Option<GatewayLiquidity>,
+    /// Whether the gateway's Lightning node has to be the recipient of invoices it receives
+    /// payments for, in which case clients request invoices from the gateway instead of creating
+    /// them themselves
+    #[serde(default)]
+    pub issues_invoices: bool,
+    /// Whether the gateway can pay a part of an invoice whose rest is paid by other gateways
+    #[serde(default)]
+    pub supports_mpp: bool,
+}
+
+impl LightningGateway {
+    /// Message the gateway signs with the key belonging to `mint_pub_key` to register itself
+    pub fn registration_message(&self) -> bitcoin_hashes::sha256::Hash {
+        let mut engine = bitcoin_hashes::sha256::Hash::engine();
+        Encodable::consensus_encode(&GATEWAY_REGISTRATION_TAG.as_bytes(), &mut engine)
+            .expect("Hashing never fails");
+        Encodable::consensus_encode(self, &mut engine).expect("Hashing never fails");
+        bitcoin_hashes::sha256::Hash::from_engine(engine)
+    }
+
+    /// Whether the registration's validity ended at the given unix time in seconds
+    pub fn is_expired(&self, time: u64) -> bool {
+        self.valid_until <= SystemTime::UNIX_EPOCH + Duration::from_secs(time)
+    }
+
+    /// Whether the gateway can probably pay an invoice of `amount`, assumed if it didn't report
+    /// its liquidity
+    pub fn can_send(&self, amount: Amount) -> bool {
+        self.liquidity
+            .map_or(true, |liquidity| liquidity.outbound >= amount)
+    }
+
+    /// Whether the gateway can probably receive a payment of `amount`, assumed if it didn't report
+    /// its liquidity
+    pub fn can_receive(&self, amount: Amount) -> bool {
+        self.liquidity
+            .map_or(true, |liquidity| liquidity.inbound >= amount)
+    }
+}
+
+/// Registration of a gateway, signed with its `mint_pub_key` so nobody else can change it
+#[derive(Debug, Clone, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash)]
+pub struct SignedLightningGateway {
+    pub gateway: LightningGateway,
+    pub signature: secp256k1::schnorr::Signature,
+}
+
+impl SignedLightningGateway {
+    pub fn verify(&self) -> Result<(), LightningError> {
+        secp256k1::global::SECP256K1
+            .verify_schnorr(
+                &self.signature,
+                &self.gateway.registration_message().into(),
+                &self.gateway.mint_pub_key,
+            )
+            .map_err(|_| LightningError::InvalidGatewaySignature)
+    }
+}
+
+/// Channel balances of a gateway's Lightning node, rounded down to a power of ten satoshis so the
+/// registration doesn't reveal its exact balances
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash)]
+pub struct GatewayLiquidity {
+    /// Amount the gateway can send over its channels
+    pub outbound: Amount,
+    /// Amount the gateway can receive over its channels
+    pub inbound: Amount,
+}
+
+impl GatewayLiquidity {
+    pub fn from_balances(outbound: Amount, inbound: Amount) -> Self {
+        GatewayLiquidity {
+            outbound: liquidity_bucket(outbound),
+            inbound: liquidity_bucket(inbound),
+        }
+    }
+}
+
+fn liquidity_bucket(amount: Amount) -> Amount {
+    let mut bucket = Amount::from_sats(1);
+    if amount < bucket {
+        return Amount::ZERO;
+    }
+
+    while bucket.msats * 10 <= amount.msats {
+        bucket = Amount::from_msats(bucket.msats * 10);
+    }
+    bucket
+}
+
+/// Routing fee charged by a gateway for a single payment, modeled after Lightning's channel fees
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash,
+)]
+pub struct RoutingFee {
+    /// Flat fee per payment in msat
+    pub base_msat: u32,
+    /// Fee proportional to the payment amount in millionths
+    pub proportional_millionths: u32,
+}
+
+impl RoutingFee {
+    /// Fee charged for routing a payment of `payment` amount
+    pub fn amount(&self, payment: Amount) -> Amount {
+        let proportional_msats =
+            (payment.msats as u128 * self.proportional_millionths as u128 / 1_000_000) as u64;
+        Amount::from_msats(self.base_msat as u64 + proportional_msats)
+    }
+}
+
+/// Fees a gateway charges for both payment directions
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash,
+)]
+pub struct GatewayFees {
+    /// Fee for receiving payments from the Lightning network into the federation
+    pub incoming: RoutingFee,
+    /// Fee for paying Lightning invoices on behalf of federation users
+    pub outgoing: RoutingFee,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable, Serialize, Deserialize)]
-pub struct LightningConsensusItem {
-    pub contract_id: ContractId,
-    pub share: PreimageDecryptionShare,
+pub enum LightningConsensusItem {
+    /// All decryption shares the proposing guardian produced for funded incoming contracts, batched
+    /// into a single item per epoch
+    DecryptionShares(BTreeMap<ContractId, PreimageDecryptionShare>),
+    /// A gateway registration one of the guardians received, so all of them end up with the same
+    /// list of gateways
+    Gateway(SignedLightningGateway),
+    /// The proposing guardian's current unix time in seconds, only proposed once one of the offers
+    /// expired according to it so the federation can agree on removing the offer
+    Time(u64),
 }
 
 impl std::fmt::Display for LightningConsensusItem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "LN Decryption Share for contract {}", self.contract_id)
+        match self {
+            LightningConsensusItem::DecryptionShares(shares) => {
+                write!(f, "LN Decryption Shares for {} contracts", shares.len())
+            }
+            LightningConsensusItem::Gateway(gateway) => {
+                write!(
+                    f,
+                    "LN Gateway Registration of {}",
+                    gateway.gateway.mint_pub_key
+                )
+            }
+            LightningConsensusItem::Time(time) => write!(f, "LN Time {time}"),
+        }
     }
 }
 
@@ -255,11 +504,11 @@ impl ModuleGen for LightningGen {
     async fn init(
         &self,
         cfg: ServerModuleConfig,
-        _db: Database,
+        db: Database,
         _env: &BTreeMap<OsString, OsString>,
         _task_group: &mut TaskGroup,
     ) -> anyhow::Result<DynServerModule> {
-        Ok(Lightning::new(cfg.to_typed()?).into())
+        Ok(Lightning::new(cfg.to_typed()?, db).into())
     }
 
     fn trusted_dealer_gen(
@@ -281,9 +530,11 @@ impl ModuleGen for LightningGen {
                         consensus: LightningConfigConsensus {
                             threshold_pub_keys: pks.clone(),
                             fee_consensus: FeeConsensus::default(),
+                            offer_rate_limit: OfferRateLimit::new_federation(),
                         },
                         private: LightningConfigPrivate {
                             threshold_sec_key: threshold_crypto::serde_impl::SerdeSecret(sk),
+                            our_peer_id: peer,
                         },
                     }
                     .to_erased(),
@@ -319,9 +570,11 @@ impl ModuleGen for LightningGen {
             consensus: LightningConfigConsensus {
                 threshold_pub_keys: keys.public_key_set,
                 fee_consensus: Default::default(),
+                offer_rate_limit: OfferRateLimit::new_federation(),
             },
             private: LightningConfigPrivate {
                 threshold_sec_key: keys.secret_key_share,
+                our_peer_id: *our_id,
             },
         };
 
@@ -400,11 +653,21 @@ impl ModuleGen for LightningGen {
                         dbtx,
                         LightningGatewayKeyPrefix,
                         LightningGatewayKey,
-                        LightningGateway,
+                        SignedLightningGateway,
                         lightning,
                         "Lightning Gateways"
                     );
                 }
+                DbKeyPrefix::ProposeGateway => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ProposeGatewayKeyPrefix,
+                        ProposeGatewayKey,
+                        SignedLightningGateway,
+                        lightning,
+                        "Proposed Lightning Gateways"
+                    );
+                }
                 DbKeyPrefix::Offer => {
                     push_db_pair_items!(
                         dbtx,
@@ -425,6 +688,36 @@ impl ModuleGen for LightningGen {
                         "Proposed Decryption Shares"
                     );
                 }
+                DbKeyPrefix::PeerTime => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PeerTimeKeyPrefix,
+                        PeerTimeKey,
+                        u64,
+                        lightning,
+                        "Peer Times"
+                    );
+                }
+                DbKeyPrefix::ExpiredOfferCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ExpiredOfferCountKeyPrefix,
+                        ExpiredOfferCountKey,
+                        u64,
+                        lightning,
+                        "Expired Offer Count"
+                    );
+                }
+                DbKeyPrefix::EpochOfferCount => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EpochOfferCountKeyPrefix,
+                        EpochOfferCountKey,
+                        u64,
+                        lightning,
+                        "Epoch Offer Count"
+                    );
+                }
             }
         }
 
@@ -443,8 +736,25 @@ impl ServerModule for Lightning {
     }
 
     async fn await_consensus_proposal(&self, dbtx: &mut DatabaseTransaction<'_>) {
-        if self.consensus_proposal(dbtx).await.is_empty() {
-            std::future::pending().await
+        if !self.consensus_proposal(dbtx).await.is_empty() {
+            return;
+        }
+
+        // Gateway registrations are written by API requests, which `dbtx` doesn't see, and offers
+        // expire without anything being written at all
+        loop {
+            sleep(GATEWAY_PROPOSAL_POLL_INTERVAL).await;
+            let mut dbtx = self.db.begin_transaction().await;
+            if dbtx
+                .find_by_prefix(&ProposeGatewayKeyPrefix)
+                .await
+                .next()
+                .await
+                .is_some()
+                || self.should_propose_time(&mut dbtx).await
+            {
+                return;
+            }
         }
     }
 
@@ -452,14 +762,34 @@ impl ServerModule for Lightning {
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
     ) -> Vec<LightningConsensusItem> {
-        dbtx.find_by_prefix(&ProposeDecryptionShareKeyPrefix)
+        let mut items = vec![];
+
+        let decryption_shares = dbtx
+            .find_by_prefix(&ProposeDecryptionShareKeyPrefix)
             .await
             .map(|res| {
                 let (ProposeDecryptionShareKey(contract_id), share) = res.expect("DB error");
-                LightningConsensusItem { contract_id, share }
+                (contract_id, share)
             })
-            .collect::<Vec<LightningConsensusItem>>()
-            .await
+            .collect::<BTreeMap<_, _>>()
+            .await;
+        if !decryption_shares.is_empty() {
+            items.push(LightningConsensusItem::DecryptionShares(decryption_shares));
+        }
+
+        items.extend(
+            dbtx.find_by_prefix(&ProposeGatewayKeyPrefix)
+                .await
+                .map(|res| LightningConsensusItem::Gateway(res.expect("DB error").1))
+                .collect::<Vec<LightningConsensusItem>>()
+                .await,
+        );
+
+        if self.should_propose_time(dbtx).await {
+            items.push(LightningConsensusItem::Time(unix_time_now()));
+        }
+
+        items
     }
 
     async fn begin_consensus_epoch<'a, 'b>(
@@ -467,16 +797,55 @@ impl ServerModule for Lightning {
         dbtx: &mut DatabaseTransaction<'b>,
         consensus_items: Vec<(PeerId, LightningConsensusItem)>,
     ) {
-        for (peer, decryption_share) in consensus_items.into_iter() {
-            let span = info_span!("process decryption share", %peer);
-            let _guard = span.enter();
+        // Whether a contract awaits decryption, cached since every peer sends shares for it
+        let mut awaiting_decryption = HashMap::new();
+
+        for (peer, consensus_item) in consensus_items.into_iter() {
+            match consensus_item {
+                LightningConsensusItem::DecryptionShares(shares) => {
+                    let span = info_span!("process decryption shares", %peer);
+                    let _guard = span.enter();
+
+                    for (contract_id, share) in shares {
+                        let awaiting = match awaiting_decryption.get(&contract_id) {
+                            Some(awaiting) => *awaiting,
+                            None => {
+                                let awaiting = self.awaits_decryption(dbtx, contract_id).await;
+                                awaiting_decryption.insert(contract_id, awaiting);
+                                awaiting
+                            }
+                        };
+
+                        // Late or duplicate shares are dropped before ever being verified
+                        let key = AgreedDecryptionShareKey(contract_id, peer);
+                        if !awaiting || dbtx.get_value(&key).await.expect("DB error").is_some() {
+                            debug!(%contract_id, "Dropping decryption share");
+                            continue;
+                        }
 
-            dbtx.insert_new_entry(
-                &AgreedDecryptionShareKey(decryption_share.contract_id, peer),
-                &decryption_share.share,
-            )
-            .await
-            .expect("DB Error");
+                        dbtx.insert_new_entry(&key, &share).await.expect("DB Error");
+                    }
+                }
+                LightningConsensusItem::Gateway(gateway) => {
+                    let span = info_span!("process gateway registration", %peer);
+                    let _guard = span.enter();
+
+                    self.process_gateway_registration(dbtx, gateway).await;
+                }
+                LightningConsensusItem::Time(time) => {
+                    // Times only ever move forward so peers can't turn back the consensus time
+                    let previous = dbtx
+                        .get_value(&PeerTimeKey(peer))
+                        .await
+                        .expect("DB error")
+                        .unwrap_or(0);
+                    if previous < time {
+                        dbtx.insert_entry(&PeerTimeKey(peer), &time)
+                            .await
+                            .expect("DB error");
+                    }
+                }
+            }
         }
     }
 
@@ -534,16 +903,60 @@ impl ServerModule for Lightning {
                 }
             }
             FundedContract::Account(acc_contract) => acc_contract.key,
-            FundedContract::Incoming(incoming) => match incoming.contract.decrypted_preimage {
-                // Once the preimage has been decrypted …
-                DecryptedPreimage::Pending => {
-                    return Err(LightningError::ContractNotReady).into_module_error_other();
+            FundedContract::Htlc(htlc) => {
+                if htlc.contract.timelock > block_height(interconnect).await {
+                    // Before the timelock expires the recipient can claim the funds by revealing
+                    // the preimage …
+                    let preimage = input
+                        .witness
+                        .as_ref()
+                        .ok_or(LightningError::MissingPreimage)
+                        .into_module_error_other()?;
+                    if bitcoin_hashes::sha256::Hash::hash(&preimage.0) != htlc.contract.hash {
+                        return Err(LightningError::InvalidPreimage).into_module_error_other();
+                    }
+                    htlc.contract.recipient_key
+                } else {
+                    // … afterwards the sender can claim them back.
+                    htlc.contract.sender_key
                 }
+            }
+            FundedContract::Incoming(incoming) => match incoming.contract.decrypted_preimage {
+                // A held contract whose payment wasn't accepted in time is refunded to the gateway …
+                DecryptedPreimage::Pending => match incoming.contract.hold {
+                    Some(hold) if !hold.accepted => {
+                        if hold.timelock > block_height(interconnect).await {
+                            return Err(LightningError::NotAccepted).into_module_error_other();
+                        }
+                        incoming.contract.gateway_key
+                    }
+                    // … otherwise we have to wait until the preimage has been decrypted …
+                    _ => {
+                        return Err(LightningError::ContractNotReady).into_module_error_other();
+                    }
+                },
                 // … either the user may spend the funds since they sold a valid preimage …
-                DecryptedPreimage::Some(preimage) => match preimage.to_public_key() {
-                    Ok(pub_key) => pub_key,
-                    Err(_) => {
-                        return Err(LightningError::InvalidPreimage).into_module_error_other()
+                DecryptedPreimage::Some(preimage) => match incoming.contract.version {
+                    IncomingContractVersion::V0 => match preimage.to_public_key() {
+                        Ok(pub_key) => pub_key,
+                        Err(_) => {
+                            return Err(LightningError::InvalidPreimage).into_module_error_other()
+                        }
+                    },
+                    // … revealing the key the preimage is the hash of …
+                    IncomingContractVersion::V1 => {
+                        let claim_key = input
+                            .witness
+                            .as_ref()
+                            .ok_or(LightningError::MissingClaimKey)
+                            .into_module_error_other()?
+                            .to_public_key()
+                            .map_err(|_| LightningError::InvalidClaimKey)
+                            .into_module_error_other()?;
+                        if Preimage::from_claim_key(&claim_key) != preimage {
+                            return Err(LightningError::InvalidClaimKey).into_module_error_other();
+                        }
+                        claim_key
                     }
                 },
                 // … or the gateway may claim back funds for not receiving the advertised preimage.
@@ -578,6 +991,15 @@ impl ServerModule for Lightning {
             .expect("DB error")
             .expect("Should fail validation if contract account doesn't exist");
         contract_account.amount -= meta.amount.amount;
+
+        // Keep the preimage revealed by the recipient of an HTLC for the counterparty of a swap
+        if let (FundedContract::Htlc(htlc), Some(preimage)) =
+            (&mut contract_account.contract, &input.witness)
+        {
+            if bitcoin_hashes::sha256::Hash::hash(&preimage.0) == htlc.contract.hash {
+                htlc.revealed_preimage = Some(preimage.clone());
+            }
+        }
         dbtx.insert_entry(&account_db_key, &contract_account)
             .await
             .expect("DB Error");
@@ -601,6 +1023,11 @@ impl ServerModule for Lightning {
                         .ok_or(LightningError::NoOffer(incoming.hash))
                         .into_module_error_other()?;
 
+                    if offer.is_expired(self.consensus_time(dbtx).await) {
+                        return Err(LightningError::ExpiredOffer(incoming.hash))
+                            .into_module_error_other();
+                    }
+
                     if contract.amount < offer.amount {
                         // If the account is not sufficiently funded fail the output
                         return Err(LightningError::InsufficientIncomingFunding(
@@ -609,6 +1036,23 @@ impl ServerModule for Lightning {
                         ))
                         .into_module_error_other();
                     }
+
+                    // Hold invoices have to be funded with held contracts that aren't accepted yet
+                    let hold_matches = match (&incoming.hold, offer.hold_key) {
+                        (None, None) => true,
+                        (Some(hold), Some(hold_key)) => {
+                            hold.accept_key == hold_key && !hold.accepted
+                        }
+                        _ => false,
+                    };
+                    if !hold_matches {
+                        return Err(LightningError::InvalidHoldTerms).into_module_error_other();
+                    }
+
+                    if incoming.version != offer.version {
+                        return Err(LightningError::InvalidContractVersion)
+                            .into_module_error_other();
+                    }
                 }
 
                 if contract.amount == Amount::ZERO {
@@ -623,7 +1067,12 @@ impl ServerModule for Lightning {
             LightningOutput::Offer(offer) => {
                 if !offer.encrypted_preimage.0.verify() {
                     Err(LightningError::InvalidEncryptedPreimage).into_module_error_other()
+                } else if offer.is_expired(self.consensus_time(dbtx).await) {
+                    Err(LightningError::ExpiredOffer(offer.hash)).into_module_error_other()
                 } else {
+                    self.validate_offer_rate_limit(dbtx, offer)
+                        .await
+                        .into_module_error_other()?;
                     Ok(TransactionItemAmount::ZERO)
                 }
             }
@@ -656,6 +1105,46 @@ impl ServerModule for Lightning {
 
                 Ok(TransactionItemAmount::ZERO)
             }
+            LightningOutput::AcceptIncoming {
+                contract,
+                signature,
+            } => {
+                let contract_account = dbtx
+                    .get_value(&ContractKey(*contract))
+                    .await
+                    .expect("DB error")
+                    .ok_or(LightningError::UnknownContract(*contract))
+                    .into_module_error_other()?;
+
+                let incoming_contract = match &contract_account.contract {
+                    FundedContract::Incoming(incoming) => &incoming.contract,
+                    _ => {
+                        return Err(LightningError::NotHeldIncomingContract)
+                            .into_module_error_other();
+                    }
+                };
+                let hold = incoming_contract
+                    .hold
+                    .as_ref()
+                    .ok_or(LightningError::NotHeldIncomingContract)
+                    .into_module_error_other()?;
+
+                // Once the gateway claimed its refund the payment can't be accepted anymore
+                if hold.accepted || contract_account.amount == Amount::ZERO {
+                    return Err(LightningError::HoldNotPending).into_module_error_other();
+                }
+
+                secp256k1::global::SECP256K1
+                    .verify_schnorr(
+                        signature,
+                        &incoming_contract.acceptance_message().into(),
+                        &hold.accept_key,
+                    )
+                    .map_err(|_| LightningError::InvalidAcceptanceSignature)
+                    .into_module_error_other()?;
+
+                Ok(TransactionItemAmount::ZERO)
+            }
         }
     }
 
@@ -703,18 +1192,10 @@ impl ServerModule for Lightning {
                         .expect("DB error")
                         .expect("offer exists if output is valid");
 
-                    let decryption_share = self
-                        .cfg
-                        .private
-                        .threshold_sec_key
-                        .decrypt_share(&incoming.encrypted_preimage.0)
-                        .expect("We checked for decryption share validity on contract creation");
-                    dbtx.insert_new_entry(
-                        &ProposeDecryptionShareKey(contract.contract.contract_id()),
-                        &PreimageDecryptionShare(decryption_share),
-                    )
-                    .await
-                    .expect("DB Error");
+                    // Held contracts are only decrypted once the payment was accepted
+                    if incoming.hold.is_none() {
+                        self.propose_decryption_share(dbtx, incoming).await;
+                    }
                     dbtx.remove_entry(&OfferKey(offer.hash))
                         .await
                         .expect("DB Error");
@@ -731,6 +1212,11 @@ impl ServerModule for Lightning {
                 dbtx.insert_new_entry(&OfferKey(offer.hash), &(*offer).clone())
                     .await
                     .expect("DB Error");
+
+                let epoch_offer_count = self.epoch_offer_count(dbtx).await;
+                dbtx.insert_entry(&EpochOfferCountKey, &(epoch_offer_count + 1))
+                    .await
+                    .expect("DB Error");
             }
             LightningOutput::CancelOutgoing { contract, .. } => {
                 let updated_contract_account = {
@@ -756,6 +1242,30 @@ impl ServerModule for Lightning {
                     .await
                     .expect("DB Error");
             }
+            LightningOutput::AcceptIncoming { contract, .. } => {
+                let mut contract_account = dbtx
+                    .get_value(&ContractKey(*contract))
+                    .await
+                    .expect("DB error")
+                    .expect("Contract exists if output is valid");
+
+                let incoming_contract = match &mut contract_account.contract {
+                    FundedContract::Incoming(incoming) => &mut incoming.contract,
+                    _ => {
+                        panic!("Contract type was checked in validate_output");
+                    }
+                };
+                incoming_contract
+                    .hold
+                    .as_mut()
+                    .expect("Hold was checked in validate_output")
+                    .accepted = true;
+                self.propose_decryption_share(dbtx, incoming_contract).await;
+
+                dbtx.insert_entry(&ContractKey(*contract), &contract_account)
+                    .await
+                    .expect("DB Error");
+            }
         }
 
         Ok(amount)
@@ -767,6 +1277,12 @@ impl ServerModule for Lightning {
         consensus_peers: &HashSet<PeerId>,
         dbtx: &mut DatabaseTransaction<'b>,
     ) -> Vec<PeerId> {
+        self.remove_expired_gateways(dbtx).await;
+        self.remove_expired_offers(dbtx).await;
+        dbtx.remove_entry(&EpochOfferCountKey)
+            .await
+            .expect("DB Error");
+
         // Decrypt preimages
         let preimage_decryption_shares = dbtx
             .find_by_prefix(&AgreedDecryptionShareKeyPrefix)
@@ -786,81 +1302,51 @@ impl ServerModule for Lightning {
             let span = info_span!("decrypt_preimage", %contract_id);
             let _gaurd = span.enter();
 
-            let incoming_contract = match self.get_contract_account(dbtx, contract_id).await {
-                Some(ContractAccount {
-                    contract: FundedContract::Incoming(incoming),
-                    ..
-                }) => incoming.contract,
-                _ => {
-                    warn!("Received decryption share for non-existent incoming contract");
-                    for peer in peers {
-                        dbtx.remove_entry(&AgreedDecryptionShareKey(contract_id, peer))
-                            .await
-                            .expect("DB Error");
+            let (incoming_contract, out_point) =
+                match self.get_contract_account(dbtx, contract_id).await {
+                    Some(ContractAccount {
+                        contract: FundedContract::Incoming(incoming),
+                        ..
+                    }) if incoming.contract.decrypted_preimage == DecryptedPreimage::Pending => {
+                        (incoming.contract, incoming.out_point)
                     }
-                    continue;
-                }
-            };
+                    _ => {
+                        warn!("Received decryption share for contract not awaiting decryption");
+                        for peer in peers {
+                            dbtx.remove_entry(&AgreedDecryptionShareKey(contract_id, peer))
+                                .await
+                                .expect("DB Error");
+                        }
+                        continue;
+                    }
+                };
 
-            let valid_shares: HashMap<PeerId, PreimageDecryptionShare> = shares
-                .into_iter()
-                .filter(|(peer, share)| {
-                    self.validate_decryption_share(
-                        *peer,
-                        share,
-                        &incoming_contract.encrypted_preimage,
-                    )
-                })
-                .collect();
+            let shares: BTreeMap<PeerId, PreimageDecryptionShare> = shares.into_iter().collect();
+            let (valid_peers, preimage_vec) = self.decrypt_preimage(&shares, &incoming_contract);
 
-            for peer in consensus_peers.sub(&valid_shares.keys().cloned().collect()) {
+            for peer in consensus_peers.sub(&valid_peers) {
                 bad_peers.push(peer);
                 warn!("{} did not contribute valid decryption shares", peer);
             }
 
-            if valid_shares.len() < self.cfg.consensus.threshold() {
+            if valid_peers.len() < self.cfg.consensus.threshold() {
                 warn!(
-                    valid_shares = %valid_shares.len(),
+                    valid_shares = %valid_peers.len(),
                     shares_needed = %self.cfg.consensus.threshold(),
                     "Too few decryption shares"
                 );
                 continue;
             }
-            debug!("Beginning to decrypt preimage");
-
-            let contract = self
-                .get_contract_account(dbtx, contract_id)
-                .await
-                .expect("decryption shares without contracts should be discarded earlier"); // FIXME: verify
-
-            let (incoming_contract, out_point) = match contract.contract {
-                FundedContract::Incoming(incoming) => (incoming.contract, incoming.out_point),
-                _ => panic!(
-                    "decryption shares without incoming contracts should be discarded earlier"
-                ),
-            };
-
-            if !matches!(
-                incoming_contract.decrypted_preimage,
-                DecryptedPreimage::Pending
-            ) {
-                warn!("Tried to decrypt the same preimage twice, this should not happen.");
-                continue;
-            }
 
-            let preimage_vec = match self.cfg.consensus.threshold_pub_keys.decrypt(
-                valid_shares
-                    .iter()
-                    .map(|(peer, share)| (peer.to_usize(), &share.0)),
-                &incoming_contract.encrypted_preimage.0,
-            ) {
-                Ok(preimage_vec) => preimage_vec,
-                Err(_) => {
+            let preimage_vec = match preimage_vec {
+                Some(preimage_vec) => preimage_vec,
+                None => {
                     // TODO: check if that can happen even though shares are verified before
                     error!(contract_hash = %incoming_contract.hash, "Failed to decrypt preimage");
                     continue;
                 }
             };
+            debug!("Decrypted preimage");
 
             // Delete decryption shares once we've decrypted the preimage
             dbtx.remove_entry(&ProposeDecryptionShareKey(contract_id))
@@ -881,7 +1367,7 @@ impl ServerModule for Lightning {
                         .try_into()
                         .expect("Invalid preimage length"),
                 );
-                if preimage.to_public_key().is_ok() {
+                if incoming_contract.version.is_valid_preimage(&preimage) {
                     DecryptedPreimage::Some(preimage)
                 } else {
                     DecryptedPreimage::Invalid
@@ -946,6 +1432,10 @@ impl ServerModule for Lightning {
         audit
             .add_items(dbtx, &ContractKeyPrefix, |_, v| -(v.amount.msats as i64))
             .await;
+        // Offers don't hold any funds, their counts still show how many are outstanding
+        let OfferCounts { active, expired } = self.offer_counts(dbtx).await;
+        audit.add_summary("Active offers", active);
+        audit.add_summary("Expired offers", expired);
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -971,6 +1461,18 @@ impl ServerModule for Lightning {
                     Ok(offer)
                 }
             },
+            api_endpoint! {
+                "/offer_difficulty",
+                async |module: &Lightning, dbtx, _v: ()| -> u8 {
+                    Ok(module.offer_difficulty(dbtx).await)
+                }
+            },
+            api_endpoint! {
+                "/offer_counts",
+                async |module: &Lightning, dbtx, _v: ()| -> OfferCounts {
+                    Ok(module.offer_counts(dbtx).await)
+                }
+            },
             api_endpoint! {
                 "/list_gateways",
                 async |module: &Lightning, dbtx, _v: ()| -> Vec<LightningGateway> {
@@ -979,9 +1481,8 @@ impl ServerModule for Lightning {
             },
             api_endpoint! {
                 "/register_gateway",
-                async |module: &Lightning, dbtx, gateway: LightningGateway| -> () {
-                    module.register_gateway(dbtx, gateway).await;
-                    Ok(())
+                async |module: &Lightning, dbtx, gateway: SignedLightningGateway| -> () {
+                    module.register_gateway(dbtx, gateway).await
                 }
             },
         ]
@@ -989,8 +1490,8 @@ impl ServerModule for Lightning {
 }
 
 impl Lightning {
-    pub fn new(cfg: LightningConfig) -> Self {
-        Lightning { cfg }
+    pub fn new(cfg: LightningConfig, db: Database) -> Self {
+        Lightning { cfg, db }
     }
 
     fn validate_decryption_share(
@@ -1006,6 +1507,123 @@ impl Lightning {
             .verify_decryption_share(&share.0, &message.0)
     }
 
+    /// Verifies the decryption shares of a contract as one batch and decrypts its preimage
+    ///
+    /// Optimistically the first `threshold` shares decrypt a preimage matching the contract's hash,
+    /// which proves them valid. Every further share is then checked by decrypting the same preimage
+    /// together with the others, which only takes an interpolation instead of two pairings. Shares
+    /// failing that check are verified on their own before their peer is considered faulty. If the
+    /// decrypted preimage doesn't match, e.g. because the user encrypted an invalid one or one of the
+    /// first shares is faulty, we fall back to verifying each share on its own.
+    ///
+    /// Returns the peers whose shares are valid and the decrypted preimage if there were enough.
+    fn decrypt_preimage(
+        &self,
+        shares: &BTreeMap<PeerId, PreimageDecryptionShare>,
+        incoming: &IncomingContract,
+    ) -> (HashSet<PeerId>, Option<Vec<u8>>) {
+        let threshold = self.cfg.consensus.threshold();
+        let shares = shares.iter().collect::<Vec<_>>();
+
+        if shares.len() >= threshold {
+            let (combined, remaining) = shares.split_at(threshold);
+            if let Some(preimage_vec) =
+                self.combine_decryption_shares(combined, &incoming.encrypted_preimage)
+            {
+                if bitcoin_hashes::sha256::Hash::hash(&preimage_vec) == incoming.hash {
+                    let mut valid_peers = combined
+                        .iter()
+                        .map(|(peer, _)| **peer)
+                        .collect::<HashSet<_>>();
+                    for share in remaining {
+                        let mut replaced = combined[1..].to_vec();
+                        replaced.push(*share);
+                        let decrypted =
+                            self.combine_decryption_shares(&replaced, &incoming.encrypted_preimage);
+                        if decrypted.as_ref() == Some(&preimage_vec)
+                            || self.validate_decryption_share(
+                                *share.0,
+                                share.1,
+                                &incoming.encrypted_preimage,
+                            )
+                        {
+                            valid_peers.insert(*share.0);
+                        }
+                    }
+                    return (valid_peers, Some(preimage_vec));
+                }
+            }
+        }
+
+        let valid_shares = shares
+            .into_iter()
+            .filter(|(peer, share)| {
+                self.validate_decryption_share(**peer, share, &incoming.encrypted_preimage)
+            })
+            .collect::<Vec<_>>();
+        let valid_peers = valid_shares.iter().map(|(peer, _)| **peer).collect();
+        if valid_shares.len() < threshold {
+            return (valid_peers, None);
+        }
+
+        let preimage_vec =
+            self.combine_decryption_shares(&valid_shares, &incoming.encrypted_preimage);
+        (valid_peers, preimage_vec)
+    }
+
+    /// Whether the contract is an incoming one whose preimage wasn't decrypted yet
+    async fn awaits_decryption(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        contract_id: ContractId,
+    ) -> bool {
+        matches!(
+            self.get_contract_account(dbtx, contract_id).await,
+            Some(ContractAccount {
+                contract: FundedContract::Incoming(incoming),
+                ..
+            }) if incoming.contract.decrypted_preimage == DecryptedPreimage::Pending
+        )
+    }
+
+    /// Decrypts the preimage from the first `threshold` shares without verifying them
+    fn combine_decryption_shares(
+        &self,
+        shares: &[(&PeerId, &PreimageDecryptionShare)],
+        encrypted_preimage: &EncryptedPreimage,
+    ) -> Option<Vec<u8>> {
+        self.cfg
+            .consensus
+            .threshold_pub_keys
+            .decrypt(
+                shares
+                    .iter()
+                    .map(|(peer, share)| (peer.to_usize(), &share.0)),
+                &encrypted_preimage.0,
+            )
+            .ok()
+    }
+
+    /// Starts the threshold decryption of the preimage of a funded incoming contract
+    async fn propose_decryption_share(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        incoming: &IncomingContract,
+    ) {
+        let decryption_share = self
+            .cfg
+            .private
+            .threshold_sec_key
+            .decrypt_share(&incoming.encrypted_preimage.0)
+            .expect("We checked for decryption share validity on contract creation");
+        dbtx.insert_new_entry(
+            &ProposeDecryptionShareKey(incoming.contract_id()),
+            &PreimageDecryptionShare(decryption_share),
+        )
+        .await
+        .expect("DB Error");
+    }
+
     pub async fn get_offer(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -1027,6 +1645,18 @@ impl Lightning {
             .await
     }
 
+    /// Number of offers that can still be funded and of offers that expired before being funded
+    pub async fn offer_counts(&self, dbtx: &mut DatabaseTransaction<'_>) -> OfferCounts {
+        let active = self.get_offers(dbtx).await.len() as u64;
+        let expired = dbtx
+            .get_value(&ExpiredOfferCountKey)
+            .await
+            .expect("DB error")
+            .unwrap_or(0);
+
+        OfferCounts { active, expired }
+    }
+
     pub async fn get_contract_account(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -1037,31 +1667,249 @@ impl Lightning {
             .expect("DB error")
     }
 
+    /// Gateways whose registration didn't expire according to the federation's consensus time,
+    /// so all guardians list the same gateways regardless of their clocks
     pub async fn list_gateways(&self, dbtx: &mut DatabaseTransaction<'_>) -> Vec<LightningGateway> {
+        let consensus_time = self.consensus_time(dbtx).await;
         let stream = dbtx.find_by_prefix(&LightningGatewayKeyPrefix).await;
         stream
             .filter_map(|res| async {
-                let gw = res.expect("DB error").1;
-                // FIXME: actually remove from DB
-                if gw.valid_until > SystemTime::now() {
-                    Some(gw)
-                } else {
+                let gw = res.expect("DB error").1.gateway;
+                // Expired registrations are only removed at the end of the next epoch
+                if gw.is_expired(consensus_time) {
                     None
+                } else {
+                    Some(gw)
                 }
             })
             .collect::<Vec<LightningGateway>>()
             .await
     }
 
+    /// Checks a gateway registration received through the API and proposes it to the other
+    /// guardians, it's only listed once the federation agreed on it
     pub async fn register_gateway(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
-        gateway: LightningGateway,
+        gateway: SignedLightningGateway,
+    ) -> Result<(), ApiError> {
+        gateway
+            .verify()
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+        if gateway.gateway.valid_until <= SystemTime::now() {
+            return Err(ApiError::bad_request(
+                LightningError::ExpiredGatewayRegistration.to_string(),
+            ));
+        }
+
+        dbtx.insert_entry(&ProposeGatewayKey(gateway.gateway.mint_pub_key), &gateway)
+            .await
+            .expect("DB error");
+        Ok(())
+    }
+
+    /// Stores a gateway registration the federation agreed on unless it's forged or older than
+    /// the one we already have for that gateway, replacing the gateway's previous registration
+    async fn process_gateway_registration(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        gateway: SignedLightningGateway,
     ) {
-        dbtx.insert_entry(&LightningGatewayKey(gateway.node_pub_key), &gateway)
+        if let Err(e) = gateway.verify() {
+            warn!("Discarding gateway registration: {}", e);
+            return;
+        }
+
+        let mint_pub_key = gateway.gateway.mint_pub_key;
+        let current = dbtx
+            .get_value(&LightningGatewayKey(mint_pub_key))
             .await
             .expect("DB error");
+        let current = match current {
+            Some(current) if current.gateway.valid_until >= gateway.gateway.valid_until => {
+                debug!(%mint_pub_key, "Ignoring outdated gateway registration");
+                current
+            }
+            _ => {
+                dbtx.insert_entry(&LightningGatewayKey(mint_pub_key), &gateway)
+                    .await
+                    .expect("DB error");
+                gateway
+            }
+        };
+
+        // We stop proposing our registration once the federation agreed on it or a newer one
+        let proposal = dbtx
+            .get_value(&ProposeGatewayKey(mint_pub_key))
+            .await
+            .expect("DB error");
+        if let Some(proposal) = proposal {
+            if proposal.gateway.valid_until <= current.gateway.valid_until {
+                dbtx.remove_entry(&ProposeGatewayKey(mint_pub_key))
+                    .await
+                    .expect("DB error");
+            }
+        }
+    }
+
+    /// Removes gateway registrations whose validity ended according to the consensus time,
+    /// including ones we didn't get to propose in time
+    async fn remove_expired_gateways(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let consensus_time = self.consensus_time(dbtx).await;
+
+        let expired_gateways = dbtx
+            .find_by_prefix(&LightningGatewayKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .filter(|(_, gateway)| {
+                futures::future::ready(gateway.gateway.is_expired(consensus_time))
+            })
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired_gateways {
+            debug!(mint_pub_key = %key.0, "Removing expired gateway registration");
+            dbtx.remove_entry(&key).await.expect("DB error");
+        }
+
+        let expired_proposals = dbtx
+            .find_by_prefix(&ProposeGatewayKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .filter(|(_, gateway)| {
+                futures::future::ready(gateway.gateway.is_expired(consensus_time))
+            })
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired_proposals {
+            dbtx.remove_entry(&key).await.expect("DB error");
+        }
+    }
+
+    /// Unix time in seconds the federation agreed on: the latest time proposed by at least a
+    /// threshold of guardians, so a minority of faulty guardians can't move it into the future
+    pub async fn consensus_time(&self, dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+        let mut peer_times = dbtx
+            .find_by_prefix(&PeerTimeKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").1)
+            .collect::<Vec<u64>>()
+            .await;
+        peer_times.sort_unstable_by(|a, b| b.cmp(a));
+
+        peer_times
+            .get(self.cfg.consensus.threshold() - 1)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Whether one of the offers or gateway registrations expired according to our clock but the
+    /// federation didn't agree on it yet and we haven't told it about our time since
+    async fn should_propose_time(&self, dbtx: &mut DatabaseTransaction<'_>) -> bool {
+        let now = unix_time_now();
+        let consensus_time = self.consensus_time(dbtx).await;
+        let our_time = self.our_proposed_time(dbtx).await;
+
+        let offer_expired = self.get_offers(dbtx).await.iter().any(|offer| {
+            offer.is_expired(now)
+                && !offer.is_expired(consensus_time)
+                && !offer.is_expired(our_time)
+        });
+        offer_expired
+            || dbtx
+                .find_by_prefix(&LightningGatewayKeyPrefix)
+                .await
+                .map(|res| res.expect("DB error").1.gateway)
+                .any(|gateway| {
+                    futures::future::ready(
+                        gateway.is_expired(now)
+                            && !gateway.is_expired(consensus_time)
+                            && !gateway.is_expired(our_time),
+                    )
+                })
+                .await
+    }
+
+    /// The latest time the federation agreed on being proposed by us
+    async fn our_proposed_time(&self, dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+        dbtx.get_value(&PeerTimeKey(self.cfg.private.our_peer_id))
+            .await
+            .expect("DB error")
+            .unwrap_or(0)
     }
+
+    async fn remove_expired_offers(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let consensus_time = self.consensus_time(dbtx).await;
+
+        let expired_offers = dbtx
+            .find_by_prefix(&OfferKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .filter(|(_, offer)| futures::future::ready(offer.is_expired(consensus_time)))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        if expired_offers.is_empty() {
+            return;
+        }
+
+        let expired_count = dbtx
+            .get_value(&ExpiredOfferCountKey)
+            .await
+            .expect("DB error")
+            .unwrap_or(0);
+        dbtx.insert_entry(
+            &ExpiredOfferCountKey,
+            &(expired_count + expired_offers.len() as u64),
+        )
+        .await
+        .expect("DB error");
+
+        for key in expired_offers {
+            debug!(payment_hash = %key.0, "Removing expired offer");
+            dbtx.remove_entry(&key).await.expect("DB error");
+        }
+    }
+
+    /// Number of offers created in the current consensus epoch
+    async fn epoch_offer_count(&self, dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+        dbtx.get_value(&EpochOfferCountKey)
+            .await
+            .expect("DB error")
+            .unwrap_or(0)
+    }
+
+    /// Proof of work difficulty the next offer has to meet, see [`OfferRateLimit`]
+    pub async fn offer_difficulty(&self, dbtx: &mut DatabaseTransaction<'_>) -> u8 {
+        let epoch_offer_count = self.epoch_offer_count(dbtx).await;
+        self.cfg
+            .consensus
+            .offer_rate_limit
+            .difficulty(epoch_offer_count)
+    }
+
+    /// Checks that the offer meets the proof of work difficulty of the federation's
+    /// [`OfferRateLimit`] at the current load
+    async fn validate_offer_rate_limit(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        offer: &IncomingContractOffer,
+    ) -> Result<(), LightningError> {
+        let difficulty = self.offer_difficulty(dbtx).await;
+        if !offer.meets_proof_of_work(difficulty) {
+            return Err(LightningError::InsufficientProofOfWork(difficulty));
+        }
+        Ok(())
+    }
+}
+
+/// Current unix time in seconds according to our own clock
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
 
 plugin_types_trait_impl!(
@@ -1119,6 +1967,24 @@ pub mod route_hints {
     pub struct RouteHint(pub Vec<RouteHintHop>);
 
     impl RouteHint {
+        pub fn from_ldk_route_hint(route_hint: &lightning::routing::router::RouteHint) -> Self {
+            RouteHint(
+                route_hint
+                    .0
+                    .iter()
+                    .map(|hop| RouteHintHop {
+                        src_node_id: hop.src_node_id,
+                        short_channel_id: hop.short_channel_id,
+                        base_msat: hop.fees.base_msat,
+                        proportional_millionths: hop.fees.proportional_millionths,
+                        cltv_expiry_delta: hop.cltv_expiry_delta,
+                        htlc_minimum_msat: hop.htlc_minimum_msat,
+                        htlc_maximum_msat: hop.htlc_maximum_msat,
+                    })
+                    .collect(),
+            )
+        }
+
         pub fn to_ldk_route_hint(&self) -> lightning::routing::router::RouteHint {
             lightning::routing::router::RouteHint(
                 self.0
@@ -1166,4 +2032,227 @@ pub enum LightningError {
     NotOutgoingContract,
     #[error("Cancellation request wasn't properly signed")]
     InvalidCancellationSignature,
+    #[error("The incoming contract's hold terms don't match its offer")]
+    InvalidHoldTerms,
+    #[error("Only held incoming contracts can be accepted")]
+    NotHeldIncomingContract,
+    #[error("The held incoming contract was already accepted or refunded")]
+    HoldNotPending,
+    #[error("Acceptance request wasn't properly signed")]
+    InvalidAcceptanceSignature,
+    #[error("Held incoming contract wasn't accepted yet and its timelock didn't expire")]
+    NotAccepted,
+    #[error("Gateway registration wasn't signed by the gateway's mint key")]
+    InvalidGatewaySignature,
+    #[error("Gateway registration already expired")]
+    ExpiredGatewayRegistration,
+    #[error("The offer for payment hash {0} expired")]
+    ExpiredOffer(secp256k1::hashes::sha256::Hash),
+    #[error("The incoming contract's version doesn't match its offer")]
+    InvalidContractVersion,
+    #[error("Claiming the incoming contract requires the key its preimage is the hash of")]
+    MissingClaimKey,
+    #[error("The claim key doesn't hash to the incoming contract's preimage")]
+    InvalidClaimKey,
+    #[error("The offer's proof of work doesn't meet the difficulty of {0} bits")]
+    InsufficientProofOfWork(u8),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway(liquidity: Option<GatewayLiquidity>) -> LightningGateway {
+        LightningGateway {
+            mint_channel_id: 0,
+            mint_pub_key: secp256k1::XOnlyPublicKey::from_slice(&[42; 32][..]).unwrap(),
+            node_pub_key: secp256k1::PublicKey::from_slice(&[2; 33][..]).unwrap(),
+            api: Url::parse("http://gateway.example.com").unwrap(),
+            route_hints: vec![],
+            fees: Default::default(),
+            valid_until: SystemTime::now(),
+            liquidity,
+            issues_invoices: false,
+            supports_mpp: false,
+        }
+    }
+
+    #[test]
+    fn liquidity_is_rounded_down_to_powers_of_ten_sats() {
+        assert_eq!(liquidity_bucket(Amount::ZERO), Amount::ZERO);
+        assert_eq!(liquidity_bucket(Amount::from_msats(999)), Amount::ZERO);
+        assert_eq!(liquidity_bucket(Amount::from_sats(1)), Amount::from_sats(1));
+        assert_eq!(
+            liquidity_bucket(Amount::from_msats(9_999)),
+            Amount::from_sats(1)
+        );
+        assert_eq!(
+            liquidity_bucket(Amount::from_sats(10)),
+            Amount::from_sats(10)
+        );
+        assert_eq!(
+            liquidity_bucket(Amount::from_sats(1_000_000)),
+            Amount::from_sats(1_000_000)
+        );
+        assert_eq!(
+            liquidity_bucket(Amount::from_sats(9_999_999)),
+            Amount::from_sats(1_000_000)
+        );
+    }
+
+    #[test]
+    fn gateways_can_route_up_to_their_reported_liquidity() {
+        let gateway = gateway(Some(GatewayLiquidity::from_balances(
+            Amount::from_sats(15_000),
+            Amount::from_sats(900),
+        )));
+
+        assert!(gateway.can_send(Amount::from_sats(10_000)));
+        assert!(!gateway.can_send(Amount::from_msats(10_000_001)));
+        assert!(gateway.can_receive(Amount::from_sats(100)));
+        assert!(!gateway.can_receive(Amount::from_sats(101)));
+    }
+
+    #[test]
+    fn gateways_without_reported_liquidity_are_assumed_to_route() {
+        let gateway = gateway(None);
+
+        assert!(gateway.can_send(Amount::from_sats(21_000_000 * 100_000_000)));
+        assert!(gateway.can_receive(Amount::from_sats(21_000_000 * 100_000_000)));
+    }
+
+    #[test]
+    fn gateway_registrations_expire_at_their_validity_end() {
+        let mut gateway = gateway(None);
+        gateway.valid_until = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+
+        assert!(!gateway.is_expired(999));
+        assert!(gateway.is_expired(1_000));
+        assert!(gateway.is_expired(1_001));
+    }
+
+    fn lightning(peer: PeerId) -> Lightning {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let cfg = LightningGen
+            .trusted_dealer_gen(&peers, &ConfigGenParams::new())
+            .remove(&peer)
+            .unwrap();
+        Lightning::new(
+            cfg.to_typed().unwrap(),
+            Database::new(
+                fedimint_api::db::mem_impl::MemDatabase::new(),
+                Default::default(),
+            ),
+        )
+    }
+
+    async fn set_peer_times(dbtx: &mut DatabaseTransaction<'_>, times: &[u64]) {
+        for (peer, time) in times.iter().enumerate() {
+            dbtx.insert_entry(&PeerTimeKey(PeerId::from(peer as u16)), time)
+                .await
+                .expect("DB error");
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn our_proposed_time_is_looked_up_by_our_peer_id() {
+        let lightning = lightning(PeerId::from(2));
+        let mut dbtx = lightning.db.begin_transaction().await;
+
+        assert_eq!(lightning.our_proposed_time(&mut dbtx).await, 0);
+        set_peer_times(&mut dbtx, &[10, 11, 12, 13]).await;
+        assert_eq!(lightning.our_proposed_time(&mut dbtx).await, 12);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn gateways_are_listed_until_consensus_time_passes_their_validity() {
+        let lightning = lightning(PeerId::from(0));
+        let mut dbtx = lightning.db.begin_transaction().await;
+
+        // Long expired according to our clock, but not according to the federation's
+        let mut gateway = gateway(None);
+        gateway.valid_until = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        dbtx.insert_entry(
+            &LightningGatewayKey(gateway.mint_pub_key),
+            &SignedLightningGateway {
+                gateway: gateway.clone(),
+                signature: secp256k1::schnorr::Signature::from_slice(&[42; 64]).unwrap(),
+            },
+        )
+        .await
+        .expect("DB error");
+
+        // A single guardian's clock can't expire the registration
+        set_peer_times(&mut dbtx, &[2_000, 999, 999, 999]).await;
+        assert_eq!(lightning.consensus_time(&mut dbtx).await, 999);
+        assert_eq!(lightning.list_gateways(&mut dbtx).await, vec![gateway]);
+
+        set_peer_times(&mut dbtx, &[2_000, 1_000, 1_000, 999]).await;
+        assert_eq!(lightning.consensus_time(&mut dbtx).await, 1_000);
+        assert_eq!(lightning.list_gateways(&mut dbtx).await, vec![]);
+    }
+
+    #[test]
+    fn faulty_decryption_shares_are_attributed_to_their_peer() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let cfgs = LightningGen
+            .trusted_dealer_gen(&peers, &ConfigGenParams::new())
+            .into_values()
+            .map(|cfg| cfg.to_typed::<LightningConfig>().unwrap())
+            .collect::<Vec<_>>();
+        let lightning = Lightning::new(
+            cfgs[0].clone(),
+            Database::new(
+                fedimint_api::db::mem_impl::MemDatabase::new(),
+                Default::default(),
+            ),
+        );
+
+        let preimage = Preimage([42; 32]);
+        let contract = IncomingContract {
+            hash: bitcoin_hashes::sha256::Hash::hash(&preimage.0),
+            encrypted_preimage: EncryptedPreimage::new(
+                preimage.clone(),
+                &cfgs[0].consensus.threshold_pub_keys.public_key(),
+            ),
+            decrypted_preimage: DecryptedPreimage::Pending,
+            gateway_key: secp256k1::XOnlyPublicKey::from_slice(&[42; 32][..]).unwrap(),
+            hold: None,
+            version: IncomingContractVersion::V1,
+        };
+        let decryption_share = |key_of: PeerId| {
+            PreimageDecryptionShare(
+                cfgs[key_of.to_usize()]
+                    .private
+                    .threshold_sec_key
+                    .decrypt_share_no_verify(&contract.encrypted_preimage.0),
+            )
+        };
+
+        // The faulty share is among the first `threshold` shares and among the remaining ones
+        for faulty_peer in [peers[0], peers[3]] {
+            let shares = peers
+                .iter()
+                .map(|&peer| {
+                    let key_of = if peer == faulty_peer {
+                        peers[(peer.to_usize() + 1) % peers.len()]
+                    } else {
+                        peer
+                    };
+                    (peer, decryption_share(key_of))
+                })
+                .collect::<BTreeMap<_, _>>();
+
+            let (valid_peers, preimage_vec) = lightning.decrypt_preimage(&shares, &contract);
+            assert_eq!(preimage_vec, Some(preimage.0.to_vec()));
+            assert_eq!(
+                valid_peers,
+                peers
+                    .iter()
+                    .copied()
+                    .filter(|peer| *peer != faulty_peer)
+                    .collect::<HashSet<_>>()
+            );
+        }
+    }
 }
@@ -1,23 +1,40 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
 use bitcoin_hashes::sha256;
 use bitcoin_hashes::Hash as BitcoinHash;
-use fedimint_api::config::ConfigGenParams;
+use fedimint_api::config::{ConfigGenParams, TypedServerModuleConfig};
 use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_LN;
-use fedimint_api::{Amount, OutPoint};
-use fedimint_ln::config::LightningClientConfig;
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_api::module::{ModuleGen, ServerModule};
+use fedimint_api::{Amount, OutPoint, PeerId};
+use fedimint_ln::config::{
+    LightningClientConfig, LightningConfig, OfferRateLimit, DEFAULT_OFFERS_PER_DIFFICULTY_BIT,
+    DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY, MAX_OFFER_PROOF_OF_WORK_DIFFICULTY,
+};
 use fedimint_ln::contracts::account::AccountContract;
-use fedimint_ln::contracts::incoming::{IncomingContract, IncomingContractOffer};
-use fedimint_ln::contracts::outgoing::OutgoingContract;
+use fedimint_ln::contracts::htlc::HtlcContract;
+use fedimint_ln::contracts::incoming::{
+    IncomingContract, IncomingContractHold, IncomingContractOffer, IncomingContractVersion,
+};
+use fedimint_ln::contracts::outgoing::{OutgoingContract, PrunedInvoice};
 use fedimint_ln::contracts::{
     AccountContractOutcome, Contract, ContractOutcome, DecryptedPreimage, EncryptedPreimage,
-    IdentifyableContract, OutgoingContractOutcome, Preimage,
+    FundedContract, HtlcContractOutcome, IdentifyableContract, OutgoingContractOutcome, Preimage,
+    PreimageDecryptionShare,
 };
+use fedimint_ln::db::AgreedDecryptionShareKeyPrefix;
 use fedimint_ln::LightningGen;
 use fedimint_ln::{
-    ContractOutput, Lightning, LightningError, LightningInput, LightningOutput,
-    LightningOutputOutcome,
+    ContractOutput, GatewayFees, Lightning, LightningConsensusItem, LightningError,
+    LightningGateway, LightningInput, LightningOutput, LightningOutputOutcome, OfferCounts,
+    SignedLightningGateway,
 };
 use fedimint_testing::FakeFed;
-use secp256k1::KeyPair;
+use futures::StreamExt;
+use secp256k1::{KeyPair, Message};
+use threshold_crypto::SecretKeyShare;
 
 #[test_log::test(tokio::test)]
 async fn test_account() {
@@ -25,7 +42,7 @@ async fn test_account() {
 
     let mut fed = FakeFed::<Lightning>::new(
         4,
-        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
         &ConfigGenParams::new(),
         &LightningGen,
         LEGACY_HARDCODED_INSTANCE_ID_LN,
@@ -76,7 +93,7 @@ async fn test_outgoing() {
 
     let mut fed = FakeFed::<Lightning>::new(
         4,
-        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
         &ConfigGenParams::new(),
         &LightningGen,
         LEGACY_HARDCODED_INSTANCE_ID_LN,
@@ -110,7 +127,7 @@ j5r6drg6k6zcqj0fcwg"
         gateway_key: gw_pk,
         timelock: 42,
         user_key: user_pk,
-        invoice,
+        invoice: PrunedInvoice::from(&invoice),
         cancelled: false,
     });
 
@@ -170,13 +187,120 @@ j5r6drg6k6zcqj0fcwg"
     fed.consensus_round(&[account_input_no_witness], &[]).await;
 }
 
+#[test_log::test(tokio::test)]
+async fn test_htlc() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let recipient_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let sender_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let preimage = Preimage([42u8; 32]);
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let contract = Contract::Htlc(HtlcContract {
+        hash,
+        recipient_key: recipient_pk,
+        timelock: 42,
+        sender_key: sender_pk,
+    });
+
+    let htlc_output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: contract.clone(),
+    });
+    let htlc_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"htlc").into(),
+        out_idx: 0,
+    };
+
+    fed.consensus_round(&[], &[(htlc_out_point, htlc_output)])
+        .await;
+    match fed.output_outcome(htlc_out_point).await.unwrap() {
+        LightningOutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(outcome, ContractOutcome::Htlc(HtlcContractOutcome {}));
+        }
+        _ => panic!(),
+    };
+
+    // Before the timeout the recipient has to reveal the preimage
+    fed.set_block_height(0);
+
+    let htlc_input_no_witness = LightningInput {
+        contract_id: contract.contract_id(),
+        amount: Amount::from_sats(42),
+        witness: None,
+    };
+    let err = fed.verify_input(&htlc_input_no_witness).await.unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        format!("{}", LightningError::MissingPreimage)
+    );
+
+    let htlc_input_wrong_witness = LightningInput {
+        witness: Some(Preimage([0u8; 32])),
+        ..htlc_input_no_witness.clone()
+    };
+    let err = fed
+        .verify_input(&htlc_input_wrong_witness)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        format!("{}", LightningError::InvalidPreimage)
+    );
+
+    let htlc_input_witness = LightningInput {
+        witness: Some(preimage.clone()),
+        ..htlc_input_no_witness.clone()
+    };
+    let meta = fed.verify_input(&htlc_input_witness).await.unwrap();
+    assert_eq!(meta.keys, vec![recipient_pk]);
+
+    // After the timeout the sender can claim the funds back
+    fed.set_block_height(42);
+    let meta = fed.verify_input(&htlc_input_no_witness).await.unwrap();
+    assert_eq!(meta.keys, vec![sender_pk]);
+
+    // The preimage revealed by the recipient is kept for the counterparty of a swap
+    fed.set_block_height(0);
+    fed.consensus_round(&[htlc_input_witness], &[]).await;
+    let account = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            m.get_contract_account(
+                &mut db
+                    .begin_transaction()
+                    .await
+                    .with_module_prefix(*module_instance_id),
+                contract.contract_id(),
+            )
+            .await
+        })
+        .await
+        .unwrap();
+    assert_eq!(account.amount, Amount::ZERO);
+    match account.contract {
+        FundedContract::Htlc(htlc) => assert_eq!(htlc.revealed_preimage, Some(preimage)),
+        _ => panic!(),
+    };
+}
+
 #[test_log::test(tokio::test)]
 async fn test_incoming() {
     let mut rng = secp256k1::rand::rngs::OsRng;
 
     let mut fed = FakeFed::<Lightning>::new(
         4,
-        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
         &ConfigGenParams::new(),
         &LightningGen,
         LEGACY_HARDCODED_INSTANCE_ID_LN,
@@ -191,7 +315,7 @@ async fn test_incoming() {
     let preimage = Preimage(user_pk.serialize());
     let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
 
-    let offer = IncomingContractOffer {
+    let mut offer = IncomingContractOffer {
         amount: Amount::from_sats(42),
         hash,
         encrypted_preimage: EncryptedPreimage::new(
@@ -201,7 +325,11 @@ async fn test_incoming() {
                 .threshold_pub_key,
         ),
         expiry_time: None,
+        hold_key: None,
+        version: IncomingContractVersion::V0,
+        nonce: 0,
     };
+    offer.solve_proof_of_work(DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY);
     let offer_output = LightningOutput::Offer(offer.clone());
     let offer_out_point = OutPoint {
         txid: sha256::Hash::hash(b"").into(),
@@ -228,6 +356,8 @@ async fn test_incoming() {
         encrypted_preimage: offer.encrypted_preimage,
         decrypted_preimage: DecryptedPreimage::Pending, // TODO: check what happens if this is not pending
         gateway_key: gw_pk,
+        hold: None,
+        version: IncomingContractVersion::V0,
     });
     let incoming_output = LightningOutput::Contract(ContractOutput {
         amount: Amount::from_sats(42),
@@ -275,5 +405,992 @@ async fn test_incoming() {
     let meta = fed.verify_input(&incoming_input).await.unwrap();
     assert_eq!(meta.keys, vec![user_pk]);
 
+    // Late shares for the decrypted contract are dropped before being verified
+    let late_share = PreimageDecryptionShare(
+        SecretKeyShare::default().decrypt_share_no_verify(&offers[0].encrypted_preimage.0),
+    );
+    let agreed_shares = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            let mut dbtx = db.begin_transaction().await;
+            let mut module_dbtx = dbtx.with_module_prefix(*module_instance_id);
+            m.begin_consensus_epoch(
+                &mut module_dbtx,
+                vec![(
+                    PeerId::from(0),
+                    LightningConsensusItem::DecryptionShares(BTreeMap::from([(
+                        contract.contract_id(),
+                        late_share.clone(),
+                    )])),
+                )],
+            )
+            .await;
+            module_dbtx
+                .find_by_prefix(&AgreedDecryptionShareKeyPrefix)
+                .await
+                .count()
+                .await
+        })
+        .await;
+    assert_eq!(agreed_shares, 0);
+
     // TODO: test faulty encrypted preimage
 }
+
+#[test_log::test(tokio::test)]
+async fn test_incoming_hashed_claim_key() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let other_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+
+    let preimage = Preimage::from_claim_key(&user_pk);
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let mut offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage.clone(),
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: None,
+        hold_key: None,
+        version: IncomingContractVersion::V1,
+        nonce: 0,
+    };
+    offer.solve_proof_of_work(DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY);
+    let offer_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, LightningOutput::Offer(offer.clone()))],
+    )
+    .await;
+
+    let mut incoming = IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage,
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        hold: None,
+        version: IncomingContractVersion::V0,
+    };
+
+    // The gateway can't fund a contract of another version than the offer
+    let legacy_output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: Contract::Incoming(incoming.clone()),
+    });
+    assert!(!fed.verify_output(&legacy_output).await);
+
+    incoming.version = IncomingContractVersion::V1;
+    let contract = Contract::Incoming(incoming);
+    let incoming_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 1,
+    };
+    let incoming_output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: contract.clone(),
+    });
+    fed.consensus_round(&[], &[(incoming_out_point, incoming_output)])
+        .await;
+
+    // Decrypting the preimage, which isn't a public key
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).await.unwrap() {
+        LightningOutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Some(preimage))
+            );
+        }
+        _ => panic!(),
+    };
+
+    let claim_input = |witness: Option<Preimage>| LightningInput {
+        contract_id: contract.contract_id(),
+        amount: Amount::from_sats(42),
+        witness,
+    };
+
+    let error = fed.verify_input(&claim_input(None)).await.unwrap_err();
+    assert_eq!(
+        format!("{error}"),
+        format!("{}", LightningError::MissingClaimKey)
+    );
+
+    let error = fed
+        .verify_input(&claim_input(Some(Preimage(other_pk.serialize()))))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        format!("{error}"),
+        format!("{}", LightningError::InvalidClaimKey)
+    );
+
+    let meta = fed
+        .verify_input(&claim_input(Some(Preimage(user_pk.serialize()))))
+        .await
+        .unwrap();
+    assert_eq!(meta.keys, vec![user_pk]);
+}
+
+#[test]
+fn test_incoming_version_encoding() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+    let ctx = secp256k1::Secp256k1::new();
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let preimage = Preimage::from_claim_key(&user_pk);
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage,
+            &threshold_crypto::SecretKey::random().public_key(),
+        ),
+        expiry_time: Some(42),
+        hold_key: Some(user_pk),
+        version: IncomingContractVersion::V0,
+        nonce: 0,
+    };
+    let contract = IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage.clone(),
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        hold: None,
+        version: IncomingContractVersion::V0,
+    };
+
+    // Version 0 offers without proof of work and contracts encode exactly like before versions and
+    // proof of work were introduced
+    let mut legacy_offer = vec![];
+    offer.amount.consensus_encode(&mut legacy_offer).unwrap();
+    offer.hash.consensus_encode(&mut legacy_offer).unwrap();
+    offer
+        .encrypted_preimage
+        .consensus_encode(&mut legacy_offer)
+        .unwrap();
+    offer
+        .expiry_time
+        .consensus_encode(&mut legacy_offer)
+        .unwrap();
+    offer.hold_key.consensus_encode(&mut legacy_offer).unwrap();
+    assert_eq!(offer.consensus_encode_to_vec().unwrap(), legacy_offer);
+
+    let mut legacy_contract = vec![];
+    contract
+        .hash
+        .consensus_encode(&mut legacy_contract)
+        .unwrap();
+    contract
+        .encrypted_preimage
+        .consensus_encode(&mut legacy_contract)
+        .unwrap();
+    contract
+        .decrypted_preimage
+        .consensus_encode(&mut legacy_contract)
+        .unwrap();
+    contract
+        .gateway_key
+        .consensus_encode(&mut legacy_contract)
+        .unwrap();
+    contract
+        .hold
+        .consensus_encode(&mut legacy_contract)
+        .unwrap();
+    assert_eq!(contract.consensus_encode_to_vec().unwrap(), legacy_contract);
+
+    // Both versions and nonces survive a roundtrip, with and without the optional field they are
+    // encoded in
+    let hold = IncomingContractHold {
+        accept_key: user_pk,
+        timelock: 42,
+        accepted: false,
+    };
+    for version in [IncomingContractVersion::V0, IncomingContractVersion::V1] {
+        for hold in [None, Some(hold.clone())] {
+            for nonce in [0, 1, u64::MAX] {
+                let offer = IncomingContractOffer {
+                    hold_key: hold.as_ref().map(|hold| hold.accept_key),
+                    version,
+                    nonce,
+                    ..offer.clone()
+                };
+                let bytes = offer.consensus_encode_to_vec().unwrap();
+                let decoded = IncomingContractOffer::consensus_decode(
+                    &mut &bytes[..],
+                    &ModuleDecoderRegistry::default(),
+                )
+                .unwrap();
+                assert_eq!(decoded, offer);
+            }
+
+            let contract = IncomingContract {
+                hold,
+                version,
+                ..contract.clone()
+            };
+            let bytes = contract.consensus_encode_to_vec().unwrap();
+            let decoded = IncomingContract::consensus_decode(
+                &mut &bytes[..],
+                &ModuleDecoderRegistry::default(),
+            )
+            .unwrap();
+            assert_eq!(decoded, contract);
+        }
+    }
+}
+
+#[test]
+fn test_incoming_baseline_encoding() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+    let ctx = secp256k1::Secp256k1::new();
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let preimage = Preimage(user_pk.serialize());
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+    let encrypted_preimage = EncryptedPreimage::new(
+        preimage,
+        &threshold_crypto::SecretKey::random().public_key(),
+    );
+    let out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    let decode = |bytes: &[u8]| {
+        LightningOutput::consensus_decode(&mut &bytes[..], &ModuleDecoderRegistry::default())
+    };
+
+    // Offers and contracts as encoded before they had any optional fields
+    let mut baseline_offer = vec![];
+    Amount::from_sats(42)
+        .consensus_encode(&mut baseline_offer)
+        .unwrap();
+    hash.consensus_encode(&mut baseline_offer).unwrap();
+    encrypted_preimage
+        .consensus_encode(&mut baseline_offer)
+        .unwrap();
+    Some(42u64).consensus_encode(&mut baseline_offer).unwrap();
+
+    let mut baseline_contract = vec![];
+    hash.consensus_encode(&mut baseline_contract).unwrap();
+    encrypted_preimage
+        .consensus_encode(&mut baseline_contract)
+        .unwrap();
+    DecryptedPreimage::Pending
+        .consensus_encode(&mut baseline_contract)
+        .unwrap();
+    gw_pk.consensus_encode(&mut baseline_contract).unwrap();
+
+    let offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: encrypted_preimage.clone(),
+        expiry_time: Some(42),
+        hold_key: None,
+        version: IncomingContractVersion::V0,
+        nonce: 0,
+    };
+    let contract = IncomingContract {
+        hash,
+        encrypted_preimage,
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        hold: None,
+        version: IncomingContractVersion::V0,
+    };
+
+    // Offers were stored in the database on their own
+    assert_eq!(
+        IncomingContractOffer::consensus_decode(
+            &mut &baseline_offer[..],
+            &ModuleDecoderRegistry::default()
+        )
+        .unwrap(),
+        offer
+    );
+
+    // Inside enums they decode as version 0 and are still encoded the same way
+    let offer_output = [
+        &1u64.consensus_encode_to_vec().unwrap()[..],
+        &baseline_offer,
+    ]
+    .concat();
+    assert_eq!(
+        decode(&offer_output).unwrap(),
+        LightningOutput::Offer(offer.clone())
+    );
+    assert_eq!(
+        LightningOutput::Offer(offer.clone())
+            .consensus_encode_to_vec()
+            .unwrap(),
+        offer_output
+    );
+
+    let contract_output = [
+        &0u64.consensus_encode_to_vec().unwrap()[..],
+        &Amount::from_sats(42).consensus_encode_to_vec().unwrap(),
+        &1u64.consensus_encode_to_vec().unwrap(),
+        &baseline_contract,
+    ]
+    .concat();
+    let output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: Contract::Incoming(contract.clone()),
+    });
+    assert_eq!(decode(&contract_output).unwrap(), output);
+    assert_eq!(output.consensus_encode_to_vec().unwrap(), contract_output);
+
+    let funded_contract = [
+        &1u64.consensus_encode_to_vec().unwrap()[..],
+        &baseline_contract,
+        &out_point.consensus_encode_to_vec().unwrap(),
+    ]
+    .concat();
+    let funded = Contract::Incoming(contract.clone()).to_funded(out_point);
+    assert_eq!(
+        FundedContract::consensus_decode(
+            &mut &funded_contract[..],
+            &ModuleDecoderRegistry::default()
+        )
+        .unwrap(),
+        funded
+    );
+    assert_eq!(funded.consensus_encode_to_vec().unwrap(), funded_contract);
+
+    // Offers and contracts using the new fields need the flag byte and get their own variant
+    let v1_offer = LightningOutput::Offer(IncomingContractOffer {
+        version: IncomingContractVersion::V1,
+        nonce: 1,
+        ..offer.clone()
+    });
+    let v1_offer_output = v1_offer.consensus_encode_to_vec().unwrap();
+    assert_ne!(v1_offer_output[..8], offer_output[..8]);
+    assert_eq!(decode(&v1_offer_output).unwrap(), v1_offer);
+
+    let hold_contract = Contract::Incoming(IncomingContract {
+        hold: Some(IncomingContractHold {
+            accept_key: user_pk,
+            timelock: 42,
+            accepted: false,
+        }),
+        ..contract.clone()
+    });
+    let bytes = hold_contract.consensus_encode_to_vec().unwrap();
+    assert_eq!(
+        Contract::consensus_decode(&mut &bytes[..], &ModuleDecoderRegistry::default()).unwrap(),
+        hold_contract
+    );
+    let funded = hold_contract.to_funded(out_point);
+    let bytes = funded.consensus_encode_to_vec().unwrap();
+    assert_eq!(
+        FundedContract::consensus_decode(&mut &bytes[..], &ModuleDecoderRegistry::default())
+            .unwrap(),
+        funded
+    );
+
+    // Version 0 offers with the flag byte are rejected, so every offer has a single encoding
+    let flagged_offer = [
+        &v1_offer_output[..8],
+        &offer.consensus_encode_to_vec().unwrap(),
+    ]
+    .concat();
+    decode(&flagged_offer).unwrap_err();
+}
+
+#[test_log::test(tokio::test)]
+async fn test_incoming_hold() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let accept_kp = KeyPair::new(&ctx, &mut rng);
+
+    let preimage = Preimage(user_pk.serialize());
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let mut offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage.clone(),
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: None,
+        hold_key: Some(accept_kp.x_only_public_key().0),
+        version: IncomingContractVersion::V0,
+        nonce: 0,
+    };
+    offer.solve_proof_of_work(DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY);
+    let offer_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, LightningOutput::Offer(offer.clone()))],
+    )
+    .await;
+
+    // Hold invoices can only be paid with held contracts
+    let unheld_contract = Contract::Incoming(IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage.clone(),
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        hold: None,
+        version: IncomingContractVersion::V0,
+    });
+    assert!(
+        fed.verify_output(&LightningOutput::Contract(ContractOutput {
+            amount: Amount::from_sats(42),
+            contract: unheld_contract,
+        }))
+        .await
+    );
+
+    let incoming = IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage,
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        hold: Some(IncomingContractHold {
+            accept_key: accept_kp.x_only_public_key().0,
+            timelock: 42,
+            accepted: false,
+        }),
+        version: IncomingContractVersion::V0,
+    };
+    let contract = Contract::Incoming(incoming.clone());
+    let incoming_output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: contract.clone(),
+    });
+    let incoming_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 1,
+    };
+    fed.consensus_round(&[], &[(incoming_out_point, incoming_output)])
+        .await;
+
+    // The preimage isn't decrypted before the payment was accepted
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).await.unwrap() {
+        LightningOutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Pending)
+            );
+        }
+        _ => panic!(),
+    };
+
+    let incoming_input = LightningInput {
+        contract_id: contract.contract_id(),
+        amount: Amount::from_sats(42),
+        witness: None,
+    };
+    fed.set_block_height(0);
+    let error = fed.verify_input(&incoming_input).await.unwrap_err();
+    assert_eq!(
+        format!("{error}"),
+        format!("{}", LightningError::NotAccepted)
+    );
+
+    // The gateway can claim back the funds once the timelock expired
+    fed.set_block_height(42);
+    let meta = fed.verify_input(&incoming_input).await.unwrap();
+    assert_eq!(meta.keys, vec![gw_pk]);
+
+    let wrong_signature = ctx.sign_schnorr(
+        &Message::from(incoming.acceptance_message()),
+        &KeyPair::new(&ctx, &mut rng),
+    );
+    assert!(
+        fed.verify_output(&LightningOutput::AcceptIncoming {
+            contract: contract.contract_id(),
+            signature: wrong_signature,
+        })
+        .await
+    );
+
+    let signature = ctx.sign_schnorr(&Message::from(incoming.acceptance_message()), &accept_kp);
+    let accept_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"accept").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(
+            accept_out_point,
+            LightningOutput::AcceptIncoming {
+                contract: contract.contract_id(),
+                signature,
+            },
+        )],
+    )
+    .await;
+
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).await.unwrap() {
+        LightningOutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Some(preimage))
+            );
+        }
+        _ => panic!(),
+    };
+
+    // Once accepted the funds belong to the user even after the timelock
+    let meta = fed.verify_input(&incoming_input).await.unwrap();
+    assert_eq!(meta.keys, vec![user_pk]);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_expired_offer() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+
+    let preimage = Preimage(user_pk.serialize());
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    // The offer already expired according to the guardians' clocks, but they didn't agree on it yet
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage.clone(),
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: Some(now - 10),
+        hold_key: None,
+        version: IncomingContractVersion::V0,
+        nonce: 0,
+    };
+    offer.solve_proof_of_work(DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY);
+    let offer_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, LightningOutput::Offer(offer.clone()))],
+    )
+    .await;
+    let counts = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            m.offer_counts(
+                &mut db
+                    .begin_transaction()
+                    .await
+                    .with_module_prefix(*module_instance_id),
+            )
+            .await
+        })
+        .await;
+    assert_eq!(
+        counts,
+        OfferCounts {
+            active: 1,
+            expired: 0
+        }
+    );
+
+    // The guardians propose their time and remove the offer once they agree it expired
+    fed.consensus_round(&[], &[]).await;
+    let counts = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            m.offer_counts(
+                &mut db
+                    .begin_transaction()
+                    .await
+                    .with_module_prefix(*module_instance_id),
+            )
+            .await
+        })
+        .await;
+    assert_eq!(
+        counts,
+        OfferCounts {
+            active: 0,
+            expired: 1
+        }
+    );
+
+    let incoming_output = LightningOutput::Contract(ContractOutput {
+        amount: Amount::from_sats(42),
+        contract: Contract::Incoming(IncomingContract {
+            hash,
+            encrypted_preimage: offer.encrypted_preimage.clone(),
+            decrypted_preimage: DecryptedPreimage::Pending,
+            gateway_key: gw_pk,
+            hold: None,
+            version: IncomingContractVersion::V0,
+        }),
+    });
+    assert!(!fed.verify_output(&incoming_output).await);
+
+    // Re-creating the offer doesn't work either
+    assert!(!fed.verify_output(&LightningOutput::Offer(offer)).await);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_gateway_registration() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move { Ok(Lightning::new(cfg.to_typed()?, db)) },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_kp = KeyPair::new(&ctx, &mut rng);
+    let gateway = LightningGateway {
+        mint_channel_id: 1,
+        mint_pub_key: gw_kp.x_only_public_key().0,
+        node_pub_key: gw_kp.public_key(),
+        api: "http://127.0.0.1:8080".parse().unwrap(),
+        route_hints: vec![],
+        fees: GatewayFees::default(),
+        valid_until: SystemTime::now() + Duration::from_secs(600),
+        liquidity: None,
+        issues_invoices: false,
+        supports_mpp: false,
+    };
+    let sign = |gateway: LightningGateway, kp: &KeyPair| SignedLightningGateway {
+        signature: ctx.sign_schnorr(&Message::from(gateway.registration_message()), kp),
+        gateway,
+    };
+
+    // Registrations not signed by the gateway's mint key are rejected
+    let forged = sign(gateway.clone(), &KeyPair::new(&ctx, &mut rng));
+    assert_eq!(
+        forged.verify(),
+        Err(LightningError::InvalidGatewaySignature)
+    );
+    assert!(!register_gateway(&mut fed, forged).await);
+
+    // Valid registrations are only listed once the federation agreed on them
+    assert!(register_gateway(&mut fed, sign(gateway.clone(), &gw_kp)).await);
+    assert_eq!(list_gateways(&mut fed).await, vec![]);
+
+    fed.consensus_round(&[], &[]).await;
+    assert_eq!(list_gateways(&mut fed).await, vec![gateway.clone()]);
+
+    // Older registrations don't replace newer ones
+    let outdated = LightningGateway {
+        mint_channel_id: 2,
+        valid_until: gateway.valid_until - Duration::from_secs(1),
+        ..gateway.clone()
+    };
+    assert!(register_gateway(&mut fed, sign(outdated, &gw_kp)).await);
+
+    fed.consensus_round(&[], &[]).await;
+    assert_eq!(list_gateways(&mut fed).await, vec![gateway]);
+}
+
+/// Submits the registration to all guardians, returns whether they accepted it
+async fn register_gateway(fed: &mut FakeFed<Lightning>, gateway: SignedLightningGateway) -> bool {
+    fed.fetch_from_all(|m, db, module_instance_id| {
+        let gateway = gateway.clone();
+        async move {
+            let mut dbtx = db.begin_transaction().await;
+            let result = m
+                .register_gateway(&mut dbtx.with_module_prefix(*module_instance_id), gateway)
+                .await;
+            dbtx.commit_tx().await.expect("DB Error");
+            result.is_ok()
+        }
+    })
+    .await
+}
+
+async fn list_gateways(fed: &mut FakeFed<Lightning>) -> Vec<LightningGateway> {
+    fed.fetch_from_all(|m, db, module_instance_id| async move {
+        m.list_gateways(
+            &mut db
+                .begin_transaction()
+                .await
+                .with_module_prefix(*module_instance_id),
+        )
+        .await
+    })
+    .await
+}
+
+#[test_log::test(tokio::test)]
+async fn test_offer_difficulty_scales_with_load() {
+    const OFFERS_PER_DIFFICULTY_BIT: u64 = 2;
+    const ATTACK_OFFERS: u64 = 16;
+
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move {
+            // Solving the proof of work at the default difficulty for that many offers would take
+            // too long, we only need it to rise with the load
+            let mut cfg: LightningConfig = cfg.to_typed()?;
+            cfg.consensus.offer_rate_limit = OfferRateLimit {
+                proof_of_work_difficulty: 0,
+                offers_per_difficulty_bit: Some(OFFERS_PER_DIFFICULTY_BIT),
+            };
+            Ok(Lightning::new(cfg, db))
+        },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    // New federations demand proof of work that gets harder with the offers of the epoch
+    let client_cfg = fed.client_cfg_typed::<LightningClientConfig>().unwrap();
+    assert_eq!(
+        client_cfg.offer_rate_limit,
+        OfferRateLimit {
+            proof_of_work_difficulty: DEFAULT_OFFER_PROOF_OF_WORK_DIFFICULTY,
+            offers_per_difficulty_bit: Some(DEFAULT_OFFERS_PER_DIFFICULTY_BIT),
+        }
+    );
+
+    let rate_limit = OfferRateLimit {
+        proof_of_work_difficulty: 0,
+        offers_per_difficulty_bit: Some(OFFERS_PER_DIFFICULTY_BIT),
+    };
+    let ctx = secp256k1::Secp256k1::new();
+    let mut new_offer = || {
+        let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+        let preimage = Preimage::from_claim_key(&user_pk);
+        IncomingContractOffer {
+            amount: Amount::from_sats(42),
+            hash: secp256k1::hashes::sha256::Hash::hash(&preimage.0),
+            encrypted_preimage: EncryptedPreimage::new(preimage, &client_cfg.threshold_pub_key),
+            expiry_time: None,
+            hold_key: None,
+            version: IncomingContractVersion::V1,
+            nonce: 0,
+        }
+    };
+
+    // An attacker saturates the epoch with as many offers as it can afford, each one taking more
+    // work than the one before
+    let attack_offers = (0..ATTACK_OFFERS)
+        .map(|epoch_offer_count| {
+            let mut offer = new_offer();
+            offer.solve_proof_of_work(rate_limit.difficulty(epoch_offer_count));
+            offer
+        })
+        .collect::<Vec<_>>();
+    let attack_difficulty = rate_limit.difficulty(ATTACK_OFFERS);
+    assert_eq!(attack_difficulty, 8);
+
+    let mut cheap_offer = new_offer();
+    while cheap_offer.meets_proof_of_work(attack_difficulty) {
+        cheap_offer.nonce += 1;
+    }
+
+    // Offers with the work of an idle epoch are rejected while the attack goes on
+    let (difficulty, cheap_offer_rejected) = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            let mut dbtx = db.begin_transaction().await;
+            let mut module_dbtx = dbtx.with_module_prefix(*module_instance_id);
+            for (out_idx, offer) in attack_offers.iter().enumerate() {
+                let out_point = OutPoint {
+                    txid: sha256::Hash::hash(b"").into(),
+                    out_idx: out_idx as u64,
+                };
+                m.apply_output(
+                    &mut module_dbtx,
+                    &LightningOutput::Offer(offer.clone()),
+                    out_point,
+                )
+                .await
+                .expect("Offer meets the difficulty");
+            }
+            let difficulty = m.offer_difficulty(&mut module_dbtx).await;
+            let cheap_offer_rejected = m
+                .validate_output(
+                    &mut module_dbtx,
+                    &LightningOutput::Offer(cheap_offer.clone()),
+                )
+                .await
+                .is_err();
+            (difficulty, cheap_offer_rejected)
+        })
+        .await;
+    assert_eq!(difficulty, attack_difficulty);
+    assert!(cheap_offer_rejected);
+
+    // An honest offer solved for the difficulty the federation reports still gets in, no matter
+    // how many offers the attacker created before it
+    let mut honest_offer = new_offer();
+    honest_offer.solve_proof_of_work(difficulty);
+    let outputs = attack_offers
+        .iter()
+        .chain([&honest_offer])
+        .enumerate()
+        .map(|(out_idx, offer)| {
+            let out_point = OutPoint {
+                txid: sha256::Hash::hash(b"").into(),
+                out_idx: out_idx as u64,
+            };
+            (out_point, LightningOutput::Offer(offer.clone()))
+        })
+        .collect::<Vec<_>>();
+    fed.consensus_round(&[], &outputs).await;
+    let honest_offer_created = fed
+        .fetch_from_all(|m, db, module_instance_id| async {
+            m.get_offer(
+                &mut db
+                    .begin_transaction()
+                    .await
+                    .with_module_prefix(*module_instance_id),
+                honest_offer.hash,
+            )
+            .await
+            .is_some()
+        })
+        .await;
+    assert!(honest_offer_created);
+
+    // Once the epoch is over the difficulty falls back
+    assert!(
+        fed.verify_output(&LightningOutput::Offer(cheap_offer))
+            .await
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_offer_proof_of_work() {
+    const DIFFICULTY: u8 = 8;
+
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, db| async move {
+            let mut cfg: LightningConfig = cfg.to_typed()?;
+            cfg.consensus.offer_rate_limit = OfferRateLimit {
+                proof_of_work_difficulty: DIFFICULTY,
+                offers_per_difficulty_bit: None,
+            };
+            Ok(Lightning::new(cfg, db))
+        },
+        &ConfigGenParams::new(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let preimage = Preimage::from_claim_key(&user_pk);
+    let mut offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash: secp256k1::hashes::sha256::Hash::hash(&preimage.0),
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage,
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: None,
+        hold_key: None,
+        version: IncomingContractVersion::V1,
+        nonce: 0,
+    };
+    while offer.meets_proof_of_work(DIFFICULTY) {
+        offer.nonce += 1;
+    }
+    assert!(
+        fed.verify_output(&LightningOutput::Offer(offer.clone()))
+            .await
+    );
+
+    offer.solve_proof_of_work(DIFFICULTY);
+    assert!(!fed.verify_output(&LightningOutput::Offer(offer)).await);
+}
+
+#[test]
+fn test_offer_proof_of_work_difficulty_bound() {
+    let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+    let cfg = LightningGen
+        .trusted_dealer_gen(&peers, &ConfigGenParams::new())
+        .remove(&peers[0])
+        .unwrap();
+    let mut cfg: LightningConfig = cfg.to_typed().unwrap();
+
+    cfg.consensus.offer_rate_limit.proof_of_work_difficulty = MAX_OFFER_PROOF_OF_WORK_DIFFICULTY;
+    assert!(cfg.validate_config(&peers[0]).is_ok());
+
+    // Clients would never finish solving the proof of work
+    cfg.consensus.offer_rate_limit.proof_of_work_difficulty =
+        MAX_OFFER_PROOF_OF_WORK_DIFFICULTY + 1;
+    assert!(cfg.validate_config(&peers[0]).is_err());
+
+    // The difficulty can't rise without any offers
+    cfg.consensus.offer_rate_limit.proof_of_work_difficulty = MAX_OFFER_PROOF_OF_WORK_DIFFICULTY;
+    cfg.consensus.offer_rate_limit.offers_per_difficulty_bit = Some(0);
+    assert!(cfg.validate_config(&peers[0]).is_err());
+}
@@ -1,4 +1,6 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::bail;
 use anyhow::format_err;
@@ -10,13 +12,39 @@ use fedimint_api::core::ModuleKind;
 use fedimint_api::encoding::Encodable;
 use fedimint_api::module::__reexports::serde_json;
 use fedimint_api::{Feerate, PeerId};
-use miniscript::descriptor::Wsh;
+use miniscript::descriptor::{TapTree, Wsh};
+use miniscript::miniscript::decode::Terminal;
+use miniscript::{Descriptor, Miniscript};
 use secp256k1::SecretKey;
 use serde::{Deserialize, Serialize};
 
+use crate::frost::{FrostKey, FrostKeyShare};
 use crate::keys::CompressedPublicKey;
 use crate::PegInDescriptor;
 
+/// Script type of the federation's peg-in descriptor
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PegInDescriptorType {
+    /// P2WSH `sortedmulti` of all guardian keys
+    #[default]
+    Wsh,
+    /// P2TR with a FROST threshold key as internal key, so peg-outs are signed via the key path,
+    /// and a threshold `multi_a` script path as fallback if key path signing fails
+    Tr,
+}
+
+impl FromStr for PegInDescriptorType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wsh" => Ok(PegInDescriptorType::Wsh),
+            "tr" => Ok(PegInDescriptorType::Tr),
+            _ => bail!("Unknown peg-in descriptor type {s}, expected wsh or tr"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalletConfig {
     /// Contains all configuration that is locally configurable and not secret
@@ -34,6 +62,9 @@ pub struct WalletConfigLocal;
 pub struct WalletConfigPrivate {
     /// Secret key for signing bitcoin multisig transactions
     pub peg_in_key: SecretKey,
+    /// Our share of the FROST key that spends taproot peg-ins via the key path
+    #[serde(default)]
+    pub frost_key_share: Option<SecretKey>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Encodable)]
@@ -50,6 +81,10 @@ pub struct WalletConfigConsensus {
     pub default_fee: Feerate,
     /// Fees for bitcoin transactions
     pub fee_consensus: FeeConsensus,
+    /// FROST key that spends taproot peg-ins via the key path, `None` if peg-outs can't be
+    /// signed via the key path
+    #[serde(default)]
+    pub frost_key: Option<FrostKey>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
@@ -73,6 +108,18 @@ impl TypedClientModuleConfig for WalletClientConfig {
 pub struct FeeConsensus {
     pub peg_in_abs: fedimint_api::Amount,
     pub peg_out_abs: fedimint_api::Amount,
+    /// Fee for registering a peg-in script, has to be non-zero since every registration makes the
+    /// guardians watch another script until it expires
+    #[serde(default = "default_peg_in_registration_fee")]
+    pub peg_in_registration_abs: fedimint_api::Amount,
+}
+
+/// Default fee for registering a peg-in script
+pub const DEFAULT_PEG_IN_REGISTRATION_FEE: fedimint_api::Amount =
+    fedimint_api::Amount::from_sats(100);
+
+fn default_peg_in_registration_fee() -> fedimint_api::Amount {
+    DEFAULT_PEG_IN_REGISTRATION_FEE
 }
 
 impl Default for FeeConsensus {
@@ -80,6 +127,7 @@ impl Default for FeeConsensus {
         Self {
             peg_in_abs: fedimint_api::Amount::ZERO,
             peg_out_abs: fedimint_api::Amount::ZERO,
+            peg_in_registration_abs: DEFAULT_PEG_IN_REGISTRATION_FEE,
         }
     }
 }
@@ -129,25 +177,59 @@ impl TypedServerModuleConfig for WalletConfig {
             bail!(" Bitcoin wallet private key doesn't match multisig pubkey");
         }
 
+        match (&self.private.frost_key_share, &self.consensus.frost_key) {
+            (None, None) => {}
+            (Some(share), Some(key)) => {
+                if key.verification_share(*identity)
+                    != Some(secp256k1::PublicKey::from_secret_key_global(share))
+                {
+                    bail!("FROST key share doesn't match its public key");
+                }
+            }
+            _ => bail!("FROST key share and public key have to be configured together"),
+        }
+
         Ok(())
     }
 }
 
 impl WalletConfig {
+    /// Creates a config with a P2WSH peg-in descriptor, or a taproot one if a `frost_key` share
+    /// is given, which is then the internal key
     pub fn new(
         pubkeys: BTreeMap<PeerId, CompressedPublicKey>,
         sk: SecretKey,
         threshold: usize,
         network: Network,
         finality_delay: u32,
+        frost_key: Option<FrostKeyShare>,
     ) -> Self {
-        let peg_in_descriptor = PegInDescriptor::Wsh(
-            Wsh::new_sortedmulti(threshold, pubkeys.values().copied().collect()).unwrap(),
-        );
+        let peg_in_descriptor = match &frost_key {
+            None => PegInDescriptor::Wsh(
+                Wsh::new_sortedmulti(threshold, pubkeys.values().copied().collect()).unwrap(),
+            ),
+            Some(frost_key) => {
+                let keys = pubkeys.values().copied().collect::<Vec<_>>();
+                let script_path = Miniscript::from_ast(Terminal::MultiA(threshold, keys)).unwrap();
+                Descriptor::new_tr(
+                    CompressedPublicKey::new(frost_key.key.group_key()),
+                    Some(TapTree::Leaf(Arc::new(script_path))),
+                )
+                .unwrap()
+            }
+        };
+
+        let (frost_key_share, frost_key) = match frost_key {
+            Some(FrostKeyShare { secret_share, key }) => (Some(secret_share), Some(key)),
+            None => (None, None),
+        };
 
         Self {
             local: WalletConfigLocal,
-            private: WalletConfigPrivate { peg_in_key: sk },
+            private: WalletConfigPrivate {
+                peg_in_key: sk,
+                frost_key_share,
+            },
             consensus: WalletConfigConsensus {
                 network,
                 peg_in_descriptor,
@@ -155,6 +237,7 @@ impl WalletConfig {
                 finality_delay,
                 default_fee: Feerate { sats_per_kvb: 1000 },
                 fee_consensus: Default::default(),
+                frost_key,
             },
         }
     }
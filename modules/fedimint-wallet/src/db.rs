@@ -1,12 +1,15 @@
-use bitcoin::{BlockHash, Txid};
+use bitcoin::{BlockHash, Script, Txid};
 use fedimint_api::db::DatabaseKeyPrefixConst;
 use fedimint_api::encoding::{Decodable, Encodable};
-use secp256k1::ecdsa::Signature;
+use fedimint_api::PeerId;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::frost::{NonceCommitment, SigningNonces};
+use crate::txoproof::PegInProof;
 use crate::{
-    PendingTransaction, RoundConsensus, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome,
+    PegOutInputSignature, PegOutSigningSession, PendingTransaction, RoundConsensus, SpendableUTXO,
+    UnsignedTransaction, WalletOutputOutcome, WatchedPegInScript,
 };
 
 #[repr(u8)]
@@ -19,6 +22,13 @@ pub enum DbKeyPrefix {
     PendingTransaction = 0x35,
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
+    PegInRegistration = 0x38,
+    RegisteredPegIn = 0x39,
+    PegInRegistrationOutPoint = 0x3a,
+    PegOutNonceCi = 0x3b,
+    PegOutSecretNonces = 0x3c,
+    PegOutNonces = 0x3d,
+    PegOutSigningSession = 0x3e,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -114,7 +124,7 @@ pub struct PegOutTxSignatureCI(pub Txid);
 impl DatabaseKeyPrefixConst for PegOutTxSignatureCI {
     const DB_PREFIX: u8 = DbKeyPrefix::PegOutTxSigCi as u8;
     type Key = Self;
-    type Value = Vec<Signature>; // TODO: define newtype
+    type Value = Vec<PegOutInputSignature>; // TODO: define newtype
 }
 
 #[derive(Clone, Debug, Encodable, Decodable)]
@@ -123,7 +133,7 @@ pub struct PegOutTxSignatureCIPrefix;
 impl DatabaseKeyPrefixConst for PegOutTxSignatureCIPrefix {
     const DB_PREFIX: u8 = DbKeyPrefix::PegOutTxSigCi as u8;
     type Key = PegOutTxSignatureCI;
-    type Value = Vec<Signature>;
+    type Value = Vec<PegOutInputSignature>;
 }
 
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
@@ -143,3 +153,147 @@ impl DatabaseKeyPrefixConst for PegOutBitcoinTransactionPrefix {
     type Key = PegOutBitcoinTransaction;
     type Value = WalletOutputOutcome;
 }
+
+/// Peg-in script registered by a client that is watched until the registration expires
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegInRegistrationKey(pub Script);
+
+impl DatabaseKeyPrefixConst for PegInRegistrationKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegInRegistration as u8;
+    type Key = Self;
+    type Value = WatchedPegInScript;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegInRegistrationPrefixKey;
+
+impl DatabaseKeyPrefixConst for PegInRegistrationPrefixKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegInRegistration as u8;
+    type Key = PegInRegistrationKey;
+    type Value = WatchedPegInScript;
+}
+
+/// Confirmed deposit to a registered peg-in script that has not been claimed yet
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct RegisteredPegInKey(pub bitcoin::OutPoint);
+
+impl DatabaseKeyPrefixConst for RegisteredPegInKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::RegisteredPegIn as u8;
+    type Key = Self;
+    type Value = PegInProof;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct RegisteredPegInPrefixKey;
+
+impl DatabaseKeyPrefixConst for RegisteredPegInPrefixKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::RegisteredPegIn as u8;
+    type Key = RegisteredPegInKey;
+    type Value = PegInProof;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegInRegistrationOutPoint(pub fedimint_api::OutPoint);
+
+impl DatabaseKeyPrefixConst for PegInRegistrationOutPoint {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegInRegistrationOutPoint as u8;
+    type Key = Self;
+    type Value = WalletOutputOutcome;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegInRegistrationOutPointPrefix;
+
+impl DatabaseKeyPrefixConst for PegInRegistrationOutPointPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegInRegistrationOutPoint as u8;
+    type Key = PegInRegistrationOutPoint;
+    type Value = WalletOutputOutcome;
+}
+
+/// Our nonce commitments for signing a taproot peg-out via the key path, proposed until the
+/// signing set is fixed
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutNonceCI(pub Txid);
+
+impl DatabaseKeyPrefixConst for PegOutNonceCI {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutNonceCi as u8;
+    type Key = Self;
+    type Value = Vec<NonceCommitment>;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutNonceCIPrefix;
+
+impl DatabaseKeyPrefixConst for PegOutNonceCIPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutNonceCi as u8;
+    type Key = PegOutNonceCI;
+    type Value = Vec<NonceCommitment>;
+}
+
+/// Our secret nonces matching [`PegOutNonceCI`], deleted as soon as they were used
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutSecretNoncesKey(pub Txid);
+
+impl DatabaseKeyPrefixConst for PegOutSecretNoncesKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutSecretNonces as u8;
+    type Key = Self;
+    type Value = Vec<SigningNonces>;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutSecretNoncesPrefix;
+
+impl DatabaseKeyPrefixConst for PegOutSecretNoncesPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutSecretNonces as u8;
+    type Key = PegOutSecretNoncesKey;
+    type Value = Vec<SigningNonces>;
+}
+
+/// Nonce commitments a peer submitted for signing the inputs of a taproot peg-out
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutNonceKey {
+    pub txid: Txid,
+    pub peer: PeerId,
+}
+
+impl DatabaseKeyPrefixConst for PegOutNonceKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutNonces as u8;
+    type Key = Self;
+    type Value = Vec<NonceCommitment>;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutNonceTxPrefix(pub Txid);
+
+impl DatabaseKeyPrefixConst for PegOutNonceTxPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutNonces as u8;
+    type Key = PegOutNonceKey;
+    type Value = Vec<NonceCommitment>;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutNoncePrefix;
+
+impl DatabaseKeyPrefixConst for PegOutNoncePrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutNonces as u8;
+    type Key = PegOutNonceKey;
+    type Value = Vec<NonceCommitment>;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutSigningSessionKey(pub Txid);
+
+impl DatabaseKeyPrefixConst for PegOutSigningSessionKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutSigningSession as u8;
+    type Key = Self;
+    type Value = PegOutSigningSession;
+}
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutSigningSessionPrefix;
+
+impl DatabaseKeyPrefixConst for PegOutSigningSessionPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PegOutSigningSession as u8;
+    type Key = PegOutSigningSessionKey;
+    type Value = PegOutSigningSession;
+}
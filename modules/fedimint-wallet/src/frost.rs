@@ -0,0 +1,640 @@
+//! FROST threshold Schnorr signatures over secp256k1, used to spend taproot peg-ins via the key path
+//! with signatures from only `threshold` peers.
+//!
+//! The protocol itself is implemented by [`schnorr_fun::frost`], this module maps its party indices
+//! to [`PeerId`]s and its types to the secp256k1 ones used in the wallet's config, database and
+//! consensus items. Keys are either dealt by a trusted dealer or generated by the library's DKG, in
+//! which every peer deals shares of a random polynomial and proves possession of its constant term.
+//! A dealer whose share doesn't match its commitment or whose proof is invalid aborts the DKG and is
+//! named in the resulting [`DkgError`].
+
+use std::collections::BTreeMap;
+
+use bitcoin::hashes::Hash as BitcoinHash;
+use bitcoin::util::taproot::{TapBranchHash, TapTweakHash};
+use fedimint_api::encoding::{Decodable, Encodable, SerdeEncodable};
+use fedimint_api::PeerId;
+use rand::rngs::ThreadRng;
+use rand::{CryptoRng, RngCore};
+use schnorr_fun::binonce::{Nonce, NonceKeyPair};
+use schnorr_fun::frost::{self, FinishKeyGenError, Frost, NewKeyGenError, SignSession};
+use schnorr_fun::fun::marker::{EvenY, Normal, Public, Secret, Zero};
+use schnorr_fun::fun::{Point, Scalar};
+use schnorr_fun::nonce::{GlobalRng, Synthetic};
+use schnorr_fun::{Message, Signature};
+use secp256k1::{schnorr, PublicKey, Secp256k1, SecretKey, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::tweakable::{tweak_scalar, Contract};
+
+/// Proofs of possession are signed with synthetic nonces, so they don't depend on the dealt
+/// polynomial alone
+type FrostContext = Frost<Sha256, Synthetic<Sha256, GlobalRng<ThreadRng>>>;
+
+fn frost_context() -> FrostContext {
+    frost::new_with_synthetic_nonces::<Sha256, ThreadRng>()
+}
+
+/// FROST group key together with the verification shares of all peers' key shares
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FrostKey(frost::FrostKey<Normal>);
+
+impl FrostKey {
+    /// Public key of the group secret nobody knows
+    pub fn group_key(&self) -> PublicKey {
+        to_public_key(&self.0.public_key())
+    }
+
+    /// Public key of the key share of `peer`, `None` if `peer` doesn't hold a share
+    pub fn verification_share(&self, peer: PeerId) -> Option<PublicKey> {
+        self.0
+            .verification_shares()
+            .nth(peer.to_usize())
+            .and_then(|share| PublicKey::from_slice(&share.to_bytes()).ok())
+    }
+}
+
+impl Encodable for FrostKey {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        SerdeEncodable(&self.0).consensus_encode(writer)
+    }
+}
+
+/// A peer's share of a [`FrostKey`]
+#[derive(Clone, Debug)]
+pub struct FrostKeyShare {
+    pub secret_share: SecretKey,
+    pub key: FrostKey,
+}
+
+#[derive(Debug, Error)]
+pub enum DkgError {
+    #[error("FROST peers have to be numbered consecutively starting at 0")]
+    NonConsecutivePeers,
+    #[error("FROST threshold is larger than the number of peers")]
+    NotEnoughPeers,
+    #[error("FROST commitment of {0} has the wrong number of coefficients")]
+    InvalidCommitment(PeerId),
+    #[error("FROST group key is zero, a dealer is malicious")]
+    ZeroGroupKey,
+    #[error("Missing FROST dealing from {0}")]
+    MissingDealing(PeerId),
+    #[error("FROST share dealt by {0} doesn't match its commitment")]
+    InvalidShare(PeerId),
+    #[error("Invalid FROST proof of possession from {0}")]
+    InvalidProofOfPossession(PeerId),
+}
+
+/// Deals FROST key shares to `peers`, which have to be numbered consecutively starting at 0
+pub fn deal<R: RngCore + CryptoRng>(
+    peers: &[PeerId],
+    threshold: usize,
+    rng: &mut R,
+) -> BTreeMap<PeerId, FrostKeyShare> {
+    assert!(
+        are_consecutive(peers.iter()),
+        "FROST peers have to be numbered consecutively starting at 0"
+    );
+    let (key, secret_shares) = frost_context().simulate_keygen(threshold, peers.len(), rng);
+    let key = FrostKey(key);
+
+    peers
+        .iter()
+        .zip(secret_shares)
+        .map(|(peer, secret_share)| {
+            let share = FrostKeyShare {
+                secret_share: to_secret_key(&secret_share),
+                key: key.clone(),
+            };
+            (*peer, share)
+        })
+        .collect()
+}
+
+/// Secret polynomial a peer deals shares of in the DKG, its constant term is the peer's
+/// contribution to the group secret
+pub struct SecretPolynomial(Vec<Scalar>);
+
+impl SecretPolynomial {
+    /// Random polynomial of degree `threshold - 1`
+    pub fn random<R: RngCore + CryptoRng>(threshold: usize, rng: &mut R) -> Self {
+        SecretPolynomial(frost::generate_scalar_poly(threshold, rng))
+    }
+
+    /// Public commitment to all coefficients, sent to all peers in the first round of the DKG
+    pub fn commitment(&self) -> Vec<PublicKey> {
+        frost::to_point_poly(&self.0)
+            .iter()
+            .map(to_public_key)
+            .collect()
+    }
+}
+
+/// A DKG session, determined by the commitments of all peers
+pub struct KeyGen(frost::KeyGen);
+
+impl KeyGen {
+    pub fn new(
+        commitments: &BTreeMap<PeerId, Vec<PublicKey>>,
+        threshold: usize,
+    ) -> Result<Self, DkgError> {
+        if !are_consecutive(commitments.keys()) {
+            return Err(DkgError::NonConsecutivePeers);
+        }
+        if let Some((peer, _)) = commitments
+            .iter()
+            .find(|(_, commitment)| commitment.len() != threshold)
+        {
+            return Err(DkgError::InvalidCommitment(*peer));
+        }
+
+        let point_polys = commitments
+            .values()
+            .map(|commitment| commitment.iter().map(to_point).collect())
+            .collect();
+        let keygen = frost_context()
+            .new_keygen(point_polys)
+            .map_err(|error| match error {
+                NewKeyGenError::ZeroFrostKey => DkgError::ZeroGroupKey,
+                NewKeyGenError::NotEnoughParties | NewKeyGenError::PolyDifferentLength(_) => {
+                    DkgError::NotEnoughPeers
+                }
+            })?;
+
+        Ok(KeyGen(keygen))
+    }
+
+    /// Our shares for every peer and a proof of possession of the polynomial's constant term that
+    /// is bound to this session, shares must only be sent to the peer they are dealt to
+    pub fn deal(
+        &self,
+        polynomial: SecretPolynomial,
+    ) -> (BTreeMap<PeerId, SecretKey>, schnorr::Signature) {
+        let (shares, proof) = frost_context().create_shares(&self.0, polynomial.0);
+        let shares = shares
+            .iter()
+            .enumerate()
+            .map(|(idx, share)| (peer_id(idx), to_secret_key(share)))
+            .collect();
+
+        (shares, to_schnorr_signature(&proof))
+    }
+
+    /// Verifies the shares dealt to us against their dealers' commitments and the dealers' proofs
+    /// of possession and combines the shares into our key share
+    pub fn finish(
+        self,
+        our_id: PeerId,
+        dealings: &BTreeMap<PeerId, (SecretKey, schnorr::Signature)>,
+    ) -> Result<FrostKeyShare, DkgError> {
+        let mut shares = vec![];
+        let mut proofs = vec![];
+        for idx in 0..self.0.n_parties() {
+            let dealer = peer_id(idx);
+            let (share, proof) = dealings
+                .get(&dealer)
+                .ok_or(DkgError::MissingDealing(dealer))?;
+            shares.push(
+                Scalar::<Secret, Zero>::from_bytes(share.secret_bytes())
+                    .expect("secret keys are valid scalars"),
+            );
+            proofs.push(
+                Signature::from_bytes(schnorr_signature_bytes(proof))
+                    .ok_or(DkgError::InvalidProofOfPossession(dealer))?,
+            );
+        }
+
+        let (secret_share, key) = frost_context()
+            .finish_keygen(self.0, our_id.to_usize(), shares, proofs)
+            .map_err(|error| match error {
+                FinishKeyGenError::InvalidShare(idx) => DkgError::InvalidShare(peer_id(idx)),
+                FinishKeyGenError::InvalidProofOfPossession(idx) => {
+                    DkgError::InvalidProofOfPossession(peer_id(idx))
+                }
+            })?;
+
+        Ok(FrostKeyShare {
+            secret_share: to_secret_key(&secret_share),
+            key: FrostKey(key),
+        })
+    }
+}
+
+/// Secret nonces of a single signing session, must never be used twice
+#[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SigningNonces {
+    hiding: [u8; 32],
+    binding: [u8; 32],
+}
+
+/// Public commitment to [`SigningNonces`] that is shared before signing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct NonceCommitment {
+    pub hiding: PublicKey,
+    pub binding: PublicKey,
+}
+
+impl SigningNonces {
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let nonces = NonceKeyPair::random(rng);
+        SigningNonces {
+            hiding: nonces.secret[0].to_bytes(),
+            binding: nonces.secret[1].to_bytes(),
+        }
+    }
+
+    pub fn commitment(&self) -> NonceCommitment {
+        let [hiding, binding] = self.key_pair().public().0;
+        NonceCommitment {
+            hiding: to_public_key(&hiding),
+            binding: to_public_key(&binding),
+        }
+    }
+
+    fn key_pair(&self) -> NonceKeyPair {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.hiding);
+        bytes[32..].copy_from_slice(&self.binding);
+        NonceKeyPair::from_bytes(bytes).expect("generated from non-zero scalars")
+    }
+}
+
+impl NonceCommitment {
+    fn nonce(&self) -> Nonce {
+        Nonce([to_point(&self.hiding), to_point(&self.binding)])
+    }
+}
+
+/// Taproot output key derived from a FROST key, the group key is first tweaked with a contract
+/// (like [`crate::tweakable::Tweakable`] does) to get the internal key, which is then tweaked with
+/// the script tree's merkle root
+#[derive(Clone, Debug)]
+pub struct TweakedKey {
+    /// Key signatures verify against
+    pub output_key: XOnlyPublicKey,
+    key: frost::FrostKey<EvenY>,
+}
+
+impl TweakedKey {
+    pub fn new<Ctr: Contract>(
+        key: &FrostKey,
+        contract: &Ctr,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Self {
+        let contract_tweak = tweak_scalar(&key.group_key(), contract).to_be_bytes();
+        let internal_key = key
+            .0
+            .clone()
+            .tweak(to_scalar(contract_tweak))
+            .expect("negligible probability")
+            .into_xonly_key();
+
+        let tap_tweak =
+            TapTweakHash::from_key_and_tweak(to_x_only(&internal_key.public_key()), merkle_root);
+        let key = internal_key
+            .tweak(to_scalar(tap_tweak.into_inner()))
+            .expect("negligible probability");
+
+        TweakedKey {
+            output_key: to_x_only(&key.public_key()),
+            key,
+        }
+    }
+}
+
+/// Everything signers agree on before signing a message: the key, the message and the nonce
+/// commitments of the signers, which also determine who the signers are
+pub struct SigningPackage {
+    key: TweakedKey,
+    message: [u8; 32],
+    session: SignSession,
+}
+
+impl SigningPackage {
+    /// # Panics
+    /// If there are fewer `commitments` than the key's threshold
+    pub fn new(
+        key: TweakedKey,
+        message: [u8; 32],
+        commitments: &BTreeMap<PeerId, NonceCommitment>,
+    ) -> Self {
+        let nonces = commitments
+            .iter()
+            .map(|(peer, commitment)| (peer.to_usize(), commitment.nonce()))
+            .collect();
+        let session =
+            frost_context().start_sign_session(&key.key, nonces, Message::<Public>::raw(&message));
+
+        SigningPackage {
+            key,
+            message,
+            session,
+        }
+    }
+
+    /// Creates our signature share, `nonces` have to match our commitment in the package
+    pub fn sign(&self, peer: PeerId, key_share: &SecretKey, nonces: &SigningNonces) -> [u8; 32] {
+        let key_share = Scalar::<Secret, Zero>::from_bytes(key_share.secret_bytes())
+            .and_then(|scalar| scalar.non_zero())
+            .expect("secret keys are valid non-zero scalars");
+
+        frost_context()
+            .sign(
+                &self.key.key,
+                &self.session,
+                peer.to_usize(),
+                &key_share,
+                nonces.key_pair(),
+            )
+            .to_bytes()
+    }
+
+    /// Verifies the signature `share` of `peer` against the verification share of its key share
+    pub fn verify_share(&self, peer: PeerId, share: &[u8; 32]) -> bool {
+        let idx = peer.to_usize();
+        if !self.session.participants().any(|signer| signer == idx) {
+            return false;
+        }
+        let Some(share) = Scalar::<Public, Zero>::from_bytes(*share) else {
+            return false;
+        };
+
+        frost_context().verify_signature_share(&self.key.key, &self.session, idx, share)
+    }
+
+    /// Aggregates the verified shares of all signers into a BIP-340 signature for the output key
+    pub fn aggregate<'a>(
+        &self,
+        shares: impl IntoIterator<Item = &'a [u8; 32]>,
+    ) -> schnorr::Signature {
+        let shares = shares
+            .into_iter()
+            .map(|share| Scalar::<Public, Zero>::from_bytes(*share).expect("shares are verified"))
+            .collect();
+        let signature =
+            frost_context().combine_signature_shares(&self.key.key, &self.session, shares);
+
+        to_schnorr_signature(&signature)
+    }
+
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        signature: &schnorr::Signature,
+    ) -> bool {
+        secp.verify_schnorr(
+            signature,
+            &secp256k1::Message::from_slice(&self.message).expect("message is 32 bytes"),
+            &self.key.output_key,
+        )
+        .is_ok()
+    }
+}
+
+/// The library evaluates the share of the `idx`-th party at `idx + 1`, so peers have to be
+/// numbered consecutively starting at 0 for party indices to match their ids
+fn are_consecutive<'a>(peers: impl Iterator<Item = &'a PeerId>) -> bool {
+    peers.enumerate().all(|(idx, peer)| peer.to_usize() == idx)
+}
+
+fn peer_id(idx: usize) -> PeerId {
+    PeerId::from(u16::try_from(idx).expect("fewer than 2^16 peers"))
+}
+
+fn to_point(key: &PublicKey) -> Point {
+    Point::from_bytes(key.serialize()).expect("public keys are valid points")
+}
+
+fn to_public_key<T: schnorr_fun::fun::marker::Normalized>(point: &Point<T>) -> PublicKey {
+    PublicKey::from_slice(&point.to_bytes()).expect("points are valid public keys")
+}
+
+fn to_x_only<T: schnorr_fun::fun::marker::Normalized>(point: &Point<T>) -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&point.to_xonly_bytes()).expect("points are valid public keys")
+}
+
+fn to_scalar(bytes: [u8; 32]) -> Scalar<Public, Zero> {
+    Scalar::from_bytes(bytes).expect("negligible probability")
+}
+
+fn to_secret_key<Z>(scalar: &Scalar<Secret, Z>) -> SecretKey {
+    SecretKey::from_slice(&scalar.to_bytes()).expect("negligible probability")
+}
+
+fn to_schnorr_signature(signature: &Signature) -> schnorr::Signature {
+    schnorr::Signature::from_slice(&signature.to_bytes()).expect("signature is 64 bytes")
+}
+
+fn schnorr_signature_bytes(signature: &schnorr::Signature) -> [u8; 64] {
+    let mut bytes = [0u8; 64];
+    bytes.copy_from_slice(&signature[..]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin::hashes::Hash as BitcoinHash;
+    use bitcoin::util::taproot::TapBranchHash;
+    use fedimint_api::PeerId;
+    use rand::rngs::OsRng;
+    use secp256k1::{schnorr, PublicKey, Secp256k1, SecretKey};
+
+    use super::{
+        deal, DkgError, FrostKeyShare, KeyGen, SecretPolynomial, SigningNonces, SigningPackage,
+        TweakedKey,
+    };
+
+    fn sign(
+        keys: &BTreeMap<PeerId, FrostKeyShare>,
+        signers: &[PeerId],
+        contract: &[u8; 32],
+        merkle_root: Option<TapBranchHash>,
+    ) -> bool {
+        let secp = Secp256k1::new();
+        let key = TweakedKey::new(&keys.values().next().unwrap().key, contract, merkle_root);
+
+        let nonces = signers
+            .iter()
+            .map(|peer| (*peer, SigningNonces::new(&mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let commitments = nonces
+            .iter()
+            .map(|(peer, nonces)| (*peer, nonces.commitment()))
+            .collect();
+        let package = SigningPackage::new(key, [7; 32], &commitments);
+
+        let shares = signers
+            .iter()
+            .map(|peer| package.sign(*peer, &keys[peer].secret_share, &nonces[peer]))
+            .collect::<Vec<_>>();
+        for (peer, share) in signers.iter().zip(&shares) {
+            assert!(package.verify_share(*peer, share));
+        }
+
+        package.verify(&secp, &package.aggregate(&shares))
+    }
+
+    /// Runs the DKG among `peers`, `tamper` may modify the dealings each peer receives
+    fn run_dkg(
+        peers: &[PeerId],
+        tamper: impl Fn(PeerId, &mut BTreeMap<PeerId, (SecretKey, schnorr::Signature)>),
+    ) -> BTreeMap<PeerId, Result<FrostKeyShare, DkgError>> {
+        let polynomials = peers
+            .iter()
+            .map(|peer| (*peer, SecretPolynomial::random(3, &mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let commitments = polynomials
+            .iter()
+            .map(|(peer, polynomial)| (*peer, polynomial.commitment()))
+            .collect::<BTreeMap<_, _>>();
+
+        let dealings = polynomials
+            .into_iter()
+            .map(|(dealer, polynomial)| {
+                let keygen = KeyGen::new(&commitments, 3).unwrap();
+                (dealer, keygen.deal(polynomial))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        peers
+            .iter()
+            .map(|our_id| {
+                let mut received = dealings
+                    .iter()
+                    .map(|(dealer, (shares, proof))| (*dealer, (shares[our_id], *proof)))
+                    .collect();
+                tamper(*our_id, &mut received);
+                let keygen = KeyGen::new(&commitments, 3).unwrap();
+                (*our_id, keygen.finish(*our_id, &received))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn threshold_of_dealt_shares_signs_for_tweaked_key() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let keys = deal(&peers, 3, &mut OsRng);
+
+        // Random contracts cover all parity combinations of the internal and output keys
+        for contract in 0..8u8 {
+            let merkle_root = (contract % 2 == 0).then(|| TapBranchHash::hash(&[contract]));
+            assert!(sign(&keys, &peers[..3], &[contract; 32], merkle_root));
+            assert!(sign(&keys, &peers[1..], &[contract; 32], merkle_root));
+            assert!(sign(&keys, &peers, &[contract; 32], merkle_root));
+        }
+    }
+
+    #[test]
+    fn dkg_shares_sign_for_combined_group_key() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let keys = run_dkg(&peers, |_, _| {})
+            .into_iter()
+            .map(|(peer, key)| (peer, key.unwrap()))
+            .collect::<BTreeMap<_, _>>();
+
+        let secp = Secp256k1::new();
+        let group_key = &keys[&peers[0]].key;
+        for (peer, key) in &keys {
+            assert_eq!(&key.key, group_key);
+            assert_eq!(
+                group_key.verification_share(*peer),
+                Some(PublicKey::from_secret_key(&secp, &key.secret_share))
+            );
+        }
+
+        assert!(sign(&keys, &peers[..3], &[1; 32], None));
+        assert!(sign(&keys, &peers[1..], &[2; 32], None));
+    }
+
+    #[test]
+    fn dkg_names_dealer_of_share_not_matching_commitment() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let results = run_dkg(&peers, |our_id, received| {
+            if our_id == peers[0] {
+                received.get_mut(&peers[2]).unwrap().0 = SecretKey::new(&mut OsRng);
+            }
+        });
+
+        assert!(matches!(
+            results[&peers[0]],
+            Err(DkgError::InvalidShare(dealer)) if dealer == peers[2]
+        ));
+        assert!(results[&peers[1]].is_ok());
+    }
+
+    #[test]
+    fn dkg_names_dealer_of_invalid_proof_of_possession() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        // A dealer can't reuse another dealer's proof to claim its polynomial
+        let results = run_dkg(&peers, |_, received| {
+            let proof = received[&peers[0]].1;
+            received.get_mut(&peers[3]).unwrap().1 = proof;
+        });
+
+        for result in results.values() {
+            assert!(matches!(
+                result,
+                Err(DkgError::InvalidProofOfPossession(dealer)) if *dealer == peers[3]
+            ));
+        }
+    }
+
+    #[test]
+    fn dkg_rejects_malformed_commitments() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let mut commitments = peers
+            .iter()
+            .map(|peer| (*peer, SecretPolynomial::random(3, &mut OsRng).commitment()))
+            .collect::<BTreeMap<_, _>>();
+
+        let short = SecretPolynomial::random(2, &mut OsRng).commitment();
+        let honest = commitments.insert(peers[1], short).unwrap();
+        assert!(matches!(
+            KeyGen::new(&commitments, 3),
+            Err(DkgError::InvalidCommitment(dealer)) if dealer == peers[1]
+        ));
+
+        commitments.insert(peers[1], honest);
+        let commitment = commitments.remove(&peers[2]).unwrap();
+        commitments.insert(PeerId::from(7), commitment);
+        assert!(matches!(
+            KeyGen::new(&commitments, 3),
+            Err(DkgError::NonConsecutivePeers)
+        ));
+    }
+
+    #[test]
+    fn faulty_signature_share_is_detected() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let keys = deal(&peers, 3, &mut OsRng);
+        let key = TweakedKey::new(&keys[&peers[0]].key, &[3; 32], None);
+
+        let nonces = peers[..3]
+            .iter()
+            .map(|peer| (*peer, SigningNonces::new(&mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let commitments = nonces
+            .iter()
+            .map(|(peer, nonces)| (*peer, nonces.commitment()))
+            .collect();
+        let package = SigningPackage::new(key, [7; 32], &commitments);
+
+        // Signing with another peer's key share or with the wrong nonces produces invalid shares
+        let wrong_key = package.sign(peers[0], &keys[&peers[1]].secret_share, &nonces[&peers[0]]);
+        let wrong_nonces =
+            package.sign(peers[0], &keys[&peers[0]].secret_share, &nonces[&peers[1]]);
+        assert!(!package.verify_share(peers[0], &wrong_key));
+        assert!(!package.verify_share(peers[0], &wrong_nonces));
+        assert!(!package.verify_share(peers[0], &[0xff; 32]));
+
+        // Peers that didn't commit to nonces can't contribute shares
+        let outsider = package.sign(peers[0], &keys[&peers[0]].secret_share, &nonces[&peers[0]]);
+        assert!(package.verify_share(peers[0], &outsider));
+        assert!(!package.verify_share(peers[3], &outsider));
+    }
+}
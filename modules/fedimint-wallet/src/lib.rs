@@ -11,19 +11,21 @@ use async_trait::async_trait;
 use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine};
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
+use bitcoin::util::merkleblock::PartialMerkleTree;
 use bitcoin::util::psbt::raw::ProprietaryKey;
 use bitcoin::util::psbt::{Input, PartiallySignedTransaction};
-use bitcoin::util::sighash::SighashCache;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
 use bitcoin::{
-    Address, AddressType, Amount, BlockHash, EcdsaSig, EcdsaSighashType, Network, Script,
-    Transaction, TxIn, TxOut, Txid,
+    Address, AddressType, Amount, Block, BlockHash, EcdsaSig, EcdsaSighashType, Network,
+    SchnorrSig, SchnorrSighashType, Script, Transaction, TxIn, TxOut, Txid,
 };
 use bitcoin::{PackedLockTime, Sequence};
 use config::WalletConfigConsensus;
 use db::DbKeyPrefix;
 use fedimint_api::bitcoin_rpc::{
     select_bitcoin_backend_from_envs, BitcoinRpcBackendType, FM_BITCOIND_RPC_ENV,
-    FM_ELECTRUM_RPC_ENV,
+    FM_BITCOIN_RPC_QUORUM_ENV, FM_ELECTRUM_RPC_ENV, FM_ESPLORA_RPC_ENV,
 };
 use fedimint_api::cancellable::{Cancellable, Cancelled};
 use fedimint_api::config::{
@@ -49,34 +51,42 @@ use fedimint_api::{
     plugin_types_trait_impl, push_db_key_items, push_db_pair_items, Feerate, NumPeers, OutPoint,
     PeerId, ServerModule,
 };
-use fedimint_bitcoind::DynBitcoindRpc;
+use fedimint_bitcoind::{BitcoinRpcHealth, DynBitcoindRpc};
 use futures::{stream, StreamExt};
 use impl_tools::autoimpl;
 use miniscript::psbt::PsbtExt;
 use miniscript::{Descriptor, TranslatePk};
 use rand::rngs::OsRng;
 use rand::Rng;
-use secp256k1::{Message, Scalar};
+use secp256k1::{KeyPair, Message, Scalar};
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use thiserror::Error;
 use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::common::WalletDecoder;
-use crate::config::{WalletClientConfig, WalletConfig};
+use crate::config::{PegInDescriptorType, WalletClientConfig, WalletConfig};
 use crate::db::{
-    BlockHashKey, BlockHashKeyPrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
-    PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingTransactionKey,
-    PendingTransactionPrefixKey, RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
-    UnsignedTransactionPrefixKey,
+    BlockHashKey, BlockHashKeyPrefix, PegInRegistrationKey, PegInRegistrationOutPoint,
+    PegInRegistrationOutPointPrefix, PegInRegistrationPrefixKey, PegOutBitcoinTransaction,
+    PegOutBitcoinTransactionPrefix, PegOutNonceCI, PegOutNonceCIPrefix, PegOutNonceKey,
+    PegOutNoncePrefix, PegOutNonceTxPrefix, PegOutSecretNoncesKey, PegOutSecretNoncesPrefix,
+    PegOutSigningSessionKey, PegOutSigningSessionPrefix, PegOutTxSignatureCI,
+    PegOutTxSignatureCIPrefix, PendingTransactionKey, PendingTransactionPrefixKey,
+    RegisteredPegInKey, RegisteredPegInPrefixKey, RoundConsensusKey, UTXOKey, UTXOPrefixKey,
+    UnsignedTransactionKey, UnsignedTransactionPrefixKey,
+};
+use crate::frost::{
+    FrostKey, KeyGen, NonceCommitment, SecretPolynomial, SigningNonces, SigningPackage, TweakedKey,
 };
 use crate::keys::CompressedPublicKey;
-use crate::tweakable::Tweakable;
-use crate::txoproof::{PegInProof, PegInProofError};
+use crate::tweakable::{tweak_secret_key, Tweakable};
+use crate::txoproof::{PegInProof, PegInProofError, TxOutProof};
 
 pub mod common;
 pub mod config;
 pub mod db;
+pub mod frost;
 pub mod keys;
 pub mod tweakable;
 pub mod txoproof;
@@ -95,6 +105,7 @@ pub type PegInDescriptor = Descriptor<CompressedPublicKey>;
 pub enum WalletConsensusItem {
     RoundConsensus(RoundConsensusItem),
     PegOutSignature(PegOutSignatureItem),
+    PegOutNonces(PegOutNoncesItem),
 }
 
 impl std::fmt::Display for WalletConsensusItem {
@@ -106,6 +117,9 @@ impl std::fmt::Display for WalletConsensusItem {
             WalletConsensusItem::PegOutSignature(sig) => {
                 write!(f, "Wallet PegOut signature for Bitcoin TxId {}", sig.txid)
             }
+            WalletConsensusItem::PegOutNonces(nonces) => {
+                write!(f, "Wallet PegOut nonces for Bitcoin TxId {}", nonces.txid)
+            }
         }
     }
 }
@@ -120,7 +134,42 @@ pub struct RoundConsensusItem {
 #[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
 pub struct PegOutSignatureItem {
     pub txid: Txid,
-    pub signature: Vec<secp256k1::ecdsa::Signature>,
+    pub signature: Vec<PegOutInputSignature>,
+}
+
+/// Signature for a single peg-out input, its type depends on the peg-in descriptor
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PegOutInputSignature {
+    Ecdsa(secp256k1::ecdsa::Signature),
+    /// Script path signature for taproot descriptors
+    Schnorr(secp256k1::schnorr::Signature),
+    /// Share of a FROST key path signature for taproot descriptors
+    FrostShare([u8; 32]),
+}
+
+/// Nonce commitments for signing every input of a taproot peg-out via the key path
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PegOutNoncesItem {
+    pub txid: Txid,
+    pub nonces: Vec<NonceCommitment>,
+}
+
+/// Number of blocks a taproot peg-out waits for a key path signature before the federation falls
+/// back to signing it via the script path
+pub const KEY_PATH_SIGNING_TIMEOUT: u32 = 6;
+
+/// How the inputs of a taproot peg-out are being signed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PegOutSigningSession {
+    /// Collecting nonce commitments for a key path signature
+    KeyPathNonces { started_at_height: u32 },
+    /// Waiting for the signature shares of the first `threshold` peers that committed to nonces
+    KeyPathShares {
+        signers: Vec<PeerId>,
+        started_at_height: u32,
+    },
+    /// Key path signing failed or timed out, the inputs are signed via the script path
+    ScriptPath,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
@@ -133,6 +182,7 @@ pub struct RoundConsensus {
 #[derive(Debug)]
 pub struct Wallet {
     cfg: WalletConfig,
+    our_peer_id: PeerId,
     secp: Secp256k1<All>,
     btc_rpc: DynBitcoindRpc,
 }
@@ -219,13 +269,39 @@ pub struct PegOut {
     pub fees: PegOutFees,
 }
 
-/// Contains the Bitcoin transaction id of the transaction created by the withdraw request
+/// Registers a tweaked peg-in script with the federation so guardians scan confirmed blocks for
+/// deposits to it and the client can later claim them by outpoint alone
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct WalletOutputOutcome(pub bitcoin::Txid);
+pub struct PegInRegistration {
+    pub tweak_contract_key: secp256k1::XOnlyPublicKey,
+}
+
+/// Number of blocks after its registration a peg-in script is watched for deposits
+pub const PEG_IN_REGISTRATION_EXPIRY: u32 = 1008;
+
+/// Registered peg-in script guardians watch for deposits
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct WatchedPegInScript {
+    /// Key the peg-in descriptor was tweaked with to derive the script
+    pub tweak_contract_key: secp256k1::XOnlyPublicKey,
+    /// Last consensus block height that is scanned for deposits to the script
+    pub expiry_height: u32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum WalletOutputOutcome {
+    /// Contains the Bitcoin transaction id of the transaction created by the withdraw request
+    PegOut(bitcoin::Txid),
+    /// The peg-in script was registered and will be watched from now on
+    PegInRegistered,
+}
 
 impl std::fmt::Display for WalletOutputOutcome {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Wallet PegOut Bitcoin TxId {}", self.0)
+        match self {
+            WalletOutputOutcome::PegOut(txid) => write!(f, "Wallet PegOut Bitcoin TxId {txid}"),
+            WalletOutputOutcome::PegInRegistered => write!(f, "Wallet PegIn Registered"),
+        }
     }
 }
 
@@ -269,6 +345,11 @@ impl ModuleGen for WalletGen {
             .map(|&id| (id, secp.generate_keypair(&mut OsRng)))
             .collect::<Vec<_>>();
 
+        let mut frost_keys = match params.descriptor_type {
+            PegInDescriptorType::Wsh => BTreeMap::new(),
+            PegInDescriptorType::Tr => frost::deal(peers, peers.threshold(), &mut OsRng),
+        };
+
         let wallet_cfg: BTreeMap<PeerId, WalletConfig> = btc_pegin_keys
             .iter()
             .map(|(id, (sk, _))| {
@@ -281,6 +362,7 @@ impl ModuleGen for WalletGen {
                     peers.threshold(),
                     params.network,
                     params.finality_delay,
+                    frost_keys.remove(id),
                 );
                 (*id, cfg)
             })
@@ -321,12 +403,83 @@ impl ModuleGen for WalletGen {
             return Ok(Err(Cancelled));
         }
 
+        // For taproot descriptors the peers also run a FROST DKG, in the first round everyone
+        // commits to a polynomial, once all commitments are known everyone deals shares of it
+        let mut frost_polynomial = match params.descriptor_type {
+            PegInDescriptorType::Wsh => None,
+            PegInDescriptorType::Tr => {
+                Some(SecretPolynomial::random(peers.threshold(), &mut OsRng))
+            }
+        };
+        let expected_frost_dealings = if frost_polynomial.is_some() {
+            peers.len()
+        } else {
+            0
+        };
+        let mut frost_commitments = BTreeMap::new();
+        let mut frost_keygen = None;
+        let mut frost_dealings = BTreeMap::new();
+        if let Some(polynomial) = &frost_polynomial {
+            let commitment = polynomial.commitment();
+            if let Err(Cancelled) = connections
+                .send(
+                    peers,
+                    module_instance_id,
+                    DkgPeerMsg::FrostCommitment(commitment.clone()),
+                )
+                .await
+            {
+                return Ok(Err(Cancelled));
+            }
+            frost_commitments.insert(*our_id, commitment);
+        }
+
         peer_peg_in_keys.insert(*our_id, our_key);
-        while peer_peg_in_keys.len() < peers.len() {
+        loop {
+            if frost_commitments.len() == expected_frost_dealings && frost_keygen.is_none() {
+                if let Some(polynomial) = frost_polynomial.take() {
+                    let keygen = KeyGen::new(&frost_commitments, peers.threshold())?;
+                    let (mut shares, proof) = keygen.deal(polynomial);
+                    for peer in peers.iter().filter(|peer| *peer != our_id) {
+                        if let Err(Cancelled) = connections
+                            .send(
+                                &[*peer],
+                                module_instance_id,
+                                DkgPeerMsg::FrostShare(shares[peer], proof),
+                            )
+                            .await
+                        {
+                            return Ok(Err(Cancelled));
+                        }
+                    }
+                    let our_share = shares.remove(our_id).expect("dealt to all peers");
+                    frost_dealings.insert(*our_id, (our_share, proof));
+                    frost_keygen = Some(keygen);
+                }
+            }
+
+            if peer_peg_in_keys.len() == peers.len()
+                && frost_dealings.len() == expected_frost_dealings
+            {
+                break;
+            }
+
             match connections.receive(module_instance_id).await {
                 Ok((peer, DkgPeerMsg::PublicKey(key))) => {
                     peer_peg_in_keys.insert(peer, CompressedPublicKey { key });
                 }
+                Ok((peer, DkgPeerMsg::FrostCommitment(commitment)))
+                    if expected_frost_dealings != 0 =>
+                {
+                    frost_commitments.insert(peer, commitment);
+                }
+                // Shares may arrive before we have seen all commitments, they are only verified
+                // once the DKG finishes
+                Ok((peer, DkgPeerMsg::FrostShare(share, proof)))
+                    if expected_frost_dealings != 0 =>
+                {
+                    frost_dealings.insert(peer, (share, proof));
+                }
                 Ok((peer, msg)) => {
                     bail!("Invalid message received from: {peer}: {msg:?}");
                 }
@@ -336,12 +489,17 @@ impl ModuleGen for WalletGen {
             }
         }
 
+        let frost_key = frost_keygen
+            .map(|keygen| keygen.finish(*our_id, &frost_dealings))
+            .transpose()?;
+
         let wallet_cfg = WalletConfig::new(
             peer_peg_in_keys,
             sk,
             peers.threshold(),
             params.network,
             params.finality_delay,
+            frost_key,
         );
 
         Ok(Ok(wallet_cfg.to_erased()))
@@ -396,7 +554,7 @@ impl ModuleGen for WalletGen {
                         dbtx,
                         PegOutTxSignatureCIPrefix,
                         PegOutTxSignatureCI,
-                        Vec<secp256k1::ecdsa::Signature>,
+                        Vec<PegOutInputSignature>,
                         wallet,
                         "Peg Out Transaction Signatures"
                     );
@@ -437,6 +595,76 @@ impl ModuleGen for WalletGen {
                         "UTXOs"
                     );
                 }
+                DbKeyPrefix::PegInRegistration => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegInRegistrationPrefixKey,
+                        PegInRegistrationKey,
+                        WatchedPegInScript,
+                        wallet,
+                        "Peg In Registrations"
+                    );
+                }
+                DbKeyPrefix::RegisteredPegIn => {
+                    push_db_pair_items!(
+                        dbtx,
+                        RegisteredPegInPrefixKey,
+                        RegisteredPegInKey,
+                        PegInProof,
+                        wallet,
+                        "Registered Peg Ins"
+                    );
+                }
+                DbKeyPrefix::PegInRegistrationOutPoint => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegInRegistrationOutPointPrefix,
+                        PegInRegistrationOutPoint,
+                        WalletOutputOutcome,
+                        wallet,
+                        "Peg In Registration Outcomes"
+                    );
+                }
+                DbKeyPrefix::PegOutNonceCi => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutNonceCIPrefix,
+                        PegOutNonceCI,
+                        Vec<NonceCommitment>,
+                        wallet,
+                        "Peg Out Nonce Commitments"
+                    );
+                }
+                DbKeyPrefix::PegOutSecretNonces => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutSecretNoncesPrefix,
+                        PegOutSecretNoncesKey,
+                        Vec<SigningNonces>,
+                        wallet,
+                        "Peg Out Secret Nonces"
+                    );
+                }
+                DbKeyPrefix::PegOutNonces => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutNoncePrefix,
+                        PegOutNonceKey,
+                        Vec<NonceCommitment>,
+                        wallet,
+                        "Peg Out Peer Nonce Commitments"
+                    );
+                }
+                DbKeyPrefix::PegOutSigningSession => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutSigningSessionPrefix,
+                        PegOutSigningSessionKey,
+                        PegOutSigningSession,
+                        wallet,
+                        "Peg Out Signing Sessions"
+                    );
+                }
             }
         }
 
@@ -448,6 +676,8 @@ impl ModuleGen for WalletGen {
 pub struct WalletGenParams {
     pub network: bitcoin::network::constants::Network,
     pub finality_delay: u32,
+    #[serde(default)]
+    pub descriptor_type: PegInDescriptorType,
 }
 
 impl ModuleGenParams for WalletGenParams {
@@ -468,13 +698,28 @@ impl std::fmt::Display for WalletInput {
     }
 }
 
-#[autoimpl(Deref, DerefMut using self.0)]
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct WalletOutput(pub PegOut);
+pub enum WalletOutput {
+    PegOut(PegOut),
+    RegisterPegIn(PegInRegistration),
+}
 
 impl std::fmt::Display for WalletOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Wallet PegOut {} to {}", self.0.amount, self.0.recipient)
+        match self {
+            WalletOutput::PegOut(peg_out) => {
+                write!(
+                    f,
+                    "Wallet PegOut {} to {}",
+                    peg_out.amount, peg_out.recipient
+                )
+            }
+            WalletOutput::RegisterPegIn(registration) => write!(
+                f,
+                "Wallet PegIn Registration for key {}",
+                registration.tweak_contract_key
+            ),
+        }
     }
 }
 
@@ -540,6 +785,19 @@ impl ServerModule for Wallet {
             randomness: OsRng.gen(),
         });
 
+        let nonce_cis = dbtx
+            .find_by_prefix(&PegOutNonceCIPrefix)
+            .await
+            .map(|res| {
+                let (key, val) = res.expect("DB error");
+                WalletConsensusItem::PegOutNonces(PegOutNoncesItem {
+                    txid: key.0,
+                    nonces: val,
+                })
+            })
+            .collect::<Vec<WalletConsensusItem>>()
+            .await;
+
         dbtx.find_by_prefix(&PegOutTxSignatureCIPrefix)
             .await
             .map(|res| {
@@ -549,6 +807,7 @@ impl ServerModule for Wallet {
                     signature: val,
                 })
             })
+            .chain(stream::iter(nonce_cis))
             .chain(stream::once(async { round_ci }))
             .collect::<Vec<WalletConsensusItem>>()
             .await
@@ -566,10 +825,12 @@ impl ServerModule for Wallet {
         let UnzipWalletConsensusItem {
             peg_out_signature: peg_out_signatures,
             round_consensus,
+            peg_out_nonces,
         } = consensus_items.into_iter().unzip_wallet_consensus_item();
 
-        // Save signatures to the database
+        // Save signatures and nonce commitments to the database
         self.save_peg_out_signatures(dbtx, peg_out_signatures).await;
+        self.save_peg_out_nonces(dbtx, peg_out_nonces).await;
 
         // FIXME: also warn on less than 1/3, that should never happen
         // Make sure we have enough contributions to continue
@@ -667,6 +928,10 @@ impl ServerModule for Wallet {
         )
         .await
         .expect("DB Error");
+        // A peg-in detected by block scanning is now claimed and doesn't need to be served anymore
+        dbtx.remove_entry(&RegisteredPegInKey(input.outpoint()))
+            .await
+            .expect("DB Error");
 
         Ok(meta)
     }
@@ -676,6 +941,22 @@ impl ServerModule for Wallet {
         dbtx: &mut DatabaseTransaction,
         output: &WalletOutput,
     ) -> Result<TransactionItemAmount, ModuleError> {
+        let output = match output {
+            WalletOutput::PegOut(peg_out) => peg_out,
+            WalletOutput::RegisterPegIn(_) => {
+                let fee = self.cfg.consensus.fee_consensus.peg_in_registration_abs;
+                // Without a fee registrations would be free to spam while every one of them has
+                // to be watched until it expires
+                if fee == fedimint_api::Amount::ZERO {
+                    return Err(WalletError::PegInRegistrationDisabled).into_module_error_other();
+                }
+                return Ok(TransactionItemAmount {
+                    amount: fedimint_api::Amount::ZERO,
+                    fee,
+                });
+            }
+        };
+
         if !is_address_valid_for_network(&output.recipient, self.cfg.consensus.network) {
             return Err(WalletError::WrongNetwork(
                 self.cfg.consensus.network,
@@ -707,6 +988,38 @@ impl ServerModule for Wallet {
         out_point: fedimint_api::OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError> {
         let amount = self.validate_output(dbtx, output).await?;
+
+        let output = match output {
+            WalletOutput::PegOut(peg_out) => peg_out,
+            WalletOutput::RegisterPegIn(registration) => {
+                let script = self
+                    .cfg
+                    .consensus
+                    .peg_in_descriptor
+                    .tweak(&registration.tweak_contract_key, &self.secp)
+                    .script_pubkey();
+                let expiry_height =
+                    self.consensus_height(dbtx).await.unwrap_or(0) + PEG_IN_REGISTRATION_EXPIRY;
+                debug!(?script, expiry_height, "Registering peg-in script");
+                dbtx.insert_entry(
+                    &PegInRegistrationKey(script),
+                    &WatchedPegInScript {
+                        tweak_contract_key: registration.tweak_contract_key,
+                        expiry_height,
+                    },
+                )
+                .await
+                .expect("DB Error");
+                dbtx.insert_new_entry(
+                    &PegInRegistrationOutPoint(out_point),
+                    &WalletOutputOutcome::PegInRegistered,
+                )
+                .await
+                .expect("DB Error");
+                return Ok(amount);
+            }
+        };
+
         debug!(
             amount = %output.amount, recipient = %output.recipient,
             "Queuing peg-out",
@@ -716,38 +1029,44 @@ impl ServerModule for Wallet {
             .create_peg_out_tx(dbtx, output)
             .await
             .expect("Should have been validated");
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
         let txid = tx.psbt.unsigned_tx.txid();
         info!(
             %txid,
             "Signing peg out",
         );
 
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
-
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
+        if self.frost_key().is_some() {
+            // Taproot peg-outs are signed via the key path in two rounds, we first commit to nonces
+            let nonces = tx
+                .psbt
+                .inputs
+                .iter()
+                .map(|_| SigningNonces::new(&mut OsRng))
+                .collect::<Vec<_>>();
+            let commitments = nonces
+                .iter()
+                .map(|nonces| nonces.commitment())
+                .collect::<Vec<_>>();
+            let started_at_height = self.consensus_height(dbtx).await.unwrap_or(0);
 
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
+            dbtx.insert_new_entry(&PegOutSecretNoncesKey(txid), &nonces)
+                .await
+                .expect("DB Error");
+            dbtx.insert_new_entry(&PegOutNonceCI(txid), &commitments)
+                .await
+                .expect("DB Error");
+            dbtx.insert_new_entry(
+                &PegOutSigningSessionKey(txid),
+                &PegOutSigningSession::KeyPathNonces { started_at_height },
+            )
+            .await
+            .expect("DB Error");
+        } else {
+            let sigs = self.sign_peg_out_inputs(&mut tx.psbt);
+            dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
+                .await
+                .expect("DB Error");
+        }
 
         // Delete used UTXOs
         for input in tx.psbt.unsigned_tx.input.iter() {
@@ -759,12 +1078,9 @@ impl ServerModule for Wallet {
         dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
             .await
             .expect("DB Error");
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await
-            .expect("DB Error");
         dbtx.insert_new_entry(
             &PegOutBitcoinTransaction(out_point),
-            &WalletOutputOutcome(txid),
+            &WalletOutputOutcome::PegOut(txid),
         )
         .await
         .expect("DB Error");
@@ -787,12 +1103,50 @@ impl ServerModule for Wallet {
             .collect::<Vec<(UnsignedTransactionKey, UnsignedTransaction)>>()
             .await;
 
-        let unsigned_txs = unsigned_txs
-            .into_iter()
-            .filter(|(_, unsigned)| !unsigned.signatures.is_empty());
+        let consensus_height = self.consensus_height(dbtx).await.unwrap_or(0);
 
         let mut drop_peers = Vec::<PeerId>::new();
         for (key, unsigned) in unsigned_txs {
+            match dbtx
+                .get_value(&PegOutSigningSessionKey(key.0))
+                .await
+                .expect("DB error")
+            {
+                Some(PegOutSigningSession::KeyPathNonces { started_at_height }) => {
+                    self.start_key_path_signing(
+                        dbtx,
+                        key.0,
+                        unsigned,
+                        started_at_height,
+                        consensus_height,
+                    )
+                    .await;
+                    continue;
+                }
+                Some(PegOutSigningSession::KeyPathShares {
+                    signers,
+                    started_at_height,
+                }) => {
+                    let faulty_signers = self
+                        .finish_key_path_signing(
+                            dbtx,
+                            key.0,
+                            unsigned,
+                            &signers,
+                            started_at_height,
+                            consensus_height,
+                        )
+                        .await;
+                    drop_peers.extend(faulty_signers);
+                    continue;
+                }
+                Some(PegOutSigningSession::ScriptPath) | None => {}
+            }
+
+            if unsigned.signatures.is_empty() {
+                continue;
+            }
+
             let UnsignedTransaction {
                 mut psbt,
                 signatures,
@@ -829,6 +1183,9 @@ impl ServerModule for Wallet {
                     dbtx.remove_entry(&PegOutTxSignatureCI(key.0))
                         .await
                         .expect("DB Error");
+                    dbtx.remove_entry(&PegOutSigningSessionKey(key.0))
+                        .await
+                        .expect("DB Error");
                     dbtx.remove_entry(&key).await.expect("DB Error");
                 }
                 Err(e) => {
@@ -844,7 +1201,15 @@ impl ServerModule for Wallet {
         dbtx: &mut DatabaseTransaction<'_>,
         out_point: OutPoint,
     ) -> Option<WalletOutputOutcome> {
-        dbtx.get_value(&PegOutBitcoinTransaction(out_point))
+        if let Some(outcome) = dbtx
+            .get_value(&PegOutBitcoinTransaction(out_point))
+            .await
+            .expect("DB error")
+        {
+            return Some(outcome);
+        }
+
+        dbtx.get_value(&PegInRegistrationOutPoint(out_point))
             .await
             .expect("DB error")
     }
@@ -889,6 +1254,21 @@ impl ServerModule for Wallet {
                     Ok(tx.map(|tx| tx.fees))
                 }
             },
+            api_endpoint! {
+                "/bitcoin_rpc_health",
+                async |module: &Wallet, _dbtx, _params: ()| -> Option<BitcoinRpcHealth> {
+                    Ok(module.btc_rpc.health())
+                }
+            },
+            api_endpoint! {
+                "/registered_peg_in",
+                async |_module: &Wallet, dbtx, outpoint: bitcoin::OutPoint| -> Option<PegInProof> {
+                    Ok(dbtx
+                        .get_value(&RegisteredPegInKey(outpoint))
+                        .await
+                        .expect("DB error"))
+                }
+            },
         ]
     }
 }
@@ -906,6 +1286,10 @@ impl Wallet {
                 .map(OsString::as_os_str),
             env.get(OsStr::new(FM_ELECTRUM_RPC_ENV))
                 .map(OsString::as_os_str),
+            env.get(OsStr::new(FM_ESPLORA_RPC_ENV))
+                .map(OsString::as_os_str),
+            env.get(OsStr::new(FM_BITCOIN_RPC_QUORUM_ENV))
+                .map(OsString::as_os_str),
         )?;
 
         let btc_rpc = fedimint_bitcoind::bitcoincore_rpc::make_bitcoin_rpc_backend(
@@ -953,8 +1337,19 @@ impl Wallet {
             ));
         }
 
+        let our_peer_id = cfg
+            .consensus
+            .peer_peg_in_keys
+            .iter()
+            .find(|(_, key)| {
+                key.key == secp256k1::PublicKey::from_secret_key_global(&cfg.private.peg_in_key)
+            })
+            .map(|(peer, _)| *peer)
+            .ok_or(WalletError::UnknownPegInKey)?;
+
         let wallet = Wallet {
             cfg,
+            our_peer_id,
             secp: Default::default(),
             btc_rpc: bitcoind_rpc,
         };
@@ -988,6 +1383,8 @@ impl Wallet {
 
         for (peer, sig) in signatures.into_iter() {
             match cache.get_mut(&sig.txid) {
+                // Signatures are proposed every epoch until the transaction is finalized
+                Some(unsigned) if unsigned.signatures.contains(&(peer, sig.clone())) => {}
                 Some(unsigned) => unsigned.signatures.push((peer, sig)),
                 None => warn!(
                     "{} sent peg-out signature for unknown PSBT {}",
@@ -1003,6 +1400,387 @@ impl Wallet {
         }
     }
 
+    async fn save_peg_out_nonces<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        nonces: Vec<(PeerId, PegOutNoncesItem)>,
+    ) {
+        for (peer, item) in nonces {
+            match dbtx
+                .get_value(&PegOutSigningSessionKey(item.txid))
+                .await
+                .expect("DB error")
+            {
+                Some(PegOutSigningSession::KeyPathNonces { .. }) => {
+                    dbtx.insert_entry(
+                        &PegOutNonceKey {
+                            txid: item.txid,
+                            peer,
+                        },
+                        &item.nonces,
+                    )
+                    .await
+                    .expect("DB Error");
+                }
+                _ => debug!(
+                    "{} sent nonces for PSBT {} that isn't collecting nonces",
+                    peer, item.txid
+                ),
+            }
+        }
+    }
+
+    /// Signs all inputs of `psbt` with our peg-in key, via the script path for taproot inputs
+    fn sign_peg_out_inputs(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+    ) -> Vec<PegOutInputSignature> {
+        self.offline_wallet().sign_psbt(psbt);
+
+        psbt.inputs
+            .iter_mut()
+            .map(|input| {
+                // TODO: don't put sig into PSBT in the first place
+                // We actually take out our own signature so everyone finalizes the tx in the
+                // same epoch.
+                if !input.tap_scripts.is_empty() {
+                    assert_eq!(
+                        input.tap_script_sigs.len(),
+                        1,
+                        "There was already more than one (our) or no signatures in input"
+                    );
+
+                    let sig = std::mem::take(&mut input.tap_script_sigs)
+                        .into_values()
+                        .next()
+                        .expect("asserted previously");
+
+                    return PegOutInputSignature::Schnorr(sig.sig);
+                }
+
+                assert_eq!(
+                    input.partial_sigs.len(),
+                    1,
+                    "There was already more than one (our) or no signatures in input"
+                );
+
+                let sig = std::mem::take(&mut input.partial_sigs)
+                    .into_values()
+                    .next()
+                    .expect("asserted previously");
+
+                // We drop SIGHASH_ALL, because we always use that and it is only present in the
+                // PSBT for compatibility with other tools.
+                PegOutInputSignature::Ecdsa(
+                    secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                        .expect("we serialized it ourselves that way"),
+                )
+            })
+            .collect()
+    }
+
+    /// FROST key whose group key is the internal key of taproot peg-in descriptors, `None` for
+    /// other descriptors and taproot federations set up without a FROST key
+    fn frost_key(&self) -> Option<&FrostKey> {
+        match &self.cfg.consensus.peg_in_descriptor {
+            Descriptor::Tr(_) => self.cfg.consensus.frost_key.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn threshold(&self) -> usize {
+        self.cfg
+            .consensus
+            .peer_peg_in_keys
+            .keys()
+            .copied()
+            .collect::<Vec<_>>()
+            .threshold()
+    }
+
+    async fn peg_out_nonces(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        txid: Txid,
+    ) -> BTreeMap<PeerId, Vec<NonceCommitment>> {
+        dbtx.find_by_prefix(&PegOutNonceTxPrefix(txid))
+            .await
+            .map(|res| {
+                let (key, nonces) = res.expect("DB error");
+                (key.peer, nonces)
+            })
+            .collect()
+            .await
+    }
+
+    /// Signing packages for the key path of every input of `psbt`, `commitments` determine the
+    /// signing set
+    fn key_path_signing_packages(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        commitments: &BTreeMap<PeerId, Vec<NonceCommitment>>,
+    ) -> Vec<SigningPackage> {
+        let frost_key = self
+            .frost_key()
+            .expect("only called for descriptors with a FROST key");
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
+        let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
+
+        psbt.inputs
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| {
+                let tweak = input
+                    .proprietary
+                    .get(&proprietary_tweak_key())
+                    .expect("we saved it with a tweak");
+                let key = TweakedKey::new(frost_key, tweak, input.tap_merkle_root);
+                let tx_hash = tx_hasher
+                    .taproot_key_spend_signature_hash(
+                        idx,
+                        &Prevouts::All(&prevouts),
+                        SchnorrSighashType::Default,
+                    )
+                    .expect("Failed to create taproot sighash");
+                let input_commitments = commitments
+                    .iter()
+                    .map(|(peer, nonces)| (*peer, nonces[idx]))
+                    .collect();
+
+                SigningPackage::new(key, tx_hash.into_inner(), &input_commitments)
+            })
+            .collect()
+    }
+
+    /// Fixes the signing set of a taproot peg-out to the first `threshold` peers that committed
+    /// to nonces and contributes our signature shares if we are part of it
+    async fn start_key_path_signing(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        txid: Txid,
+        unsigned: UnsignedTransaction,
+        started_at_height: u32,
+        consensus_height: u32,
+    ) {
+        let input_count = unsigned.psbt.inputs.len();
+        let commitments = self
+            .peg_out_nonces(dbtx, txid)
+            .await
+            .into_iter()
+            .filter(|(_, nonces)| nonces.len() == input_count)
+            .take(self.threshold())
+            .collect::<BTreeMap<_, _>>();
+
+        if commitments.len() < self.threshold() {
+            if consensus_height >= started_at_height + KEY_PATH_SIGNING_TIMEOUT {
+                self.fall_back_to_script_path(dbtx, txid, unsigned).await;
+            }
+            return;
+        }
+
+        let signers = commitments.keys().copied().collect::<Vec<_>>();
+        debug!(%txid, ?signers, "Signing peg-out via the key path");
+        dbtx.remove_entry(&PegOutNonceCI(txid))
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(
+            &PegOutSigningSessionKey(txid),
+            &PegOutSigningSession::KeyPathShares {
+                signers: signers.clone(),
+                started_at_height,
+            },
+        )
+        .await
+        .expect("DB Error");
+
+        // Nonces must never be used for more than one signature, so we delete them either way
+        let secret_nonces = dbtx
+            .remove_entry(&PegOutSecretNoncesKey(txid))
+            .await
+            .expect("DB Error");
+        if !signers.contains(&self.our_peer_id) {
+            return;
+        }
+        let Some(secret_nonces) = secret_nonces else {
+            warn!(%txid, "Missing our secret nonces, can't sign peg-out via the key path");
+            return;
+        };
+
+        let key_share = self
+            .cfg
+            .private
+            .frost_key_share
+            .as_ref()
+            .expect("config with FROST keys has a key share");
+        let shares = self
+            .key_path_signing_packages(&unsigned.psbt, &commitments)
+            .iter()
+            .zip(secret_nonces.iter())
+            .map(|(package, nonces)| {
+                PegOutInputSignature::FrostShare(package.sign(self.our_peer_id, key_share, nonces))
+            })
+            .collect::<Vec<_>>();
+        dbtx.insert_entry(&PegOutTxSignatureCI(txid), &shares)
+            .await
+            .expect("DB Error");
+    }
+
+    /// Finalizes a taproot peg-out via the key path once all signers contributed valid shares.
+    /// Falls back to the script path if a share is invalid or the signers timed out and returns
+    /// the peers that sent invalid shares.
+    async fn finish_key_path_signing(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        txid: Txid,
+        unsigned: UnsignedTransaction,
+        signers: &[PeerId],
+        started_at_height: u32,
+        consensus_height: u32,
+    ) -> Vec<PeerId> {
+        let commitments = self
+            .peg_out_nonces(dbtx, txid)
+            .await
+            .into_iter()
+            .filter(|(peer, _)| signers.contains(peer))
+            .collect::<BTreeMap<_, _>>();
+        let packages = self.key_path_signing_packages(&unsigned.psbt, &commitments);
+
+        let mut shares = BTreeMap::new();
+        let mut faulty_signers = vec![];
+        for (peer, signature) in &unsigned.signatures {
+            match self.verify_key_path_shares(&packages, *peer, signers, &signature.signature) {
+                Ok(peer_shares) => {
+                    shares.insert(*peer, peer_shares);
+                }
+                Err(error) => {
+                    warn!(%txid, %peer, ?error, "Invalid key path signature share");
+                    faulty_signers.push(*peer);
+                }
+            }
+        }
+
+        if !faulty_signers.is_empty() {
+            self.fall_back_to_script_path(dbtx, txid, unsigned).await;
+            return faulty_signers;
+        }
+
+        if shares.len() < signers.len() {
+            if consensus_height >= started_at_height + KEY_PATH_SIGNING_TIMEOUT {
+                self.fall_back_to_script_path(dbtx, txid, unsigned).await;
+            }
+            return vec![];
+        }
+
+        let mut psbt = unsigned.psbt.clone();
+        for (idx, (input, package)) in psbt.inputs.iter_mut().zip(&packages).enumerate() {
+            let signature = package.aggregate(shares.values().map(|shares| &shares[idx]));
+            input.tap_key_sig = Some(SchnorrSig {
+                sig: signature,
+                hash_ty: SchnorrSighashType::Default,
+            });
+        }
+
+        match self.finalize_peg_out_psbt(&mut psbt, unsigned.change) {
+            Ok(pending_tx) => {
+                dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
+                    .await
+                    .expect("DB Error");
+                dbtx.remove_entry(&PegOutTxSignatureCI(txid))
+                    .await
+                    .expect("DB Error");
+                dbtx.remove_entry(&PegOutSigningSessionKey(txid))
+                    .await
+                    .expect("DB Error");
+                dbtx.remove_entry(&UnsignedTransactionKey(txid))
+                    .await
+                    .expect("DB Error");
+                self.remove_key_path_signing_state(dbtx, txid).await;
+            }
+            Err(e) => {
+                warn!("Unable to finalize key path signed PSBT due to {:?}", e);
+                self.fall_back_to_script_path(dbtx, txid, unsigned).await;
+            }
+        }
+
+        vec![]
+    }
+
+    fn verify_key_path_shares(
+        &self,
+        packages: &[SigningPackage],
+        peer: PeerId,
+        signers: &[PeerId],
+        signature: &[PegOutInputSignature],
+    ) -> Result<Vec<[u8; 32]>, ProcessPegOutSigError> {
+        if !signers.contains(&peer) {
+            return Err(ProcessPegOutSigError::NotInSigningSet(peer));
+        }
+
+        if packages.len() != signature.len() {
+            return Err(ProcessPegOutSigError::WrongSignatureCount(
+                packages.len(),
+                signature.len(),
+            ));
+        }
+
+        packages
+            .iter()
+            .zip(signature)
+            .map(|(package, signature)| {
+                let PegOutInputSignature::FrostShare(share) = signature else {
+                    return Err(ProcessPegOutSigError::WrongSignatureType);
+                };
+                if !package.verify_share(peer, share) {
+                    return Err(ProcessPegOutSigError::InvalidSignature);
+                }
+                Ok(*share)
+            })
+            .collect()
+    }
+
+    /// Switches a taproot peg-out to script path signing and contributes our signatures
+    async fn fall_back_to_script_path(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        txid: Txid,
+        mut unsigned: UnsignedTransaction,
+    ) {
+        warn!(%txid, "Key path signing failed, signing peg-out via the script path");
+
+        unsigned.signatures.clear();
+        let sigs = self.sign_peg_out_inputs(&mut unsigned.psbt);
+
+        dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(&PegOutTxSignatureCI(txid), &sigs)
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(
+            &PegOutSigningSessionKey(txid),
+            &PegOutSigningSession::ScriptPath,
+        )
+        .await
+        .expect("DB Error");
+        self.remove_key_path_signing_state(dbtx, txid).await;
+    }
+
+    async fn remove_key_path_signing_state(&self, dbtx: &mut DatabaseTransaction<'_>, txid: Txid) {
+        dbtx.remove_entry(&PegOutNonceCI(txid))
+            .await
+            .expect("DB Error");
+        dbtx.remove_entry(&PegOutSecretNoncesKey(txid))
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&PegOutNonceTxPrefix(txid))
+            .await
+            .expect("DB Error");
+    }
+
     /// Try to attach signatures to a pending peg-out tx.
     fn sign_peg_out_psbt(
         &self,
@@ -1024,6 +1802,11 @@ impl Wallet {
             ));
         }
 
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
         for (idx, (input, signature)) in psbt
             .inputs
@@ -1031,37 +1814,74 @@ impl Wallet {
             .zip(signature.signature.iter())
             .enumerate()
         {
-            let tx_hash = tx_hasher
-                .segwit_signature_hash(
-                    idx,
-                    input
-                        .witness_script
-                        .as_ref()
-                        .expect("Missing witness script"),
-                    input.witness_utxo.as_ref().expect("Missing UTXO").value,
-                    EcdsaSighashType::All,
-                )
-                .map_err(|_| ProcessPegOutSigError::SighashError)?;
-
             let tweak = input
                 .proprietary
                 .get(&proprietary_tweak_key())
                 .expect("we saved it with a tweak");
 
             let tweaked_peer_key = peer_key.tweak(tweak, &self.secp);
-            self.secp
-                .verify_ecdsa(
-                    &Message::from_slice(&tx_hash[..]).unwrap(),
-                    signature,
-                    &tweaked_peer_key.key,
-                )
-                .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
 
-            if input
-                .partial_sigs
-                .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
-                .is_some()
-            {
+            let is_duplicate = match (signature, tap_leaf_hash(input)) {
+                (PegOutInputSignature::Ecdsa(signature), None) => {
+                    let tx_hash = tx_hasher
+                        .segwit_signature_hash(
+                            idx,
+                            input
+                                .witness_script
+                                .as_ref()
+                                .expect("Missing witness script"),
+                            input.witness_utxo.as_ref().expect("Missing UTXO").value,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    self.secp
+                        .verify_ecdsa(
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            signature,
+                            &tweaked_peer_key.key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    input
+                        .partial_sigs
+                        .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
+                        .is_some()
+                }
+                (PegOutInputSignature::Schnorr(signature), Some(leaf_hash)) => {
+                    let tx_hash = tx_hasher
+                        .taproot_script_spend_signature_hash(
+                            idx,
+                            &Prevouts::All(&prevouts),
+                            leaf_hash,
+                            SchnorrSighashType::Default,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    let tweaked_peer_key = tweaked_peer_key.key.x_only_public_key().0;
+                    self.secp
+                        .verify_schnorr(
+                            signature,
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            &tweaked_peer_key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    input
+                        .tap_script_sigs
+                        .insert(
+                            (tweaked_peer_key, leaf_hash),
+                            SchnorrSig {
+                                sig: *signature,
+                                hash_ty: SchnorrSighashType::Default,
+                            },
+                        )
+                        .is_some()
+                }
+                _ => return Err(ProcessPegOutSigError::WrongSignatureType),
+            };
+
+            if is_duplicate {
                 // Should never happen since peers only sign a PSBT once
                 return Err(ProcessPegOutSigError::DuplicateSignature);
             }
@@ -1188,6 +2008,16 @@ impl Wallet {
             "New consensus height, syncing up",
         );
 
+        let watched_scripts = dbtx
+            .find_by_prefix(&PegInRegistrationPrefixKey)
+            .await
+            .map(|res| {
+                let (key, watched) = res.expect("DB error");
+                (key.0, watched)
+            })
+            .collect::<HashMap<_, _>>()
+            .await;
+
         for height in (old_height + 1)..=(new_height) {
             if height % 100 == 0 {
                 debug!("Caught up to block {}", height);
@@ -1211,20 +2041,30 @@ impl Wallet {
                 .collect::<HashMap<_, _>>()
                 .await;
 
+            let registered_scripts = watched_scripts
+                .iter()
+                .filter(|(_, watched)| height <= watched.expiry_height)
+                .map(|(script, watched)| (script.clone(), watched.tweak_contract_key))
+                .collect::<HashMap<_, _>>();
+
             match self.btc_rpc.backend_type() {
                 BitcoinRpcBackendType::Bitcoind => {
-                    if !pending_transactions.is_empty() {
+                    if !pending_transactions.is_empty() || !registered_scripts.is_empty() {
                         let block = self
                             .btc_rpc
                             .get_block(&block_hash)
                             .await
                             .expect("bitcoin rpc failed");
-                        for transaction in block.txdata {
+                        for transaction in &block.txdata {
                             if let Some(pending_tx) = pending_transactions.get(&transaction.txid())
                             {
                                 self.recognize_change_utxo(dbtx, pending_tx).await;
                             }
                         }
+                        if !registered_scripts.is_empty() {
+                            self.recognize_registered_peg_ins(dbtx, &block, &registered_scripts)
+                                .await;
+                        }
                     }
                 }
                 BitcoinRpcBackendType::Electrum => {
@@ -1238,6 +2078,34 @@ impl Wallet {
                             self.recognize_change_utxo(dbtx, transaction.1).await;
                         }
                     }
+                    for (script, tweak_contract_key) in &registered_scripts {
+                        let payments = self
+                            .btc_rpc
+                            .get_script_payments_in(script, height as u64)
+                            .await
+                            .expect("bitcoin rpc backend failed");
+                        for payment in payments {
+                            if payment.block_header.block_hash() != block_hash {
+                                warn!(
+                                    block = %payment.block_header.block_hash(),
+                                    expected = %block_hash,
+                                    "Peg-in payment proof is for the wrong block, skipping it"
+                                );
+                                continue;
+                            }
+                            self.recognize_registered_peg_in(
+                                dbtx,
+                                TxOutProof {
+                                    block_header: payment.block_header,
+                                    merkle_proof: payment.merkle_proof,
+                                },
+                                &payment.transaction,
+                                script,
+                                *tweak_contract_key,
+                            )
+                            .await;
+                        }
+                    }
                 }
             }
 
@@ -1248,6 +2116,95 @@ impl Wallet {
             .await
             .expect("DB Error");
         }
+
+        for (script, watched) in watched_scripts {
+            if watched.expiry_height <= new_height {
+                debug!(?script, "Peg-in registration expired");
+                dbtx.remove_entry(&PegInRegistrationKey(script))
+                    .await
+                    .expect("DB Error");
+            }
+        }
+    }
+
+    /// Build peg-in proofs for all outputs in `block` paying to a registered peg-in script so
+    /// clients can claim them by outpoint without supplying a proof themselves.
+    async fn recognize_registered_peg_ins<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        block: &Block,
+        registered_scripts: &HashMap<Script, secp256k1::XOnlyPublicKey>,
+    ) {
+        if !block.check_merkle_root() {
+            warn!(block = %block.block_hash(), "Block merkle root mismatch, skipping peg-in scan");
+            return;
+        }
+
+        let txids = block.txdata.iter().map(|tx| tx.txid()).collect::<Vec<_>>();
+
+        for (tx_idx, transaction) in block.txdata.iter().enumerate() {
+            let paid_scripts = transaction
+                .output
+                .iter()
+                .filter_map(|output| registered_scripts.get_key_value(&output.script_pubkey))
+                .collect::<HashMap<_, _>>();
+
+            for (script, tweak_contract_key) in paid_scripts {
+                let matches = (0..txids.len())
+                    .map(|idx| idx == tx_idx)
+                    .collect::<Vec<_>>();
+                let txout_proof = TxOutProof {
+                    block_header: block.header,
+                    merkle_proof: PartialMerkleTree::from_txids(&txids, &matches),
+                };
+                self.recognize_registered_peg_in(
+                    dbtx,
+                    txout_proof,
+                    transaction,
+                    script,
+                    *tweak_contract_key,
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Store peg-in proofs for all outputs of `transaction` paying to the registered `script`
+    async fn recognize_registered_peg_in<'a>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'a>,
+        txout_proof: TxOutProof,
+        transaction: &Transaction,
+        script: &Script,
+        tweak_contract_key: secp256k1::XOnlyPublicKey,
+    ) {
+        for (output_idx, output) in transaction.output.iter().enumerate() {
+            if &output.script_pubkey != script {
+                continue;
+            }
+
+            let outpoint = bitcoin::OutPoint {
+                txid: transaction.txid(),
+                vout: output_idx as u32,
+            };
+
+            match PegInProof::new(
+                txout_proof.clone(),
+                transaction.clone(),
+                output_idx as u32,
+                tweak_contract_key,
+            ) {
+                Ok(proof) => {
+                    debug!(%outpoint, amount = output.value, "Detected registered peg-in");
+                    dbtx.insert_entry(&RegisteredPegInKey(outpoint), &proof)
+                        .await
+                        .expect("DB Error");
+                }
+                Err(error) => {
+                    warn!(%outpoint, ?error, "Unable to create proof for registered peg-in");
+                }
+            }
+        }
     }
 
     /// Add a change UTXO to our spendable UTXO database after it was included in a block that we
@@ -1455,10 +2412,44 @@ impl<'a> StatelessWallet<'a> {
             inputs: selected_utxos
                 .into_iter()
                 .map(|(_utxo_key, utxo)| {
-                    let script_pubkey = self
-                        .descriptor
-                        .tweak(&utxo.tweak, self.secp)
-                        .script_pubkey();
+                    let descriptor = self.descriptor.tweak(&utxo.tweak, self.secp);
+                    let script_pubkey = descriptor.script_pubkey();
+
+                    // Taproot inputs are signed via the FROST key path if possible and carry
+                    // the multisig script path in case key path signing fails
+                    let (witness_script, tap_scripts, tap_internal_key, tap_merkle_root) =
+                        match &descriptor {
+                            Descriptor::Tr(tr) => {
+                                let spend_info = tr.spend_info();
+                                let tap_scripts = tr
+                                    .iter_scripts()
+                                    .map(|(_depth, ms)| {
+                                        let script_ver = (ms.encode(), LeafVersion::TapScript);
+                                        let control_block = spend_info
+                                            .control_block(&script_ver)
+                                            .expect("Script is part of the tap tree");
+                                        (control_block, script_ver)
+                                    })
+                                    .collect();
+                                (
+                                    None,
+                                    tap_scripts,
+                                    Some(spend_info.internal_key()),
+                                    spend_info.merkle_root(),
+                                )
+                            }
+                            _ => (
+                                Some(
+                                    descriptor
+                                        .script_code()
+                                        .expect("Failed to tweak descriptor"),
+                                ),
+                                Default::default(),
+                                None,
+                                None,
+                            ),
+                        };
+
                     Input {
                         non_witness_utxo: None,
                         witness_utxo: Some(TxOut {
@@ -1468,12 +2459,7 @@ impl<'a> StatelessWallet<'a> {
                         partial_sigs: Default::default(),
                         sighash_type: None,
                         redeem_script: None,
-                        witness_script: Some(
-                            self.descriptor
-                                .tweak(&utxo.tweak, self.secp)
-                                .script_code()
-                                .expect("Failed to tweak descriptor"),
-                        ),
+                        witness_script,
                         bip32_derivation: Default::default(),
                         final_script_sig: None,
                         final_script_witness: None,
@@ -1486,10 +2472,10 @@ impl<'a> StatelessWallet<'a> {
                             .collect(),
                         tap_key_sig: Default::default(),
                         tap_script_sigs: Default::default(),
-                        tap_scripts: Default::default(),
+                        tap_scripts,
                         tap_key_origins: Default::default(),
-                        tap_internal_key: Default::default(),
-                        tap_merkle_root: Default::default(),
+                        tap_internal_key,
+                        tap_merkle_root,
                         unknown: Default::default(),
                     }
                 })
@@ -1509,6 +2495,11 @@ impl<'a> StatelessWallet<'a> {
     }
 
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
 
         for (idx, (psbt_input, _tx_input)) in psbt
@@ -1517,23 +2508,37 @@ impl<'a> StatelessWallet<'a> {
             .zip(psbt.unsigned_tx.input.iter())
             .enumerate()
         {
-            let tweaked_secret = {
-                let tweak_pk_bytes = psbt_input
-                    .proprietary
-                    .get(&proprietary_tweak_key())
-                    .expect("Malformed PSBT: expected tweak");
-                let pub_key = secp256k1::PublicKey::from_secret_key(self.secp, self.secret_key);
-
-                let tweak = {
-                    let mut hasher = HmacEngine::<sha256::Hash>::new(&pub_key.serialize()[..]);
-                    hasher.input(&tweak_pk_bytes[..]);
-                    Hmac::from_engine(hasher).into_inner()
-                };
+            let tweak_pk_bytes = psbt_input
+                .proprietary
+                .get(&proprietary_tweak_key())
+                .expect("Malformed PSBT: expected tweak");
+            let tweaked_secret = tweak_secret_key(self.secret_key, tweak_pk_bytes, self.secp);
+
+            if let Some(leaf_hash) = tap_leaf_hash(psbt_input) {
+                let tx_hash = tx_hasher
+                    .taproot_script_spend_signature_hash(
+                        idx,
+                        &Prevouts::All(&prevouts),
+                        leaf_hash,
+                        SchnorrSighashType::Default,
+                    )
+                    .expect("Failed to create taproot sighash");
+
+                let key_pair = KeyPair::from_secret_key(self.secp, &tweaked_secret);
+                let signature = self.secp.sign_schnorr_no_aux_rand(
+                    &Message::from_slice(&tx_hash[..]).unwrap(),
+                    &key_pair,
+                );
 
-                self.secret_key
-                    .add_tweak(&Scalar::from_be_bytes(tweak).expect("can't fail"))
-                    .expect("Tweaking priv key failed") // TODO: why could this happen?
-            };
+                psbt_input.tap_script_sigs.insert(
+                    (key_pair.x_only_public_key().0, leaf_hash),
+                    SchnorrSig {
+                        sig: signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    },
+                );
+                continue;
+            }
 
             let tx_hash = tx_hasher
                 .segwit_signature_hash(
@@ -1613,6 +2618,15 @@ impl<'a> StatelessWallet<'a> {
     }
 }
 
+/// Leaf hash of the script path taproot peg-out inputs are signed for, `None` for non-taproot inputs
+fn tap_leaf_hash(input: &Input) -> Option<TapLeafHash> {
+    input
+        .tap_scripts
+        .values()
+        .next()
+        .map(|(script, leaf_version)| TapLeafHash::from_script(script, *leaf_version))
+}
+
 fn proprietary_tweak_key() -> ProprietaryKey {
     ProprietaryKey {
         prefix: b"fedimint".to_vec(),
@@ -1668,7 +2682,11 @@ impl std::hash::Hash for PegOutSignatureItem {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.txid.hash(state);
         for sig in self.signature.iter() {
-            sig.serialize_der().hash(state);
+            match sig {
+                PegOutInputSignature::Ecdsa(sig) => sig.serialize_der().hash(state),
+                PegOutInputSignature::Schnorr(sig) => sig[..].hash(state),
+                PegOutInputSignature::FrostShare(share) => share.hash(state),
+            }
         }
     }
 }
@@ -1708,6 +2726,10 @@ pub enum WalletError {
     PegOutFeeRate(Feerate, Feerate),
     #[error("Not enough SpendableUTXO")]
     NotEnoughSpendableUTXO,
+    #[error("Peg-in registrations are disabled since the registration fee is zero")]
+    PegInRegistrationDisabled,
+    #[error("Our peg-in key is not part of the federation's peg-in keys")]
+    UnknownPegInKey,
 }
 
 #[derive(Debug, Error)]
@@ -1718,10 +2740,14 @@ pub enum ProcessPegOutSigError {
     WrongSignatureCount(usize, usize),
     #[error("Bad Sighash")]
     SighashError,
+    #[error("Signature type doesn't match the peg-in descriptor")]
+    WrongSignatureType,
     #[error("Malformed signature: {0}")]
     MalformedSignature(secp256k1::Error),
     #[error("Invalid signature")]
     InvalidSignature,
+    #[error("{0} is not part of the key path signing set")]
+    NotInSigningSet(PeerId),
     #[error("Duplicate signature")]
     DuplicateSignature,
     #[error("Missing change tweak")]
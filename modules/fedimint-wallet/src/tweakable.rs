@@ -1,7 +1,7 @@
 use std::io::Write;
 
 use bitcoin::hashes::{sha256, Hash as BitcoinHash, Hmac, HmacEngine};
-use secp256k1::{Scalar, Secp256k1, Verification};
+use secp256k1::{Scalar, Secp256k1, SecretKey, Signing, Verification};
 
 /// An object that can be used as a ricardian contract to tweak a key
 pub trait Contract {
@@ -15,13 +15,29 @@ pub trait Tweakable {
     fn tweak<Ctx: Verification, Ctr: Contract>(&self, tweak: &Ctr, secp: &Secp256k1<Ctx>) -> Self;
 }
 
+/// Scalar that `key` is tweaked with for `contract`, i.e. the tweaked key is `key + scalar * G`
+pub fn tweak_scalar<Ctr: Contract>(key: &secp256k1::PublicKey, contract: &Ctr) -> Scalar {
+    let mut hasher = HmacEngine::<sha256::Hash>::new(&key.serialize()[..]);
+    contract.encode(&mut hasher).expect("hashing is infallible");
+    let tweak = Hmac::from_engine(hasher).into_inner();
+
+    Scalar::from_be_bytes(tweak).expect("negligible probability")
+}
+
+/// Tweak the secret `key` with a `tweak` contract so it matches its tweaked public key
+pub fn tweak_secret_key<Ctx: Signing, Ctr: Contract>(
+    key: &SecretKey,
+    tweak: &Ctr,
+    secp: &Secp256k1<Ctx>,
+) -> SecretKey {
+    let pub_key = secp256k1::PublicKey::from_secret_key(secp, key);
+    key.add_tweak(&tweak_scalar(&pub_key, tweak))
+        .expect("tweak is always 32 bytes, other failure modes are negligible")
+}
+
 impl Tweakable for secp256k1::PublicKey {
     fn tweak<Ctx: Verification, Ctr: Contract>(&self, tweak: &Ctr, secp: &Secp256k1<Ctx>) -> Self {
-        let mut hasher = HmacEngine::<sha256::Hash>::new(&self.serialize()[..]);
-        tweak.encode(&mut hasher).expect("hashing is infallible");
-        let tweak = Hmac::from_engine(hasher).into_inner();
-
-        self.add_exp_tweak(secp, &Scalar::from_be_bytes(tweak).expect("can't fail"))
+        self.add_exp_tweak(secp, &tweak_scalar(self, tweak))
             .expect("tweak is always 32 bytes, other failure modes are negligible")
     }
 }
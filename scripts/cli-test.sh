@@ -60,6 +60,9 @@ LN_GATEWAY_BALANCE="$($FM_GATEWAY_CLI balance $FED_ID | jq -e -r '.balance_msat'
 INVOICE_RESULT="$($FM_LN2 waitinvoice test)"
 INVOICE_STATUS="$(echo $INVOICE_RESULT | jq -e -r '.status')"
 [[ "$INVOICE_STATUS" = "paid" ]]
+# the payment is kept in the client's payment history
+PAYMENT_STATUS="$($FM_MINT_CLIENT ln-history | jq -e -r '.payments[-1].status')"
+[[ "$PAYMENT_STATUS" = "succeeded" ]]
 
 # test that LN1 can still receive directly even though running the plugin
 INVOICE="$($FM_LN1 invoice 42000 test test 1m | jq -e -r '.bolt11')"
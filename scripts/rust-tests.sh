@@ -8,3 +8,6 @@ source ./scripts/setup-tests.sh ""
 
 export FM_TEST_DISABLE_MOCKS=1
 cargo test -p fedimint-tests "$@"
+
+# run the peg-in and peg-out tests again against a taproot federation
+FM_TEST_PEG_IN_DESCRIPTOR=tr cargo test -p fedimint-tests -- peg_in_ peg_outs_